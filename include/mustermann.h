@@ -0,0 +1,63 @@
+#ifndef MUSTERMANN_H
+#define MUSTERMANN_H
+
+/* Generated with cbindgen from src/ffi.rs, do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum MustermannStream {
+  MUSTERMANN_STREAM_STDOUT,
+  MUSTERMANN_STREAM_STDERR,
+} MustermannStream;
+
+// A parsed scenario, and its code once it is compiled
+typedef struct MustermannProgram MustermannProgram;
+
+// Called for every line a service prints, with the `user_data` passed to `mustermann_run`.
+// The strings are only valid during the call
+typedef void (*MustermannLogCallback)(void *user_data,
+                                      const char *service,
+                                      enum MustermannStream stream,
+                                      const char *message);
+
+// The message of the last error on this thread, or NULL. Valid until the next call that fails
+const char *mustermann_last_error(void);
+
+// Parses a scenario. Returns NULL if it does not parse
+//
+// # Safety
+//
+// `source` must be a NUL-terminated string
+struct MustermannProgram *mustermann_parse(const char *source);
+
+// Compiles every service of the program. Returns 0, or -1 if a service does not compile
+//
+// # Safety
+//
+// `program` must come from `mustermann_parse` and not be freed
+int32_t mustermann_compile(struct MustermannProgram *program);
+
+// Runs the compiled services for `duration_ms`, then shuts them down and waits until they
+// stopped. Prints go to `callback`, on the calling thread. Returns 0, or -1 if the program is not
+// compiled, a service failed or a service called one that does not exist
+//
+// # Safety
+//
+// `program` must come from `mustermann_parse` and not be freed. `callback` may be NULL
+int32_t mustermann_run(const struct MustermannProgram *program,
+                       uint64_t duration_ms,
+                       MustermannLogCallback callback,
+                       void *user_data);
+
+// Frees a program
+//
+// # Safety
+//
+// `program` must come from `mustermann_parse` or be NULL, and is invalid afterwards
+void mustermann_program_free(struct MustermannProgram *program);
+
+#endif /* MUSTERMANN_H */
@@ -0,0 +1,328 @@
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rand::seq::IndexedRandom;
+use rand::Rng;
+
+use crate::parser::AccessLogFormat;
+
+const METHODS: &[(&str, u32)] = &[
+    ("GET", 80),
+    ("POST", 12),
+    ("PUT", 3),
+    ("DELETE", 2),
+    ("HEAD", 2),
+    ("OPTIONS", 1),
+];
+
+/// Mostly successes, some redirects and client errors, few server errors
+const STATUS_CODES: &[(u16, u32)] = &[
+    (200, 820),
+    (201, 20),
+    (204, 20),
+    (301, 15),
+    (302, 15),
+    (304, 40),
+    (400, 10),
+    (403, 10),
+    (404, 35),
+    (429, 5),
+    (500, 4),
+    (502, 3),
+    (503, 2),
+    (504, 1),
+];
+
+const PATHS: &[(&str, &str)] = &[
+    ("/", "text/html"),
+    ("/index.html", "text/html"),
+    ("/products", "text/html"),
+    ("/api/v1/products", "application/json"),
+    ("/api/v1/cart", "application/json"),
+    ("/api/v1/orders", "application/json"),
+    ("/api/v1/users/me", "application/json"),
+    ("/static/app.js", "application/javascript"),
+    ("/static/styles.css", "text/css"),
+    ("/images/logo.png", "image/png"),
+    ("/favicon.ico", "image/x-icon"),
+    ("/health", "text/plain"),
+];
+
+const USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 14_5) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Safari/605.1.15",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:127.0) Gecko/20100101 Firefox/127.0",
+    "curl/8.5.0",
+    "python-requests/2.32.3",
+    "ELB-HealthChecker/2.0",
+];
+
+const EDGE_LOCATIONS: &[&str] = &[
+    "IAD89-C1", "FRA56-P3", "LHR61-C2", "NRT57-P2", "SFO53-C1", "GRU1-C1", "SYD1-C1",
+];
+
+const TLS: &[(&str, &str)] = &[
+    ("TLSv1.3", "TLS_AES_128_GCM_SHA256"),
+    ("TLSv1.2", "ECDHE-RSA-AES128-GCM-SHA256"),
+];
+
+const ALB_NAME: &str = "app/mustermann-alb/50dc6c495c0c9188";
+const TARGET_GROUP: &str =
+    "arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/mustermann/73e2d6bc24d8a067";
+const CLOUDFRONT_DOMAIN: &str = "d111111abcdef8.cloudfront.net";
+const HOST: &str = "www.example.com";
+
+/// A made-up access log line in `format` at `time`, with a plausible mix of requests, statuses
+/// and latencies
+pub fn generate(format: AccessLogFormat, time: SystemTime, rng: &mut impl Rng) -> String {
+    let request = Request::random(time, rng);
+    match format {
+        AccessLogFormat::Alb => alb(&request, rng),
+        AccessLogFormat::Cloudfront => cloudfront(&request, rng),
+    }
+}
+
+struct Request {
+    /// RFC 3339 with microseconds, e.g. `2025-01-01T00:00:00.123456Z`
+    timestamp: String,
+    method: &'static str,
+    path: &'static str,
+    content_type: &'static str,
+    status: u16,
+    user_agent: &'static str,
+    client_ip: String,
+    client_port: u16,
+    /// In seconds
+    duration: f64,
+    received_bytes: u64,
+    sent_bytes: u64,
+    tls: (&'static str, &'static str),
+}
+
+impl Request {
+    fn random(time: SystemTime, rng: &mut impl Rng) -> Self {
+        let (path, content_type) = *PATHS.choose(rng).unwrap();
+        let status = weighted(STATUS_CODES, rng);
+        let sent_bytes = match status {
+            204 | 304 => 0,
+            _ if content_type.starts_with("image") => rng.random_range(1_000..200_000),
+            _ => rng.random_range(200..20_000),
+        };
+        Request {
+            timestamp: rfc3339(time),
+            method: weighted(METHODS, rng),
+            path,
+            content_type,
+            status,
+            user_agent: USER_AGENTS.choose(rng).unwrap(),
+            client_ip: format!(
+                "{}.{}.{}.{}",
+                rng.random_range(1..224),
+                rng.random::<u8>(),
+                rng.random::<u8>(),
+                rng.random_range(1..255)
+            ),
+            client_port: rng.random_range(1024..65535),
+            duration: latency(status, rng),
+            received_bytes: rng.random_range(100..2_000),
+            sent_bytes,
+            tls: *TLS.choose(rng).unwrap(),
+        }
+    }
+}
+
+/// `time` in UTC, in RFC 3339 with microseconds
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, second_of_day) = ((seconds / 86_400) as i64, seconds % 86_400);
+    // The date of a day since 1970-01-01, after `civil_from_days` by Howard Hinnant
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3_600,
+        second_of_day / 60 % 60,
+        second_of_day % 60,
+        since_epoch.subsec_micros()
+    )
+}
+
+fn weighted<T: Copy>(choices: &[(T, u32)], rng: &mut impl Rng) -> T {
+    choices
+        .choose_weighted(rng, |(_, weight)| *weight)
+        .unwrap()
+        .0
+}
+
+/// Exponentially distributed around 50ms, with timeouts taking much longer
+fn latency(status: u16, rng: &mut impl Rng) -> f64 {
+    let mean = if status == 504 { 30.0 } else { 0.05 };
+    let uniform: f64 = rng.random_range(f64::EPSILON..1.0);
+    -uniform.ln() * mean
+}
+
+fn hex(length: usize, rng: &mut impl Rng) -> String {
+    (0..length)
+        .map(|_| char::from_digit(rng.random_range(0..16), 16).unwrap())
+        .collect()
+}
+
+// https://docs.aws.amazon.com/elasticloadbalancing/latest/application/load-balancer-access-logs.html
+fn alb(request: &Request, rng: &mut impl Rng) -> String {
+    let target = format!(
+        "10.0.{}.{}:8080",
+        rng.random_range(0..4),
+        rng.random_range(2..255)
+    );
+    // The load balancer answers 502 to 504 itself when the target fails
+    let failed = matches!(request.status, 502..=504);
+    let target = if failed { "-" } else { target.as_str() };
+    let (target_time, target_status) = if failed {
+        ("-1".to_string(), "-".to_string())
+    } else {
+        (
+            format!("{:.3}", request.duration),
+            request.status.to_string(),
+        )
+    };
+    let mut line = String::new();
+    let _ = write!(
+        line,
+        "https {} {} {}:{} {} 0.000 {} 0.000 {} {} {} {} ",
+        request.timestamp,
+        ALB_NAME,
+        request.client_ip,
+        request.client_port,
+        target,
+        target_time,
+        request.status,
+        target_status,
+        request.received_bytes,
+        request.sent_bytes,
+    );
+    let _ = write!(
+        line,
+        "\"{} https://{}:443{} HTTP/1.1\" \"{}\" {} {} {} \"Root=1-{}-{}\" \"{}\" \"-\" 0 {} \"forward\" \"-\" \"-\" \"{}\" \"{}\" \"-\" \"-\" TID_{}",
+        request.method,
+        HOST,
+        request.path,
+        request.user_agent,
+        request.tls.1,
+        request.tls.0,
+        TARGET_GROUP,
+        hex(8, rng),
+        hex(24, rng),
+        HOST,
+        request.timestamp,
+        target,
+        target_status,
+        hex(32, rng),
+    );
+    line
+}
+
+// https://docs.aws.amazon.com/AmazonCloudFront/latest/DeveloperGuide/standard-logs-reference.html
+fn cloudfront(request: &Request, rng: &mut impl Rng) -> String {
+    // 2025-01-01T00:00:00.123456Z, logged as date and time to the second
+    let (date, time) = request.timestamp.split_once('T').unwrap_or(("-", "-"));
+    let time = time.split('.').next().unwrap_or(time);
+    let result = match request.status {
+        400.. => "Error",
+        301 | 302 => "Redirect",
+        _ if rng.random_bool(0.7) => "Hit",
+        _ => "Miss",
+    };
+    let request_id: String = (0..56)
+        .map(|_| {
+            const ALPHABET: &[u8] =
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789_-";
+            ALPHABET[rng.random_range(0..ALPHABET.len())] as char
+        })
+        .collect();
+    let user_agent = request.user_agent.replace(' ', "%20");
+    let time_to_first_byte = request.duration * 0.8;
+    [
+        date.to_string(),
+        time.to_string(),
+        EDGE_LOCATIONS.choose(rng).unwrap().to_string(),
+        request.sent_bytes.to_string(),
+        request.client_ip.clone(),
+        request.method.to_string(),
+        CLOUDFRONT_DOMAIN.to_string(),
+        request.path.to_string(),
+        request.status.to_string(),
+        "-".to_string(),
+        user_agent,
+        "-".to_string(),
+        "-".to_string(),
+        result.to_string(),
+        format!("{}==", request_id),
+        HOST.to_string(),
+        "https".to_string(),
+        request.received_bytes.to_string(),
+        format!("{:.3}", request.duration),
+        "-".to_string(),
+        request.tls.0.to_string(),
+        request.tls.1.to_string(),
+        result.to_string(),
+        "HTTP/2.0".to_string(),
+        "-".to_string(),
+        "-".to_string(),
+        request.client_port.to_string(),
+        format!("{:.3}", time_to_first_byte),
+        result.to_string(),
+        request.content_type.to_string(),
+        request.sent_bytes.to_string(),
+        "-".to_string(),
+        "-".to_string(),
+    ]
+    .join("\t")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_access_logs_have_the_fields_of_their_format() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            let line = generate(AccessLogFormat::Cloudfront, SystemTime::now(), &mut rng);
+            assert_eq!(line.split('\t').count(), 33);
+
+            let line = generate(AccessLogFormat::Alb, SystemTime::now(), &mut rng);
+            assert!(line.starts_with("https "));
+            // Quoted fields stay together, so the line splits like the AWS parsers expect
+            let quoted = line.split('"').count() - 1;
+            assert_eq!(quoted, 24);
+            let unquoted: Vec<&str> = line.split('"').step_by(2).collect();
+            assert_eq!(unquoted[0].split_whitespace().count(), 12);
+        }
+    }
+
+    #[test]
+    fn test_timestamps_are_rfc_3339_in_utc() {
+        let time = UNIX_EPOCH + std::time::Duration::from_micros(1_709_210_096_123_456);
+        assert_eq!(rfc3339(time), "2024-02-29T12:34:56.123456Z");
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000000Z");
+    }
+}
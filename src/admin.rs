@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::chaos::ChaosEvent;
+use crate::health::{self, HealthReport};
+use crate::parser;
+use crate::vm_coordinator::{self, CallError, CoordinatorStats, ServiceMessage};
+
+/// The caller of the calls made through the admin API, for routes like `admin->products`
+const ADMIN_SERVICE: &str = "admin";
+
+type AdminError = (StatusCode, String);
+
+/// A call to make through the admin API
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CallRequest {
+    #[serde(default = "admin_service")]
+    pub from: String,
+    pub to: String,
+    pub function: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CallResponse {
+    pub call_id: vm_coordinator::CallId,
+}
+
+fn admin_service() -> String {
+    ADMIN_SERVICE.to_string()
+}
+
+/// Serves the admin API until `shutdown` completes:
+///
+/// - `POST /calls` makes a call, e.g. `{"to": "products", "function": "get_products"}`
+/// - `POST /injections` and `DELETE /injections` inject and lift latency or faults, given like `--inject`
+/// - `POST /services/{name}/pause` and `POST /services/{name}/resume`
+/// - `GET /stats` returns the counters of the coordinator
+/// - `GET /health`, if health checks run
+pub async fn serve(
+    listener: TcpListener,
+    coordinator: mpsc::Sender<ServiceMessage>,
+    health: Option<Arc<Mutex<HealthReport>>>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    let mut router = Router::new()
+        .route("/calls", post(make_call))
+        .route("/injections", post(inject))
+        .route("/injections", delete(lift))
+        .route("/services/:name/pause", post(pause))
+        .route("/services/:name/resume", post(resume))
+        .route("/stats", get(stats))
+        .with_state(coordinator);
+    if let Some(health) = health {
+        router = router.merge(health::router(health));
+    }
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+// Makes a call and answers once the callee's function returned
+async fn make_call(
+    State(coordinator): State<mpsc::Sender<ServiceMessage>>,
+    Json(request): Json<CallRequest>,
+) -> Result<Json<CallResponse>, AdminError> {
+    let call_id = vm_coordinator::next_call_id();
+    tracing::info!(
+        call_id,
+        "Admin API: calling {}.{}",
+        request.to,
+        request.function
+    );
+    let (reply_tx, reply_rx) = oneshot::channel();
+    send(
+        &coordinator,
+        ServiceMessage::Call {
+            id: call_id,
+            from: request.from,
+            to: request.to,
+            function: request.function,
+            context: HashMap::new(),
+            reply: Some(reply_tx),
+        },
+    )
+    .await?;
+    match reply_rx.await.unwrap_or(Err(CallError::Dropped)) {
+        Ok(()) => Ok(Json(CallResponse { call_id })),
+        Err(e @ (CallError::ServiceNotFound(_) | CallError::UnknownFunction(_))) => {
+            Err((StatusCode::NOT_FOUND, e.to_string()))
+        }
+        Err(e) => Err((StatusCode::BAD_GATEWAY, e.to_string())),
+    }
+}
+
+async fn inject(
+    State(coordinator): State<mpsc::Sender<ServiceMessage>>,
+    injection: String,
+) -> Result<StatusCode, AdminError> {
+    let injection = parser::parse_injection(&injection)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    send(
+        &coordinator,
+        ServiceMessage::Chaos(ChaosEvent::Inject(injection)),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Lifts an injection made before, given the same way
+async fn lift(
+    State(coordinator): State<mpsc::Sender<ServiceMessage>>,
+    injection: String,
+) -> Result<StatusCode, AdminError> {
+    let injection = parser::parse_injection(&injection)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    send(
+        &coordinator,
+        ServiceMessage::Chaos(ChaosEvent::Lift(injection)),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pause(
+    State(coordinator): State<mpsc::Sender<ServiceMessage>>,
+    Path(service): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    send(
+        &coordinator,
+        ServiceMessage::Chaos(ChaosEvent::Kill(service)),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn resume(
+    State(coordinator): State<mpsc::Sender<ServiceMessage>>,
+    Path(service): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    send(
+        &coordinator,
+        ServiceMessage::Chaos(ChaosEvent::Restart(service)),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn stats(
+    State(coordinator): State<mpsc::Sender<ServiceMessage>>,
+) -> Result<Json<CoordinatorStats>, AdminError> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    send(&coordinator, ServiceMessage::Stats { reply: reply_tx }).await?;
+    let stats = reply_rx.await.map_err(|_| coordinator_stopped())?;
+    Ok(Json(stats))
+}
+
+async fn send(
+    coordinator: &mpsc::Sender<ServiceMessage>,
+    msg: ServiceMessage,
+) -> Result<(), AdminError> {
+    coordinator
+        .send(msg)
+        .await
+        .map_err(|_| coordinator_stopped())
+}
+
+fn coordinator_stopped() -> AdminError {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        vm_coordinator::CoordinatorStopped.to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm_coordinator::ServiceCoordinator;
+
+    #[tokio::test]
+    async fn test_admin_api_controls_the_coordinator() {
+        let coordinator =
+            ServiceCoordinator::new().with_pausable_services(["products".to_string()]);
+        let coordinator_handle = coordinator.handle();
+        let mut suspension = coordinator_handle.suspension("products").unwrap();
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+        let products = tokio::spawn(async move {
+            while let Some(call) = products_rx.recv().await {
+                assert_eq!(call.function, "get_products");
+                call.reply.unwrap().send(Ok(())).unwrap();
+            }
+        });
+        let sender = || State(coordinator_handle.sender());
+
+        let request = CallRequest {
+            from: admin_service(),
+            to: "products".to_string(),
+            function: "get_products".to_string(),
+        };
+        let Json(response) = make_call(sender(), Json(request.clone())).await.unwrap();
+        assert!(response.call_id > 0);
+        let missing = CallRequest {
+            to: "payments".to_string(),
+            ..request.clone()
+        };
+        let (status, _) = make_call(sender(), Json(missing)).await.unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let injection = "faults admin->products error 100%".to_string();
+        inject(sender(), injection.clone()).await.unwrap();
+        let (status, _) = make_call(sender(), Json(request.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        lift(sender(), injection).await.unwrap();
+        let (status, _) = inject(sender(), "faults".to_string()).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        pause(sender(), Path("products".to_string())).await.unwrap();
+        suspension.wait_for(|paused| *paused).await.unwrap();
+        let Json(stats) = stats(sender()).await.unwrap();
+        assert_eq!(stats.injections, 0);
+        assert_eq!(stats.services.len(), 1);
+        assert_eq!(stats.services[0].delivered_calls, 1);
+        assert!(stats.services[0].killed);
+        resume(sender(), Path("products".to_string()))
+            .await
+            .unwrap();
+        suspension.wait_for(|paused| !*paused).await.unwrap();
+        assert!(make_call(sender(), Json(request)).await.is_ok());
+
+        coordinator_handle
+            .deregister_service("products", None)
+            .await
+            .unwrap();
+        drop(coordinator_handle);
+        handle.await.unwrap();
+        products.await.unwrap();
+    }
+}
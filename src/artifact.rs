@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::code_gen::error::CodeGenError;
+use crate::code_gen::instruction::Instruction;
+use crate::code_gen::CodeGenerator;
+use crate::decoder::{self, DecodeError};
+use crate::parser::{LatencySpike, Program, ServiceConfig};
+
+/// The first bytes of a compiled file
+const MAGIC: &[u8; 3] = b"MBC";
+/// Bumped whenever the layout of compiled files or the instruction set changes
+const VERSION: u8 = 7;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArtifactError {
+    /// The file does not start with the header of a compiled file
+    NotCompiled,
+    /// The file was compiled by a version of mustermann with another bytecode format
+    UnsupportedVersion(u8),
+    /// The file ends in the middle of a service
+    UnexpectedEnd,
+    /// A service name is not valid UTF-8
+    InvalidName,
+    /// The span name template of the named service is not valid UTF-8
+    InvalidSpanName(String),
+    /// An attribute of the named service is not valid UTF-8
+    InvalidAttribute(String),
+    /// The bytecode of the named service does not decode
+    InvalidBytecode(String, DecodeError),
+}
+
+impl std::error::Error for ArtifactError {}
+
+impl std::fmt::Display for ArtifactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArtifactError::NotCompiled => write!(f, "Not a compiled mustermann file"),
+            ArtifactError::UnsupportedVersion(version) => write!(
+                f,
+                "Compiled with bytecode version {}, this version of mustermann runs version {}",
+                version, VERSION
+            ),
+            ArtifactError::UnexpectedEnd => write!(f, "Unexpected end of the compiled file"),
+            ArtifactError::InvalidName => write!(f, "Invalid UTF-8 service name"),
+            ArtifactError::InvalidSpanName(service) => {
+                write!(f, "Invalid UTF-8 span name in service {}", service)
+            }
+            ArtifactError::InvalidAttribute(service) => {
+                write!(f, "Invalid UTF-8 attribute in service {}", service)
+            }
+            ArtifactError::InvalidBytecode(service, e) => {
+                write!(f, "Invalid bytecode in service {}: {}", service, e)
+            }
+        }
+    }
+}
+
+/// A service, ready to run without its source
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompiledService {
+    pub name: String,
+    pub config: ServiceConfig,
+    pub code: Vec<Instruction>,
+}
+
+/// Generates the code of every service of the program
+pub fn compile(program: &Program) -> Result<Vec<CompiledService>, CodeGenError> {
+    program
+        .services
+        .iter()
+        .map(|service| {
+            Ok(CompiledService {
+                name: service.name.clone(),
+                config: service.config.clone(),
+                code: CodeGenerator::new(service).process()?,
+            })
+        })
+        .collect()
+}
+
+/// Writes the services as a compiled file. Numbers are little endian u64s, the rate of a spike
+/// is the bits of an f64, strings and bytecode are prefixed with their length:
+///
+/// ```text
+/// "MBC" version service_count (name config span_name windows attributes spike code_length code)*
+/// ```
+pub fn to_bytes(services: &[CompiledService]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    write_u64(&mut bytes, services.len() as u64);
+    for service in services {
+        write_u64(&mut bytes, service.name.len() as u64);
+        bytes.extend_from_slice(service.name.as_bytes());
+        let config = &service.config;
+        for setting in [
+            config.max_instructions,
+            config.remote_call_limit,
+            config.print_rate_limit,
+            config.print_sample_rate,
+            config.replicas,
+        ] {
+            match setting {
+                Some(value) => {
+                    bytes.push(1);
+                    write_u64(&mut bytes, value as u64);
+                }
+                None => bytes.push(0),
+            }
+        }
+        match &config.span_name {
+            Some(span_name) => {
+                bytes.push(1);
+                write_u64(&mut bytes, span_name.len() as u64);
+                bytes.extend_from_slice(span_name.as_bytes());
+            }
+            None => bytes.push(0),
+        }
+        for window in [config.start_after, config.stop_after] {
+            match window {
+                Some(window) => {
+                    bytes.push(1);
+                    write_u64(&mut bytes, window.as_millis() as u64);
+                }
+                None => bytes.push(0),
+            }
+        }
+        write_u64(&mut bytes, config.attributes.len() as u64);
+        for (key, value) in &config.attributes {
+            for s in [key, value] {
+                write_u64(&mut bytes, s.len() as u64);
+                bytes.extend_from_slice(s.as_bytes());
+            }
+        }
+        match &config.spike {
+            Some(spike) => {
+                bytes.push(1);
+                write_u64(&mut bytes, spike.rate.to_bits());
+                write_u64(&mut bytes, spike.to.as_millis() as u64);
+            }
+            None => bytes.push(0),
+        }
+        let code: Vec<u8> = service.code.iter().flat_map(|i| i.to_bytes()).collect();
+        write_u64(&mut bytes, code.len() as u64);
+        bytes.extend_from_slice(&code);
+    }
+    bytes
+}
+
+/// Reads the services of a compiled file, decoding their bytecode
+pub fn from_bytes(bytes: &[u8]) -> Result<Vec<CompiledService>, ArtifactError> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.read(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(ArtifactError::NotCompiled);
+    }
+    let version = reader.read(1)?[0];
+    if version != VERSION {
+        return Err(ArtifactError::UnsupportedVersion(version));
+    }
+    let service_count = reader.read_u64()?;
+    let mut services = Vec::new();
+    for _ in 0..service_count {
+        let name_length = reader.read_u64()? as usize;
+        let name = std::str::from_utf8(reader.read(name_length)?)
+            .map_err(|_| ArtifactError::InvalidName)?
+            .to_string();
+        let mut settings = [None; 5];
+        for setting in &mut settings {
+            if reader.read(1)?[0] == 1 {
+                *setting = Some(reader.read_u64()? as usize);
+            }
+        }
+        let [max_instructions, remote_call_limit, print_rate_limit, print_sample_rate, replicas] =
+            settings;
+        let span_name = if reader.read(1)?[0] == 1 {
+            let length = reader.read_u64()? as usize;
+            let span_name = std::str::from_utf8(reader.read(length)?)
+                .map_err(|_| ArtifactError::InvalidSpanName(name.clone()))?;
+            Some(span_name.to_string())
+        } else {
+            None
+        };
+        let mut windows = [None; 2];
+        for window in &mut windows {
+            if reader.read(1)?[0] == 1 {
+                *window = Some(Duration::from_millis(reader.read_u64()?));
+            }
+        }
+        let [start_after, stop_after] = windows;
+        let mut attributes = BTreeMap::new();
+        for _ in 0..reader.read_u64()? {
+            let mut pair = [String::new(), String::new()];
+            for s in &mut pair {
+                let length = reader.read_u64()? as usize;
+                *s = std::str::from_utf8(reader.read(length)?)
+                    .map_err(|_| ArtifactError::InvalidAttribute(name.clone()))?
+                    .to_string();
+            }
+            let [key, value] = pair;
+            attributes.insert(key, value);
+        }
+        let spike = if reader.read(1)?[0] == 1 {
+            Some(LatencySpike {
+                rate: f64::from_bits(reader.read_u64()?),
+                to: Duration::from_millis(reader.read_u64()?),
+            })
+        } else {
+            None
+        };
+        let code_length = reader.read_u64()? as usize;
+        let code = decoder::decode_instructions(reader.read(code_length)?)
+            .map_err(|e| ArtifactError::InvalidBytecode(name.clone(), e))?;
+        services.push(CompiledService {
+            name,
+            config: ServiceConfig {
+                max_instructions,
+                remote_call_limit,
+                print_rate_limit,
+                print_sample_rate,
+                replicas,
+                span_name,
+                start_after,
+                stop_after,
+                attributes,
+                spike,
+            },
+            code,
+        });
+    }
+    if reader.position < bytes.len() {
+        return Err(ArtifactError::NotCompiled);
+    }
+    Ok(services)
+}
+
+fn write_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, len: usize) -> Result<&'a [u8], ArtifactError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(ArtifactError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_u64(&mut self) -> Result<u64, ArtifactError> {
+        Ok(u64::from_le_bytes(self.read(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn test_compiled_services_round_trip() {
+        let program = parser::parse(
+            "
+            service products {
+                config {
+                    replicas 3;
+                    span_name \"HTTP GET /{method}\";
+                    start_after 30s;
+                    attribute env = \"production\";
+                    spike 0.5% of calls to 5s;
+                }
+                method get_products {
+                    print \"Fetching products\";
+                }
+            }
+            service frontend {
+                method main_page {
+                    call products.get_products;
+                }
+                loop {
+                    call main_page;
+                }
+            }
+            ",
+        )
+        .unwrap();
+        let services = compile(&program).unwrap();
+        let bytes = to_bytes(&services);
+        assert_eq!(from_bytes(&bytes).unwrap(), services);
+        assert_eq!(services[0].config.replicas, Some(3));
+        assert_eq!(
+            services[0].config.start_after,
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(services[0].config.attributes["env"], "production");
+        assert_eq!(
+            services[0].config.spike,
+            Some(LatencySpike {
+                rate: 0.005,
+                to: Duration::from_secs(5),
+            })
+        );
+
+        assert_eq!(
+            from_bytes(b"service products {}"),
+            Err(ArtifactError::NotCompiled)
+        );
+        assert_eq!(
+            from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ArtifactError::UnexpectedEnd)
+        );
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        assert_eq!(
+            from_bytes(&newer),
+            Err(ArtifactError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! The assertions of `mustermann test`: how many logs and calls the services of a scenario have
+//! to emit in a dry run, to gate demo scenarios in CI.
+
+use serde::Deserialize;
+
+use crate::run_stats::{Counter, RunStats};
+
+/// A bound on a counter of a service, e.g. that `frontend` emits at least 100 info logs
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Assertion {
+    pub service: String,
+    pub count: Counter,
+    #[serde(default)]
+    pub at_least: Option<usize>,
+    #[serde(default)]
+    pub at_most: Option<usize>,
+}
+
+impl std::fmt::Display for Assertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.service, self.count)?;
+        if let Some(at_least) = self.at_least {
+            write!(f, " >= {}", at_least)?;
+        }
+        if let Some(at_most) = self.at_most {
+            write!(f, " <= {}", at_most)?;
+        }
+        Ok(())
+    }
+}
+
+impl Assertion {
+    fn holds(&self, count: usize) -> bool {
+        self.at_least.is_none_or(|at_least| count >= at_least)
+            && self.at_most.is_none_or(|at_most| count <= at_most)
+    }
+}
+
+/// What an assertion found after the run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub assertion: Assertion,
+    /// The counter of the service, `None` if the service did not run
+    pub count: Option<usize>,
+    pub passed: bool,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = if self.passed { "ok" } else { "FAILED" };
+        match self.count {
+            Some(count) => write!(f, "{}: {} (was {})", status, self.assertion, count),
+            None => write!(
+                f,
+                "{}: {} (the service did not run)",
+                status, self.assertion
+            ),
+        }
+    }
+}
+
+/// Reads a YAML list of assertions. Errors name the field, e.g. `[1].count: unknown variant ...`
+pub fn parse(content: &str) -> anyhow::Result<Vec<Assertion>> {
+    let value: serde_json::Value = serde_yaml::from_str(content)?;
+    let assertions: Vec<Assertion> = serde_path_to_error::deserialize(value)
+        .map_err(|e| anyhow::anyhow!("{}: {}", e.path(), e.inner()))?;
+    for (i, assertion) in assertions.iter().enumerate() {
+        if assertion.at_least.is_none() && assertion.at_most.is_none() {
+            anyhow::bail!("[{}]: needs at_least, at_most or both", i);
+        }
+    }
+    Ok(assertions)
+}
+
+/// Checks every assertion against what the services did during the run
+pub fn evaluate(assertions: &[Assertion], run_stats: &RunStats) -> Vec<Outcome> {
+    assertions
+        .iter()
+        .map(|assertion| {
+            let count = run_stats.count(&assertion.service, assertion.count);
+            Outcome {
+                assertion: assertion.clone(),
+                count,
+                passed: count.is_some_and(|count| assertion.holds(count)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VmStats;
+    use std::time::Duration;
+
+    #[test]
+    fn test_assertions_check_the_counters_of_the_run() {
+        let assertions = parse(
+            "
+            - service: frontend
+              count: stdout
+              at_least: 100
+            - service: products
+              count: calls_received
+              at_least: 10
+              at_most: 20
+            - service: checkout
+              count: error_logs
+              at_most: 0
+            ",
+        )
+        .unwrap();
+        let run_stats = RunStats::default();
+        run_stats.record_logs("frontend", 150, 0, 0);
+        let stats = VmStats {
+            incoming_calls: 25,
+            ..VmStats::default()
+        };
+        run_stats.record_instance("products", &stats, Duration::from_secs(1));
+
+        let outcomes: Vec<String> = evaluate(&assertions, &run_stats)
+            .iter()
+            .map(Outcome::to_string)
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                "ok: frontend info_logs >= 100 (was 150)",
+                "FAILED: products calls_received >= 10 <= 20 (was 25)",
+                "FAILED: checkout error_logs <= 0 (the service did not run)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assertions_need_a_bound() {
+        assert_eq!(
+            parse("- service: frontend\n  count: stdout")
+                .unwrap_err()
+                .to_string(),
+            "[0]: needs at_least, at_most or both"
+        );
+        assert!(parse("- service: frontend\n  count: lines\n  at_least: 1")
+            .unwrap_err()
+            .to_string()
+            .starts_with("[0].count: unknown variant `lines`"));
+    }
+}
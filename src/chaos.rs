@@ -0,0 +1,299 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::parser::{ChaosAction, ChaosKind, Incident, Injection, ServiceConfig};
+
+/// A step of a chaos schedule, as the coordinator carries it out
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChaosEvent {
+    Kill(String),
+    Restart(String),
+    Inject(Injection),
+    /// Ends an injection that was started by the schedule
+    Lift(Injection),
+}
+
+/// The chaos actions of a run, in the order they are due
+#[derive(Debug, Clone, Default)]
+pub struct ChaosSchedule {
+    events: VecDeque<(Duration, ChaosEvent)>,
+}
+
+impl ChaosSchedule {
+    pub fn new(actions: &[ChaosAction]) -> Self {
+        let mut events = Vec::new();
+        for action in actions {
+            match &action.kind {
+                ChaosKind::Kill(service) => {
+                    events.push((action.at, ChaosEvent::Kill(service.clone())))
+                }
+                ChaosKind::Restart(service) => {
+                    events.push((action.at, ChaosEvent::Restart(service.clone())))
+                }
+                ChaosKind::Inject { injection, until } => {
+                    events.push((action.at, ChaosEvent::Inject(injection.clone())));
+                    if let Some(until) = until {
+                        events.push((*until, ChaosEvent::Lift(injection.clone())));
+                    }
+                }
+            }
+        }
+        // Stable, so actions due at the same time happen in the order they were written
+        events.sort_by_key(|(at, _)| *at);
+        Self {
+            events: events.into(),
+        }
+    }
+
+    /// When the next event is due, counted from the start of the run
+    pub fn next_at(&self) -> Option<Duration> {
+        self.events.front().map(|(at, _)| *at)
+    }
+
+    /// Takes the next event if it is due `elapsed` after the start of the run
+    pub fn pop_due(&mut self, elapsed: Duration) -> Option<ChaosEvent> {
+        if self.next_at()? > elapsed {
+            return None;
+        }
+        self.events.pop_front().map(|(_, event)| event)
+    }
+
+    #[cfg(feature = "native")]
+    /// The services the schedule kills at some point
+    pub fn killed_services(&self) -> impl Iterator<Item = &str> {
+        self.events.iter().filter_map(|(_, event)| match event {
+            ChaosEvent::Kill(service) => Some(service.as_str()),
+            _ => None,
+        })
+    }
+}
+
+impl Incident {
+    /// The error rate `elapsed` after the start of the run, `None` before the incident and after
+    /// it recovered
+    pub fn error_rate_at(&self, elapsed: Duration) -> Option<f64> {
+        let into_incident = elapsed.checked_sub(self.at)?;
+        if into_incident < self.over {
+            let progress = into_incident.as_secs_f64() / self.over.as_secs_f64();
+            return Some(self.from + (self.to - self.from) * progress);
+        }
+        let Some(recover) = self.recover else {
+            return Some(self.to);
+        };
+        let into_recovery = into_incident - self.over;
+        if into_recovery < recover {
+            let progress = into_recovery.as_secs_f64() / recover.as_secs_f64();
+            return Some(self.to + (self.from - self.to) * progress);
+        }
+        None
+    }
+}
+
+/// The highest error rate of the incidents of `service` `elapsed` after the start of the run.
+/// `None` if no incident names the service, so services without one never roll the dice
+pub fn incident_error_rate(
+    incidents: &[Incident],
+    service: &str,
+    elapsed: Duration,
+) -> Option<f64> {
+    incidents
+        .iter()
+        .filter(|incident| incident.service == service)
+        .map(|incident| incident.error_rate_at(elapsed).unwrap_or(0.0))
+        .reduce(f64::max)
+}
+
+/// How a call fails on purpose
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The call never completes
+    Drop,
+    /// The callee answers with an error
+    Error,
+}
+
+/// Rolls the dice for the first fault injected into the route from `from` to `to`, or else for
+/// the incidents of `to` `elapsed` after the start of the run
+pub fn injected_fault(
+    injections: &[Injection],
+    incidents: &[Incident],
+    (from, to): (&str, &str),
+    elapsed: Duration,
+    mut roll: impl FnMut() -> f64,
+) -> Option<Fault> {
+    injections
+        .iter()
+        .find_map(|injection| match injection {
+            Injection::Faults {
+                route,
+                drop_rate,
+                error_rate,
+            } if route.matches(from, to) => {
+                let roll = roll();
+                if roll < *drop_rate {
+                    Some(Fault::Drop)
+                } else if roll < drop_rate + error_rate {
+                    Some(Fault::Error)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .or_else(|| {
+            let error_rate = incident_error_rate(incidents, to, elapsed)?;
+            (roll() < error_rate).then_some(Fault::Error)
+        })
+}
+
+/// The total latency injected into calls from `from` to `to`, with the jitter picked by
+/// `random_range` in nanoseconds
+pub fn injected_latency(
+    injections: &[Injection],
+    (from, to): (&str, &str),
+    mut random_range: impl FnMut(Range<u64>) -> u64,
+) -> Duration {
+    injections
+        .iter()
+        .map(|injection| match injection {
+            Injection::Latency {
+                route,
+                delay,
+                jitter,
+            } if route.matches(from, to) => {
+                let low = delay.saturating_sub(*jitter);
+                let high = *delay + *jitter;
+                let spread = (high - low).as_nanos() as u64;
+                low + Duration::from_nanos(random_range(0..spread + 1))
+            }
+            _ => Duration::ZERO,
+        })
+        .sum()
+}
+
+/// Kills services before their `start_after` and once their `stop_after` is reached, and
+/// restarts them when they start
+pub fn service_windows<'a>(
+    services: impl IntoIterator<Item = (&'a str, &'a ServiceConfig)>,
+) -> Vec<ChaosAction> {
+    let mut actions = Vec::new();
+    for (service, config) in services {
+        let mut action = |at, kind| actions.push(ChaosAction { at, kind });
+        if let Some(start_after) = config.start_after {
+            action(Duration::ZERO, ChaosKind::Kill(service.to_string()));
+            action(start_after, ChaosKind::Restart(service.to_string()));
+        }
+        if let Some(stop_after) = config.stop_after {
+            action(stop_after, ChaosKind::Kill(service.to_string()));
+        }
+    }
+    actions
+}
+
+// The killed services are only asked for by the coordinator of the native build
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::parser::parse_chaos;
+
+    #[test]
+    fn test_schedule_orders_events_and_lifts_windows() {
+        let actions = parse_chaos(
+            "
+            at 5m restart products;
+            from 3m to 4m latency frontend->api 1s;
+            at 2m kill products;
+            ",
+        )
+        .unwrap();
+        let mut schedule = ChaosSchedule::new(&actions);
+        assert_eq!(schedule.killed_services().collect::<Vec<_>>(), ["products"]);
+        assert_eq!(schedule.next_at(), Some(Duration::from_secs(120)));
+        assert_eq!(schedule.pop_due(Duration::from_secs(60)), None);
+        assert_eq!(
+            schedule.pop_due(Duration::from_secs(200)),
+            Some(ChaosEvent::Kill("products".to_string()))
+        );
+        assert!(matches!(
+            schedule.pop_due(Duration::from_secs(200)),
+            Some(ChaosEvent::Inject(_))
+        ));
+        assert_eq!(schedule.pop_due(Duration::from_secs(200)), None);
+        assert!(matches!(
+            schedule.pop_due(Duration::from_secs(240)),
+            Some(ChaosEvent::Lift(_))
+        ));
+        assert_eq!(
+            schedule.pop_due(Duration::from_secs(300)),
+            Some(ChaosEvent::Restart("products".to_string()))
+        );
+        assert_eq!(schedule.next_at(), None);
+    }
+
+    #[test]
+    fn test_service_windows_kill_services_outside_their_window() {
+        let program = crate::parser::parse(
+            "
+            service cron {
+                config {
+                    start_after 1m;
+                    stop_after 2m;
+                }
+                method run {
+                    print \"Running job\";
+                }
+            }
+            service frontend {
+                method main_page {
+                    print \"Rendering\";
+                }
+            }
+            ",
+        )
+        .unwrap();
+        let actions = service_windows(
+            program
+                .services
+                .iter()
+                .map(|service| (service.name.as_str(), &service.config)),
+        );
+        let mut schedule = ChaosSchedule::new(&actions);
+        assert_eq!(
+            schedule.pop_due(Duration::ZERO),
+            Some(ChaosEvent::Kill("cron".to_string()))
+        );
+        assert_eq!(schedule.pop_due(Duration::ZERO), None);
+        assert_eq!(
+            schedule.pop_due(Duration::from_secs(60)),
+            Some(ChaosEvent::Restart("cron".to_string()))
+        );
+        assert_eq!(
+            schedule.pop_due(Duration::from_secs(120)),
+            Some(ChaosEvent::Kill("cron".to_string()))
+        );
+        assert_eq!(schedule.next_at(), None);
+    }
+
+    #[test]
+    fn test_incident_builds_up_and_recovers() {
+        let program = crate::parser::parse(
+            "incident at 10m: products error_rate 0%->40% over 2m, recover over 4m;",
+        )
+        .unwrap();
+        let incidents = &program.incidents;
+        let error_rate =
+            |secs| incident_error_rate(incidents, "products", Duration::from_secs(secs));
+        assert_eq!(error_rate(599), Some(0.0));
+        assert_eq!(error_rate(600), Some(0.0));
+        assert_eq!(error_rate(660), Some(0.2));
+        assert_eq!(error_rate(720), Some(0.4));
+        assert_eq!(error_rate(840), Some(0.2));
+        assert_eq!(error_rate(960), Some(0.0));
+        assert_eq!(incidents[0].error_rate_at(Duration::from_secs(960)), None);
+        assert_eq!(
+            incident_error_rate(incidents, "frontend", Duration::from_secs(660)),
+            None
+        );
+    }
+}
@@ -0,0 +1,799 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::code_gen::{self, CodeGenerator};
+use crate::parser::{
+    ChaosKind, Injection, Program, Route, Service, Statement, TimelineChange, TrafficPattern,
+};
+use crate::printf;
+use crate::timeline;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The file runs, but probably not as intended
+    Warning,
+    /// The file fails at runtime
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// What a diagnostic is about, so an editor can point at it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subject {
+    /// The definition of a service
+    Service(String),
+    /// A call made by `service`, to one of its own methods if `callee` is `None`
+    Call {
+        service: String,
+        callee: Option<String>,
+        method: String,
+    },
+    /// A setting or chaos action that names a service
+    Setting(String),
+}
+
+/// A problem found in a file without running it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub subject: Subject,
+}
+
+impl Diagnostic {
+    fn error(subject: Subject, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            subject,
+        }
+    }
+
+    fn warning(subject: Subject, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+            subject,
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Compiles every service of the program and looks for calls and settings that cannot work. The
+/// messages start with the path of the field, in the shape `mustermann ast` prints
+pub fn check(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut names = HashSet::new();
+    for (i, service) in program.services.iter().enumerate() {
+        if !names.insert(service.name.as_str()) {
+            diagnostics.push(Diagnostic::error(
+                Subject::Service(service.name.clone()),
+                format!(
+                    "services[{}].name: There is more than one service {}",
+                    i, service.name
+                ),
+            ));
+        }
+    }
+
+    for (i, service) in program.services.iter().enumerate() {
+        // The problems with a path say more than the error of the code generator about the same
+        let problems = print_problems(i, service);
+        if problems.is_empty() {
+            if let Err(e) = CodeGenerator::new(service).process() {
+                diagnostics.push(Diagnostic::error(
+                    Subject::Service(service.name.clone()),
+                    format!("services[{}]: {}: {}", i, service.name, e),
+                ));
+            }
+        }
+        diagnostics.extend(
+            problems
+                .into_iter()
+                .map(|problem| Diagnostic::error(Subject::Service(service.name.clone()), problem)),
+        );
+        for (j, method) in service.methods.iter().enumerate() {
+            for (k, statement) in method.statements.iter().enumerate() {
+                let path = format!("services[{}].methods[{}].statements[{}].call", i, j, k);
+                let Statement::Call {
+                    service: Some(callee),
+                    method: function,
+                } = statement
+                else {
+                    continue;
+                };
+                let subject = Subject::Call {
+                    service: service.name.clone(),
+                    callee: Some(callee.clone()),
+                    method: function.clone(),
+                };
+                match program.services.iter().find(|s| s.name == *callee) {
+                    None => diagnostics.push(Diagnostic::error(
+                        subject,
+                        format!(
+                            "{}.service: {}.{} calls {}.{}, but there is no service {}",
+                            path, service.name, method.name, callee, function, callee
+                        ),
+                    )),
+                    Some(callee_service)
+                        if !callee_service.methods.iter().any(|m| m.name == *function) =>
+                    {
+                        diagnostics.push(Diagnostic::error(
+                            subject,
+                            format!(
+                                "{}.method: {}.{} calls {}.{}, but {} has no method {}",
+                                path, service.name, method.name, callee, function, callee, function
+                            ),
+                        ))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        let loop_statements = service.loops.iter().enumerate().flat_map(|(j, l)| {
+            l.statements
+                .iter()
+                .enumerate()
+                .map(move |(k, statement)| (j, k, statement))
+        });
+        for (j, k, statement) in loop_statements {
+            if let Statement::Call {
+                service: None,
+                method,
+            } = statement
+            {
+                if !service.methods.iter().any(|m| m.name == *method) {
+                    diagnostics.push(Diagnostic::error(
+                        Subject::Call {
+                            service: service.name.clone(),
+                            callee: None,
+                            method: method.clone(),
+                        },
+                        format!(
+                            "services[{}].loops[{}].statements[{}].call.method: The loop of {} calls {}, but {} has no method {}",
+                            i, j, k, service.name, method, service.name, method
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+
+    // Settings for services that do not exist are ignored at runtime
+    let mut routes: Vec<(&str, String, &Route)> = Vec::new();
+    let mut services: Vec<(&str, String, &str)> = Vec::new();
+    routes.extend(program.injections.iter().enumerate().map(|(i, injection)| {
+        let (kind, route) = injection_route(injection);
+        ("An injection", format!("injections[{}].{}", i, kind), route)
+    }));
+    routes.extend(program.circuit_breakers.iter().enumerate().map(|(i, c)| {
+        (
+            "A circuit breaker",
+            format!("circuit_breakers[{}]", i),
+            &c.route,
+        )
+    }));
+    routes.extend(
+        program
+            .retry_policies
+            .iter()
+            .enumerate()
+            .map(|(i, r)| ("A retry policy", format!("retry_policies[{}]", i), &r.route)),
+    );
+    routes.extend(
+        program
+            .priorities
+            .iter()
+            .enumerate()
+            .map(|(i, p)| ("A priority", format!("priorities[{}]", i), &p.route)),
+    );
+    services.extend(program.rate_limits.iter().enumerate().map(|(i, r)| {
+        (
+            "A rate limit",
+            format!("rate_limits[{}].service", i),
+            r.service.as_str(),
+        )
+    }));
+    services.extend(program.incidents.iter().enumerate().map(|(i, incident)| {
+        (
+            "An incident",
+            format!("incidents[{}].service", i),
+            incident.service.as_str(),
+        )
+    }));
+    services.extend(program.timeline.iter().enumerate().filter_map(
+        |(i, event)| match &event.change {
+            TimelineChange::Deploy(deploy) => Some((
+                "A deploy",
+                format!("timeline[{}].change.deploy.service", i),
+                deploy.service.as_str(),
+            )),
+            TimelineChange::Rollback { service } => service.as_deref().map(|service| {
+                (
+                    "A rollback",
+                    format!("timeline[{}].change.rollback.service", i),
+                    service,
+                )
+            }),
+        },
+    ));
+    for (i, action) in program.chaos.iter().enumerate() {
+        match &action.kind {
+            ChaosKind::Kill(service) => {
+                services.push(("A chaos action", format!("chaos[{}].kind.kill", i), service))
+            }
+            ChaosKind::Restart(service) => services.push((
+                "A chaos action",
+                format!("chaos[{}].kind.restart", i),
+                service,
+            )),
+            ChaosKind::Inject { injection, .. } => {
+                let (kind, route) = injection_route(injection);
+                routes.push((
+                    "A chaos action",
+                    format!("chaos[{}].kind.inject.injection.{}", i, kind),
+                    route,
+                ))
+            }
+        }
+    }
+    for (setting, path, route) in routes {
+        services.push((setting, format!("{}.route.from", path), &route.from));
+        services.push((setting, format!("{}.route.to", path), &route.to));
+    }
+    for (setting, path, service) in services {
+        if service != "*" && !names.contains(service) {
+            diagnostics.push(Diagnostic::warning(
+                Subject::Setting(service.to_string()),
+                format!(
+                    "{}: {} names {}, but there is no service {}",
+                    path, setting, service, service
+                ),
+            ));
+        }
+    }
+    diagnostics.extend(check_values(program));
+    diagnostics
+}
+
+/// Values the DSL rarely produces, but a YAML file or a generator can. The messages start with the
+/// path of the field, in the shape `mustermann ast` prints. Every command checks them when it
+/// loads a file, since the run would fail or do nothing
+pub fn check_values(program: &Program) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (i, service) in program.services.iter().enumerate() {
+        let subject = || Subject::Service(service.name.clone());
+        let config = &service.config;
+        for (field, value) in [
+            ("replicas", config.replicas),
+            ("print_sample_rate", config.print_sample_rate),
+            ("max_instructions", config.max_instructions),
+        ] {
+            if value == Some(0) {
+                diagnostics.push(Diagnostic::error(
+                    subject(),
+                    format!("services[{}].config.{}: must be at least 1", i, field),
+                ));
+            }
+        }
+        if let (Some(start_after), Some(stop_after)) = (config.start_after, config.stop_after) {
+            if stop_after <= start_after {
+                diagnostics.push(Diagnostic::error(
+                    subject(),
+                    format!(
+                        "services[{}].config.stop_after_ms: must be later than start_after_ms",
+                        i
+                    ),
+                ));
+            }
+        }
+        for (key, value) in &config.attributes {
+            if value.contains(',') {
+                diagnostics.push(Diagnostic::error(
+                    subject(),
+                    format!(
+                        "services[{}].config.attributes.{}: must not contain a comma",
+                        i, key
+                    ),
+                ));
+            }
+        }
+        if config
+            .spike
+            .as_ref()
+            .is_some_and(|spike| !(0.0..=1.0).contains(&spike.rate))
+        {
+            diagnostics.push(Diagnostic::error(
+                subject(),
+                format!("services[{}].config.spike.rate: must be between 0 and 1", i),
+            ));
+        }
+
+        for (j, l) in service.loops.iter().enumerate() {
+            // A load profile paces the loops that do not pace themselves
+            if program.load.is_none() && !paces(service, &l.statements) {
+                diagnostics.push(Diagnostic::warning(
+                    subject(),
+                    format!(
+                        "services[{}].loops[{}]: The loop of {} never sleeps or calls another service, so it runs as fast as it can",
+                        i, j, service.name
+                    ),
+                ));
+            }
+        }
+    }
+
+    let mut settings: Vec<(String, String)> = Vec::new();
+    for (i, limit) in program.rate_limits.iter().enumerate() {
+        if limit.calls == 0 {
+            settings.push((
+                format!("rate_limits[{}].calls", i),
+                "must be at least 1".into(),
+            ));
+        }
+        if limit.per.is_zero() {
+            settings.push((
+                format!("rate_limits[{}].per_ms", i),
+                "must be above 0".into(),
+            ));
+        }
+    }
+    for (i, policy) in program.retry_policies.iter().enumerate() {
+        if policy.attempts == 0 {
+            settings.push((
+                format!("retry_policies[{}].attempts", i),
+                "must be at least 1".into(),
+            ));
+        }
+    }
+    for (i, breaker) in program.circuit_breakers.iter().enumerate() {
+        if breaker.window == 0 {
+            settings.push((
+                format!("circuit_breakers[{}].window", i),
+                "must be at least 1".into(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&breaker.failure_threshold) {
+            settings.push((
+                format!("circuit_breakers[{}].failure_threshold", i),
+                "must be between 0 and 1".into(),
+            ));
+        }
+    }
+    let injections = program
+        .injections
+        .iter()
+        .enumerate()
+        .map(|(i, injection)| (format!("injections[{}]", i), injection))
+        .chain(
+            program
+                .chaos
+                .iter()
+                .enumerate()
+                .filter_map(|(i, action)| match &action.kind {
+                    ChaosKind::Inject { injection, .. } => {
+                        Some((format!("chaos[{}].kind.inject.injection", i), injection))
+                    }
+                    _ => None,
+                }),
+        );
+    for (path, injection) in injections {
+        if let Injection::Faults {
+            drop_rate,
+            error_rate,
+            ..
+        } = injection
+        {
+            for (field, rate) in [("drop_rate", drop_rate), ("error_rate", error_rate)] {
+                if !(0.0..=1.0).contains(rate) {
+                    settings.push((
+                        format!("{}.faults.{}", path, field),
+                        "must be between 0 and 1".into(),
+                    ));
+                }
+            }
+        }
+    }
+    for (i, incident) in program.incidents.iter().enumerate() {
+        for (field, rate) in [("from", incident.from), ("to", incident.to)] {
+            if !(0.0..=1.0).contains(&rate) {
+                settings.push((
+                    format!("incidents[{}].{}", i, field),
+                    "must be between 0 and 1".into(),
+                ));
+            }
+        }
+    }
+    for (i, event) in program.timeline.iter().enumerate() {
+        let TimelineChange::Deploy(deploy) = &event.change else {
+            continue;
+        };
+        if deploy
+            .error_rate
+            .is_some_and(|rate| !(0.0..=1.0).contains(&rate))
+        {
+            settings.push((
+                format!("timeline[{}].change.deploy.error_rate", i),
+                "must be between 0 and 1".into(),
+            ));
+        }
+        for (key, value) in &deploy.attributes {
+            if value.contains(',') {
+                settings.push((
+                    format!("timeline[{}].change.deploy.attributes.{}", i, key),
+                    "must not contain a comma".into(),
+                ));
+            }
+        }
+    }
+    for i in timeline::stray_rollbacks(&program.timeline) {
+        settings.push((
+            format!("timeline[{}].change.rollback", i),
+            "there is no live deploy to roll back".into(),
+        ));
+    }
+    if program
+        .load
+        .as_ref()
+        .is_some_and(|load| load.stages.is_empty())
+    {
+        settings.push(("load.stages".into(), "must not be empty".into()));
+    }
+    if let Some(TrafficPattern::Diurnal {
+        peak,
+        amplitude,
+        day,
+    }) = &program.pattern
+    {
+        if *peak >= Duration::from_secs(24 * 3600) {
+            settings.push((
+                "pattern.diurnal.peak_ms".into(),
+                "must be less than a day".into(),
+            ));
+        }
+        if !(1.0..).contains(amplitude) {
+            settings.push((
+                "pattern.diurnal.amplitude".into(),
+                "must be at least 1".into(),
+            ));
+        }
+        if day.is_some_and(|day| day.is_zero()) {
+            settings.push(("pattern.diurnal.day_ms".into(), "must be above 0".into()));
+        }
+    }
+    for (path, problem) in settings {
+        diagnostics.push(Diagnostic::error(
+            Subject::Setting(path.clone()),
+            format!("{}: {}", path, problem),
+        ));
+    }
+    diagnostics
+}
+
+// The prints of the `i`th service with weights that cannot work, by the path of the field
+fn print_problems(i: usize, service: &Service) -> Vec<String> {
+    let blocks = service
+        .methods
+        .iter()
+        .enumerate()
+        .map(|(j, method)| {
+            (
+                format!("services[{}].methods[{}]", i, j),
+                &method.statements,
+            )
+        })
+        .chain(
+            service
+                .loops
+                .iter()
+                .enumerate()
+                .map(|(j, l)| (format!("services[{}].loops[{}]", i, j), &l.statements)),
+        )
+        .chain(
+            service
+                .shutdown
+                .iter()
+                .map(|shutdown| (format!("services[{}].shutdown", i), &shutdown.statements)),
+        );
+    let mut problems = Vec::new();
+    for (block, statements) in blocks {
+        for (k, statement) in statements.iter().enumerate() {
+            if let Some(problem) = statement_problem(statement) {
+                problems.push(format!("{}.statements[{}].{}", block, k, problem));
+            }
+        }
+    }
+    problems
+}
+
+// What is wrong with the weights of a print, with the path of the field in the statement
+fn statement_problem(statement: &Statement) -> Option<String> {
+    let (channel, message, args, weights, templates, severity_mix) = match statement {
+        Statement::Stdout {
+            message,
+            args,
+            weights,
+            templates,
+            severity_mix,
+        } => (
+            "stdout",
+            message,
+            args,
+            weights,
+            templates,
+            severity_mix.as_ref(),
+        ),
+        Statement::Stderr {
+            message,
+            args,
+            weights,
+            templates,
+        } => ("stderr", message, args, weights, templates, None),
+        _ => return None,
+    };
+    if let Err(problem) = code_gen::print_arity(message, templates, args, weights) {
+        return Some(format!("{}.args: {}", channel, problem));
+    }
+    // A generator that does not parse fails every print at runtime
+    let messages = templates
+        .iter()
+        .flatten()
+        .map(|template| &template.message)
+        .chain([message]);
+    for message in messages {
+        if message.contains("gauss(") && printf::Template::parse(message).is_err() {
+            return Some(format!(
+                "{}.message: invalid generator in {:?}, e.g. %gauss(mean: 200, drift: +5/min)",
+                channel, message
+            ));
+        }
+    }
+    if let Some(weights) = weights {
+        let values = args.as_ref().map_or(0, |args| args.len());
+        if weights.len() != values {
+            return Some(format!(
+                "{}.weights: {} weights for {} values",
+                channel,
+                weights.len(),
+                values
+            ));
+        }
+        if weights.iter().all(|weight| *weight == 0) {
+            return Some(format!("{}.weights: at least one must be above 0", channel));
+        }
+    }
+    if let Some(templates) = templates {
+        if templates.iter().all(|template| template.weight == 0) {
+            return Some(format!(
+                "{}.templates: at least one weight must be above 0",
+                channel
+            ));
+        }
+    }
+    if let Some(mix) = severity_mix {
+        if mix.info == 0 && mix.error == 0 {
+            return Some(format!(
+                "{}.severity_mix: at least one weight must be above 0",
+                channel
+            ));
+        }
+    }
+    None
+}
+
+// Whether the statements sleep or wait for another service, directly or in a method they call
+fn paces(service: &Service, statements: &[Statement]) -> bool {
+    let mut methods = Vec::new();
+    let mut pending = vec![statements];
+    while let Some(statements) = pending.pop() {
+        for statement in statements {
+            match statement {
+                Statement::Sleep { duration, .. } if !duration.is_zero() => return true,
+                Statement::Call {
+                    service: Some(_), ..
+                } => return true,
+                Statement::Call {
+                    service: None,
+                    method,
+                } if !methods.contains(&method) => {
+                    methods.push(method);
+                    // A call to a method that does not exist is an error of its own
+                    match service.methods.iter().find(|m| m.name == *method) {
+                        Some(method) => pending.push(&method.statements),
+                        None => return true,
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+// The kind of the injection, as a field of the path, and its route
+fn injection_route(injection: &Injection) -> (&'static str, &Route) {
+    match injection {
+        Injection::Latency { route, .. } => ("latency", route),
+        Injection::Faults { route, .. } => ("faults", route),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn test_check_finds_calls_that_cannot_work() {
+        let program = parser::parse(
+            "
+            service products {
+                method get_products {
+                    print \"Fetching products\";
+                }
+            }
+            service frontend {
+                method main_page {
+                    call products.get_products;
+                    call products.get_stock;
+                    call payments.charge;
+                }
+                loop {
+                    call checkout;
+                }
+            }
+            inject latency frontend->payment 100ms;
+            incident at 1m: payments error_rate 0%->10% over 1m;
+            timeline {
+                at 2m: deploy product v2;
+            }
+            ",
+        )
+        .unwrap();
+        let messages: Vec<String> = check(&program)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "error: services[1].methods[0].statements[1].call.method: frontend.main_page calls products.get_stock, but products has no method get_stock",
+                "error: services[1].methods[0].statements[2].call.service: frontend.main_page calls payments.charge, but there is no service payments",
+                "error: services[1].loops[0].statements[0].call.method: The loop of frontend calls checkout, but frontend has no method checkout",
+                "warning: incidents[0].service: An incident names payments, but there is no service payments",
+                "warning: timeline[0].change.deploy.service: A deploy names product, but there is no service product",
+                "warning: injections[0].latency.route.to: An injection names payment, but there is no service payment",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_reports_every_value_that_cannot_work_by_its_path() {
+        let mut program = parser::parse(
+            r#"
+            service products {
+                config {
+                    replicas 0;
+                }
+
+                method get_products {
+                    print "Fetching %s" with ["12345": 1];
+                    print "latency=%gauss(mean: fast)ms";
+                    print "GET %s %d" with ["/products"];
+                }
+
+                loop {
+                    call get_products;
+                }
+            }
+            retry products->products attempts 3 backoff 50ms;
+            incident at 1m: products error_rate 0%->10% over 1m;
+            timeline {
+                at 1m: deploy products v2 (error_rate 5%);
+                at 2m: rollback;
+                at 3m: rollback;
+            }
+            "#,
+        )
+        .unwrap();
+        if let Statement::Stdout { weights, .. } = &mut program.services[0].methods[0].statements[0]
+        {
+            *weights = Some(vec![0]);
+        }
+        program.retry_policies[0].attempts = 0;
+        program.incidents[0].to = 1.5;
+        if let TimelineChange::Deploy(deploy) = &mut program.timeline[0].change {
+            deploy.error_rate = Some(-0.1);
+        }
+        program.services[0].config.start_after = Some(std::time::Duration::from_secs(60));
+        program.services[0].config.stop_after = Some(std::time::Duration::from_secs(30));
+        program.services[0]
+            .config
+            .attributes
+            .insert("zones".to_string(), "a,b".to_string());
+        program.services[0].config.spike = Some(crate::parser::LatencySpike {
+            rate: 2.0,
+            to: std::time::Duration::from_secs(5),
+        });
+        program.rate_limits.push(crate::parser::RateLimit {
+            service: "products".to_string(),
+            calls: 10,
+            per: std::time::Duration::ZERO,
+            burst: 1,
+        });
+        let messages: Vec<String> = check(&program)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "error: services[0].methods[0].statements[0].stdout.weights: at least one must be above 0",
+                "error: services[0].methods[0].statements[1].stdout.message: invalid generator in \"latency=%gauss(mean: fast)ms\", e.g. %gauss(mean: 200, drift: +5/min)",
+                "error: services[0].methods[0].statements[2].stdout.args: \"GET %s %d\" takes 2 values, but 1 were given",
+                "error: services[0].config.replicas: must be at least 1",
+                "error: services[0].config.stop_after_ms: must be later than start_after_ms",
+                "error: services[0].config.attributes.zones: must not contain a comma",
+                "error: services[0].config.spike.rate: must be between 0 and 1",
+                "warning: services[0].loops[0]: The loop of products never sleeps or calls another service, so it runs as fast as it can",
+                "error: rate_limits[0].per_ms: must be above 0",
+                "error: retry_policies[0].attempts: must be at least 1",
+                "error: incidents[0].to: must be between 0 and 1",
+                "error: timeline[0].change.deploy.error_rate: must be between 0 and 1",
+                "error: timeline[2].change.rollback: there is no live deploy to roll back",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_profile_and_pattern_pace_the_loops() {
+        let mut program = parser::parse(
+            r#"
+            service products {
+                method get_products {
+                    print "Fetching products";
+                }
+
+                loop {
+                    call get_products;
+                }
+            }
+            load { ramp 0->100 rps over 1m }
+            "#,
+        )
+        .unwrap();
+        assert!(check(&program).is_empty());
+
+        program.load = Some(crate::parser::LoadProfile::default());
+        program.pattern = Some(TrafficPattern::Diurnal {
+            peak: Duration::from_secs(14 * 3600),
+            amplitude: 0.5,
+            day: Some(Duration::ZERO),
+        });
+        let messages: Vec<String> = check(&program)
+            .iter()
+            .map(|diagnostic| diagnostic.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "error: load.stages: must not be empty",
+                "error: pattern.diurnal.amplitude: must be at least 1",
+                "error: pattern.diurnal.day_ms: must be above 0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls pass through, outcomes are tracked
+    Closed,
+    /// Calls are rejected until the cooldown is over
+    Open,
+    /// A single trial call decides whether the circuit closes again
+    HalfOpen,
+}
+
+impl std::fmt::Display for CircuitState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CircuitState::Closed => write!(f, "closed"),
+            CircuitState::Open => write!(f, "open"),
+            CircuitState::HalfOpen => write!(f, "half_open"),
+        }
+    }
+}
+
+/// Opens once the share of failed calls among the last `window` calls reaches the threshold,
+/// and lets a trial call through after the cooldown.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: f64,
+    window: usize,
+    cooldown: Duration,
+    state: CircuitState,
+    outcomes: VecDeque<bool>,
+    opened_at: Option<Instant>,
+    trial_in_flight: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: f64, window: usize, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            window: window.max(1),
+            cooldown,
+            state: CircuitState::Closed,
+            outcomes: VecDeque::new(),
+            opened_at: None,
+            trial_in_flight: false,
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state
+    }
+
+    /// Returns whether a call arriving at `now` may pass
+    pub fn allow(&mut self, now: Instant) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => {
+                let cooled_down = self
+                    .opened_at
+                    .is_none_or(|opened_at| now.duration_since(opened_at) >= self.cooldown);
+                if cooled_down {
+                    self.state = CircuitState::HalfOpen;
+                    self.trial_in_flight = true;
+                }
+                cooled_down
+            }
+            CircuitState::HalfOpen if self.trial_in_flight => false,
+            CircuitState::HalfOpen => {
+                self.trial_in_flight = true;
+                true
+            }
+        }
+    }
+
+    /// Records the outcome of a call that was allowed through
+    pub fn record(&mut self, success: bool, now: Instant) {
+        match self.state {
+            CircuitState::HalfOpen => {
+                self.trial_in_flight = false;
+                if success {
+                    self.state = CircuitState::Closed;
+                    self.outcomes.clear();
+                } else {
+                    self.open(now);
+                }
+            }
+            CircuitState::Closed => {
+                self.outcomes.push_back(success);
+                if self.outcomes.len() > self.window {
+                    self.outcomes.pop_front();
+                }
+                let failures = self.outcomes.iter().filter(|success| !**success).count();
+                if self.outcomes.len() == self.window
+                    && failures as f64 / self.window as f64 >= self.failure_threshold
+                {
+                    self.open(now);
+                }
+            }
+            // A call that passed before the circuit opened
+            CircuitState::Open => {}
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = CircuitState::Open;
+        self.opened_at = Some(now);
+        self.outcomes.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_when_failure_rate_reaches_threshold() {
+        let mut breaker = CircuitBreaker::new(0.5, 4, Duration::from_secs(5));
+        let now = Instant::now();
+        for success in [true, false, true] {
+            assert!(breaker.allow(now));
+            breaker.record(success, now);
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record(false, now);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_half_open_trial_closes_or_reopens() {
+        let mut breaker = CircuitBreaker::new(0.5, 2, Duration::from_secs(5));
+        let now = Instant::now();
+        breaker.record(false, now);
+        breaker.record(false, now);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let later = now + Duration::from_secs(5);
+        assert!(breaker.allow(later));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        // Only one trial call at a time
+        assert!(!breaker.allow(later));
+        breaker.record(false, later);
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        let even_later = later + Duration::from_secs(5);
+        assert!(breaker.allow(even_later));
+        breaker.record(true, even_later);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
@@ -0,0 +1,1579 @@
+//! The `mustermann` command line. The binary only calls [`run`].
+
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use clap::{Parser, Subcommand};
+use futures::future::join_all;
+use opentelemetry::metrics::{Counter, MeterProvider};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::metrics::{ManualReader, SdkMeterProvider};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info, warn};
+
+use crate::artifact::CompiledService;
+#[cfg(feature = "golden")]
+use crate::golden;
+use crate::print_limiter::PrintLimiter;
+use crate::print_sink::{PrintSink, TracingSink};
+use crate::printer::AnnotatedInstruction;
+use crate::runtime_error::RuntimeError;
+use crate::vm_builder::VmBuilder;
+use crate::{
+    admin, artifact, assertions, check, doctor, dry_run, events, files, health, init, journal,
+    load, log_file, log_format, lsp, otel, parser, printer, recording, remote, replay, run_stats,
+    runtime_metrics, scenario, sink, timeline, topology, variables, vm, vm_coordinator, zipkin,
+};
+
+/// How often --watch looks at the file
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+/// How long the file has to stay the same before --watch restarts the simulation
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(300);
+
+/// CLI tool for pattern matching
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Runs the file without a subcommand
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Parse and compile every service of a file without running it, and look for calls
+    /// and settings that cannot work. Exits with an error if the file would fail at runtime
+    Check {
+        /// The paths of the config files, or of directories with config files
+        #[arg(required = true)]
+        file_paths: Vec<String>,
+    },
+    /// Print the calls between the services of a file, without running it
+    Graph {
+        /// The paths of the config files, or of directories with config files
+        #[arg(required = true)]
+        file_paths: Vec<String>,
+        #[arg(long, value_enum, default_value_t = topology::GraphFormat::Dot)]
+        format: topology::GraphFormat,
+    },
+    /// Print a table of the services of a file, with their methods, loops and the calls they make
+    List {
+        /// The paths of the config files, or of directories with config files
+        #[arg(required = true)]
+        file_paths: Vec<String>,
+    },
+    /// Print the parsed file as JSON, for editors and other tools
+    Ast {
+        /// The paths of the config files, or of directories with config files
+        #[arg(required = true)]
+        file_paths: Vec<String>,
+    },
+    /// Print the JSON Schema of what `ast` prints, to validate generated scenarios
+    Schema,
+    /// Write a file as DSL source, e.g. to migrate a YAML, TOML or JSON scenario to a `.muster` file
+    Convert {
+        /// The paths of the config files, or of directories with config files
+        #[arg(required = true)]
+        file_paths: Vec<String>,
+        /// Where to write the source, stdout if left out
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Create a directory with an example scenario, a collector to send its telemetry to,
+    /// and a script that runs both
+    Init {
+        /// The directory to create
+        dir: String,
+    },
+    /// Compile every service of a file to bytecode, to run it later with `exec`
+    Compile {
+        /// The paths of the config files, or of directories with config files
+        #[arg(required = true)]
+        file_paths: Vec<String>,
+        /// Where to write the compiled file
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Serve the language server protocol over stdin and stdout, for editors. It reports the
+    /// problems `check` finds, jumps to services and methods and completes calls
+    Lsp,
+    /// Print the bytecode of a file written by `compile`, with the offset of every instruction
+    Disasm {
+        /// The path to the compiled file
+        file_path: String,
+    },
+    /// Send a test span, metric and log record to an OpenTelemetry endpoint, and report which of
+    /// them it took
+    Doctor {
+        /// The endpoint to check. Defaults to localhost on the usual port of the protocol
+        #[arg(long)]
+        otel_endpoint: Option<String>,
+        #[arg(long, value_enum, default_value_t = otel::Protocol::Grpc)]
+        otel_protocol: otel::Protocol,
+        /// A header sent with the telemetry, e.g. "x-honeycomb-team=<api key>". Can be repeated
+        #[arg(long = "otel-header", value_parser = otel::parse_header)]
+        otel_headers: Vec<(String, String)>,
+        #[command(flatten)]
+        otel_tls: otel::Tls,
+    },
+    /// Run a config file, the same as without a subcommand
+    Run(Box<Args>),
+    /// Run a file written by `compile`. Takes the same options as running a config file
+    Exec(Box<Args>),
+    /// Run a config file with a fixed seed, on a virtual clock, and compare what the services print
+    /// with a golden file. Exits with an error if they differ
+    #[cfg(feature = "golden")]
+    Golden {
+        /// The paths of the config files, or of directories with config files
+        #[arg(required = true)]
+        file_paths: Vec<String>,
+        /// The golden file, e.g. "shop.golden"
+        #[arg(long)]
+        golden: String,
+        /// How long the services run, in virtual time, e.g. "5m"
+        #[arg(long, default_value = "1m", value_parser = parser::parse_duration)]
+        duration: std::time::Duration,
+        /// Where the random numbers of the services and the coordinator start
+        #[arg(long, default_value = "0")]
+        seed: u64,
+        /// Write the output to the golden file instead of comparing it
+        #[arg(long)]
+        update: bool,
+    },
+    /// Dry run a config file and check it against the assertions of --assertions. Exits with an
+    /// error if one does not hold
+    Test(Box<Args>),
+}
+
+#[derive(clap::Args, Debug)]
+struct Args {
+    /// Enable debug mode
+    #[arg(short, long)]
+    print_code: bool,
+    /// The paths of the config files or of directories with config files, `-` for stdin.
+    /// The OpenTelemetry endpoint may follow them, e.g. http://localhost:4317
+    #[arg(required = true, value_name = "FILE_PATHS")]
+    file_paths: Vec<String>,
+    /// Taken from the end of the file paths
+    #[arg(skip)]
+    otel_endpoint: Option<String>,
+    /// Read variables from this file, e.g. ".env", for the `${NAME}` in the config files and in
+    /// --otel-header. Variables set in the environment keep their value
+    #[arg(long)]
+    env_file: Option<String>,
+    /// The environment and the variables of --env-file
+    #[arg(skip)]
+    variables: variables::Variables,
+    /// How telemetry is sent to the OpenTelemetry endpoint. Without an endpoint it goes to
+    /// localhost on the usual port, 4317 for grpc and 4318 for http
+    #[arg(long, value_enum, default_value_t = otel::Protocol::Grpc)]
+    otel_protocol: otel::Protocol,
+    /// A header sent with all telemetry, e.g. "x-honeycomb-team=<api key>". Can be repeated.
+    /// Headers in OTEL_EXPORTER_OTLP_HEADERS, e.g. "key1=value1,key2=value2", are sent as well
+    #[arg(long = "otel-header", value_parser = otel::parse_header)]
+    otel_headers: Vec<(String, String)>,
+    #[command(flatten)]
+    otel_tls: otel::Tls,
+    #[command(flatten)]
+    metrics_export: otel::MetricsExport,
+    /// Send traces as OTLP, or as Zipkin v2 JSON to --zipkin-endpoint. Logs and metrics stay OTLP
+    #[arg(long, value_enum, default_value_t = otel::TraceExporter::Otlp)]
+    trace_exporter: otel::TraceExporter,
+    /// Where Zipkin takes spans, with --trace-exporter zipkin
+    #[arg(long, default_value = zipkin::DEFAULT_URL)]
+    zipkin_endpoint: String,
+    /// How logs are written to the console without an OpenTelemetry endpoint
+    #[arg(long, value_enum, default_value_t = log_format::LogFormat::Pretty)]
+    log_format: log_format::LogFormat,
+    /// Log less of what mustermann itself does, -qq for errors only. The logs of the services stay
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "verbose")]
+    quiet: u8,
+    /// Log more of what mustermann itself does, -vv for everything
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Which logs to write, as an env-filter expression like RUST_LOG, e.g. "info,service=off" for the
+    /// logs of mustermann only. The logs of the services have the target `service`
+    #[arg(long, conflicts_with_all = ["quiet", "verbose"], value_parser = log_format::parse_filter)]
+    filter: Option<String>,
+    /// Write the logs to this file instead of stdout
+    #[arg(long)]
+    output: Option<String>,
+    /// Write the logs to a sink, e.g. "console", "file:app.json format=json" or
+    /// "otlp filter=service=info". Can be repeated to write to several sinks at once, each with
+    /// its own format and filter. Replaces the sinks of --output and the OpenTelemetry endpoint
+    #[arg(long = "sink", value_parser = sink::parse_sink)]
+    sinks: Vec<sink::SinkSpec>,
+    /// Start a new --output file once it reaches a size, and keep some of the old ones,
+    /// e.g. "size=100MB keep=5". Defaults to 100MB and 5 files
+    #[arg(long, requires = "output", num_args = 1..=2, value_parser = log_file::parse_rotation_setting)]
+    rotate: Option<Vec<log_file::RotationSetting>>,
+    /// The name of the service to be used in the logs of mustermann itself. Defaults to "mustermann"
+    #[arg(short, long, default_value = "mustermann")]
+    service_name: String,
+    /// The maximum number of remote calls to be made per service. Unlimited by default
+    #[arg(short, long)]
+    remote_call_limit: Option<usize>,
+    /// The maximum number of instructions to be executed. Defaults to 1000000
+    #[arg(short, long)]
+    max_instructions: Option<usize>,
+
+    /// The size of the print queue. Defaults to 1
+    #[arg(long, default_value = "1")]
+    print_queue_size: u32,
+    /// The size of the remote call queue. Defaults to 1
+    #[arg(long, default_value = "1")]
+    remote_call_queue_size: u32,
+    /// How long an idle service waits for incoming calls before continuing, in milliseconds. Defaults to 100
+    #[arg(long, default_value = "100")]
+    interrupt_interval_ms: u64,
+    /// Also export the instruction_duration and remote_call_duration gauges next to the duration histograms
+    #[arg(long)]
+    legacy_duration_gauges: bool,
+    /// Also add every print to the span it runs in as an event, next to the log record
+    #[arg(long)]
+    span_events: bool,
+    /// Name spans after this template instead of "{service}/{method}", e.g. "HTTP GET /{method}".
+    /// A span_name in the config of a service overrides it
+    #[arg(long, value_parser = parser::parse_span_name)]
+    span_name: Option<String>,
+    /// A baggage entry every service starts with, e.g. "tenant=acme". Can be repeated.
+    /// Methods can set more with `baggage key = "value";`
+    #[arg(long = "baggage", value_parser = otel::parse_baggage)]
+    baggage: Vec<(String, String)>,
+    /// How calls are spread across the replicas of a service
+    #[arg(long, value_enum, default_value_t = vm_coordinator::LoadBalancing::RoundRobin)]
+    load_balancing: vm_coordinator::LoadBalancing,
+    /// Injects latency or faults into a route, e.g. "latency frontend->products 200ms±50ms"
+    /// or "faults frontend->payments drop 5% error 10%". Can be repeated
+    #[arg(long = "inject", value_parser = parser::parse_injection)]
+    injections: Vec<parser::Injection>,
+    /// Puts a circuit breaker on a route, e.g. "frontend->products threshold 50% window 10 cooldown 5s".
+    /// Can be repeated
+    #[arg(long = "circuit-breaker", value_parser = parser::parse_circuit_breaker)]
+    circuit_breakers: Vec<parser::CircuitBreakerConfig>,
+    /// Retries failed calls on a route, e.g. "frontend->products attempts 3 backoff 100ms".
+    /// Can be repeated
+    #[arg(long = "retry", value_parser = parser::parse_retry_policy)]
+    retry_policies: Vec<parser::RetryPolicy>,
+    /// Gives the calls on a route a priority, e.g. "*->health 10". Services that fall behind
+    /// take calls with a higher priority first. Can be repeated
+    #[arg(long = "priority", value_parser = parser::parse_call_priority)]
+    priorities: Vec<parser::CallPriority>,
+    /// Caps the rate of calls a service accepts, e.g. "products 50/s burst 10".
+    /// Calls above it fail with "Too many requests". Can be repeated
+    #[arg(long = "limit", value_parser = parser::parse_rate_limit)]
+    rate_limits: Vec<parser::RateLimit>,
+    /// Renames or drops a metric, e.g. "remote_invocation_counter rename rpc.client.calls unit \"{call}\""
+    /// or "local_invocation_counter drop". Can be repeated
+    #[arg(long = "metric", value_parser = parser::parse_metric_view)]
+    metric_views: Vec<parser::MetricView>,
+    /// Read a chaos schedule from this file, e.g. "at 2m kill products;" or
+    /// "from 3m to 4m latency frontend->api 1s;". It applies along with the one in the file
+    #[arg(long)]
+    chaos: Option<String>,
+    /// Pace the loops of all services together along a load profile, e.g.
+    /// "ramp 0->500 rps over 5m, hold 10m, ramp down 2m". Replaces the load block of the file
+    #[arg(long, value_parser = parser::parse_load_profile)]
+    load: Option<parser::LoadProfile>,
+    /// Scale the rate of the loops over the day, e.g. "diurnal(peak: 14:00, amplitude: 5x, day: 60m)".
+    /// Replaces the pattern of the file
+    #[arg(long, value_parser = parser::parse_traffic_pattern)]
+    pattern: Option<parser::TrafficPattern>,
+    /// Ping every instance at this interval, e.g. "5s", and log the instances that stop answering.
+    /// The latency and outcome of the checks are exported as metrics
+    #[arg(long, value_parser = parser::parse_duration)]
+    health_checks: Option<std::time::Duration>,
+    /// Serve the health of the instances as JSON on this address, e.g. "127.0.0.1:9000",
+    /// at /health
+    #[arg(long, requires = "health_checks")]
+    health_address: Option<String>,
+    /// Serve the admin API on this address, e.g. "127.0.0.1:9001". It makes calls, injects
+    /// latency or faults, pauses and resumes services and returns runtime stats
+    #[arg(long)]
+    admin_address: Option<String>,
+    /// Abort the run on the first call to a service that does not exist
+    #[arg(long)]
+    strict: bool,
+    /// Write the call graph of the services to this file once the run ends,
+    /// with how often each call happened
+    #[arg(long)]
+    topology: Option<String>,
+    /// The format of the call graph written with --topology
+    #[arg(long, value_enum, default_value_t = topology::GraphFormat::Dot)]
+    topology_format: topology::GraphFormat,
+    /// Append every call the coordinator delivers to this file, as newline-delimited JSON
+    #[arg(long)]
+    journal: Option<String>,
+    /// Replay the calls of a journal written with --journal instead of running the loops of the
+    /// services. The run ends once every replayed call completed
+    #[arg(long, conflicts_with_all = ["processes", "listen", "join"])]
+    replay: Option<String>,
+    /// How much faster than recorded the calls are replayed, e.g. 2 for twice as fast
+    #[arg(long, default_value = "1", value_parser = parse_replay_speed)]
+    replay_speed: f64,
+    /// Record the random numbers of the run and the order the services took their calls in to
+    /// this file, to repeat the run with --replay-decisions
+    #[arg(long, conflicts_with_all = ["processes", "listen", "join", "replay_decisions"])]
+    record_decisions: Option<String>,
+    /// Repeat a run recorded with --record-decisions: the services draw the recorded random
+    /// numbers and take their calls in the recorded order
+    #[arg(long, conflicts_with_all = ["processes", "listen", "join"])]
+    replay_decisions: Option<String>,
+    /// Run every service in a process of its own. The services reach each other
+    /// through the coordinator in this process, over gRPC
+    #[arg(long, conflicts_with = "join")]
+    processes: bool,
+    /// Serve the coordinator on this address, e.g. "0.0.0.0:7000", and let the services join
+    /// from other hosts with --join. The run ends once all services of the file joined and stopped
+    #[arg(long, conflicts_with_all = ["processes", "join"])]
+    listen: Option<String>,
+    /// Join the coordinator at this address instead of running one, e.g. "10.0.0.1:7000"
+    #[arg(long, requires = "run")]
+    join: Option<String>,
+    /// The service to run after joining a coordinator with --join. Can be repeated
+    #[arg(long = "run", requires = "join")]
+    run: Vec<String>,
+    /// Run only these services, e.g. "frontend,products". Calls to the others go to stubs
+    #[arg(long, value_delimiter = ',')]
+    only: Vec<String>,
+    /// Run every service but these, e.g. "batch". Calls to them go to stubs
+    #[arg(long, value_delimiter = ',')]
+    exclude: Vec<String>,
+    /// How calls to services left out with --only or --exclude are answered
+    #[arg(long, value_enum, default_value_t = vm_coordinator::StubCalls::Fail)]
+    stub_calls: vm_coordinator::StubCalls,
+    /// Restart the simulation whenever the file changes
+    #[arg(long, conflicts_with_all = ["processes", "listen", "join"])]
+    watch: bool,
+    /// Print a table of what every service did once the run ends: instructions, logs by level,
+    /// remote calls and wall time
+    #[arg(long)]
+    stats: bool,
+    /// Run every loop --iterations times without sleeping or sending any telemetry, then print
+    /// how many logs and calls the services would emit
+    #[arg(long, conflicts_with_all = ["processes", "listen", "join", "watch", "replay"])]
+    dry_run: bool,
+    /// How often every loop runs with --dry-run and in `test`
+    #[arg(long, default_value = "100")]
+    iterations: usize,
+    /// Check the counters of the services against the assertions of this YAML file once the run
+    /// ends, and fail if one does not hold, e.g. "- {service: frontend, count: stdout, at_least: 100}"
+    #[arg(long)]
+    assertions: Option<String>,
+}
+
+fn parse_replay_speed(speed: &str) -> Result<f64, String> {
+    match speed.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(format!("Expected a positive number, got {}", speed)),
+    }
+}
+
+impl Args {
+    /// An OpenTelemetry endpoint after the files is told apart from them by its scheme
+    fn with_otel_endpoint(mut self) -> Self {
+        if self.file_paths.len() > 1 && self.file_paths.last().is_some_and(|p| p.contains("://")) {
+            self.otel_endpoint = self.file_paths.pop();
+        }
+        self
+    }
+
+    /// Reads --env-file, and replaces the variables in the OTLP headers
+    fn with_variables(mut self) -> anyhow::Result<Self> {
+        self.variables = variables::Variables::from_env();
+        if let Some(path) = &self.env_file {
+            self.variables = self.variables.with_env_file(path)?;
+        }
+        for header in &mut self.otel_headers {
+            let value = self
+                .variables
+                .interpolate(&header.1)
+                .map_err(|e| anyhow::anyhow!("--otel-header {}: {}", header.0, e))?;
+            *header = otel::parse_header(&format!("{}={}", header.0, value))
+                .map_err(|e| anyhow::anyhow!("--otel-header {}", e))?;
+        }
+        Ok(self)
+    }
+
+    fn endpoint(&self) -> otel::Endpoint {
+        let endpoint = match &self.otel_endpoint {
+            Some(url) => otel::Endpoint {
+                url: url.clone(),
+                protocol: self.otel_protocol,
+                headers: Vec::new(),
+                tls: otel::Tls::default(),
+                zipkin: None,
+                metrics: otel::MetricsExport::default(),
+            },
+            None => otel::Endpoint::local(self.otel_protocol),
+        };
+        let endpoint = endpoint
+            .with_headers(self.otel_headers.clone())
+            .with_tls(self.otel_tls.clone())
+            .with_metrics(self.metrics_export.clone());
+        match self.trace_exporter {
+            otel::TraceExporter::Otlp => endpoint,
+            otel::TraceExporter::Zipkin => endpoint.with_zipkin(self.zipkin_endpoint.clone()),
+        }
+    }
+
+    fn reads_stdin(&self) -> bool {
+        self.file_paths.iter().any(|path| path == files::STDIN_PATH)
+    }
+}
+
+/// Parses the command line and runs what it asks for
+pub async fn run() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let (args, compiled) = match cli.command {
+        Some(Command::Check { file_paths }) => return check_file(&file_paths),
+        Some(Command::Graph { file_paths, format }) => return print_graph(&file_paths, format),
+        Some(Command::List { file_paths }) => return list_services(&file_paths),
+        Some(Command::Ast { file_paths }) => return print_ast(&file_paths),
+        Some(Command::Schema) => return print_schema(),
+        Some(Command::Convert { file_paths, output }) => {
+            return convert_file(&file_paths, output.as_deref())
+        }
+        Some(Command::Init { dir }) => return init_project(&dir),
+        Some(Command::Compile { file_paths, output }) => return compile_file(&file_paths, &output),
+        Some(Command::Disasm { file_path }) => return disassemble_file(&file_path),
+        #[cfg(feature = "golden")]
+        Some(Command::Golden {
+            file_paths,
+            golden,
+            duration,
+            seed,
+            update,
+        }) => return compare_golden(&file_paths, &golden, duration, seed, update).await,
+        Some(Command::Doctor {
+            otel_endpoint,
+            otel_protocol,
+            otel_headers,
+            otel_tls,
+        }) => {
+            let endpoint = match otel_endpoint {
+                Some(url) => otel::Endpoint {
+                    url,
+                    protocol: otel_protocol,
+                    headers: Vec::new(),
+                    tls: otel::Tls::default(),
+                    zipkin: None,
+                    metrics: otel::MetricsExport::default(),
+                },
+                None => otel::Endpoint::local(otel_protocol),
+            };
+            let endpoint = endpoint.with_headers(otel_headers).with_tls(otel_tls);
+            endpoint.check_tls().map_err(anyhow::Error::msg)?;
+            return check_endpoint(&endpoint).await;
+        }
+        Some(Command::Lsp) => return Ok(lsp::serve(std::io::stdin().lock(), std::io::stdout())?),
+        Some(Command::Run(args)) => (*args, false),
+        Some(Command::Exec(args)) => (*args, true),
+        Some(Command::Test(args)) => {
+            if args.assertions.is_none() {
+                anyhow::bail!("test needs --assertions");
+            }
+            if args.watch || args.processes || args.listen.is_some() || args.join.is_some() {
+                anyhow::bail!("test runs the services once, in this process");
+            }
+            (
+                Args {
+                    dry_run: true,
+                    ..*args
+                },
+                false,
+            )
+        }
+        None => (cli.args, false),
+    };
+    let args = args.with_otel_endpoint().with_variables()?;
+    args.endpoint().check_tls().map_err(anyhow::Error::msg)?;
+    let output = match &args.output {
+        Some(path) => {
+            let rotation = args
+                .rotate
+                .as_deref()
+                .map(log_file::Rotation::from_settings);
+            Some(log_file::LogFile::open(path, rotation)?)
+        }
+        None => None,
+    };
+    let verbosity = args.verbose.min(2) as i8 - args.quiet.min(2) as i8;
+
+    // A process that runs a single service logs as that service
+    let service_name = match args.run.as_slice() {
+        [service_name] => service_name,
+        _ => &args.service_name,
+    };
+    let context = sink::SinkContext {
+        endpoint: args.endpoint(),
+        service_name: service_name.clone(),
+        log_format: args.log_format,
+    };
+    let default_filter = || log_format::filter(verbosity, args.filter.as_deref());
+    let mut sinks = sink::Sinks::new(
+        &sink::SinkRegistry::new(),
+        &args.sinks,
+        &context,
+        default_filter,
+    )?;
+    if args.sinks.is_empty() {
+        if args.otel_endpoint.is_some() {
+            let loggers = otel::service_loggers(&context.endpoint, service_name)?;
+            sinks = sinks
+                .with_sink(
+                    Box::new(sink::WriterSink {
+                        format: log_format::LogFormat::Json,
+                        output,
+                    }),
+                    default_filter(),
+                )
+                .with_sink(Box::new(sink::OtlpSink(loggers)), default_filter());
+        } else {
+            let console = sink::WriterSink {
+                format: args.log_format,
+                output,
+            };
+            sinks = sinks.with_sink(Box::new(console), default_filter());
+        }
+    }
+    sinks.install()?;
+
+    if args.watch && args.reads_stdin() {
+        anyhow::bail!("--watch needs files, stdin cannot change");
+    }
+    if args.print_code {
+        print_code(
+            &args,
+            &files::read_all(&args.file_paths, compiled)?,
+            compiled,
+        )?;
+    } else {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let ctrlc_shutdown = shutdown.clone();
+        // SIGTERM and SIGHUP shut down the same way, so e.g. a recording is complete
+        ctrlc::set_handler(move || {
+            info!("Received Ctrl+C or SIGTERM, shutting down");
+            ctrlc_shutdown.store(true, Ordering::SeqCst);
+        })?;
+        if args.watch {
+            watch_code(&args, compiled, shutdown).await?;
+        } else {
+            let sources = files::read_all(&args.file_paths, compiled)?;
+            execute_code(&args, &sources, compiled, shutdown).await?;
+        }
+    }
+
+    sinks.shutdown()?;
+    opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .build()
+        .shutdown()?;
+
+    Ok(())
+}
+
+/// Parses the files as one program
+fn parse_files(file_paths: &[String]) -> anyhow::Result<parser::Program> {
+    let program = files::parse(
+        &files::read_all(file_paths, false)?,
+        &variables::Variables::from_env(),
+    )?;
+    check_values(&program)?;
+    Ok(program)
+}
+
+/// Fails with every value of the program that cannot work, e.g. a service without replicas, which
+/// would otherwise run nothing without a word
+fn check_values(program: &parser::Program) -> anyhow::Result<()> {
+    let errors: Vec<String> = check::check_values(program)
+        .into_iter()
+        .filter(|diagnostic| diagnostic.severity == check::Severity::Error)
+        .map(|diagnostic| diagnostic.message)
+        .collect();
+    if !errors.is_empty() {
+        anyhow::bail!("{}", errors.join("\n"));
+    }
+    Ok(())
+}
+
+/// Prints what is wrong with the files, failing if they would fail at runtime
+fn check_file(file_paths: &[String]) -> anyhow::Result<()> {
+    let file_path = file_paths.join(", ");
+    // The values are checked along with everything else below
+    let ast = match files::read_all(file_paths, false)
+        .and_then(|sources| files::parse(&sources, &variables::Variables::from_env()))
+    {
+        Ok(ast) => ast,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            anyhow::bail!("{} is not valid", file_path);
+        }
+    };
+    let diagnostics = check::check(&ast);
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == check::Severity::Error)
+        .count();
+    if errors > 0 {
+        anyhow::bail!("{} has {} errors", file_path, errors);
+    }
+    println!(
+        "{}: {} services, {} warnings",
+        file_path,
+        ast.services.len(),
+        diagnostics.len()
+    );
+    Ok(())
+}
+
+/// Runs the files on a virtual clock and compares what the services print with the golden file,
+/// or writes it
+#[cfg(feature = "golden")]
+async fn compare_golden(
+    file_paths: &[String],
+    golden_path: &str,
+    duration: std::time::Duration,
+    seed: u64,
+    update: bool,
+) -> anyhow::Result<()> {
+    let program = parse_files(file_paths)?;
+    // The run gets a runtime of its own, with a paused clock
+    let lines =
+        tokio::task::spawn_blocking(move || golden::capture(&program, duration, seed)).await??;
+    let output = golden::render(&lines);
+    if update {
+        fs::write(golden_path, output)?;
+        println!("Wrote {}", golden_path);
+        return Ok(());
+    }
+    let expected = fs::read_to_string(golden_path)
+        .map_err(|e| anyhow::anyhow!("{}: {}, write it with --update", golden_path, e))?;
+    if let Some(difference) = golden::compare(&expected, &output) {
+        anyhow::bail!("The output differs from {}, {}", golden_path, difference);
+    }
+    println!("The output matches {}", golden_path);
+    Ok(())
+}
+
+/// Prints whether the endpoint took each signal, failing if it did not take all of them
+async fn check_endpoint(endpoint: &otel::Endpoint) -> anyhow::Result<()> {
+    println!(
+        "Sending a test span, metric and log record to {} over {}",
+        endpoint.url, endpoint.protocol
+    );
+    let reports = doctor::diagnose(endpoint).await;
+    for report in &reports {
+        match &report.result {
+            Ok(()) => println!("{:<8} ok", report.signal),
+            Err(e) => {
+                println!("{:<8} failed: {}", report.signal, e);
+                if let Some(hint) = report.hint(endpoint) {
+                    println!("{:<8} {}", "", hint);
+                }
+            }
+        }
+    }
+    let failed = reports
+        .iter()
+        .filter(|report| report.result.is_err())
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} signals failed", failed, reports.len());
+    }
+    Ok(())
+}
+
+fn print_graph(file_paths: &[String], format: topology::GraphFormat) -> anyhow::Result<()> {
+    let ast = parse_files(file_paths)?;
+    print!("{}", topology::Topology::from_program(&ast).render(format));
+    Ok(())
+}
+
+fn list_services(file_paths: &[String]) -> anyhow::Result<()> {
+    let ast = parse_files(file_paths)?;
+    let mut table = tabled::Table::new(printer::summarize(&ast));
+    println!("{}", table.with(tabled::settings::Style::sharp()));
+    Ok(())
+}
+
+fn print_ast(file_paths: &[String]) -> anyhow::Result<()> {
+    let ast = parse_files(file_paths)?;
+    println!("{}", serde_json::to_string_pretty(&ast)?);
+    Ok(())
+}
+
+fn print_schema() -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(&parser::json_schema())?);
+    Ok(())
+}
+
+fn convert_file(file_paths: &[String], output: Option<&str>) -> anyhow::Result<()> {
+    let source = parse_files(file_paths)?.to_source()?;
+    match output {
+        Some(path) => {
+            fs::write(path, source)?;
+            println!("Converted {} to {}", file_paths.join(", "), path);
+        }
+        None => print!("{}", source),
+    }
+    Ok(())
+}
+
+fn init_project(dir: &str) -> anyhow::Result<()> {
+    for path in init::scaffold(std::path::Path::new(dir))? {
+        println!("Created {}", path.display());
+    }
+    println!(
+        "Run {}/run.sh and open Jaeger at http://localhost:16686",
+        dir
+    );
+    Ok(())
+}
+
+fn compile_file(file_paths: &[String], output: &str) -> anyhow::Result<()> {
+    let ast = parse_files(file_paths)?;
+    let services = artifact::compile(&ast)?;
+    fs::write(output, artifact::to_bytes(&services))?;
+    println!("Compiled {} services to {}", services.len(), output);
+    Ok(())
+}
+
+fn disassemble_file(file_path: &str) -> anyhow::Result<()> {
+    let services = artifact::from_bytes(&files::read(file_path)?)?;
+    for service in services {
+        println!("service {}", service.name);
+        let mut table = tabled::Table::new(printer::disassemble(&service.code));
+        println!("{}", table.with(tabled::settings::Style::sharp()));
+    }
+    Ok(())
+}
+
+/// Reads the services to run from the config files, or from files written by `compile`.
+/// A compiled file has no injections, circuit breakers or other settings, they come from the command line.
+fn load(
+    args: &Args,
+    sources: &[files::SourceFile],
+    compiled: bool,
+) -> anyhow::Result<(parser::Program, Vec<CompiledService>)> {
+    // During a replay, the services only answer the replayed calls
+    let replaying = args.replay.is_some() && args.join.is_none();
+    if compiled {
+        if replaying {
+            anyhow::bail!(
+                "--replay needs the config file, compiled services always run their loops"
+            );
+        }
+        return Ok((parser::Program::default(), files::decode(sources)?));
+    }
+    let mut ast = files::parse(sources, &args.variables)?;
+    check_values(&ast)?;
+    if replaying {
+        for service in &mut ast.services {
+            service.loops.clear();
+        }
+    }
+    let services = artifact::compile(&ast)?;
+    Ok((ast, services))
+}
+
+fn print_code(args: &Args, sources: &[files::SourceFile], compiled: bool) -> anyhow::Result<()> {
+    let (_, services) = load(args, sources, compiled)?;
+    for service in services {
+        let rows: Vec<AnnotatedInstruction> =
+            service.code.iter().map(|i| i.into()).collect::<Vec<_>>();
+        let mut table = tabled::Table::new(rows);
+        println!("{}", table.with(tabled::settings::Style::sharp()));
+    }
+    Ok(())
+}
+
+/// Runs the files and restarts the simulation whenever one of them changes, until Ctrl+C.
+/// Files that fail to load or run are reported, and run again once they change.
+async fn watch_code(
+    args: &Args,
+    compiled: bool,
+    interrupted: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let paths = args.file_paths.clone();
+    let description = paths.join(", ");
+    let mut last_modified = modified(&paths, compiled);
+    loop {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        // Polling the files sleeps between looks, so they are watched on a blocking thread
+        let watcher = {
+            let paths = paths.clone();
+            let last_modified = last_modified.clone();
+            let interrupted = interrupted.clone();
+            let shutdown = shutdown.clone();
+            tokio::task::spawn_blocking(move || {
+                let changed = wait_for_change(&paths, compiled, &last_modified, &interrupted);
+                shutdown.store(true, Ordering::SeqCst);
+                changed
+            })
+        };
+        let result = match files::read_all(&paths, compiled) {
+            Ok(sources) => execute_code(args, &sources, compiled, shutdown).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+        }
+        if !watcher.is_finished() {
+            info!("Simulation stopped, waiting for {} to change", description);
+        }
+        match watcher.await? {
+            Some(_) if interrupted.load(Ordering::SeqCst) => return Ok(()),
+            Some(modified) => last_modified = modified,
+            None => return Ok(()),
+        }
+        info!("======== Reloaded {} ========", description);
+    }
+}
+
+/// When each of the files was modified
+type Modified = Vec<(String, Option<std::time::SystemTime>)>;
+
+/// Files added to or removed from a directory change the result as well
+fn modified(paths: &[String], compiled: bool) -> Modified {
+    files::expand(paths, compiled)
+        .unwrap_or_else(|_| paths.to_vec())
+        .into_iter()
+        .map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+            (path, modified)
+        })
+        .collect()
+}
+
+/// Blocks until the files changed and then stayed the same for a moment, since editors often write
+/// a file in several steps. Returns when they were modified, or `None` on Ctrl+C.
+fn wait_for_change(
+    paths: &[String],
+    compiled: bool,
+    last_modified: &Modified,
+    interrupted: &AtomicBool,
+) -> Option<Modified> {
+    let mut changed: Option<(Modified, std::time::Instant)> = None;
+    while !interrupted.load(Ordering::SeqCst) {
+        std::thread::sleep(WATCH_INTERVAL);
+        let modified = modified(paths, compiled);
+        if modified == *last_modified {
+            continue;
+        }
+        match &changed {
+            Some((previous, since)) if *previous == modified => {
+                if since.elapsed() >= WATCH_DEBOUNCE {
+                    return Some(modified);
+                }
+            }
+            _ => changed = Some((modified, std::time::Instant::now())),
+        }
+    }
+    None
+}
+
+async fn execute_code(
+    args: &Args,
+    sources: &[files::SourceFile],
+    compiled: bool,
+    shutdown: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let (ast, services) = load(args, sources, compiled)?;
+    let run_stats = run_stats::RunStats::default();
+    // Read before the run, to fail early on a broken file
+    let assertions = match &args.assertions {
+        Some(path) => Some(
+            assertions::parse(&fs::read_to_string(path)?)
+                .map_err(|e| anyhow::anyhow!("{}: {}", path, e))?,
+        ),
+        None => None,
+    };
+    // The first view of an instrument wins, so the ones from the command line go first
+    let mut metric_views = args.metric_views.clone();
+    metric_views.extend(ast.metric_views.iter().cloned());
+    if let Some(coordinator_url) = &args.join {
+        execute_joined_services(
+            &services,
+            coordinator_url,
+            args,
+            &run_stats,
+            shutdown,
+            &metric_views,
+        )
+        .await?;
+        print_stats(args, &run_stats);
+        return Ok(());
+    }
+    let replay_entries = args.replay.as_ref().map(journal::read).transpose()?;
+    let (services, stubs) = pick_services(services, args)?;
+
+    let mut scenario = scenario::Scenario::new(&ast);
+    // Injections from the command line apply on top of the ones in the file
+    scenario.injections.extend(args.injections.iter().cloned());
+    // The first matching circuit breaker wins, so the ones from the command line go first
+    scenario
+        .circuit_breakers
+        .splice(0..0, args.circuit_breakers.iter().cloned());
+    scenario
+        .retry_policies
+        .splice(0..0, args.retry_policies.iter().cloned());
+    scenario
+        .priorities
+        .splice(0..0, args.priorities.iter().cloned());
+    scenario
+        .rate_limits
+        .splice(0..0, args.rate_limits.iter().cloned());
+    if let Some(path) = &args.chaos {
+        scenario
+            .chaos
+            .extend(parser::parse_chaos(&fs::read_to_string(path)?)?);
+    }
+    if args.load.is_some() {
+        scenario.load = args.load.clone();
+    }
+    if args.pattern.is_some() {
+        scenario.pattern = args.pattern.clone();
+    }
+    let decider = match (&args.record_decisions, &args.replay_decisions) {
+        (Some(path), _) => recording::Decider::Record(recording::Recorder::create(path)?),
+        (None, Some(path)) => recording::Decider::Replay(recording::Recording::read(path)?),
+        (None, None) => recording::Decider::Live,
+    };
+    let mut coordinator = scenario
+        .coordinator(&services, &decider)
+        .with_load_balancing(args.load_balancing)
+        .with_stubs(stubs, args.stub_calls);
+    // Only retries record spans in the coordinator. A dry run exports nothing
+    if !scenario.retry_policies.is_empty() && !args.dry_run {
+        let tracer = vm::setup_tracer(&args.endpoint(), &args.service_name, None)
+            .map_err(RuntimeError::InitTraceError)?;
+        coordinator = coordinator.with_tracer(tracer);
+    }
+    if (scenario.load.is_some() || scenario.pattern.is_some())
+        && (args.listen.is_some() || args.processes)
+    {
+        warn!("The load profile and traffic pattern only pace services that run in this process, they are ignored");
+    }
+    // In strict mode the first dead letter shuts all services down
+    let mut strict_handle = None;
+    if args.strict {
+        let (events_tx, mut events_rx) = mpsc::channel(100);
+        coordinator = coordinator.with_events(events_tx);
+        let strict_shutdown = shutdown.clone();
+        strict_handle = Some(tokio::spawn(async move {
+            let mut first_error = None;
+            // The coordinator already logs dead letters, the services may cause a few more while they stop
+            while let Some(event) = events_rx.recv().await {
+                if let (None, events::RunEvent::Error { error, .. }) = (&first_error, event) {
+                    strict_shutdown.store(true, Ordering::SeqCst);
+                    first_error = Some(error);
+                }
+            }
+            first_error
+        }));
+    }
+    let mut observed_topology = None;
+    if args.topology.is_some() || args.dry_run {
+        let topology = Arc::new(Mutex::new(
+            topology::Topology::from_program(&ast).with_observed_calls(),
+        ));
+        coordinator = coordinator.with_topology(topology.clone());
+        observed_topology = Some(topology);
+    }
+    if let Some(path) = &args.journal {
+        coordinator = coordinator.with_journal(journal::Journal::open(path)?);
+    }
+    // The admin API can pause any service
+    if args.admin_address.is_some() {
+        coordinator =
+            coordinator.with_pausable_services(services.iter().map(|service| service.name.clone()));
+    }
+    let mut health_server = None;
+    let mut health_report = None;
+    if let Some(interval) = args.health_checks {
+        // Exports like the runtime metrics, so not at all in a dry run
+        let meter_provider =
+            runtime_meter_provider(args, &metric_views).map_err(RuntimeError::InitMeterError)?;
+        let report = Arc::new(Mutex::new(health::HealthReport::default()));
+        coordinator = coordinator
+            .with_health_checks(interval, report.clone())
+            .with_meter_provider(meter_provider);
+        if let Some(address) = &args.health_address {
+            let listener = tokio::net::TcpListener::bind(address).await?;
+            info!("Serving health at http://{}/health", listener.local_addr()?);
+            let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+            let server = tokio::spawn(health::serve(listener, report.clone(), async {
+                let _ = stop_rx.await;
+            }));
+            health_server = Some((stop_tx, server));
+        }
+        health_report = Some(report);
+    }
+    let coordinator_handle = coordinator.handle();
+    let coordinator_task = tokio::spawn(coordinator.run());
+    let mut admin_server = None;
+    if let Some(address) = &args.admin_address {
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        info!("Serving the admin API at http://{}", listener.local_addr()?);
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(admin::serve(
+            listener,
+            coordinator_handle.sender(),
+            health_report,
+            async {
+                let _ = stop_rx.await;
+            },
+        ));
+        admin_server = Some((stop_tx, server));
+    }
+
+    if let Some(address) = &args.listen {
+        execute_listening(
+            &services,
+            &coordinator_handle,
+            address,
+            args,
+            shutdown.clone(),
+        )
+        .await?;
+    } else if args.processes {
+        execute_processes(&services, sources, &coordinator_handle, args).await?;
+    } else {
+        let mut handles = Vec::new();
+        let dry_run = args
+            .dry_run
+            .then(|| Arc::new(dry_run::DryRun::new(args.iterations)));
+        let runtime_meter_provider =
+            runtime_meter_provider(args, &metric_views).map_err(RuntimeError::InitMeterError)?;
+        let runtime_metrics =
+            runtime_metrics::RuntimeMetrics::new(&runtime_meter_provider, &coordinator_handle);
+        let run = scenario::Run {
+            coordinator: coordinator_handle.clone(),
+            decider: decider.clone(),
+            shutdown: shutdown.clone(),
+            // A dry run runs its iterations as fast as it can
+            load: if args.dry_run {
+                None
+            } else {
+                scenario.load_shaper(load::time_of_day(std::time::SystemTime::now()))
+            },
+            // Incidents and the timeline are timed from the start of the run
+            started: std::time::Instant::now(),
+            started_at: std::time::SystemTime::now(),
+        };
+        // Services start once all of them are registered, so no call goes to a service that is not registered yet
+        let (start_tx, start_rx) = watch::channel(false);
+        for service in &services {
+            let service_handles = execute_service(
+                service,
+                start_rx.clone(),
+                args,
+                &run_stats,
+                dry_run.clone(),
+                &runtime_metrics,
+                &metric_views,
+                (&scenario, &run),
+            )
+            .await?;
+            handles.extend(service_handles);
+        }
+        start_tx.send(true)?;
+        if let Some(dry_run) = dry_run {
+            dry_run.settled().await;
+            // The services only answer calls by now, and no more calls are coming
+            shutdown.store(true, Ordering::SeqCst);
+        }
+        if let Some(entries) = replay_entries {
+            let summary =
+                replay::replay(entries, args.replay_speed, coordinator_handle.sender()).await;
+            info!(
+                calls = summary.calls,
+                failed = summary.failed,
+                "Replay finished"
+            );
+            // Without loops, the services run until they are told to stop
+            shutdown.store(true, Ordering::SeqCst);
+        }
+        join_all(handles).await;
+    }
+    // Every service stopped, the coordinator only has to finish the calls they left behind
+    coordinator_handle.shutdown().await?;
+    coordinator_task.await?;
+    if let recording::Decider::Record(recorder) = &decider {
+        recorder.flush();
+    }
+    for (stop_tx, server) in health_server.into_iter().chain(admin_server) {
+        let _ = stop_tx.send(());
+        server.await??;
+    }
+    if let (Some(path), Some(topology)) = (&args.topology, &observed_topology) {
+        let graph = topology.lock().unwrap().render(args.topology_format);
+        fs::write(path, graph)?;
+        info!("Wrote the call graph to {}", path);
+    }
+    print_stats(args, &run_stats);
+    if let Some(topology) = observed_topology.filter(|_| args.dry_run) {
+        print_dry_run(args, &topology.lock().unwrap());
+    }
+    if let Some(assertions) = assertions {
+        check_assertions(&assertions, &run_stats)?;
+    }
+    if let Some(strict_handle) = strict_handle {
+        if let Some(error) = strict_handle.await? {
+            return Err(RuntimeError::DeadLetter(error).into());
+        }
+    }
+    Ok(())
+}
+
+/// Prints the stats of the services that ran in this process, if asked for with --stats or --dry-run
+fn print_stats(args: &Args, run_stats: &run_stats::RunStats) {
+    if (args.stats || args.dry_run) && !run_stats.is_empty() {
+        let mut table = tabled::Table::new(run_stats.rows());
+        println!("{}", table.with(tabled::settings::Style::sharp()));
+    }
+}
+
+/// Prints the outcome of every assertion, failing if one does not hold
+fn check_assertions(
+    assertions: &[assertions::Assertion],
+    run_stats: &run_stats::RunStats,
+) -> anyhow::Result<()> {
+    let outcomes = assertions::evaluate(assertions, run_stats);
+    for outcome in &outcomes {
+        println!("{}", outcome);
+    }
+    let failed = outcomes.iter().filter(|outcome| !outcome.passed).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} assertions failed", failed, outcomes.len());
+    }
+    Ok(())
+}
+
+/// Prints how often the services called each other during a dry run
+fn print_dry_run(args: &Args, topology: &topology::Topology) {
+    println!(
+        "Dry run of {} iterations per loop, no telemetry was sent",
+        args.iterations
+    );
+    let mut matrix = topology.call_matrix();
+    if matrix.count_rows() > 1 {
+        println!("{}", matrix.with(tabled::settings::Style::sharp()));
+    } else {
+        println!("No remote calls");
+    }
+}
+
+/// Splits the services into the ones to run and the names of the others, as picked with --only and --exclude
+fn pick_services(
+    services: Vec<CompiledService>,
+    args: &Args,
+) -> anyhow::Result<(Vec<CompiledService>, Vec<String>)> {
+    for service_name in args.only.iter().chain(&args.exclude) {
+        if !services.iter().any(|service| service.name == *service_name) {
+            anyhow::bail!("No service named {} in the file", service_name);
+        }
+    }
+    let (picked, stubs): (Vec<_>, Vec<_>) = services.into_iter().partition(|service| {
+        (args.only.is_empty() || args.only.contains(&service.name))
+            && !args.exclude.contains(&service.name)
+    });
+    Ok((
+        picked,
+        stubs.into_iter().map(|service| service.name).collect(),
+    ))
+}
+
+/// Serves the coordinator for services in other processes. Services start once all instances of the file joined
+fn remote_coordinator(
+    services: &[CompiledService],
+    coordinator: &vm_coordinator::CoordinatorHandle,
+    args: &Args,
+) -> remote::RemoteCoordinator {
+    let instances = services
+        .iter()
+        .map(|service| service.config.replicas.unwrap_or(1))
+        .sum();
+    remote::RemoteCoordinator::new(
+        coordinator.clone(),
+        instances,
+        args.remote_call_queue_size as usize,
+    )
+}
+
+/// Serves the coordinator on `address` for services that join from other hosts
+async fn execute_listening(
+    services: &[CompiledService],
+    coordinator: &vm_coordinator::CoordinatorHandle,
+    address: &str,
+    args: &Args,
+    shutdown: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    info!("Coordinator listening on {}", listener.local_addr()?);
+    let remote_coordinator = remote_coordinator(services, coordinator, args);
+    let stopped = remote_coordinator.stopped();
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(remote::serve(listener, remote_coordinator, async {
+        let _ = stop_rx.await;
+    }));
+    // Ctrl+C stops the coordinator even if services on other hosts keep running
+    let interrupted = async {
+        while !shutdown.load(Ordering::SeqCst) {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+    };
+    tokio::select! {
+        _ = stopped => {}
+        _ = interrupted => {}
+    }
+    let _ = stop_tx.send(());
+    server.await??;
+    Ok(())
+}
+
+/// Runs every service in a child process of this binary, with the coordinator served over gRPC
+async fn execute_processes(
+    services: &[CompiledService],
+    sources: &[files::SourceFile],
+    coordinator: &vm_coordinator::CoordinatorHandle,
+    args: &Args,
+) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let coordinator_url = format!("http://{}", listener.local_addr()?);
+    let remote_coordinator = remote_coordinator(services, coordinator, args);
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(remote::serve(listener, remote_coordinator, async {
+        let _ = stop_rx.await;
+    }));
+
+    // The processes get the same arguments, so they read the same file and use the same settings
+    let service_args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| arg != "--processes")
+        .collect();
+    let executable = std::env::current_exe()?;
+    let mut processes = Vec::new();
+    for service in services {
+        let mut command = tokio::process::Command::new(&executable);
+        command
+            .args(&service_args)
+            .args(["--join", &coordinator_url])
+            .args(["--run", &service.name])
+            .kill_on_drop(true);
+        // A file read from stdin reaches the processes on their stdin
+        let stdin_source = sources
+            .iter()
+            .find(|source| source.path == files::STDIN_PATH);
+        if stdin_source.is_some() {
+            command.stdin(std::process::Stdio::piped());
+        }
+        let mut process = command.spawn()?;
+        if let (Some(mut stdin), Some(source)) = (process.stdin.take(), stdin_source) {
+            stdin.write_all(&source.content).await?;
+        }
+        info!(app_name = %service.name, pid = process.id(), "Started service process");
+        processes.push((service.name.clone(), process));
+    }
+    for (service_name, mut process) in processes {
+        let status = process.wait().await?;
+        if !status.success() {
+            error!(app_name = %service_name, "Service process exited with {}", status);
+        }
+    }
+    let _ = stop_tx.send(());
+    server.await??;
+    Ok(())
+}
+
+/// Runs the services picked with --run against the coordinator of another process
+async fn execute_joined_services(
+    all_services: &[CompiledService],
+    coordinator_url: &str,
+    args: &Args,
+    run_stats: &run_stats::RunStats,
+    shutdown: Arc<AtomicBool>,
+    metric_views: &[parser::MetricView],
+) -> anyhow::Result<()> {
+    let mut services = Vec::new();
+    for service_name in &args.run {
+        let service = all_services
+            .iter()
+            .find(|service| service.name == *service_name)
+            .ok_or_else(|| anyhow::anyhow!("No service named {} in the file", service_name))?;
+        services.push(service);
+    }
+    let (coordinator, relay) = remote::connect(coordinator_url).await?;
+    let runtime_meter_provider =
+        runtime_meter_provider(args, metric_views).map_err(RuntimeError::InitMeterError)?;
+    let runtime_metrics =
+        runtime_metrics::RuntimeMetrics::new(&runtime_meter_provider, &coordinator);
+    // The coordinator process applies the scenario of the file
+    let run = scenario::Run {
+        coordinator: coordinator.clone(),
+        decider: recording::Decider::Live,
+        shutdown,
+        load: None,
+        started: std::time::Instant::now(),
+        started_at: std::time::SystemTime::now(),
+    };
+    let (start_tx, start_rx) = watch::channel(false);
+    let mut handles = Vec::new();
+    for service in services {
+        let service_handles = execute_service(
+            service,
+            start_rx.clone(),
+            args,
+            run_stats,
+            None,
+            &runtime_metrics,
+            metric_views,
+            (&scenario::Scenario::default(), &run),
+        )
+        .await?;
+        handles.extend(service_handles);
+    }
+    // The services in the other processes have to be registered too before the first call goes out
+    remote::wait_for_services(coordinator_url).await?;
+    start_tx.send(true)?;
+    join_all(handles).await;
+    coordinator.shutdown().await?;
+    relay.await?;
+    Ok(())
+}
+
+/// Exports the metrics of mustermann itself. A dry run exports nothing
+fn runtime_meter_provider(
+    args: &Args,
+    metric_views: &[parser::MetricView],
+) -> Result<SdkMeterProvider, opentelemetry_otlp::ExporterBuildError> {
+    if args.dry_run {
+        // A provider without a reader rejects the observable gauges, so give it one nobody reads
+        return Ok(SdkMeterProvider::builder()
+            .with_reader(ManualReader::builder().build())
+            .build());
+    }
+    vm::init_meter_provider(
+        Some(&args.endpoint()),
+        &args.service_name,
+        None,
+        metric_views,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn execute_service(
+    service: &CompiledService,
+    start_rx: watch::Receiver<bool>,
+    args: &Args,
+    run_stats: &run_stats::RunStats,
+    dry_run: Option<Arc<dry_run::DryRun>>,
+    runtime_metrics: &runtime_metrics::RuntimeMetrics,
+    metric_views: &[parser::MetricView],
+    (scenario, run): (&scenario::Scenario, &scenario::Run),
+) -> Result<Vec<tokio::task::JoinHandle<Result<(), vm::VMError>>>, RuntimeError> {
+    let service_name = service.name.as_str();
+    let service_config = &service.config;
+    let otel_endpoint = args.endpoint();
+    // The limits of the command line apply to the services that don't set their own
+    let defaults = parser::ServiceConfig {
+        remote_call_limit: args.remote_call_limit,
+        max_instructions: args.max_instructions,
+        span_name: args.span_name.clone(),
+        ..Default::default()
+    };
+
+    let mut handles = Vec::new();
+    for replica in 0..service_config.replicas.unwrap_or(1) {
+        let instance = vm::instance_id(service_name, replica);
+        // Every replica has its own tracer and meter provider, to tell the instances apart in
+        // telemetry. A dry run exports nothing
+        let (tracer, meter_provider) = if dry_run.is_some() {
+            (None, SdkMeterProvider::builder().build())
+        } else {
+            let tracer = vm::setup_tracer(&otel_endpoint, service_name, Some(replica))
+                .map_err(RuntimeError::InitTraceError)?;
+            let meter_provider = vm::init_meter_provider(
+                Some(&otel_endpoint),
+                service_name,
+                Some(replica),
+                metric_views,
+            )
+            .map_err(RuntimeError::InitMeterError)?;
+            (Some(tracer), meter_provider)
+        };
+        let print_dropped_counter = meter_provider
+            .meter("print_dropped_counter")
+            .u64_counter("print_dropped_counter")
+            .with_description("The number of print messages dropped by rate limiting or sampling")
+            .build();
+
+        let mut builder = scenario
+            .vm_builder(service, &instance, run, &defaults)
+            .with_print_queue_size(args.print_queue_size as usize)
+            .with_incoming_calls(args.remote_call_queue_size as usize)
+            .with_meter_provider(meter_provider.clone())
+            .with_legacy_duration_gauges(args.legacy_duration_gauges)
+            .with_span_events(args.span_events)
+            .with_baggage(args.baggage.iter().cloned().collect())
+            .with_instruction_counter(runtime_metrics.instructions())
+            .with_interrupt_interval(std::time::Duration::from_millis(args.interrupt_interval_ms));
+        if let Some(tracer) = &tracer {
+            builder = builder.with_tracer(tracer.clone());
+        }
+        if let Some(dry_run) = &dry_run {
+            builder = builder.with_dry_run(dry_run.clone());
+        }
+        let print_limiter = scenario::print_limiter(service_config);
+
+        let print_sink = TracingSink::new(service_name, &instance)
+            .with_attributes(&service_config.attributes)
+            .with_attribute_changes(
+                timeline::attribute_changes(
+                    &scenario.timeline,
+                    service_name,
+                    &service_config.attributes,
+                ),
+                run.started,
+            );
+        let instance_handles = execute_instance(
+            service_name,
+            &instance,
+            builder,
+            print_sink,
+            print_limiter,
+            print_dropped_counter,
+            &run.coordinator,
+            start_rx.clone(),
+            run_stats.clone(),
+            dry_run.is_some(),
+            runtime_metrics.clone(),
+        )
+        .await?;
+        handles.extend(instance_handles);
+    }
+    Ok(handles)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn execute_instance(
+    service_name: &str,
+    instance: &str,
+    builder: VmBuilder,
+    print_sink: TracingSink,
+    mut print_limiter: PrintLimiter,
+    print_dropped_counter: Counter<u64>,
+    coordinator: &vm_coordinator::CoordinatorHandle,
+    mut start_rx: watch::Receiver<bool>,
+    run_stats: run_stats::RunStats,
+    dry_run: bool,
+    runtime_metrics: runtime_metrics::RuntimeMetrics,
+) -> Result<Vec<tokio::task::JoinHandle<Result<(), vm::VMError>>>, RuntimeError> {
+    let (mut vm, channels) = builder.build()?;
+    let mut print_rx = channels.print_rx;
+    if let Some(incoming_call_tx) = channels.incoming_call_tx {
+        coordinator
+            .register_service(service_name, instance, incoming_call_tx)
+            .await?;
+    }
+
+    let mut handles = Vec::new();
+    let app_name = service_name.to_string();
+    let instance_id = instance.to_string();
+    let print_stats = run_stats.clone();
+    let failed_remote_calls = vm.failed_remote_calls();
+    let print_metrics = runtime_metrics.clone();
+    let print_handle = tokio::spawn(async move {
+        let (mut info_logs, mut error_logs) = (0, 0);
+        while let Some(print) = print_rx.recv().await {
+            print_metrics.record_print_queue_depth(print_rx.len(), &app_name, &instance_id);
+            if !print_limiter.allow(std::time::Instant::now()) {
+                print_dropped_counter.add(
+                    1,
+                    &[
+                        KeyValue::new("service", app_name.clone()),
+                        KeyValue::new("instance", instance_id.clone()),
+                    ],
+                );
+                print_metrics.message_dropped(&app_name);
+                continue;
+            }
+            match print.message {
+                vm::PrintMessage::Stdout(_) => info_logs += 1,
+                vm::PrintMessage::Stderr(_) => error_logs += 1,
+            }
+            // A dry run only counts the messages
+            if !dry_run {
+                let _ = print_sink.print(print).await;
+            }
+        }
+        // The replies to the calls of the instance print their failures, so all of them arrived by now
+        print_stats.record_logs(&app_name, info_logs, error_logs, print_limiter.dropped());
+        print_stats.record_failed_calls(&app_name, failed_remote_calls.load(Ordering::SeqCst));
+        if print_limiter.dropped() > 0 {
+            info!(
+                app_name = %app_name,
+                instance = %instance_id,
+                dropped = print_limiter.dropped(),
+                "Dropped print messages"
+            );
+        }
+        Ok(())
+    });
+    handles.push(print_handle);
+    let app_name = service_name.to_string();
+    let instance_id = instance.to_string();
+    let coordinator = coordinator.clone();
+    handles.push(tokio::spawn(async move {
+        if start_rx.wait_for(|started| *started).await.is_err() {
+            return Ok(());
+        }
+        let started = std::time::Instant::now();
+        runtime_metrics.vm_started(&app_name);
+        let result = vm.run().await;
+        runtime_metrics.vm_stopped(&app_name);
+        let wall_time = started.elapsed();
+        // Calls to a stopped instance go to the remaining replicas instead of queueing up
+        if coordinator
+            .deregister_service(&app_name, Some(&instance_id))
+            .await
+            .is_err()
+        {
+            tracing::debug!(
+                app_name = %app_name,
+                instance = %instance_id,
+                "Coordinator stopped before the service"
+            );
+        }
+        drop(coordinator);
+        let stats = vm.stats();
+        run_stats.record_instance(&app_name, &stats, wall_time);
+        info!(
+            app_name = %app_name,
+            instance = %instance_id,
+            instructions = stats.instructions,
+            stdout = stats.stdout,
+            stderr = stats.stderr,
+            remote_calls = stats.remote_calls,
+            incoming_calls = stats.incoming_calls,
+            failed_remote_calls = stats.failed_remote_calls,
+            "Service stopped"
+        );
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error: {}", e);
+                Err(e)
+            }
+        }
+    }));
+    Ok(handles)
+}
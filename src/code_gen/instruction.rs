@@ -1,4 +1,10 @@
-#[derive(Debug, Clone, PartialEq, Eq)]
+use serde::{Deserialize, Serialize};
+
+use crate::extension;
+use crate::parser::AccessLogFormat;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum StackValue {
     String(String),
     Int(u64),
@@ -13,7 +19,8 @@ impl std::fmt::Display for StackValue {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Instruction {
     /// Push a value onto the stack
     Push(StackValue),
@@ -42,13 +49,13 @@ pub enum Instruction {
     Jump(String),
     /// Takes the top value of the stack and prints it as a formatted string
     /// example:
-    /// ```
+    /// ```text
     /// "Hello, %s!"
     /// ```
     /// will print "Hello, John!" if the name variable is "John"
     Printf,
     /// Remote call, expected stack layout:
-    /// ```
+    /// ```text
     /// [service_name, method_name]
     /// ```
     RemoteCall,
@@ -62,6 +69,18 @@ pub enum Instruction {
     Call(String),
     /// Return from a local function
     Ret,
+    /// Set a baggage entry for the rest of the current function and the calls it makes
+    SetBaggage(String, String),
+    /// Push a made-up access log line in the given format
+    AccessLog(AccessLogFormat),
+    /// Run the handler registered for an extension code, with the operand of its statement
+    Extension(u8, String),
+    /// Push one of the values, picked at random by its weight
+    PushWeighted(Vec<(String, u64)>),
+    /// Sleep for a number of milliseconds, plus or minus up to the second number at random
+    SleepJittered(u64, u64),
+    /// Print the top of the stack to stdout or stderr, picked at random by the weights of the two
+    PrintMixed(u64, u64),
 }
 
 pub const PUSH_STRING_CODE: u8 = 0x01;
@@ -84,6 +103,11 @@ pub const END_CONTEXT_CODE: u8 = 0x11;
 pub const CHECK_INTERRUPT_CODE: u8 = 0x12;
 pub const CALL_CODE: u8 = 0x13;
 pub const RET_CODE: u8 = 0x14;
+pub const SET_BAGGAGE_CODE: u8 = 0x15;
+pub const ACCESS_LOG_CODE: u8 = 0x16;
+pub const PUSH_WEIGHTED_CODE: u8 = 0x17;
+pub const SLEEP_JITTERED_CODE: u8 = 0x18;
+pub const PRINT_MIXED_CODE: u8 = 0x19;
 
 pub fn code_to_name(code: u8) -> String {
     match code {
@@ -107,6 +131,12 @@ pub fn code_to_name(code: u8) -> String {
         CHECK_INTERRUPT_CODE => "CheckInterrupt".to_string(),
         CALL_CODE => "Call".to_string(),
         RET_CODE => "Ret".to_string(),
+        SET_BAGGAGE_CODE => "SetBaggage".to_string(),
+        ACCESS_LOG_CODE => "AccessLog".to_string(),
+        PUSH_WEIGHTED_CODE => "PushWeighted".to_string(),
+        SLEEP_JITTERED_CODE => "SleepJittered".to_string(),
+        PRINT_MIXED_CODE => "PrintMixed".to_string(),
+        extension::FIRST_CODE..=extension::LAST_CODE => "Extension".to_string(),
         _ => "Unknown".to_string(),
     }
 }
@@ -134,6 +164,12 @@ impl Instruction {
             Instruction::CheckInterrupt => CHECK_INTERRUPT_CODE,
             Instruction::Call(_) => CALL_CODE,
             Instruction::Ret => RET_CODE,
+            Instruction::SetBaggage(_, _) => SET_BAGGAGE_CODE,
+            Instruction::AccessLog(_) => ACCESS_LOG_CODE,
+            Instruction::Extension(code, _) => *code,
+            Instruction::PushWeighted(_) => PUSH_WEIGHTED_CODE,
+            Instruction::SleepJittered(_, _) => SLEEP_JITTERED_CODE,
+            Instruction::PrintMixed(_, _) => PRINT_MIXED_CODE,
         }
     }
 
@@ -182,7 +218,7 @@ impl Instruction {
                 bytes.extend_from_slice(&ms_bytes.len().to_le_bytes());
                 bytes.extend_from_slice(&ms_bytes);
             }
-            Instruction::StoreVar(key, value) => {
+            Instruction::StoreVar(key, value) | Instruction::SetBaggage(key, value) => {
                 bytes.push(self.code());
                 bytes.extend_from_slice(&key.len().to_le_bytes());
                 bytes.extend_from_slice(key.as_bytes());
@@ -225,6 +261,36 @@ impl Instruction {
             Instruction::Ret => {
                 bytes.push(self.code());
             }
+            Instruction::AccessLog(format) => {
+                let name = format.name();
+                bytes.push(self.code());
+                bytes.extend_from_slice(&name.len().to_le_bytes());
+                bytes.extend_from_slice(name.as_bytes());
+            }
+            Instruction::Extension(_, operand) => {
+                bytes.push(self.code());
+                bytes.extend_from_slice(&operand.len().to_le_bytes());
+                bytes.extend_from_slice(operand.as_bytes());
+            }
+            Instruction::PushWeighted(values) => {
+                bytes.push(self.code());
+                bytes.extend_from_slice(&values.len().to_le_bytes());
+                for (value, weight) in values {
+                    bytes.extend_from_slice(&value.len().to_le_bytes());
+                    bytes.extend_from_slice(value.as_bytes());
+                    let weight_bytes = weight.to_le_bytes();
+                    bytes.extend_from_slice(&weight_bytes.len().to_le_bytes());
+                    bytes.extend_from_slice(&weight_bytes);
+                }
+            }
+            Instruction::SleepJittered(first, second) | Instruction::PrintMixed(first, second) => {
+                bytes.push(self.code());
+                for value in [first, second] {
+                    let value_bytes = value.to_le_bytes();
+                    bytes.extend_from_slice(&value_bytes.len().to_le_bytes());
+                    bytes.extend_from_slice(&value_bytes);
+                }
+            }
         }
         bytes
     }
@@ -252,6 +318,24 @@ impl std::fmt::Display for Instruction {
             Instruction::CheckInterrupt => write!(f, "CheckInterrupt"),
             Instruction::Call(label) => write!(f, "Call({})", label),
             Instruction::Ret => write!(f, "Ret"),
+            Instruction::SetBaggage(key, value) => write!(f, "SetBaggage({} = {})", key, value),
+            Instruction::AccessLog(format) => write!(f, "AccessLog({})", format.name()),
+            Instruction::Extension(code, operand) => {
+                write!(f, "Extension({:#04x}, {})", code, operand)
+            }
+            Instruction::PushWeighted(values) => {
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|(value, weight)| format!("{}: {}", value, weight))
+                    .collect();
+                write!(f, "PushWeighted({})", values.join(", "))
+            }
+            Instruction::SleepJittered(ms, jitter_ms) => {
+                write!(f, "SleepJittered({}±{})", ms, jitter_ms)
+            }
+            Instruction::PrintMixed(info, error) => {
+                write!(f, "PrintMixed(info: {}, error: {})", info, error)
+            }
         }
     }
 }
@@ -520,4 +604,27 @@ mod tests {
         assert_eq!(bytes[0], instruction.code());
         assert_eq!(bytes.len(), 1);
     }
+
+    #[test]
+    fn test_instructions_round_trip_through_json() {
+        let instructions = vec![
+            Instruction::Label("start_main".to_string()),
+            Instruction::Push(StackValue::String("Hello %s".to_string())),
+            Instruction::Push(StackValue::Int(3)),
+            Instruction::Printf,
+            Instruction::AccessLog(AccessLogFormat::Cloudfront),
+            Instruction::Extension(0x80, "ADT^A01".to_string()),
+            Instruction::Ret,
+        ];
+        let json = serde_json::to_value(&instructions).unwrap();
+        assert_eq!(
+            json[1],
+            serde_json::json!({ "push": { "string": "Hello %s" } })
+        );
+        assert_eq!(json[6], serde_json::json!("ret"));
+        assert_eq!(
+            serde_json::from_value::<Vec<Instruction>>(json).unwrap(),
+            instructions
+        );
+    }
 }
@@ -1,7 +1,11 @@
+//! Compiles the AST of a service into the instructions a [`crate::vm::VM`] runs.
+
 use instruction::{Instruction, StackValue};
 
 use crate::code_gen::error::CodeGenError;
-use crate::parser::{Method, Service, Statement};
+use crate::extension::ExtensionRegistry;
+use crate::parser::{Method, Service, SeverityMix, Statement, Template};
+use crate::printf;
 
 pub mod error;
 pub mod instruction;
@@ -10,17 +14,40 @@ pub mod instruction;
 pub enum PrintType {
     Stdout,
     Stderr,
+    Mixed(SeverityMix),
+}
+
+impl PrintType {
+    fn instruction(&self) -> Instruction {
+        match self {
+            PrintType::Stdout => Instruction::Stdout,
+            PrintType::Stderr => Instruction::Stderr,
+            PrintType::Mixed(mix) => Instruction::PrintMixed(mix.info, mix.error),
+        }
+    }
 }
 
+/// Compiles one service, with a `start_<method>` label per method
 pub struct CodeGenerator<'a> {
     ast: &'a Service,
+    extensions: Option<&'a ExtensionRegistry>,
 }
 
 impl<'a> CodeGenerator<'a> {
     pub fn new(ast: &'a Service) -> Self {
-        Self { ast }
+        Self {
+            ast,
+            extensions: None,
+        }
     }
 
+    /// Compiles `ext` statements to the instructions of these extensions
+    pub fn with_extensions(mut self, extensions: &'a ExtensionRegistry) -> Self {
+        self.extensions = Some(extensions);
+        self
+    }
+
+    /// The instructions of the service, which runs its loop or else waits for calls
     pub fn process(&self) -> Result<Vec<Instruction>, CodeGenError> {
         self.process_service(self.ast)
     }
@@ -32,13 +59,19 @@ impl<'a> CodeGenerator<'a> {
         for method in &service.methods {
             instructions.extend(self.process_method(method)?);
         }
-        let has_loop = service.loops.len() > 0;
+        if let Some(shutdown) = &service.shutdown {
+            instructions.extend(self.process_method(&Method {
+                name: format!("{}_shutdown", service.name),
+                statements: shutdown.statements.clone(),
+            })?);
+        }
+        let has_loop = !service.loops.is_empty();
         instructions.push(Instruction::Label(format!("start_{}_main", service.name)));
         if has_loop {
             instructions.push(Instruction::StartContext);
         }
         if let Some(loop_def) = service.loops.first() {
-            self.process_loop(&mut instructions, &loop_def)?;
+            self.process_loop(&mut instructions, loop_def)?;
         } else {
             instructions.push(Instruction::CheckInterrupt);
             instructions.push(Instruction::Jump(format!("start_{}_main", service.name)));
@@ -63,7 +96,7 @@ impl<'a> CodeGenerator<'a> {
                     if let Some(_service) = service {
                         return Err(CodeGenError::InvalidStatement(format!(
                             "Expected Local Call - Got {}",
-                            statements.to_string()
+                            statements
                         )));
                     }
                     instructions.push(Instruction::Call(format!("start_{}", method)));
@@ -71,27 +104,44 @@ impl<'a> CodeGenerator<'a> {
                 _ => {
                     return Err(CodeGenError::InvalidStatement(format!(
                         "Expected Call - Got {}",
-                        statements.to_string()
+                        statements
                     )));
                 }
             }
-            instructions.push(Instruction::Jump(format!("start_loop")));
+            instructions.push(Instruction::Jump("start_loop".to_string()));
             instructions.push(Instruction::Label("end_loop".to_string()));
         }
         Ok(())
     }
 
-    fn process_method(&self, method: &'a Method) -> Result<Vec<Instruction>, CodeGenError> {
+    fn process_method(&self, method: &Method) -> Result<Vec<Instruction>, CodeGenError> {
         let mut instructions = Vec::new();
         instructions.push(Instruction::Label(format!("start_{}", method.name)));
         for statement in &method.statements {
             match statement {
-                Statement::Stdout { message, args } => {
-                    instructions.extend(self.process_print(message, args, PrintType::Stdout));
+                Statement::Stdout {
+                    message,
+                    args,
+                    weights,
+                    severity_mix,
+                    templates,
+                } => {
+                    let print_type = match severity_mix {
+                        Some(mix) => PrintType::Mixed(*mix),
+                        None => PrintType::Stdout,
+                    };
+                    instructions
+                        .extend(self.process_print(message, templates, args, weights, print_type)?);
                 }
-                Statement::Sleep { duration } => {
+                Statement::Sleep { duration, jitter } if jitter.is_zero() => {
                     instructions.push(Instruction::Sleep(duration.as_millis() as u64));
                 }
+                Statement::Sleep { duration, jitter } => {
+                    instructions.push(Instruction::SleepJittered(
+                        duration.as_millis() as u64,
+                        jitter.as_millis() as u64,
+                    ));
+                }
                 Statement::Call { service, method } => {
                     if let Some(service) = service {
                         instructions.push(Instruction::Push(StackValue::String(service.clone())));
@@ -100,12 +150,42 @@ impl<'a> CodeGenerator<'a> {
                     } else {
                         return Err(CodeGenError::InvalidStatement(format!(
                             "Expected Remote Call - Got {}",
-                            statement.to_string()
+                            statement
                         )));
                     }
                 }
-                Statement::Stderr { message, args } => {
-                    instructions.extend(self.process_print(message, args, PrintType::Stderr));
+                Statement::Stderr {
+                    message,
+                    args,
+                    weights,
+                    templates,
+                } => {
+                    instructions.extend(self.process_print(
+                        message,
+                        templates,
+                        args,
+                        weights,
+                        PrintType::Stderr,
+                    )?);
+                }
+                Statement::Baggage { key, value } => {
+                    instructions.push(Instruction::SetBaggage(key.clone(), value.clone()));
+                }
+                Statement::Extension { name, operand } => {
+                    let compiled = self
+                        .extensions
+                        .and_then(|extensions| extensions.compile(name, operand))
+                        .ok_or_else(|| {
+                            CodeGenError::InvalidStatement(format!(
+                                "Unknown extension - Got {}",
+                                statement
+                            ))
+                        })?;
+                    instructions.extend(compiled);
+                }
+                Statement::AccessLog { format } => {
+                    instructions.push(Instruction::AccessLog(*format));
+                    instructions.push(Instruction::Stdout);
                 }
             }
         }
@@ -117,28 +197,133 @@ impl<'a> CodeGenerator<'a> {
     fn process_print(
         &self,
         message: &str,
+        templates: &Option<Vec<Template>>,
         args: &Option<Vec<String>>,
+        weights: &Option<Vec<u64>>,
         print_type: PrintType,
-    ) -> Vec<Instruction> {
+    ) -> Result<Vec<Instruction>, CodeGenError> {
+        // One template per print, picked when the print runs
+        let push_message = match templates {
+            Some(templates) if templates.iter().all(|template| template.weight == 0) => {
+                return Err(CodeGenError::InvalidStatement(format!(
+                    "No template of {:?} has a weight above 0",
+                    message
+                )));
+            }
+            Some(templates) => Instruction::PushWeighted(
+                templates
+                    .iter()
+                    .map(|template| (template.message.clone(), template.weight))
+                    .collect(),
+            ),
+            None => Instruction::Push(StackValue::String(message.to_string())),
+        };
+        let arity = print_arity(message, templates, args, weights)
+            .map_err(CodeGenError::InvalidStatement)?;
         let mut instructions = Vec::new();
-        if let Some(args) = args {
+        if let (Some(args), Some(weights)) = (args, weights) {
+            if args.len() != weights.len() {
+                return Err(CodeGenError::InvalidStatement(format!(
+                    "{} values with {} weights",
+                    args.len(),
+                    weights.len()
+                )));
+            }
+            if weights.iter().all(|weight| *weight == 0) {
+                return Err(CodeGenError::InvalidStatement(format!(
+                    "No value of {:?} has a weight above 0",
+                    message
+                )));
+            }
+            // One value per print, picked when the print runs
+            let values = args.iter().cloned().zip(weights.iter().copied()).collect();
+            instructions.push(Instruction::PushWeighted(values));
+            instructions.push(push_message);
+            instructions.push(Instruction::Printf);
+            instructions.push(print_type.instruction());
+        } else if let Some(args) = args.as_ref().filter(|_| arity > 1) {
+            // All values go into one print. Values are pushed in reverse, so the first value is on top of the stack
+            for arg in args.iter().rev() {
+                instructions.push(Instruction::Push(StackValue::String(arg.to_string())));
+            }
+            instructions.push(push_message);
+            instructions.push(Instruction::Printf);
+            instructions.push(print_type.instruction());
+        } else if let Some(args) = args {
             for arg in args {
-                instructions.push(Instruction::Push(StackValue::String(message.to_string())));
                 instructions.push(Instruction::Push(StackValue::String(arg.to_string())));
+                instructions.push(push_message.clone());
                 instructions.push(Instruction::Printf);
-                match print_type {
-                    PrintType::Stdout => instructions.push(Instruction::Stdout),
-                    PrintType::Stderr => instructions.push(Instruction::Stderr),
-                }
+                instructions.push(print_type.instruction());
             }
         } else {
-            instructions.push(Instruction::Push(StackValue::String(message.to_string())));
-            match print_type {
-                PrintType::Stdout => instructions.push(Instruction::Stdout),
-                PrintType::Stderr => instructions.push(Instruction::Stderr),
+            instructions.push(push_message);
+            // Baggage placeholders and generators are filled in by Printf at runtime
+            let fills_in = |message: &str| {
+                message.contains("%{")
+                    || printf::Template::parse(message)
+                        .is_ok_and(|template| template.generators().next().is_some())
+            };
+            let has_placeholders = match templates {
+                Some(templates) => templates.iter().any(|template| fills_in(&template.message)),
+                None => fills_in(message),
+            };
+            if has_placeholders {
+                instructions.push(Instruction::Printf);
             }
+            instructions.push(print_type.instruction());
         }
-        instructions
+        Ok(instructions)
+    }
+}
+
+/// The number of values every print of a statement takes, or why its templates cannot take the
+/// values. A template with one placeholder prints every value on its own, a template with more
+/// takes all of them at once. Prints without values take none
+pub(crate) fn print_arity(
+    message: &str,
+    templates: &Option<Vec<Template>>,
+    args: &Option<Vec<String>>,
+    weights: &Option<Vec<u64>>,
+) -> Result<usize, String> {
+    let Some(args) = args.as_ref().filter(|args| !args.is_empty()) else {
+        return Ok(0);
+    };
+    let messages: Vec<&str> = match templates {
+        Some(templates) => templates
+            .iter()
+            .map(|template| template.message.as_str())
+            .collect(),
+        None => vec![message],
+    };
+    let mut arity = None;
+    for message in messages {
+        let parsed = printf::Template::parse(message).map_err(|e| e.to_string())?;
+        match arity {
+            Some(arity) if arity != parsed.arity() => {
+                return Err(format!(
+                    "the templates take {} and {} values",
+                    arity,
+                    parsed.arity()
+                ))
+            }
+            _ => arity = Some(parsed.arity()),
+        }
+    }
+    match arity.unwrap_or(0) {
+        0 => Err(format!("{:?} has no placeholder for the values", message)),
+        1 => Ok(1),
+        arity if weights.is_some() => Err(format!(
+            "{:?} takes {} values, but one is picked per print",
+            message, arity
+        )),
+        arity if arity != args.len() => Err(format!(
+            "{:?} takes {} values, but {} were given",
+            message,
+            arity,
+            args.len()
+        )),
+        arity => Ok(arity),
     }
 }
 
@@ -149,7 +334,7 @@ mod tests {
             instruction::{Instruction, StackValue},
             CodeGenerator,
         },
-        parser,
+        parser::{self, Statement},
     };
 
     fn service() -> String {
@@ -363,12 +548,12 @@ mod tests {
             Instruction::Label("start_products".to_string()),
             Instruction::Jump("start_products_main".to_string()),
             Instruction::Label("start_get_products".to_string()),
-            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Push(StackValue::String("12345".to_string())),
+            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Printf,
             Instruction::Stdout,
-            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Push(StackValue::String("67890".to_string())),
+            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Printf,
             Instruction::Stdout,
             Instruction::Sleep(500),
@@ -383,6 +568,102 @@ mod tests {
         assert_eq!(code, expected);
     }
 
+    #[test]
+    fn test_service_with_several_templates() {
+        let ast = parser::parse(
+            r#"
+            service products {
+                method get_products {
+                    print ["Cache hit for %s": 9, "Cache miss for %s"] with ["12345"];
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        assert_eq!(
+            code[3..7],
+            [
+                Instruction::Push(StackValue::String("12345".to_string())),
+                Instruction::PushWeighted(vec![
+                    ("Cache hit for %s".to_string(), 9),
+                    ("Cache miss for %s".to_string(), 1),
+                ]),
+                Instruction::Printf,
+                Instruction::Stdout,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_template_takes_all_its_values_at_once() {
+        let ast = parser::parse(
+            r#"
+            service products {
+                method get_products {
+                    print "GET %s %d" with ["/products", "200"];
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+        assert_eq!(
+            code[3..8],
+            [
+                Instruction::Push(StackValue::String("200".to_string())),
+                Instruction::Push(StackValue::String("/products".to_string())),
+                Instruction::Push(StackValue::String("GET %s %d".to_string())),
+                Instruction::Printf,
+                Instruction::Stdout,
+            ]
+        );
+
+        for (print, problem) in [
+            (
+                r#"print "GET %s %d" with ["/products"];"#,
+                r#""GET %s %d" takes 2 values, but 1 were given"#,
+            ),
+            (
+                r#"print "GET %s %d" with ["/products": 1, "/cart": 1];"#,
+                r#""GET %s %d" takes 2 values, but one is picked per print"#,
+            ),
+            (
+                r#"print "Main page" with ["12345", "67890"];"#,
+                r#""Main page" has no placeholder for the values"#,
+            ),
+            (
+                r#"print ["GET %s", "GET %s %d"] with ["/products"];"#,
+                "the templates take 1 and 2 values",
+            ),
+        ] {
+            let source = format!("service products {{ method get_products {{ {} }} }}", print);
+            let ast = parser::parse(&source).unwrap();
+            let e = CodeGenerator::new(&ast.services[0]).process().unwrap_err();
+            assert_eq!(e.to_string(), format!("Invalid statement: {}", problem));
+        }
+    }
+
+    #[test]
+    fn test_values_without_weight_are_rejected() {
+        let mut ast = parser::parse(
+            r#"
+            service products {
+                method get_products {
+                    print "Fetching %s" with ["12345": 1, "67890": 1];
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        // The parser rejects them, but a JSON or YAML scenario can hold them
+        if let Statement::Stdout { weights, .. } = &mut ast.services[0].methods[0].statements[0] {
+            *weights = Some(vec![0, 0]);
+        }
+        assert!(CodeGenerator::new(&ast.services[0]).process().is_err());
+    }
+
     #[test]
     fn test_service_with_template_and_empty_var_list() {
         let service = service_with_template_and_empty_var_list();
@@ -415,12 +696,12 @@ mod tests {
             Instruction::Label("start_products".to_string()),
             Instruction::Jump("start_products_main".to_string()),
             Instruction::Label("start_get_products".to_string()),
-            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Push(StackValue::String("12345".to_string())),
+            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Printf,
             Instruction::Stderr,
-            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Push(StackValue::String("67890".to_string())),
+            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Printf,
             Instruction::Stderr,
             Instruction::Sleep(500),
@@ -468,12 +749,12 @@ mod tests {
             Instruction::Label("start_products".to_string()),
             Instruction::Jump("start_products_main".to_string()),
             Instruction::Label("start_get_products".to_string()),
-            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Push(StackValue::String("12345".to_string())),
+            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Printf,
             Instruction::Stdout,
-            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Push(StackValue::String("67890".to_string())),
+            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Printf,
             Instruction::Stdout,
             Instruction::Sleep(500),
@@ -509,6 +790,35 @@ mod tests {
         assert_eq!(frontend_code, expected_frontend);
     }
 
+    #[test]
+    fn test_service_with_shutdown_block() {
+        let service = "
+        service frontend {
+            shutdown {
+                print \"Bye\";
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let expected = vec![
+            Instruction::Label("start_frontend".to_string()),
+            Instruction::Jump("start_frontend_main".to_string()),
+            Instruction::Label("start_frontend_shutdown".to_string()),
+            Instruction::Push(StackValue::String("Bye".to_string())),
+            Instruction::Stdout,
+            Instruction::Ret,
+            Instruction::Label("end_frontend_shutdown".to_string()),
+            Instruction::Label("start_frontend_main".to_string()),
+            Instruction::CheckInterrupt,
+            Instruction::Jump("start_frontend_main".to_string()),
+            Instruction::Label("end_frontend_main".to_string()),
+            Instruction::Label("end_frontend".to_string()),
+        ];
+        assert_eq!(code, expected);
+    }
+
     #[test]
     fn test_call_other_service_without_loop() {
         let service = call_other_service_without_loop();
@@ -520,12 +830,12 @@ mod tests {
             Instruction::Label("start_products".to_string()),
             Instruction::Jump("start_products_main".to_string()),
             Instruction::Label("start_get_products".to_string()),
-            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Push(StackValue::String("12345".to_string())),
+            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Printf,
             Instruction::Stdout,
-            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Push(StackValue::String("67890".to_string())),
+            Instruction::Push(StackValue::String("Fetching product orders %s".to_string())),
             Instruction::Printf,
             Instruction::Stdout,
             Instruction::Sleep(500),
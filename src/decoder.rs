@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[cfg(feature = "native")]
+use crate::code_gen::instruction::{Instruction, StackValue};
+use crate::code_gen::instruction::{
+    ACCESS_LOG_CODE, CALL_CODE, CHECK_INTERRUPT_CODE, DEC_CODE, DUP_CODE, END_CONTEXT_CODE,
+    JMP_IF_ZERO_CODE, JUMP_CODE, LABEL_CODE, LOAD_VAR_CODE, POP_CODE, PRINTF_CODE,
+    PRINT_MIXED_CODE, PUSH_INT_CODE, PUSH_STRING_CODE, PUSH_WEIGHTED_CODE, REMOTE_CALL_CODE,
+    RET_CODE, SET_BAGGAGE_CODE, SLEEP_CODE, SLEEP_JITTERED_CODE, START_CONTEXT_CODE, STDERR_CODE,
+    STDOUT_CODE, STORE_VAR_CODE,
+};
+use crate::extension;
+use crate::parser::AccessLogFormat;
+use crate::printf::Template;
+use crate::string_table::{StringTable, Symbol};
+
+///The length of the length byte array for an operand
+const LENGTH_OFFSET: usize = std::mem::size_of::<usize>();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The byte at the given position is not a known instruction
+    InvalidInstruction(usize, u8),
+    /// The instruction at the given position ends after the end of the bytecode
+    UnexpectedEnd(usize),
+    /// The string operand of the instruction at the given position is not valid UTF-8
+    InvalidString(usize),
+    /// The access log instruction at the given position names an unknown format
+    UnknownAccessLogFormat(usize),
+}
+
+impl std::error::Error for DecodeError {}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::InvalidInstruction(position, code) => {
+                write!(f, "Invalid instruction {} at position {}", code, position)
+            }
+            DecodeError::UnexpectedEnd(position) => {
+                write!(f, "Unexpected end of bytecode at position {}", position)
+            }
+            DecodeError::InvalidString(position) => {
+                write!(f, "Invalid UTF-8 string at position {}", position)
+            }
+            DecodeError::UnknownAccessLogFormat(position) => {
+                write!(f, "Unknown access log format at position {}", position)
+            }
+        }
+    }
+}
+
+/// An instruction decoded from the byte stream once at load time.
+/// String operands are interned, and labels are referenced by their symbol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedInstr {
+    PushString(Arc<str>),
+    PushInt(u64),
+    Pop,
+    Dec,
+    JmpIfZero(Symbol),
+    Label(Symbol),
+    Stdout,
+    Stderr,
+    Sleep(u64),
+    StoreVar(Symbol, Arc<str>),
+    LoadVar(Symbol),
+    Dup,
+    Jump(Symbol),
+    Printf,
+    RemoteCall,
+    StartContext,
+    EndContext,
+    CheckInterrupt,
+    Call(Symbol),
+    Ret,
+    SetBaggage(Arc<str>, Arc<str>),
+    AccessLog(AccessLogFormat),
+    Extension(u8, Arc<str>),
+    PushWeighted(Vec<(Arc<str>, u64)>),
+    SleepJittered(u64, u64),
+    PrintMixed(u64, u64),
+}
+
+// The VM reports instructions by their code
+#[cfg(feature = "native")]
+impl DecodedInstr {
+    pub fn code(&self) -> u8 {
+        match self {
+            DecodedInstr::PushString(_) => PUSH_STRING_CODE,
+            DecodedInstr::PushInt(_) => PUSH_INT_CODE,
+            DecodedInstr::Pop => POP_CODE,
+            DecodedInstr::Dec => DEC_CODE,
+            DecodedInstr::JmpIfZero(_) => JMP_IF_ZERO_CODE,
+            DecodedInstr::Label(_) => LABEL_CODE,
+            DecodedInstr::Stdout => STDOUT_CODE,
+            DecodedInstr::Stderr => STDERR_CODE,
+            DecodedInstr::Sleep(_) => SLEEP_CODE,
+            DecodedInstr::StoreVar(_, _) => STORE_VAR_CODE,
+            DecodedInstr::LoadVar(_) => LOAD_VAR_CODE,
+            DecodedInstr::Dup => DUP_CODE,
+            DecodedInstr::Jump(_) => JUMP_CODE,
+            DecodedInstr::Printf => PRINTF_CODE,
+            DecodedInstr::RemoteCall => REMOTE_CALL_CODE,
+            DecodedInstr::StartContext => START_CONTEXT_CODE,
+            DecodedInstr::EndContext => END_CONTEXT_CODE,
+            DecodedInstr::CheckInterrupt => CHECK_INTERRUPT_CODE,
+            DecodedInstr::Call(_) => CALL_CODE,
+            DecodedInstr::Ret => RET_CODE,
+            DecodedInstr::SetBaggage(_, _) => SET_BAGGAGE_CODE,
+            DecodedInstr::AccessLog(_) => ACCESS_LOG_CODE,
+            DecodedInstr::Extension(code, _) => *code,
+            DecodedInstr::PushWeighted(_) => PUSH_WEIGHTED_CODE,
+            DecodedInstr::SleepJittered(_, _) => SLEEP_JITTERED_CODE,
+            DecodedInstr::PrintMixed(_, _) => PRINT_MIXED_CODE,
+        }
+    }
+}
+
+/// The value of a `PushWeighted` that `roll`, below the sum of the weights, falls on
+pub fn pick_weighted(values: &[(Arc<str>, u64)], mut roll: u64) -> Option<Arc<str>> {
+    for (value, weight) in values {
+        if roll < *weight {
+            return Some(value.clone());
+        }
+        roll -= weight;
+    }
+    None
+}
+
+/// The instruction table of a service
+pub struct DecodedProgram {
+    pub instructions: Vec<DecodedInstr>,
+    pub strings: StringTable,
+    /// Jump target for every label symbol: the index of the instruction following the label
+    pub labels: Vec<Option<usize>>,
+    /// The templates the code pushes right before a `Printf`, parsed once here. Templates that
+    /// do not parse are left out, so `Printf` reports them when it runs
+    pub templates: HashMap<Arc<str>, Arc<Template>>,
+}
+
+struct Reader<'a> {
+    code: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn read_bytes(&mut self, instruction_start: usize, len: usize) -> Result<&[u8], DecodeError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.code.len())
+            .ok_or(DecodeError::UnexpectedEnd(instruction_start))?;
+        let bytes = &self.code[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_operand(&mut self, instruction_start: usize) -> Result<&[u8], DecodeError> {
+        let length_bytes: [u8; LENGTH_OFFSET] = self
+            .read_bytes(instruction_start, LENGTH_OFFSET)?
+            .try_into()
+            .unwrap();
+        let length = usize::from_le_bytes(length_bytes);
+        self.read_bytes(instruction_start, length)
+    }
+
+    fn read_string(&mut self, instruction_start: usize) -> Result<&str, DecodeError> {
+        let bytes = self.read_operand(instruction_start)?;
+        std::str::from_utf8(bytes).map_err(|_| DecodeError::InvalidString(instruction_start))
+    }
+
+    fn read_access_log_format(
+        &mut self,
+        instruction_start: usize,
+    ) -> Result<AccessLogFormat, DecodeError> {
+        AccessLogFormat::from_name(self.read_string(instruction_start)?)
+            .ok_or(DecodeError::UnknownAccessLogFormat(instruction_start))
+    }
+
+    fn read_count(&mut self, instruction_start: usize) -> Result<usize, DecodeError> {
+        let count_bytes: [u8; LENGTH_OFFSET] = self
+            .read_bytes(instruction_start, LENGTH_OFFSET)?
+            .try_into()
+            .unwrap();
+        Ok(usize::from_le_bytes(count_bytes))
+    }
+
+    // A value and its weight for every entry
+    fn read_weighted(
+        &mut self,
+        instruction_start: usize,
+    ) -> Result<Vec<(String, u64)>, DecodeError> {
+        let count = self.read_count(instruction_start)?;
+        let mut values = Vec::new();
+        for _ in 0..count {
+            let value = self.read_string(instruction_start)?.to_string();
+            values.push((value, self.read_u64(instruction_start)?));
+        }
+        Ok(values)
+    }
+
+    fn read_u64(&mut self, instruction_start: usize) -> Result<u64, DecodeError> {
+        let bytes: [u8; 8] = self
+            .read_operand(instruction_start)?
+            .try_into()
+            .map_err(|_| DecodeError::UnexpectedEnd(instruction_start))?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+/// Decodes a byte stream into an instruction table.
+/// Length prefixes and UTF-8 are only parsed here, so the interpreter can dispatch on the decoded instructions.
+pub fn decode(code: &[u8]) -> Result<DecodedProgram, DecodeError> {
+    let mut strings = StringTable::new();
+    let mut instructions = Vec::new();
+    let mut label_positions = Vec::new();
+    let mut reader = Reader { code, position: 0 };
+
+    while reader.position < code.len() {
+        let start = reader.position;
+        let opcode = code[start];
+        reader.position += 1;
+        let instruction = match opcode {
+            PUSH_STRING_CODE => {
+                let value = strings.intern(reader.read_string(start)?);
+                DecodedInstr::PushString(strings.get(value).clone())
+            }
+            PUSH_INT_CODE => DecodedInstr::PushInt(reader.read_u64(start)?),
+            POP_CODE => DecodedInstr::Pop,
+            DEC_CODE => DecodedInstr::Dec,
+            JMP_IF_ZERO_CODE => DecodedInstr::JmpIfZero(strings.intern(reader.read_string(start)?)),
+            LABEL_CODE => {
+                let label = strings.intern(reader.read_string(start)?);
+                label_positions.push((label, instructions.len() + 1));
+                DecodedInstr::Label(label)
+            }
+            STDOUT_CODE => DecodedInstr::Stdout,
+            STDERR_CODE => DecodedInstr::Stderr,
+            SLEEP_CODE => DecodedInstr::Sleep(reader.read_u64(start)?),
+            SLEEP_JITTERED_CODE => {
+                DecodedInstr::SleepJittered(reader.read_u64(start)?, reader.read_u64(start)?)
+            }
+            PRINT_MIXED_CODE => {
+                DecodedInstr::PrintMixed(reader.read_u64(start)?, reader.read_u64(start)?)
+            }
+            STORE_VAR_CODE => {
+                let key = strings.intern(reader.read_string(start)?);
+                let value = strings.intern(reader.read_string(start)?);
+                DecodedInstr::StoreVar(key, strings.get(value).clone())
+            }
+            LOAD_VAR_CODE => DecodedInstr::LoadVar(strings.intern(reader.read_string(start)?)),
+            DUP_CODE => DecodedInstr::Dup,
+            JUMP_CODE => DecodedInstr::Jump(strings.intern(reader.read_string(start)?)),
+            PRINTF_CODE => DecodedInstr::Printf,
+            REMOTE_CALL_CODE => DecodedInstr::RemoteCall,
+            START_CONTEXT_CODE => DecodedInstr::StartContext,
+            END_CONTEXT_CODE => DecodedInstr::EndContext,
+            CHECK_INTERRUPT_CODE => DecodedInstr::CheckInterrupt,
+            CALL_CODE => DecodedInstr::Call(strings.intern(reader.read_string(start)?)),
+            RET_CODE => DecodedInstr::Ret,
+            SET_BAGGAGE_CODE => {
+                let key = strings.intern(reader.read_string(start)?);
+                let value = strings.intern(reader.read_string(start)?);
+                DecodedInstr::SetBaggage(strings.get(key).clone(), strings.get(value).clone())
+            }
+            ACCESS_LOG_CODE => DecodedInstr::AccessLog(reader.read_access_log_format(start)?),
+            PUSH_WEIGHTED_CODE => DecodedInstr::PushWeighted(
+                reader
+                    .read_weighted(start)?
+                    .into_iter()
+                    .map(|(value, weight)| {
+                        let value = strings.intern(&value);
+                        (strings.get(value).clone(), weight)
+                    })
+                    .collect(),
+            ),
+            extension::FIRST_CODE..=extension::LAST_CODE => {
+                let operand = strings.intern(reader.read_string(start)?);
+                DecodedInstr::Extension(opcode, strings.get(operand).clone())
+            }
+            _ => return Err(DecodeError::InvalidInstruction(start, opcode)),
+        };
+        instructions.push(instruction);
+    }
+
+    let mut labels = Vec::new();
+    for (label, position) in label_positions {
+        if labels.len() <= label {
+            labels.resize(label + 1, None);
+        }
+        labels[label] = Some(position);
+    }
+
+    let templates = parse_templates(&instructions);
+    Ok(DecodedProgram {
+        instructions,
+        strings,
+        labels,
+        templates,
+    })
+}
+
+// The templates of every `Printf`, which the instruction before it pushes
+fn parse_templates(instructions: &[DecodedInstr]) -> HashMap<Arc<str>, Arc<Template>> {
+    let mut templates = HashMap::new();
+    for pair in instructions.windows(2) {
+        let pushed: Vec<&Arc<str>> = match pair {
+            [DecodedInstr::PushString(template), DecodedInstr::Printf] => vec![template],
+            [DecodedInstr::PushWeighted(values), DecodedInstr::Printf] => {
+                values.iter().map(|(template, _)| template).collect()
+            }
+            _ => continue,
+        };
+        for template in pushed {
+            if let Ok(parsed) = Template::parse(template) {
+                templates.insert(template.clone(), Arc::new(parsed));
+            }
+        }
+    }
+    templates
+}
+
+/// Decodes a byte stream back into the instructions it was generated from
+#[cfg(feature = "native")]
+pub fn decode_instructions(code: &[u8]) -> Result<Vec<Instruction>, DecodeError> {
+    let mut instructions = Vec::new();
+    let mut reader = Reader { code, position: 0 };
+
+    while reader.position < code.len() {
+        let start = reader.position;
+        let opcode = code[start];
+        reader.position += 1;
+        let instruction = match opcode {
+            PUSH_STRING_CODE => {
+                Instruction::Push(StackValue::String(reader.read_string(start)?.to_string()))
+            }
+            PUSH_INT_CODE => Instruction::Push(StackValue::Int(reader.read_u64(start)?)),
+            POP_CODE => Instruction::Pop,
+            DEC_CODE => Instruction::Dec,
+            JMP_IF_ZERO_CODE => Instruction::JmpIfZero(reader.read_string(start)?.to_string()),
+            LABEL_CODE => Instruction::Label(reader.read_string(start)?.to_string()),
+            STDOUT_CODE => Instruction::Stdout,
+            STDERR_CODE => Instruction::Stderr,
+            SLEEP_CODE => Instruction::Sleep(reader.read_u64(start)?),
+            SLEEP_JITTERED_CODE => {
+                Instruction::SleepJittered(reader.read_u64(start)?, reader.read_u64(start)?)
+            }
+            PRINT_MIXED_CODE => {
+                Instruction::PrintMixed(reader.read_u64(start)?, reader.read_u64(start)?)
+            }
+            STORE_VAR_CODE => {
+                let key = reader.read_string(start)?.to_string();
+                let value = reader.read_string(start)?.to_string();
+                Instruction::StoreVar(key, value)
+            }
+            LOAD_VAR_CODE => Instruction::LoadVar(reader.read_string(start)?.to_string()),
+            DUP_CODE => Instruction::Dup,
+            JUMP_CODE => Instruction::Jump(reader.read_string(start)?.to_string()),
+            PRINTF_CODE => Instruction::Printf,
+            REMOTE_CALL_CODE => Instruction::RemoteCall,
+            START_CONTEXT_CODE => Instruction::StartContext,
+            END_CONTEXT_CODE => Instruction::EndContext,
+            CHECK_INTERRUPT_CODE => Instruction::CheckInterrupt,
+            CALL_CODE => Instruction::Call(reader.read_string(start)?.to_string()),
+            RET_CODE => Instruction::Ret,
+            SET_BAGGAGE_CODE => {
+                let key = reader.read_string(start)?.to_string();
+                let value = reader.read_string(start)?.to_string();
+                Instruction::SetBaggage(key, value)
+            }
+            ACCESS_LOG_CODE => Instruction::AccessLog(reader.read_access_log_format(start)?),
+            PUSH_WEIGHTED_CODE => Instruction::PushWeighted(reader.read_weighted(start)?),
+            extension::FIRST_CODE..=extension::LAST_CODE => {
+                Instruction::Extension(opcode, reader.read_string(start)?.to_string())
+            }
+            _ => return Err(DecodeError::InvalidInstruction(start, opcode)),
+        };
+        instructions.push(instruction);
+    }
+    Ok(instructions)
+}
+
+// The tests encode the bytecode from `Instruction`, which only the native build has
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+
+    fn to_bytes(instructions: &[Instruction]) -> Vec<u8> {
+        instructions.iter().flat_map(|i| i.to_bytes()).collect()
+    }
+
+    #[test]
+    fn test_decode_instructions() {
+        let code = to_bytes(&[
+            Instruction::Label("start".to_string()),
+            Instruction::Push(StackValue::String("Hello".to_string())),
+            Instruction::Push(StackValue::Int(42)),
+            Instruction::Sleep(100),
+            Instruction::StoreVar("key".to_string(), "value".to_string()),
+            Instruction::Jump("start".to_string()),
+        ]);
+        let program = decode(&code).unwrap();
+        let start = program.strings.lookup("start").unwrap();
+        let key = program.strings.lookup("key").unwrap();
+
+        assert_eq!(
+            program.instructions,
+            vec![
+                DecodedInstr::Label(start),
+                DecodedInstr::PushString("Hello".into()),
+                DecodedInstr::PushInt(42),
+                DecodedInstr::Sleep(100),
+                DecodedInstr::StoreVar(key, "value".into()),
+                DecodedInstr::Jump(start),
+            ]
+        );
+        assert_eq!(program.labels[start], Some(1));
+    }
+
+    #[test]
+    fn test_decode_interns_repeated_strings() {
+        let code = to_bytes(&[
+            Instruction::Push(StackValue::String("Main page".to_string())),
+            Instruction::Push(StackValue::String("Main page".to_string())),
+        ]);
+        let program = decode(&code).unwrap();
+        match (&program.instructions[0], &program.instructions[1]) {
+            (DecodedInstr::PushString(a), DecodedInstr::PushString(b)) => {
+                assert!(Arc::ptr_eq(a, b))
+            }
+            _ => panic!("Expected two PushString instructions"),
+        }
+    }
+
+    #[test]
+    fn test_decode_instructions_round_trip() {
+        let instructions = vec![
+            Instruction::Label("start".to_string()),
+            Instruction::Push(StackValue::String("Hello".to_string())),
+            Instruction::Push(StackValue::Int(42)),
+            Instruction::StoreVar("key".to_string(), "value".to_string()),
+            Instruction::Call("main_page".to_string()),
+            Instruction::Extension(0x80, "ADT^A01".to_string()),
+            Instruction::SleepJittered(100, 20),
+            Instruction::PrintMixed(95, 5),
+            Instruction::PushWeighted(vec![
+                ("timeout".to_string(), 80),
+                ("refused".to_string(), 20),
+            ]),
+            Instruction::Jump("start".to_string()),
+        ];
+        assert_eq!(
+            decode_instructions(&to_bytes(&instructions)).unwrap(),
+            instructions
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_instruction() {
+        let result = decode(&[STDOUT_CODE, 0xff]);
+        assert_eq!(result.err(), Some(DecodeError::InvalidInstruction(1, 0xff)));
+    }
+
+    #[test]
+    fn test_decode_truncated_operand() {
+        let mut code = Instruction::Jump("label".to_string()).to_bytes();
+        code.truncate(code.len() - 2);
+        let result = decode(&code);
+        assert_eq!(result.err(), Some(DecodeError::UnexpectedEnd(0)));
+    }
+}
@@ -0,0 +1,170 @@
+use opentelemetry::logs::{LogRecord, Logger, LoggerProvider, Severity};
+use opentelemetry::trace::{Span, Tracer, TracerProvider};
+use opentelemetry::{InstrumentationScope, KeyValue};
+use opentelemetry_sdk::metrics::data::{Metric, ResourceMetrics, ScopeMetrics, Sum, SumDataPoint};
+use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+use opentelemetry_sdk::metrics::Temporality;
+use opentelemetry_sdk::Resource;
+
+use crate::otel::{self, Endpoint, Protocol};
+use crate::vm;
+
+/// The service the test telemetry comes from, to find it in the backend
+const SERVICE_NAME: &str = "mustermann-doctor";
+
+/// Whether the endpoint took the test telemetry of one signal
+#[derive(Debug)]
+pub struct Report {
+    pub signal: &'static str,
+    pub result: Result<(), String>,
+}
+
+impl Report {
+    /// What to try if the export failed
+    pub fn hint(&self, endpoint: &Endpoint) -> Option<String> {
+        let error = self.result.as_ref().err()?.to_lowercase();
+        let hint = if error.contains("connection refused") || error.contains("dns error") {
+            format!(
+                "Nothing answers at {}. Is the collector running? OTLP takes gRPC on port 4317 and HTTP on 4318",
+                endpoint.url
+            )
+        } else if ["unauthenticated", "permission", "401", "403"]
+            .iter()
+            .any(|code| error.contains(code))
+        {
+            "The endpoint turned the credentials down. Pass the headers the backend asks for with --otel-header".to_string()
+        } else if ["404", "unimplemented", "415"]
+            .iter()
+            .any(|code| error.contains(code))
+        {
+            match endpoint.protocol {
+                Protocol::Grpc => "The endpoint does not take OTLP over gRPC. Try --otel-protocol http".to_string(),
+                Protocol::Http => format!(
+                    "The endpoint does not take {} at {}. Give the base URL without /v1/..., or try --otel-protocol grpc",
+                    self.signal,
+                    endpoint.signal_url(self.signal)
+                ),
+            }
+        } else if error.contains("certificate") {
+            "The certificate of the endpoint is not trusted. Pass the CA that signed it with --otel-ca-cert".to_string()
+        } else if error.contains("timeout") || error.contains("timed out") {
+            "The endpoint did not answer in time. Is a firewall or proxy in the way?".to_string()
+        } else if error.contains("invalid uri") || error.contains("invalid url") {
+            "The endpoint must be a URL with a scheme, e.g. http://localhost:4317".to_string()
+        } else if endpoint.protocol == Protocol::Grpc
+            && (error.contains("h2 protocol error") || error.contains("http2"))
+        {
+            "The endpoint does not speak gRPC. Try --otel-protocol http".to_string()
+        } else {
+            return None;
+        };
+        Some(hint)
+    }
+}
+
+/// Sends the test telemetry of one signal
+type SendFn = fn(&Endpoint) -> Result<(), String>;
+
+/// Sends a span, a metric and a log record to `endpoint` and waits until each was exported
+pub async fn diagnose(endpoint: &Endpoint) -> Vec<Report> {
+    // Flushing blocks until the export is done, while the gRPC exporters need the runtime to send
+    let signals: [(&'static str, SendFn); 3] = [
+        ("traces", send_span),
+        ("metrics", send_metric),
+        ("logs", send_log),
+    ];
+    let handles = signals.map(|(signal, send)| {
+        let endpoint = endpoint.clone();
+        (signal, tokio::task::spawn_blocking(move || send(&endpoint)))
+    });
+    let mut reports = Vec::new();
+    for (signal, handle) in handles {
+        let result = handle.await.unwrap_or_else(|e| Err(e.to_string()));
+        reports.push(Report { signal, result });
+    }
+    reports
+}
+
+fn send_span(endpoint: &Endpoint) -> Result<(), String> {
+    let provider = vm::setup_tracer(endpoint, SERVICE_NAME, None).map_err(|e| e.to_string())?;
+    provider.tracer("doctor").start("doctor").end();
+    provider.force_flush().map_err(|e| e.to_string())
+}
+
+// Exported without a meter provider, whose flush does not tell why an export failed
+fn send_metric(endpoint: &Endpoint) -> Result<(), String> {
+    let exporter = vm::metric_exporter(endpoint).map_err(|e| e.to_string())?;
+    let now = std::time::SystemTime::now();
+    let mut metrics = ResourceMetrics {
+        resource: Resource::builder()
+            .with_service_name(SERVICE_NAME.to_string())
+            .build(),
+        scope_metrics: vec![ScopeMetrics {
+            scope: InstrumentationScope::builder("doctor").build(),
+            metrics: vec![Metric {
+                name: "doctor_checks".into(),
+                description: "Test metric of mustermann doctor".into(),
+                unit: "".into(),
+                data: Box::new(Sum {
+                    data_points: vec![SumDataPoint {
+                        attributes: vec![KeyValue::new("check", "doctor")],
+                        value: 1u64,
+                        exemplars: Vec::new(),
+                    }],
+                    start_time: now,
+                    time: now,
+                    temporality: Temporality::Delta,
+                    is_monotonic: true,
+                }),
+            }],
+        }],
+    };
+    futures::executor::block_on(exporter.export(&mut metrics)).map_err(|e| e.to_string())
+}
+
+fn send_log(endpoint: &Endpoint) -> Result<(), String> {
+    let provider = otel::logger_provider(endpoint, SERVICE_NAME).map_err(|e| e.to_string())?;
+    let logger = provider.logger("doctor");
+    let mut record = logger.create_log_record();
+    record.set_severity_number(Severity::Info);
+    record.set_body("Test log record of mustermann doctor".into());
+    logger.emit(record);
+    provider.force_flush().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_diagnose_reports_every_signal_with_a_hint() {
+        // Nothing listens on port 1
+        let endpoint = Endpoint {
+            url: "http://127.0.0.1:1".to_string(),
+            protocol: Protocol::Http,
+            headers: Vec::new(),
+            tls: otel::Tls::default(),
+            zipkin: None,
+            metrics: otel::MetricsExport::default(),
+        };
+        let reports = diagnose(&endpoint).await;
+        let signals: Vec<&str> = reports.iter().map(|report| report.signal).collect();
+        assert_eq!(signals, ["traces", "metrics", "logs"]);
+        for report in &reports {
+            assert!(report.result.is_err(), "{:?}", report);
+            assert!(report
+                .hint(&endpoint)
+                .unwrap()
+                .starts_with("Nothing answers"));
+        }
+
+        let not_found = Report {
+            signal: "logs",
+            result: Err("Operation failed: HTTP export failed. Status Code: 404".to_string()),
+        };
+        assert_eq!(
+            not_found.hint(&endpoint).unwrap(),
+            "The endpoint does not take logs at http://127.0.0.1:1/v1/logs. Give the base URL without /v1/..., or try --otel-protocol grpc"
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How often to check whether a dry run settled
+const SETTLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Shared by the VMs of a dry run. Loops stop after a number of iterations, and the run is over
+/// once every loop stopped and every remote call got its reply
+#[derive(Debug)]
+pub struct DryRun {
+    iterations: usize,
+    busy: AtomicUsize,
+}
+
+impl DryRun {
+    pub fn new(iterations: usize) -> Self {
+        Self {
+            iterations,
+            busy: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+
+    /// A loop started or a remote call went out
+    pub fn start(&self) {
+        self.busy.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// A loop stopped or a remote call got its reply
+    pub fn finish(&self) {
+        self.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Waits until there are no loops and calls left. A callee makes its own calls before it
+    /// replies, so nothing starts once this returned
+    pub async fn settled(&self) {
+        while self.busy.load(Ordering::SeqCst) > 0 {
+            tokio::time::sleep(SETTLE_INTERVAL).await;
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Runs compiled services in the process of a host program, for [`crate::events`] and the C and
+//! Python bindings. The run is set up like one of the CLI, with the replicas, limits and windows
+//! of the services and the faults, policies and timeline of the scenario
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use tokio::sync::{mpsc, watch};
+
+use crate::artifact::CompiledService;
+use crate::events::{EventSink, RunError, RunEvent, RunEvents};
+use crate::load;
+use crate::parser::ServiceConfig;
+use crate::print_sink::PrintSink;
+use crate::recording::Decider;
+use crate::scenario::{self, Run, Scenario};
+use crate::vm;
+
+/// Runs the services for `duration` on a thread of their own, then shuts them down
+pub(crate) fn start(
+    scenario: Scenario,
+    services: Vec<CompiledService>,
+    duration: Duration,
+) -> Result<RunEvents, RunError> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(RunError::Runtime)?;
+    let (events_tx, events_rx) = mpsc::channel(1000);
+    std::thread::spawn(move || {
+        runtime.block_on(async {
+            if let Err(e) = run(&scenario, &services, duration, events_tx.clone()).await {
+                let error = RunEvent::Error {
+                    service: String::new(),
+                    error: e.to_string(),
+                };
+                let _ = events_tx.send(error).await;
+            }
+            let _ = events_tx.send(RunEvent::Finished).await;
+        })
+    });
+    Ok(RunEvents::new(events_rx))
+}
+
+async fn run(
+    scenario: &Scenario,
+    services: &[CompiledService],
+    duration: Duration,
+    events: mpsc::Sender<RunEvent>,
+) -> anyhow::Result<()> {
+    let coordinator = scenario
+        .coordinator(services, &Decider::Live)
+        .with_events(events.clone());
+    let handle = coordinator.handle();
+    let coordinator = tokio::spawn(coordinator.run());
+    let run = Run {
+        coordinator: handle.clone(),
+        decider: Decider::Live,
+        shutdown: Arc::new(AtomicBool::new(false)),
+        load: scenario.load_shaper(load::time_of_day(SystemTime::now())),
+        started: Instant::now(),
+        started_at: SystemTime::now(),
+    };
+
+    let mut tasks = Vec::new();
+    // Services start once all of them are registered, like in a run of the CLI
+    let (start_tx, start_rx) = watch::channel(false);
+    for service in services {
+        for replica in 0..service.config.replicas.unwrap_or(1) {
+            let instance = vm::instance_id(&service.name, replica);
+            let (mut vm, channels) = scenario
+                .vm_builder(service, &instance, &run, &ServiceConfig::default())
+                .with_incoming_calls(100)
+                // Metrics would otherwise go to the stdout of the host
+                .with_meter_provider(SdkMeterProvider::builder().build())
+                .build()?;
+            if let Some(incoming_call_tx) = channels.incoming_call_tx {
+                handle
+                    .register_service(&service.name, &instance, incoming_call_tx)
+                    .await?;
+            }
+            let sink = EventSink {
+                service: service.name.clone(),
+                instance: instance.clone(),
+                events: events.clone(),
+            };
+            let mut print_limiter = scenario::print_limiter(&service.config);
+            let mut print_rx = channels.print_rx;
+            tasks.push(tokio::spawn(async move {
+                while let Some(print) = print_rx.recv().await {
+                    if print_limiter.allow(Instant::now()) {
+                        let _ = sink.print(print).await;
+                    }
+                }
+            }));
+            let (service, handle, events) = (service.name.clone(), handle.clone(), events.clone());
+            let mut start_rx = start_rx.clone();
+            tasks.push(tokio::spawn(async move {
+                if start_rx.wait_for(|started| *started).await.is_err() {
+                    return;
+                }
+                let started = RunEvent::ServiceStarted {
+                    service: service.clone(),
+                    instance: instance.clone(),
+                };
+                let _ = events.send(started).await;
+                if let Err(e) = vm.run().await {
+                    let error = RunEvent::Error {
+                        service: service.clone(),
+                        error: e.to_string(),
+                    };
+                    let _ = events.send(error).await;
+                }
+                let _ = handle.deregister_service(&service, Some(&instance)).await;
+            }));
+        }
+    }
+
+    let _ = start_tx.send(true);
+    tokio::time::sleep(duration).await;
+    run.shutdown.store(true, Ordering::Relaxed);
+    for task in tasks {
+        let _ = task.await;
+    }
+    let _ = handle.shutdown().await;
+    drop(run);
+    drop(handle);
+    let _ = coordinator.await;
+    Ok(())
+}
@@ -0,0 +1,265 @@
+//! Runs a scenario in the background and reports what happens as a stream of [`RunEvent`]s, to
+//! observe a run without scraping its logs:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use futures::StreamExt;
+//! use mustermann::events::{run_with_events, RunEvent};
+//! use mustermann::parser;
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let program = parser::parse(
+//!     "service payments {
+//!         method charge { print \"Processing payment\"; sleep 10ms; }
+//!         loop { call charge; }
+//!     }",
+//! )
+//! .unwrap();
+//! let mut events = run_with_events(&program, Duration::from_millis(50)).unwrap();
+//! while let Some(event) = events.next().await {
+//!     if let RunEvent::LogEmitted { service, message, .. } = event {
+//!         println!("{}: {:?}", service, message);
+//!     }
+//! }
+//! # });
+//! ```
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream;
+use tokio::sync::mpsc;
+
+use crate::artifact;
+use crate::code_gen::error::CodeGenError;
+use crate::embedded;
+use crate::parser::Program;
+use crate::print_sink::{PrintSink, PrintSinkError};
+use crate::scenario::Scenario;
+use crate::vm::{Print, PrintMessage};
+
+/// Something that happened during a run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunEvent {
+    /// An instance of a service started to run its code
+    ServiceStarted { service: String, instance: String },
+    /// An instance printed a line
+    LogEmitted {
+        service: String,
+        instance: String,
+        message: PrintMessage,
+    },
+    /// The coordinator delivered a call to an instance
+    RemoteCall {
+        from: String,
+        to: String,
+        function: String,
+        instance: String,
+    },
+    /// A service stopped with an error, or called a service that does not exist
+    Error { service: String, error: String },
+    /// All services stopped, this is the last event
+    Finished,
+}
+
+/// The run did not start
+#[derive(Debug)]
+pub enum RunError {
+    /// A service does not compile
+    Compile(CodeGenError),
+    /// The tokio runtime for the services did not start
+    Runtime(std::io::Error),
+}
+
+impl std::error::Error for RunError {}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Compile(e) => write!(f, "{}", e),
+            RunError::Runtime(e) => write!(f, "Failed to start the runtime: {}", e),
+        }
+    }
+}
+
+/// The events of a run. It ends after [`RunEvent::Finished`]. Dropping it does not stop the run,
+/// but nothing waits for the events anymore
+pub struct RunEvents {
+    events: mpsc::Receiver<RunEvent>,
+}
+
+impl RunEvents {
+    pub(crate) fn new(events: mpsc::Receiver<RunEvent>) -> Self {
+        Self { events }
+    }
+
+    /// Waits for the next event on a thread outside of an async runtime
+    pub fn blocking_next(&mut self) -> Option<RunEvent> {
+        self.events.blocking_recv()
+    }
+}
+
+impl Stream for RunEvents {
+    type Item = RunEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<RunEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// Compiles the services and runs them for `duration` on threads of their own, then shuts them
+/// down. The run applies the config of the services, like their replicas and limits, and the
+/// injections, policies, chaos and timeline of the program. Services wait for the events to be
+/// taken, so a consumer that falls behind slows them down
+pub fn run_with_events(program: &Program, duration: Duration) -> Result<RunEvents, RunError> {
+    let services = artifact::compile(program).map_err(RunError::Compile)?;
+    embedded::start(Scenario::new(program), services, duration)
+}
+
+/// Turns the prints of an instance into events
+pub(crate) struct EventSink {
+    pub(crate) service: String,
+    pub(crate) instance: String,
+    pub(crate) events: mpsc::Sender<RunEvent>,
+}
+
+#[tonic::async_trait]
+impl PrintSink for EventSink {
+    async fn print(&self, print: Print) -> Result<(), PrintSinkError> {
+        self.events
+            .send(RunEvent::LogEmitted {
+                service: self.service.clone(),
+                instance: self.instance.clone(),
+                message: print.message,
+            })
+            .await
+            .map_err(|e| PrintSinkError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_run_reports_starts_logs_calls_and_finish() {
+        let program = parse(
+            r#"
+            service products {
+                method get_products {
+                    print "Fetching products";
+                }
+            }
+
+            service frontend {
+                method main_page {
+                    call products.get_products;
+                    call payments.charge;
+                    sleep 50ms;
+                }
+
+                loop {
+                    call main_page;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let events: Vec<RunEvent> = run_with_events(&program, Duration::from_millis(300))
+            .unwrap()
+            .collect()
+            .await;
+
+        assert!(events.contains(&RunEvent::ServiceStarted {
+            service: "frontend".to_string(),
+            instance: "frontend-0".to_string(),
+        }));
+        assert!(events.contains(&RunEvent::RemoteCall {
+            from: "frontend".to_string(),
+            to: "products".to_string(),
+            function: "get_products".to_string(),
+            instance: "products-0".to_string(),
+        }));
+        assert!(events.contains(&RunEvent::LogEmitted {
+            service: "products".to_string(),
+            instance: "products-0".to_string(),
+            message: PrintMessage::Stdout("Fetching products".to_string()),
+        }));
+        assert!(events.contains(&RunEvent::Error {
+            service: "frontend".to_string(),
+            error: "frontend called payments.charge, but there is no service payments".to_string(),
+        }));
+        assert_eq!(events.last(), Some(&RunEvent::Finished));
+    }
+
+    #[tokio::test]
+    async fn test_run_applies_the_config_of_the_services_and_the_injections() {
+        let program = parse(
+            r#"
+            service products {
+                config {
+                    replicas 2;
+                }
+
+                method get_products {
+                    print "Fetching products";
+                }
+            }
+
+            service frontend {
+                method main_page {
+                    call products.get_products;
+                    sleep 50ms;
+                }
+
+                loop {
+                    call main_page;
+                }
+            }
+
+            service reports {
+                config {
+                    start_after 1m;
+                }
+
+                method report {
+                    print "Daily report";
+                    sleep 10ms;
+                }
+
+                loop {
+                    call report;
+                }
+            }
+
+            inject faults frontend->products error 100%;
+            "#,
+        )
+        .unwrap();
+        let events: Vec<RunEvent> = run_with_events(&program, Duration::from_millis(300))
+            .unwrap()
+            .collect()
+            .await;
+
+        assert!(events.contains(&RunEvent::ServiceStarted {
+            service: "products".to_string(),
+            instance: "products-1".to_string(),
+        }));
+        assert!(events.contains(&RunEvent::LogEmitted {
+            service: "frontend".to_string(),
+            instance: "frontend-0".to_string(),
+            message: PrintMessage::Stderr(
+                "Call to products.get_products failed: Injected fault on frontend->products"
+                    .to_string()
+            ),
+        }));
+        assert!(!events.iter().any(|event| matches!(
+            event,
+            RunEvent::LogEmitted { service, .. } if service != "frontend"
+        )));
+    }
+}
@@ -0,0 +1,269 @@
+//! Instructions that embedders add to the VM, e.g. to emit messages of their own domain without
+//! forking the dispatch loop. An extension takes a code from [`FIRST_CODE`] to [`LAST_CODE`] and a
+//! name, which the DSL uses in statements like `ext hl7 "ADT^A01";`:
+//!
+//! ```
+//! # // Only the native build has the VM
+//! # #[cfg(feature = "native")]
+//! # fn main() {
+//! use mustermann::code_gen::{instruction::Instruction, CodeGenerator};
+//! use mustermann::extension::ExtensionRegistry;
+//! use mustermann::vm::Value;
+//!
+//! let mut extensions = ExtensionRegistry::new();
+//! extensions
+//!     .register_with_codegen(
+//!         0x80,
+//!         "hl7",
+//!         |cx| {
+//!             let message = format!("MSH|^~\\&|{}|||||{}", cx.service_name, cx.operand);
+//!             cx.push(Value::String(message.into()));
+//!             Ok(())
+//!         },
+//!         // Print the message the handler pushed
+//!         |instruction| vec![instruction, Instruction::Stdout],
+//!     )
+//!     .unwrap();
+//!
+//! let program = mustermann::parser::parse(
+//!     r#"service admissions { method admit { ext hl7 "ADT^A01"; } }"#,
+//! )
+//! .unwrap();
+//! let code = CodeGenerator::new(&program.services[0])
+//!     .with_extensions(&extensions)
+//!     .process()
+//!     .unwrap();
+//! // Build the VM for the code `with_extensions(extensions)`
+//! # }
+//! # #[cfg(not(feature = "native"))]
+//! # fn main() {}
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::code_gen::instruction::Instruction;
+use crate::value::Value;
+
+/// The first code reserved for extensions. Codes of mustermann's own instructions stay out of the range
+pub const FIRST_CODE: u8 = 0x80;
+pub const LAST_CODE: u8 = 0xbf;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtensionError {
+    /// The code is outside of the range reserved for extensions
+    ReservedCode(u8),
+    /// Another extension already has the code or the name
+    AlreadyRegistered(String),
+}
+
+impl std::error::Error for ExtensionError {}
+
+impl std::fmt::Display for ExtensionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtensionError::ReservedCode(code) => write!(
+                f,
+                "Code {:#04x} is not in the extension range {:#04x} to {:#04x}",
+                code, FIRST_CODE, LAST_CODE
+            ),
+            ExtensionError::AlreadyRegistered(name) => {
+                write!(f, "Extension {} is already registered", name)
+            }
+        }
+    }
+}
+
+/// What the handler of an extension sees of the VM
+pub struct ExtensionContext<'a> {
+    /// The operand of the statement, e.g. `ADT^A01` of `ext hl7 "ADT^A01";`
+    pub operand: &'a str,
+    pub service_name: &'a str,
+    stack: &'a mut Vec<Value>,
+}
+
+impl<'a> ExtensionContext<'a> {
+    pub(crate) fn new(operand: &'a str, service_name: &'a str, stack: &'a mut Vec<Value>) -> Self {
+        Self {
+            operand,
+            service_name,
+            stack,
+        }
+    }
+
+    /// Takes the top value off the stack of the current function
+    pub fn pop(&mut self) -> Option<Value> {
+        self.stack.pop()
+    }
+
+    /// Pushes a value for the following instructions, e.g. a message for `Stdout`
+    pub fn push(&mut self, value: Value) {
+        self.stack.push(value);
+    }
+}
+
+/// Runs an extension instruction. An error stops the VM
+pub type Handler = Arc<dyn Fn(&mut ExtensionContext<'_>) -> Result<(), String> + Send + Sync>;
+/// Turns the instruction of an extension statement into the instructions the statement compiles to
+pub type Codegen = Arc<dyn Fn(Instruction) -> Vec<Instruction> + Send + Sync>;
+
+#[derive(Clone)]
+struct Extension {
+    name: String,
+    handler: Handler,
+    codegen: Option<Codegen>,
+}
+
+/// The extensions a program is compiled and run with
+#[derive(Clone, Default)]
+pub struct ExtensionRegistry {
+    extensions: BTreeMap<u8, Extension>,
+}
+
+impl ExtensionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `handler` for instruction `code`, which `ext <name>` statements compile to
+    pub fn register(
+        &mut self,
+        code: u8,
+        name: &str,
+        handler: impl Fn(&mut ExtensionContext<'_>) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Result<(), ExtensionError> {
+        self.insert(code, name, Arc::new(handler), None)
+    }
+
+    /// Like `register`, but `ext <name>` statements compile to what `codegen` makes of the
+    /// instruction, e.g. the instruction followed by a print of what the handler pushed
+    pub fn register_with_codegen(
+        &mut self,
+        code: u8,
+        name: &str,
+        handler: impl Fn(&mut ExtensionContext<'_>) -> Result<(), String> + Send + Sync + 'static,
+        codegen: impl Fn(Instruction) -> Vec<Instruction> + Send + Sync + 'static,
+    ) -> Result<(), ExtensionError> {
+        self.insert(code, name, Arc::new(handler), Some(Arc::new(codegen)))
+    }
+
+    fn insert(
+        &mut self,
+        code: u8,
+        name: &str,
+        handler: Handler,
+        codegen: Option<Codegen>,
+    ) -> Result<(), ExtensionError> {
+        if !(FIRST_CODE..=LAST_CODE).contains(&code) {
+            return Err(ExtensionError::ReservedCode(code));
+        }
+        if self.extensions.contains_key(&code) || self.code(name).is_some() {
+            return Err(ExtensionError::AlreadyRegistered(name.to_string()));
+        }
+        self.extensions.insert(
+            code,
+            Extension {
+                name: name.to_string(),
+                handler,
+                codegen,
+            },
+        );
+        Ok(())
+    }
+
+    fn code(&self, name: &str) -> Option<u8> {
+        self.extensions
+            .iter()
+            .find(|(_, extension)| extension.name == name)
+            .map(|(code, _)| *code)
+    }
+
+    /// The instructions of an `ext <name>` statement, if the extension is registered
+    pub(crate) fn compile(&self, name: &str, operand: &str) -> Option<Vec<Instruction>> {
+        let code = self.code(name)?;
+        let instruction = Instruction::Extension(code, operand.to_string());
+        Some(match &self.extensions[&code].codegen {
+            Some(codegen) => codegen(instruction),
+            None => vec![instruction],
+        })
+    }
+
+    pub(crate) fn handler(&self, code: u8) -> Option<Handler> {
+        self.extensions
+            .get(&code)
+            .map(|extension| extension.handler.clone())
+    }
+}
+
+// The tests run the extensions on the VM
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::code_gen::CodeGenerator;
+    use crate::parser;
+    use crate::vm::{PrintMessage, VMError, VM};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_extensions_take_free_codes_of_the_reserved_range() {
+        let mut extensions = ExtensionRegistry::new();
+        assert_eq!(
+            extensions.register(0x07, "hl7", |_| Ok(())),
+            Err(ExtensionError::ReservedCode(0x07))
+        );
+        extensions.register(0x80, "hl7", |_| Ok(())).unwrap();
+        assert!(extensions.register(0x80, "fhir", |_| Ok(())).is_err());
+        assert!(extensions.register(0x81, "hl7", |_| Ok(())).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_vm_runs_extension_instructions() {
+        let mut extensions = ExtensionRegistry::new();
+        extensions
+            .register_with_codegen(
+                0x80,
+                "hl7",
+                |cx| {
+                    let message = format!("{} {}", cx.service_name, cx.operand);
+                    cx.push(Value::String(message.into()));
+                    Ok(())
+                },
+                |instruction| vec![instruction, Instruction::Stdout],
+            )
+            .unwrap();
+        extensions
+            .register(0x81, "reject", |_| Err("Rejected".to_string()))
+            .unwrap();
+        let ast = parser::parse(
+            r#"
+            service admissions {
+                method admit {
+                    ext hl7 "ADT^A01";
+                    ext reject;
+                }
+
+                loop {
+                    call admit;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        assert!(CodeGenerator::new(&ast.services[0]).process().is_err());
+        let code = CodeGenerator::new(&ast.services[0])
+            .with_extensions(&extensions)
+            .process()
+            .unwrap();
+
+        let (print_tx, mut print_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, "admissions", print_tx).with_extensions(extensions);
+        assert_eq!(
+            vm.run().await,
+            Err(VMError::ExtensionError("Rejected".to_string()))
+        );
+        assert_eq!(
+            print_rx.recv().await.unwrap().message,
+            PrintMessage::Stdout("admissions ADT^A01".to_string())
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! A C API to run scenarios inside programs that are not written in Rust, e.g. load testing tools.
+//! The declarations are in `include/mustermann.h`, regenerate it with
+//! `cbindgen --config cbindgen.toml --output include/mustermann.h` after changing this module.
+//!
+//! ```text
+//! MustermannProgram *program = mustermann_parse(source);
+//! if (program == NULL || mustermann_compile(program) != 0) {
+//!   fprintf(stderr, "%s\n", mustermann_last_error());
+//! }
+//! mustermann_run(program, 5000, on_log, NULL);
+//! mustermann_program_free(program);
+//! ```
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::time::Duration;
+
+use crate::artifact::{self, CompiledService};
+use crate::embedded;
+use crate::events::RunEvent;
+use crate::parser::{self, Program};
+use crate::scenario::Scenario;
+use crate::vm::PrintMessage;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: impl ToString) {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(c_string(error.to_string())));
+}
+
+// C strings end at the first NUL, so the ones inside a message are dropped
+fn c_string(s: String) -> CString {
+    CString::new(s.replace('\0', "")).expect("NUL bytes are removed")
+}
+
+/// A parsed scenario, and its code once it is compiled
+pub struct MustermannProgram {
+    program: Program,
+    services: Option<Vec<CompiledService>>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MustermannStream {
+    Stdout,
+    Stderr,
+}
+
+/// Called for every line a service prints, with the `user_data` passed to `mustermann_run`.
+/// The strings are only valid during the call
+pub type MustermannLogCallback = extern "C" fn(
+    user_data: *mut c_void,
+    service: *const c_char,
+    stream: MustermannStream,
+    message: *const c_char,
+);
+
+/// The message of the last error on this thread, or NULL. Valid until the next call that fails
+#[no_mangle]
+pub extern "C" fn mustermann_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |error| error.as_ptr())
+    })
+}
+
+/// Parses a scenario. Returns NULL if it does not parse
+///
+/// # Safety
+///
+/// `source` must be a NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn mustermann_parse(source: *const c_char) -> *mut MustermannProgram {
+    if source.is_null() {
+        set_last_error("Source is NULL");
+        return std::ptr::null_mut();
+    }
+    let source = match CStr::from_ptr(source).to_str() {
+        Ok(source) => source,
+        Err(e) => {
+            set_last_error(e);
+            return std::ptr::null_mut();
+        }
+    };
+    match parser::parse(source) {
+        Ok(program) => Box::into_raw(Box::new(MustermannProgram {
+            program,
+            services: None,
+        })),
+        Err(e) => {
+            set_last_error(e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Compiles every service of the program. Returns 0, or -1 if a service does not compile
+///
+/// # Safety
+///
+/// `program` must come from `mustermann_parse` and not be freed
+#[no_mangle]
+pub unsafe extern "C" fn mustermann_compile(program: *mut MustermannProgram) -> i32 {
+    let Some(program) = program.as_mut() else {
+        set_last_error("Program is NULL");
+        return -1;
+    };
+    match artifact::compile(&program.program) {
+        Ok(services) => {
+            program.services = Some(services);
+            0
+        }
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+/// Runs the compiled services for `duration_ms`, then shuts them down and waits until they
+/// stopped. Prints go to `callback`, on the calling thread. Returns 0, or -1 if the program is not
+/// compiled, a service failed or a service called one that does not exist
+///
+/// # Safety
+///
+/// `program` must come from `mustermann_parse` and not be freed. `callback` may be NULL
+#[no_mangle]
+pub unsafe extern "C" fn mustermann_run(
+    program: *const MustermannProgram,
+    duration_ms: u64,
+    callback: Option<MustermannLogCallback>,
+    user_data: *mut c_void,
+) -> i32 {
+    let Some((program, services)) = program.as_ref().and_then(|program| {
+        program
+            .services
+            .as_ref()
+            .map(|services| (&program.program, services))
+    }) else {
+        set_last_error("Program is not compiled");
+        return -1;
+    };
+    let mut events = match embedded::start(
+        Scenario::new(program),
+        services.clone(),
+        Duration::from_millis(duration_ms),
+    ) {
+        Ok(events) => events,
+        Err(e) => {
+            set_last_error(e);
+            return -1;
+        }
+    };
+    let mut result = 0;
+    while let Some(event) = events.blocking_next() {
+        match event {
+            RunEvent::LogEmitted {
+                service, message, ..
+            } => {
+                let (stream, message) = match message {
+                    PrintMessage::Stdout(message) => (MustermannStream::Stdout, message),
+                    PrintMessage::Stderr(message) => (MustermannStream::Stderr, message),
+                };
+                if let Some(log) = callback {
+                    let (service, message) = (c_string(service), c_string(message));
+                    log(user_data, service.as_ptr(), stream, message.as_ptr());
+                }
+            }
+            RunEvent::Error { error, .. } if result == 0 => {
+                set_last_error(error);
+                result = -1;
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Frees a program
+///
+/// # Safety
+///
+/// `program` must come from `mustermann_parse` or be NULL, and is invalid afterwards
+#[no_mangle]
+pub unsafe extern "C" fn mustermann_program_free(program: *mut MustermannProgram) {
+    if !program.is_null() {
+        drop(Box::from_raw(program));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn collect(
+        user_data: *mut c_void,
+        service: *const c_char,
+        stream: MustermannStream,
+        message: *const c_char,
+    ) {
+        let lines = unsafe { &mut *(user_data as *mut Vec<(String, MustermannStream, String)>) };
+        let (service, message) = unsafe { (CStr::from_ptr(service), CStr::from_ptr(message)) };
+        lines.push((
+            service.to_string_lossy().to_string(),
+            stream,
+            message.to_string_lossy().to_string(),
+        ));
+    }
+
+    #[test]
+    fn test_runs_a_scenario_through_the_c_api() {
+        let source = CString::new(
+            r#"
+            service products {
+                method get_products {
+                    stderr "Out of stock";
+                }
+            }
+
+            service frontend {
+                method main_page {
+                    print "Loading main page";
+                    call products.get_products;
+                    sleep 50ms;
+                }
+
+                loop {
+                    call main_page;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut lines: Vec<(String, MustermannStream, String)> = Vec::new();
+        unsafe {
+            let program = mustermann_parse(source.as_ptr());
+            assert_eq!(
+                mustermann_run(program, 10, Some(collect), std::ptr::null_mut()),
+                -1
+            );
+            assert_eq!(mustermann_compile(program), 0);
+            let user_data = &mut lines as *mut _ as *mut c_void;
+            assert_eq!(mustermann_run(program, 300, Some(collect), user_data), 0);
+            mustermann_program_free(program);
+        }
+        assert!(lines.contains(&(
+            "frontend".to_string(),
+            MustermannStream::Stdout,
+            "Loading main page".to_string()
+        )));
+        assert!(lines.contains(&(
+            "products".to_string(),
+            MustermannStream::Stderr,
+            "Out of stock".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_parse_errors_are_reported() {
+        let source = CString::new("service {").unwrap();
+        unsafe {
+            assert!(mustermann_parse(source.as_ptr()).is_null());
+            assert!(!mustermann_last_error().is_null());
+        }
+    }
+}
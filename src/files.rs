@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::artifact::{self, CompiledService};
+use crate::parser::{self, ParseError, Program};
+use crate::variables::Variables;
+
+/// The file path that reads the file from stdin instead
+pub const STDIN_PATH: &str = "-";
+/// The config files a directory is searched for
+const CONFIG_EXTENSIONS: &[&str] = &["muster", "mm", "mstr"];
+/// Config files with the AST as YAML, TOML or JSON, in the shape `mustermann ast` prints.
+/// Directories are not searched for them, as projects keep the configs of their collectors next
+/// to the scenarios
+const YAML_EXTENSIONS: &[&str] = &["yaml", "yml"];
+const TOML_EXTENSION: &str = "toml";
+const JSON_EXTENSION: &str = "json";
+/// The compiled files a directory is searched for with `exec`
+const COMPILED_EXTENSION: &str = "mbc";
+
+/// A file given on the command line, read once
+pub struct SourceFile {
+    pub path: String,
+    pub content: Vec<u8>,
+}
+
+/// Replaces every directory with the config files in it, or the compiled files if `compiled`, by name
+pub fn expand(paths: &[String], compiled: bool) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path == STDIN_PATH || !Path::new(path).is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?.path();
+            let extension = entry
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default();
+            let matches = if compiled {
+                extension == COMPILED_EXTENSION
+            } else {
+                CONFIG_EXTENSIONS.contains(&extension)
+            };
+            if matches && entry.is_file() {
+                entries.push(entry.to_string_lossy().into_owned());
+            }
+        }
+        if entries.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no files to run", path),
+            ));
+        }
+        entries.sort();
+        files.extend(entries);
+    }
+    Ok(files)
+}
+
+/// Reads the file, or stdin for `-`
+pub fn read(path: &str) -> io::Result<Vec<u8>> {
+    if path == STDIN_PATH {
+        let mut content = Vec::new();
+        io::stdin().read_to_end(&mut content)?;
+        Ok(content)
+    } else {
+        fs::read(path)
+    }
+}
+
+/// Reads the files, after expanding the directories among them
+pub fn read_all(paths: &[String], compiled: bool) -> anyhow::Result<Vec<SourceFile>> {
+    expand(paths, compiled)?
+        .into_iter()
+        .map(|path| {
+            let content = read(&path).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+            Ok(SourceFile { path, content })
+        })
+        .collect()
+}
+
+/// Parses the config files as one program, after replacing the `${NAME}` variables in them.
+/// YAML, TOML and JSON files are read as the AST. A service may be defined in one of them only
+pub fn parse(files: &[SourceFile], variables: &Variables) -> anyhow::Result<Program> {
+    let mut program = Program::default();
+    let mut defined_in = HashMap::new();
+    for file in files {
+        let content = std::str::from_utf8(&file.content)
+            .map_err(|e| anyhow::anyhow!("{}: {}", file.path, e))?;
+        let content = variables
+            .interpolate(content)
+            .map_err(|e| anyhow::anyhow!("{}: {}", file.path, e))?;
+        let file_program = match ast_format(&file.path) {
+            Some(format) => {
+                from_ast(format, &content).map_err(|e| anyhow::anyhow!("{}: {}", file.path, e))?
+            }
+            None => parser::parse(&content).map_err(|e| match e {
+                ParseError::PestError(e) => anyhow::anyhow!("{}", e.with_path(&file.path)),
+                e => anyhow::anyhow!("{}: {}", file.path, e),
+            })?,
+        };
+        for service in &file_program.services {
+            defined_once(&mut defined_in, &service.name, &file.path)?;
+        }
+        program.extend(file_program);
+    }
+    Ok(program)
+}
+
+/// The formats a config file with the AST can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AstFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+// serde_yaml expects tags for enums, so every format goes through JSON to have the shape of the
+// JSON AST. Errors name the field, e.g. `services[0].methods[1].statements[0].sleep: missing field ...`
+fn from_ast(format: AstFormat, content: &str) -> anyhow::Result<Program> {
+    let value: serde_json::Value = match format {
+        AstFormat::Yaml => serde_yaml::from_str(content)?,
+        AstFormat::Toml => toml::from_str(content)?,
+        AstFormat::Json => serde_json::from_str(content)?,
+    };
+    serde_path_to_error::deserialize(value)
+        .map_err(|e| anyhow::anyhow!("{}: {}", e.path(), e.inner()))
+}
+
+fn ast_format(path: &str) -> Option<AstFormat> {
+    let extension = Path::new(path).extension().and_then(|e| e.to_str())?;
+    match extension {
+        TOML_EXTENSION => Some(AstFormat::Toml),
+        JSON_EXTENSION => Some(AstFormat::Json),
+        _ if YAML_EXTENSIONS.contains(&extension) => Some(AstFormat::Yaml),
+        _ => None,
+    }
+}
+
+/// Reads the services of compiled files. A service may be in one of them only
+pub fn decode(files: &[SourceFile]) -> anyhow::Result<Vec<CompiledService>> {
+    let mut services = Vec::new();
+    let mut defined_in = HashMap::new();
+    for file in files {
+        let file_services = artifact::from_bytes(&file.content)
+            .map_err(|e| anyhow::anyhow!("{}: {}", file.path, e))?;
+        for service in &file_services {
+            defined_once(&mut defined_in, &service.name, &file.path)?;
+        }
+        services.extend(file_services);
+    }
+    Ok(services)
+}
+
+// Twice in the same file is left to `check`, as before files could be merged
+fn defined_once<'a>(
+    defined_in: &mut HashMap<String, &'a str>,
+    service: &str,
+    path: &'a str,
+) -> anyhow::Result<()> {
+    match defined_in.insert(service.to_string(), path) {
+        Some(other) if other != path => {
+            anyhow::bail!("Service {} is defined in {} and {}", service, other, path)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files_of_a_directory_run_as_one_program() {
+        let dir = std::env::temp_dir().join(format!("mustermann-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("products.muster"),
+            "service products { method get_products { print \"Fetching products\"; } }",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("frontend.mm"),
+            "service frontend { method main_page { call products.get_products; } loop { call main_page; } }
+            inject latency frontend->products 100ms;",
+        )
+        .unwrap();
+        fs::write(dir.join("README.md"), "One file per service").unwrap();
+
+        let paths = [dir.to_string_lossy().into_owned()];
+        let files = read_all(&paths, false).unwrap();
+        assert_eq!(files.len(), 2);
+        let program = parse(&files, &Variables::default()).unwrap();
+        let services: Vec<&str> = program.services.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(services, ["frontend", "products"]);
+        assert_eq!(program.injections.len(), 1);
+
+        let copy = dir.join("copy.mstr");
+        fs::copy(dir.join("products.muster"), &copy).unwrap();
+        let error = parse(&read_all(&paths, false).unwrap(), &Variables::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Service products is defined in {} and {}",
+                copy.display(),
+                dir.join("products.muster").display()
+            )
+        );
+        assert!(read_all(&paths, true).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_yaml_files_lower_to_the_same_program() {
+        let source = r#"
+        service products {
+            method get_products {
+                print "Fetching products";
+                sleep 100ms;
+            }
+        }
+
+        service frontend {
+            method main_page {
+                call products.get_products;
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+        inject latency frontend->products 100ms;
+        "#;
+        let yaml = r#"
+services:
+  - name: products
+    methods:
+      - name: get_products
+        statements:
+          - stdout: { message: "Fetching products" }
+          - sleep: { duration_ms: 100 }
+  - name: frontend
+    methods:
+      - name: main_page
+        statements:
+          - call: { service: products, method: get_products }
+    loops:
+      - statements:
+          - call: { method: main_page }
+injections:
+  - latency:
+      route: { from: frontend, to: products }
+      delay_ms: 100
+"#;
+        let files = [SourceFile {
+            path: "shop.yaml".to_string(),
+            content: yaml.as_bytes().to_vec(),
+        }];
+        let program = parse(&files, &Variables::default()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&program).unwrap(),
+            serde_json::to_value(parser::parse(source).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_toml_and_json_files_lower_to_the_same_program() {
+        let source = r#"
+        service products {
+            method get_products {
+                print "Fetching products";
+                sleep 100ms;
+            }
+
+            loop {
+                call get_products;
+            }
+        }
+        inject latency frontend->products 100ms;
+        "#;
+        let toml = r#"
+[[services]]
+name = "products"
+
+[[services.methods]]
+name = "get_products"
+statements = [
+    { stdout = { message = "Fetching products" } },
+    { sleep = { duration_ms = 100 } },
+]
+
+[[services.loops]]
+statements = [{ call = { method = "get_products" } }]
+
+[[injections]]
+latency = { route = { from = "frontend", to = "products" }, delay_ms = 100 }
+"#;
+        let expected = serde_json::to_value(parser::parse(source).unwrap()).unwrap();
+        for (path, content) in [
+            ("shop.toml", toml.to_string()),
+            ("shop.json", expected.to_string()),
+        ] {
+            let files = [SourceFile {
+                path: path.to_string(),
+                content: content.into_bytes(),
+            }];
+            let program = parse(&files, &Variables::default()).unwrap();
+            assert_eq!(
+                serde_json::to_value(&program).unwrap(),
+                expected,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_yaml_errors_name_the_field() {
+        let yaml = r#"
+services:
+  - name: products
+    methods:
+      - name: get_products
+        statements:
+          - sleep: { duration: 100 }
+"#;
+        let files = [SourceFile {
+            path: "shop.yaml".to_string(),
+            content: yaml.as_bytes().to_vec(),
+        }];
+        let error = parse(&files, &Variables::default())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.starts_with(
+                "shop.yaml: services[0].methods[0].statements[0].sleep: missing field `duration_ms`"
+            ),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,296 @@
+//! Golden files of `mustermann golden`: what the services of a scenario print when it runs with
+//! a fixed seed, to notice when an upgrade of mustermann changes what a scenario prints. The
+//! scenario runs on the VMs and the coordinator of a real run, with chaos, incidents, the timeline
+//! and the load profile, but on a paused clock: time only passes while every service waits, so
+//! the run takes the same virtual time on every machine.
+//!
+//! ```text
+//! [frontend]
+//! 0ms stdout Rendering main page
+//! [products]
+//! 10ms stderr Out of stock
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use futures::future::join_all;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use crate::artifact;
+use crate::parser::{Program, ServiceConfig};
+use crate::recording::Decider;
+use crate::scenario::{self, Run, Scenario};
+use crate::vm::{self, PrintMessage};
+
+/// When a golden run starts since the epoch, for the timestamps of access logs: the midnight
+/// the traffic pattern starts at, 2025-01-01 in UTC
+const MIDNIGHT: Duration = Duration::from_secs(1_735_689_600);
+
+/// A message a service printed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub service: String,
+    pub message: PrintMessage,
+    /// The virtual time since the start of the run
+    pub at: Duration,
+}
+
+/// Runs the program for `duration` of virtual time, then shuts the services down, and collects
+/// what they print. Every VM and the coordinator draw their decisions from `seed`. The run starts
+/// at midnight, for the traffic pattern
+pub fn capture(program: &Program, duration: Duration, seed: u64) -> anyhow::Result<Vec<Line>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()?;
+    runtime.block_on(run(program, duration, seed))
+}
+
+async fn run(program: &Program, duration: Duration, seed: u64) -> anyhow::Result<Vec<Line>> {
+    let services = artifact::compile(program)?;
+    let scenario = Scenario::new(program);
+    let decider = Decider::Seeded(seed);
+    let coordinator = scenario.coordinator(&services, &decider);
+    let coordinator_handle = coordinator.handle();
+    let coordinator_task = tokio::spawn(coordinator.run());
+    let run = Run {
+        coordinator: coordinator_handle.clone(),
+        decider,
+        shutdown: Arc::new(AtomicBool::new(false)),
+        load: scenario.load_shaper(Duration::ZERO),
+        started: Instant::now().into_std(),
+        started_at: SystemTime::UNIX_EPOCH + MIDNIGHT,
+    };
+    let started = Instant::now();
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let mut handles = Vec::new();
+    // Services start once all of them are registered, like in a real run
+    let (start_tx, start_rx) = watch::channel(false);
+    for service in &services {
+        for replica in 0..service.config.replicas.unwrap_or(1) {
+            let instance = vm::instance_id(&service.name, replica);
+            let builder = scenario
+                .vm_builder(service, &instance, &run, &ServiceConfig::default())
+                .with_incoming_calls(1)
+                // Exports nothing, a golden run only keeps what the services print
+                .with_meter_provider(SdkMeterProvider::builder().build());
+            let (mut vm, channels) = builder.build()?;
+            if let Some(incoming_call_tx) = channels.incoming_call_tx {
+                coordinator_handle
+                    .register_service(&service.name, &instance, incoming_call_tx)
+                    .await?;
+            }
+            let (service_name, lines) = (service.name.clone(), lines.clone());
+            let mut print_rx = channels.print_rx;
+            let mut print_limiter = scenario::print_limiter(&service.config);
+            // The clock stands still until the print is taken, so it is timed when it was printed
+            handles.push(tokio::spawn(async move {
+                while let Some(print) = print_rx.recv().await {
+                    if !print_limiter.allow(Instant::now().into_std()) {
+                        continue;
+                    }
+                    lines.lock().unwrap().push(Line {
+                        service: service_name.clone(),
+                        message: print.message,
+                        at: started.elapsed(),
+                    });
+                }
+            }));
+            let (service_name, coordinator_handle) =
+                (service.name.clone(), coordinator_handle.clone());
+            let mut start_rx = start_rx.clone();
+            handles.push(tokio::spawn(async move {
+                if start_rx.wait_for(|started| *started).await.is_err() {
+                    return;
+                }
+                // A service that fails stops, the others keep running like in a real run
+                if let Err(e) = vm.run().await {
+                    tracing::debug!(app_name = %service_name, "Service stopped: {}", e);
+                }
+                let _ = coordinator_handle
+                    .deregister_service(&service_name, Some(&instance))
+                    .await;
+            }));
+        }
+    }
+    start_tx.send(true)?;
+    tokio::time::sleep(duration).await;
+    run.shutdown.store(true, Ordering::SeqCst);
+    join_all(handles).await;
+    coordinator_handle.shutdown().await?;
+    coordinator_task.await?;
+    let lines = std::mem::take(&mut *lines.lock().unwrap());
+    Ok(lines)
+}
+
+/// The lines grouped by service, in the order every service printed them
+pub fn render(lines: &[Line]) -> String {
+    let mut services: BTreeMap<&str, Vec<&Line>> = BTreeMap::new();
+    for line in lines {
+        services.entry(&line.service).or_default().push(line);
+    }
+    let mut golden = String::new();
+    for (service, lines) in services {
+        writeln!(golden, "[{}]", service).unwrap();
+        for line in lines {
+            let (stream, message) = match &line.message {
+                PrintMessage::Stdout(message) => ("stdout", message),
+                PrintMessage::Stderr(message) => ("stderr", message),
+            };
+            // Newlines in messages would split the line
+            let message = message.replace('\n', "\\n");
+            writeln!(golden, "{}ms {} {}", line.at.as_millis(), stream, message).unwrap();
+        }
+    }
+    golden
+}
+
+/// The first line that differs, `None` if the output matches the golden file
+pub fn compare(golden: &str, actual: &str) -> Option<String> {
+    let mut expected_lines = golden.lines();
+    let mut actual_lines = actual.lines();
+    let mut number = 0;
+    loop {
+        number += 1;
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => return None,
+            (Some(expected), Some(actual)) if expected == actual => continue,
+            (Some(expected), Some(actual)) => {
+                return Some(format!(
+                    "line {}: expected `{}`, got `{}`",
+                    number, expected, actual
+                ))
+            }
+            (Some(expected), None) => {
+                return Some(format!(
+                    "line {}: expected `{}`, the output ended",
+                    number, expected
+                ))
+            }
+            (None, Some(actual)) => {
+                return Some(format!(
+                    "line {}: expected the end, got `{}`",
+                    number, actual
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn test_golden_output_groups_the_services() {
+        let program = parser::parse(
+            r#"
+            service products {
+                method get_products {
+                    print "Fetching products";
+                    stderr "Out of stock";
+                }
+            }
+            service frontend {
+                method main_page {
+                    print "Rendering main page";
+                    call products.get_products;
+                    sleep 1s;
+                }
+                loop {
+                    call main_page;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let golden = render(&capture(&program, Duration::from_millis(1500), 0).unwrap());
+
+        assert_eq!(
+            golden,
+            "[frontend]\n0ms stdout Rendering main page\n1000ms stdout Rendering main page\n\
+             [products]\n0ms stdout Fetching products\n0ms stderr Out of stock\n\
+             1000ms stdout Fetching products\n1000ms stderr Out of stock\n"
+        );
+        assert_eq!(compare(&golden, &golden), None);
+        assert_eq!(
+            compare(&golden, "[frontend]\n10ms stdout Rendering main page\n"),
+            Some(
+                "line 2: expected `0ms stdout Rendering main page`, got `10ms stdout Rendering main page`"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            compare(&golden, "[frontend]\n0ms stdout Rendering main page\n"),
+            Some(
+                "line 3: expected `1000ms stdout Rendering main page`, the output ended"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_runs_with_the_same_seed_print_the_same() {
+        let program = parser::parse(
+            r#"
+            service products {
+                method get_products {
+                    print "Fetching %s" with ["shoes": 1, "hats": 1, "socks": 1];
+                }
+            }
+            service frontend {
+                method main_page {
+                    call products.get_products;
+                    sleep 100ms;
+                }
+                loop {
+                    call main_page;
+                }
+            }
+
+            at 2s kill products;
+            at 3s restart products;
+            incident at 4s: products error_rate 50%->50% over 1s;
+            "#,
+        )
+        .unwrap();
+        let golden = render(&capture(&program, Duration::from_secs(5), 7).unwrap());
+
+        assert_eq!(
+            golden,
+            render(&capture(&program, Duration::from_secs(5), 7).unwrap())
+        );
+        assert_ne!(
+            golden,
+            render(&capture(&program, Duration::from_secs(5), 8).unwrap())
+        );
+        assert!(golden.contains(
+            "2000ms stderr Call to products.get_products failed: Service unavailable: products"
+        ));
+        assert!(golden.contains(
+            "4100ms stderr Call to products.get_products failed: Injected fault on frontend->products"
+        ));
+    }
+
+    #[test]
+    fn test_access_logs_are_timestamped_in_virtual_time() {
+        let program = parser::parse(
+            "service edge { method hit { access_log alb; sleep 250ms; } loop { call hit; } }",
+        )
+        .unwrap();
+        let golden = render(&capture(&program, Duration::from_secs(1), 0).unwrap());
+
+        assert_eq!(
+            golden,
+            render(&capture(&program, Duration::from_secs(1), 0).unwrap())
+        );
+        assert!(golden.contains("250ms stdout https 2025-01-01T00:00:00.250000Z "));
+    }
+}
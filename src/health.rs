@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use tokio::net::TcpListener;
+
+/// The function name of health checks. Identifiers in the DSL start with a letter, so no function
+/// of a service has this name.
+pub const HEALTH_CHECK: &str = "_health";
+
+/// How many health checks in a row an instance has to miss before it counts as unhealthy
+const FAILURES_BEFORE_UNHEALTHY: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    Unhealthy,
+}
+
+/// The outcome of the recent health checks of an instance
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstanceHealth {
+    pub service: String,
+    pub instance: String,
+    pub status: HealthStatus,
+    /// How long the instance took to answer the last health check it answered
+    pub latency_ms: Option<f64>,
+    /// The health checks the instance missed since it last answered one
+    pub consecutive_failures: usize,
+    /// Why the last health check failed, if it did
+    pub last_error: Option<String>,
+    /// Set while a health check waits for an answer, so a wedged instance does not pile them up
+    #[serde(skip)]
+    pending: bool,
+}
+
+/// The health of every running instance, as of its last health checks
+#[derive(Debug, Default)]
+pub struct HealthReport {
+    instances: BTreeMap<(String, String), InstanceHealth>,
+}
+
+impl HealthReport {
+    /// Marks a health check of the instance as sent. Returns false if the previous one is still
+    /// waiting for an answer, in which case no new check is sent.
+    pub fn start_check(&mut self, service: &str, instance: &str) -> bool {
+        let health = self
+            .instances
+            .entry((service.to_string(), instance.to_string()))
+            .or_insert_with(|| InstanceHealth {
+                service: service.to_string(),
+                instance: instance.to_string(),
+                status: HealthStatus::Healthy,
+                latency_ms: None,
+                consecutive_failures: 0,
+                last_error: None,
+                pending: false,
+            });
+        !std::mem::replace(&mut health.pending, true)
+    }
+
+    /// Records how a health check went. Returns the new status if it changed.
+    pub fn record(
+        &mut self,
+        service: &str,
+        instance: &str,
+        outcome: Result<Duration, String>,
+    ) -> Option<HealthStatus> {
+        let health = self
+            .instances
+            .get_mut(&(service.to_string(), instance.to_string()))?;
+        health.pending = false;
+        let previous = health.status;
+        match outcome {
+            Ok(latency) => {
+                health.latency_ms = Some(latency.as_secs_f64() * 1000.0);
+                health.consecutive_failures = 0;
+                health.last_error = None;
+                health.status = HealthStatus::Healthy;
+            }
+            Err(error) => {
+                health.consecutive_failures += 1;
+                health.last_error = Some(error);
+                if health.consecutive_failures >= FAILURES_BEFORE_UNHEALTHY {
+                    health.status = HealthStatus::Unhealthy;
+                }
+            }
+        }
+        (health.status != previous).then_some(health.status)
+    }
+
+    pub fn status(&self, service: &str, instance: &str) -> Option<HealthStatus> {
+        self.instances
+            .get(&(service.to_string(), instance.to_string()))
+            .map(|health| health.status)
+    }
+
+    /// Forgets a stopped instance, or every instance of the service if `instance` is `None`
+    pub fn remove(&mut self, service: &str, instance: Option<&str>) {
+        self.instances
+            .retain(|(s, i), _| s != service || instance.is_some_and(|instance| instance != i));
+    }
+
+    /// Every instance, ordered by service and instance
+    pub fn instances(&self) -> Vec<InstanceHealth> {
+        self.instances.values().cloned().collect()
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.instances
+            .values()
+            .all(|health| health.status == HealthStatus::Healthy)
+    }
+}
+
+/// Serves the health of the instances as JSON on `GET /health` until `shutdown` completes.
+/// Answers with 503 while an instance is unhealthy, so scripts can wait for a healthy simulation.
+pub async fn serve(
+    listener: TcpListener,
+    report: Arc<Mutex<HealthReport>>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    axum::serve(listener, router(report))
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+pub fn router(report: Arc<Mutex<HealthReport>>) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .with_state(report)
+}
+
+async fn health(
+    State(report): State<Arc<Mutex<HealthReport>>>,
+) -> (StatusCode, Json<Vec<InstanceHealth>>) {
+    let report = report.lock().unwrap();
+    let status = if report.is_healthy() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report.instances()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_is_unhealthy_after_missed_checks() {
+        let mut report = HealthReport::default();
+        assert!(report.start_check("products", "products-0"));
+        assert!(!report.start_check("products", "products-0"));
+        assert_eq!(
+            report.record("products", "products-0", Ok(Duration::from_millis(2))),
+            None
+        );
+        for _ in 1..FAILURES_BEFORE_UNHEALTHY {
+            report.start_check("products", "products-0");
+            let status = report.record("products", "products-0", Err("Timed out".to_string()));
+            assert_eq!(status, None);
+        }
+        report.start_check("products", "products-0");
+        assert_eq!(
+            report.record("products", "products-0", Err("Timed out".to_string())),
+            Some(HealthStatus::Unhealthy)
+        );
+        assert!(!report.is_healthy());
+        let instances = report.instances();
+        assert_eq!(instances[0].consecutive_failures, FAILURES_BEFORE_UNHEALTHY);
+        assert_eq!(instances[0].latency_ms, Some(2.0));
+
+        report.start_check("products", "products-0");
+        assert_eq!(
+            report.record("products", "products-0", Ok(Duration::from_millis(1))),
+            Some(HealthStatus::Healthy)
+        );
+        report.remove("products", None);
+        assert!(report.instances().is_empty());
+    }
+}
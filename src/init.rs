@@ -0,0 +1,62 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The files of a new project: an example scenario, a collector with Jaeger, and a script to run both
+const FILES: &[(&str, &str)] = &[
+    (
+        "scenario.muster",
+        include_str!("../templates/init/scenario.muster"),
+    ),
+    (
+        "docker-compose.yaml",
+        include_str!("../templates/init/docker-compose.yaml"),
+    ),
+    (
+        "otel-config.yaml",
+        include_str!("../templates/init/otel-config.yaml"),
+    ),
+    ("run.sh", include_str!("../templates/init/run.sh")),
+];
+
+/// Creates a project in `dir`, which must not exist or be empty. Returns the paths of the files.
+pub fn scaffold(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists and is not empty", dir.display()),
+        ));
+    }
+    fs::create_dir_all(dir)?;
+    let mut paths = Vec::new();
+    for (name, content) in FILES {
+        let path = dir.join(name);
+        fs::write(&path, content)?;
+        paths.push(path);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir.join("run.sh"), fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{check, parser};
+
+    #[test]
+    fn test_scaffold_creates_a_valid_scenario() {
+        let dir = std::env::temp_dir().join(format!("mustermann-init-{}", std::process::id()));
+        let paths = scaffold(&dir).unwrap();
+        assert_eq!(paths.len(), FILES.len());
+
+        let scenario = fs::read_to_string(dir.join("scenario.muster")).unwrap();
+        let program = parser::parse(&scenario).unwrap();
+        assert_eq!(program.services.len(), 2);
+        assert!(check::check(&program).is_empty());
+        assert!(scaffold(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
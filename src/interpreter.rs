@@ -0,0 +1,382 @@
+//! The semantics of the instructions, shared by the [`crate::vm::VM`] and the
+//! [`crate::simulation`]: the stack, variables, jumps, local calls, baggage, weighted values and
+//! print templates. What an instruction does to the world outside of the service, like printing,
+//! sleeping or calling another service, is up to the executor, which gets it as a [`Step`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::decoder::{pick_weighted, DecodedInstr, DecodedProgram};
+use crate::extension::{ExtensionContext, ExtensionRegistry};
+use crate::parser::AccessLogFormat;
+use crate::printf::{PrintfError, Template};
+use crate::string_table::{StringTable, Symbol};
+use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterpreterError {
+    StackUnderflow,
+    InvalidStackValue,
+    MissingVar(String),
+    MissingLabel(String),
+    MissingStackFrame,
+    Printf(PrintfError),
+    /// No handler is registered for the extension code
+    UnknownExtension(u8),
+    /// The handler of an extension failed
+    ExtensionError(String),
+    /// The weights of a `PushWeighted` add up to 0, so there is no value to pick
+    ZeroTotalWeight,
+}
+
+impl From<PrintfError> for InterpreterError {
+    fn from(e: PrintfError) -> Self {
+        InterpreterError::Printf(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// What the executor lends the interpreter to run an instruction
+pub trait Host {
+    type Error: From<InterpreterError>;
+
+    /// A random number in `range`, which must not be empty
+    fn random_range(&mut self, range: Range<u64>) -> Result<u64, Self::Error>;
+
+    /// A random number in `0.0..1.0`
+    fn random_f64(&mut self) -> Result<f64, Self::Error>;
+
+    /// The time since the start of the run, that the generators of print templates drift from
+    fn elapsed(&self) -> Duration;
+
+    fn service_name(&self) -> &str;
+
+    fn extensions(&self) -> &ExtensionRegistry;
+}
+
+/// What is left to do for the executor after an instruction ran
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// Nothing, the instruction only changed the state of the service
+    Continue,
+    /// A jump went back, e.g. to the start of the main loop
+    JumpedBack,
+    Print(Stream, String),
+    /// Sleep for the duration, with the jitter already rolled
+    Sleep(Duration),
+    RemoteCall {
+        service: String,
+        method: String,
+    },
+    /// Push a line of the access log
+    AccessLog(AccessLogFormat),
+    StartContext,
+    EndContext,
+    /// Take an incoming call, or wait for one. The instruction pointer stays on the instruction,
+    /// so the service checks again once a call returns
+    CheckInterrupt,
+    /// Call the local function at the label with [`Interpreter::call`]. The instruction pointer
+    /// stays on the instruction until then
+    Call(Symbol),
+    /// The current function returned to its caller
+    Returned,
+}
+
+/// The state of a service while it runs its code
+pub struct Interpreter {
+    pub instructions: Vec<DecodedInstr>,
+    pub strings: StringTable,
+    pub labels: Vec<Option<usize>>,
+    /// The print templates of the code, parsed when it was decoded
+    pub templates: HashMap<Arc<str>, Arc<Template>>,
+    pub stack: Vec<Vec<Value>>,
+    pub vars: HashMap<Symbol, Value>,
+    pub ip: usize,
+    pub return_addresses: Vec<usize>,
+    /// The baggage of the caller of every active call, restored on return
+    caller_baggage: Vec<BTreeMap<String, String>>,
+    /// Sent along with remote calls, and read by `%{key}` in print templates
+    pub baggage: BTreeMap<String, String>,
+}
+
+impl Interpreter {
+    pub fn new(program: DecodedProgram) -> Self {
+        let DecodedProgram {
+            instructions,
+            strings,
+            labels,
+            templates,
+        } = program;
+        Self {
+            instructions,
+            strings,
+            labels,
+            templates,
+            stack: vec![Vec::new()],
+            vars: HashMap::new(),
+            ip: 0,
+            return_addresses: Vec::new(),
+            caller_baggage: Vec::new(),
+            baggage: BTreeMap::new(),
+        }
+    }
+
+    /// Whether the service ran past its last instruction
+    pub fn is_done(&self) -> bool {
+        self.ip >= self.instructions.len()
+    }
+
+    pub fn jump_target(&self, label: Symbol) -> Result<usize, InterpreterError> {
+        self.labels
+            .get(label)
+            .copied()
+            .flatten()
+            .ok_or_else(|| InterpreterError::MissingLabel(self.strings.get(label).to_string()))
+    }
+
+    /// The position of the local call that makes up the loop of the service
+    pub fn loop_call(&self) -> Option<usize> {
+        let label = self.strings.lookup("start_loop")?;
+        self.labels.get(label).copied().flatten()
+    }
+
+    #[cfg(feature = "native")]
+    /// The label of the function the instruction pointer is in
+    pub fn current_function_name(&self) -> Option<Arc<str>> {
+        self.instructions[..self.ip]
+            .iter()
+            .rev()
+            .find_map(|instruction| match instruction {
+                DecodedInstr::Label(label) => Some(self.strings.get(*label).clone()),
+                _ => None,
+            })
+    }
+
+    /// Calls the function at the label, which returns to the current instruction
+    pub fn call(&mut self, label: Symbol) -> Result<(), InterpreterError> {
+        let target = self.jump_target(label)?;
+        self.enter(target, self.ip);
+        Ok(())
+    }
+
+    /// Runs the function at `target` in a new stack frame, until it returns to `return_address`
+    pub fn enter(&mut self, target: usize, return_address: usize) {
+        self.return_addresses.push(return_address);
+        self.caller_baggage.push(self.baggage.clone());
+        self.stack.push(Vec::new());
+        self.ip = target;
+    }
+
+    pub fn push(&mut self, value: Value) -> Result<(), InterpreterError> {
+        self.current_stackframe()?.push(value);
+        Ok(())
+    }
+
+    fn current_stackframe(&mut self) -> Result<&mut Vec<Value>, InterpreterError> {
+        self.stack
+            .last_mut()
+            .ok_or(InterpreterError::MissingStackFrame)
+    }
+
+    fn pop(&mut self) -> Result<Value, InterpreterError> {
+        self.current_stackframe()?
+            .pop()
+            .ok_or(InterpreterError::StackUnderflow)
+    }
+
+    /// Pops a message for stderr, which takes strings only
+    fn pop_string(&mut self) -> Result<String, InterpreterError> {
+        match self.pop()? {
+            Value::String(s) => Ok(s.to_string()),
+            Value::Int(_) => Err(InterpreterError::InvalidStackValue),
+        }
+    }
+
+    /// Runs the instruction at the instruction pointer
+    pub fn step<H: Host>(&mut self, host: &mut H) -> Result<Step, H::Error> {
+        let instruction = self.instructions[self.ip].clone();
+        let step = match instruction {
+            DecodedInstr::PushString(str) => {
+                self.push(Value::String(str))?;
+                Step::Continue
+            }
+            DecodedInstr::PushInt(int) => {
+                self.push(Value::Int(int))?;
+                Step::Continue
+            }
+            DecodedInstr::PushWeighted(values) => {
+                let total = values.iter().map(|(_, weight)| weight).sum::<u64>();
+                if total == 0 {
+                    return Err(InterpreterError::ZeroTotalWeight.into());
+                }
+                let roll = host.random_range(0..total)?;
+                let value =
+                    pick_weighted(&values, roll).ok_or(InterpreterError::ZeroTotalWeight)?;
+                self.push(Value::String(value))?;
+                Step::Continue
+            }
+            DecodedInstr::Pop => {
+                self.stack.pop();
+                Step::Continue
+            }
+            DecodedInstr::Dec => {
+                match self.pop()? {
+                    Value::Int(n) => self.push(Value::Int(n - 1))?,
+                    _ => return Err(InterpreterError::InvalidStackValue.into()),
+                }
+                Step::Continue
+            }
+            DecodedInstr::JmpIfZero(label) => {
+                match self.pop()? {
+                    Value::Int(0) => self.ip = self.jump_target(label)?,
+                    Value::Int(_) => self.ip += 1,
+                    _ => return Err(InterpreterError::InvalidStackValue.into()),
+                }
+                return Ok(Step::Continue);
+            }
+            DecodedInstr::Label(_) => Step::Continue,
+            DecodedInstr::Stdout => Step::Print(Stream::Stdout, self.pop()?.to_string()),
+            DecodedInstr::Stderr => Step::Print(Stream::Stderr, self.pop_string()?),
+            DecodedInstr::PrintMixed(info, error) => {
+                let roll = host.random_range(0..info.saturating_add(error).max(1))?;
+                if roll < info {
+                    Step::Print(Stream::Stdout, self.pop()?.to_string())
+                } else {
+                    Step::Print(Stream::Stderr, self.pop_string()?)
+                }
+            }
+            DecodedInstr::Sleep(sleep_ms) => Step::Sleep(Duration::from_millis(sleep_ms)),
+            DecodedInstr::SleepJittered(sleep_ms, jitter_ms) => {
+                let sleep_ms = host.random_range(
+                    sleep_ms.saturating_sub(jitter_ms)
+                        ..sleep_ms.saturating_add(jitter_ms).saturating_add(1),
+                )?;
+                Step::Sleep(Duration::from_millis(sleep_ms))
+            }
+            DecodedInstr::StoreVar(key, value) => {
+                self.vars.insert(key, Value::String(value));
+                Step::Continue
+            }
+            DecodedInstr::LoadVar(key) => {
+                let value = self.vars.get(&key).cloned().ok_or_else(|| {
+                    InterpreterError::MissingVar(self.strings.get(key).to_string())
+                })?;
+                self.push(value)?;
+                Step::Continue
+            }
+            DecodedInstr::Dup => {
+                let top = self
+                    .current_stackframe()?
+                    .last()
+                    .ok_or(InterpreterError::StackUnderflow)?
+                    .clone();
+                self.push(top)?;
+                Step::Continue
+            }
+            DecodedInstr::Jump(label) => {
+                let target = self.jump_target(label)?;
+                let is_back_edge = target <= self.ip;
+                self.ip = target;
+                return Ok(if is_back_edge {
+                    Step::JumpedBack
+                } else {
+                    Step::Continue
+                });
+            }
+            DecodedInstr::Printf => {
+                self.printf(host)?;
+                Step::Continue
+            }
+            DecodedInstr::RemoteCall => {
+                let method = self.pop()?.to_string();
+                let service = self.pop()?.to_string();
+                Step::RemoteCall { service, method }
+            }
+            DecodedInstr::AccessLog(format) => Step::AccessLog(format),
+            DecodedInstr::StartContext => Step::StartContext,
+            DecodedInstr::EndContext => Step::EndContext,
+            DecodedInstr::CheckInterrupt => return Ok(Step::CheckInterrupt),
+            DecodedInstr::Call(label) => return Ok(Step::Call(label)),
+            DecodedInstr::SetBaggage(key, value) => {
+                self.baggage.insert(key.to_string(), value.to_string());
+                Step::Continue
+            }
+            DecodedInstr::Ret => {
+                self.ip = self
+                    .return_addresses
+                    .pop()
+                    .ok_or(InterpreterError::MissingStackFrame)?;
+                if let Some(baggage) = self.caller_baggage.pop() {
+                    self.baggage = baggage;
+                }
+                self.stack.pop();
+                return Ok(Step::Returned);
+            }
+            DecodedInstr::Extension(code, operand) => {
+                let handler = host
+                    .extensions()
+                    .handler(code)
+                    .ok_or(InterpreterError::UnknownExtension(code))?;
+                let stack = self
+                    .stack
+                    .last_mut()
+                    .ok_or(InterpreterError::MissingStackFrame)?;
+                handler(&mut ExtensionContext::new(
+                    &operand,
+                    host.service_name(),
+                    stack,
+                ))
+                .map_err(InterpreterError::ExtensionError)?;
+                Step::Continue
+            }
+        };
+        self.ip += 1;
+        Ok(step)
+    }
+
+    /// Fills the template on top of the stack with the values below it, the first value on top
+    fn printf<H: Host>(&mut self, host: &mut H) -> Result<(), H::Error> {
+        let template = match self.pop()? {
+            Value::String(s) => s,
+            _ => return Err(InterpreterError::InvalidStackValue.into()),
+        };
+        let parsed = match self.templates.get(&template) {
+            Some(parsed) => parsed.clone(),
+            None => Arc::new(Template::parse(&template).map_err(InterpreterError::from)?),
+        };
+        // Printf is only emitted for prints with values, baggage or generators, so a template without placeholders is broken
+        if !parsed.has_placeholders() {
+            return Err(
+                InterpreterError::from(PrintfError::InvalidTemplate(template.to_string())).into(),
+            );
+        }
+        let arity = parsed.arity();
+        let frame = self.current_stackframe()?;
+        if frame.len() < arity {
+            return Err(
+                InterpreterError::from(PrintfError::ArityMismatch(arity, frame.len())).into(),
+            );
+        }
+        let values: Vec<Value> = frame.drain(frame.len() - arity..).rev().collect();
+        let mut samples = Vec::new();
+        for gauss in parsed.generators() {
+            let rolls = (host.random_f64()?, host.random_f64()?);
+            samples.push(gauss.sample(host.elapsed(), rolls));
+        }
+        let formatted = parsed
+            .fill()
+            .with_baggage(&self.baggage)
+            .with_samples(&samples)
+            .format(&values)
+            .map_err(InterpreterError::from)?;
+        self.push(Value::String(formatted.into()))?;
+        Ok(())
+    }
+}
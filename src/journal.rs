@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::vm_coordinator::CallId;
+
+/// A call the coordinator delivered to an instance, one line of the journal
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// When the call was delivered, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub call_id: CallId,
+    pub from: String,
+    pub to: String,
+    pub function: String,
+    pub instance: String,
+    /// 1 for the first attempt, higher for retries
+    pub attempt: usize,
+    /// From the trace context of the call, if it has one
+    pub trace_id: Option<String>,
+    pub span_id: Option<String>,
+}
+
+impl JournalEntry {
+    pub fn new(
+        call_id: CallId,
+        from: &str,
+        to: &str,
+        function: &str,
+        instance: &str,
+        attempt: usize,
+        context: &HashMap<String, String>,
+    ) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        // A traceparent looks like "00-<trace id>-<span id>-<flags>"
+        let mut traceparent = context
+            .get("traceparent")
+            .map(|traceparent| traceparent.split('-').skip(1))
+            .into_iter()
+            .flatten()
+            .map(str::to_string);
+        Self {
+            timestamp_ms,
+            call_id,
+            from: from.to_string(),
+            to: to.to_string(),
+            function: function.to_string(),
+            instance: instance.to_string(),
+            attempt,
+            trace_id: traceparent.next(),
+            span_id: traceparent.next(),
+        }
+    }
+}
+
+/// Reads the entries of the journal at `path`
+pub fn read(path: impl AsRef<Path>) -> std::io::Result<Vec<JournalEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Invalid journal entry on line {}: {}", index + 1, e),
+            )
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Appends every delivered call to a file, as newline-delimited JSON
+pub struct Journal {
+    writer: BufWriter<File>,
+    /// Set after the first failed write, so a full disk is reported once
+    failed: bool,
+}
+
+impl Journal {
+    /// Appends to the file at `path`, creating it if needed
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            failed: false,
+        })
+    }
+
+    pub fn record(&mut self, entry: &JournalEntry) {
+        let result = serde_json::to_writer(&mut self.writer, entry)
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+        if let Err(e) = result {
+            if !self.failed {
+                tracing::warn!("Cannot write to the journal: {}", e);
+            }
+            self.failed = true;
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            tracing::warn!("Cannot write to the journal: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_takes_trace_ids_from_traceparent() {
+        let context = HashMap::from([(
+            "traceparent".to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        )]);
+        let entry = JournalEntry::new(7, "frontend", "products", "get", "products-0", 1, &context);
+        assert_eq!(
+            entry.trace_id.as_deref(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_eq!(entry.span_id.as_deref(), Some("00f067aa0ba902b7"));
+
+        let entry = JournalEntry::new(
+            7,
+            "frontend",
+            "products",
+            "get",
+            "products-0",
+            1,
+            &HashMap::new(),
+        );
+        assert_eq!(entry.trace_id, None);
+        assert_eq!(entry.span_id, None);
+    }
+}
@@ -0,0 +1,144 @@
+//! Mustermann generates test data for OpenTelemetry pipelines: services described in a small
+//! language that log, call each other and emit traces and metrics.
+//!
+//! Besides the `mustermann` command, the crate runs scenarios inside other programs, e.g. the test
+//! harness of a pipeline. A scenario is parsed with [`parser`], compiled per service with
+//! [`code_gen`] and run on a [`vm::VM`] built by a [`vm_builder::VmBuilder`], which sends what the service prints to a
+//! [`print_sink::PrintSink`], like the sender of a channel. Services that call each other run on VMs connected by a
+//! [`vm_coordinator::ServiceCoordinator`].
+//!
+//! ```
+//! # // Only the native build has the VM
+//! # #[cfg(feature = "native")]
+//! # fn main() {
+//! use mustermann::{code_gen::CodeGenerator, parser, vm::PrintMessage, vm_builder::VmBuilder};
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let program = parser::parse(
+//!     "service payments {
+//!         method charge { print \"Processing payment\"; }
+//!         loop { call charge; }
+//!     }",
+//! )
+//! .unwrap();
+//! let code = CodeGenerator::new(&program.services[0]).process().unwrap();
+//! let (mut vm, mut channels) = VmBuilder::new(code, "payments")
+//!     .with_print_queue_size(10)
+//!     .with_max_execution_counter(20)
+//!     .build()
+//!     .unwrap();
+//! // Stops at the execution limit
+//! let _ = vm.run().await;
+//! drop(vm);
+//! let print = channels.print_rx.recv().await.unwrap();
+//! assert_eq!(print.message, PrintMessage::Stdout("Processing payment".to_string()));
+//! # });
+//! # }
+//! # #[cfg(not(feature = "native"))]
+//! # fn main() {}
+//! ```
+//!
+//! Without the default `native` feature, the crate only builds the parser, the code generator and
+//! a single-threaded [`simulation`], which also compile to `wasm32-unknown-unknown`. The `wasm`
+//! feature adds JavaScript bindings for them.
+
+pub mod code_gen;
+#[cfg(feature = "native")]
+pub mod events;
+pub mod extension;
+pub mod parser;
+#[cfg(feature = "native")]
+pub mod print_sink;
+pub mod simulation;
+#[cfg(feature = "native")]
+pub mod vm;
+#[cfg(feature = "native")]
+pub mod vm_builder;
+#[cfg(feature = "native")]
+pub mod vm_coordinator;
+
+#[cfg(feature = "native")]
+#[doc(hidden)]
+pub mod cli;
+
+mod chaos;
+mod decoder;
+mod interpreter;
+mod load;
+mod printf;
+mod string_table;
+mod timeline;
+mod value;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+#[cfg(feature = "native")]
+mod access_log;
+#[cfg(feature = "native")]
+mod admin;
+#[cfg(feature = "native")]
+mod artifact;
+#[cfg(feature = "native")]
+mod assertions;
+#[cfg(feature = "native")]
+mod check;
+#[cfg(feature = "native")]
+mod circuit_breaker;
+#[cfg(feature = "native")]
+mod doctor;
+#[cfg(feature = "native")]
+mod dry_run;
+#[cfg(feature = "native")]
+mod embedded;
+#[cfg(feature = "native")]
+mod ffi;
+#[cfg(feature = "native")]
+mod files;
+#[cfg(any(feature = "golden", all(feature = "native", test)))]
+mod golden;
+#[cfg(feature = "native")]
+mod health;
+#[cfg(feature = "native")]
+mod init;
+#[cfg(feature = "native")]
+mod journal;
+#[cfg(feature = "native")]
+mod log_file;
+#[cfg(feature = "native")]
+mod log_format;
+#[cfg(feature = "native")]
+mod lsp;
+#[cfg(feature = "native")]
+mod metadata_map;
+#[cfg(feature = "native")]
+mod otel;
+#[cfg(feature = "native")]
+mod print_limiter;
+#[cfg(feature = "native")]
+mod printer;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "native")]
+mod rate_limiter;
+#[cfg(feature = "native")]
+mod recording;
+#[cfg(feature = "native")]
+mod remote;
+#[cfg(feature = "native")]
+mod replay;
+#[cfg(feature = "native")]
+mod run_stats;
+#[cfg(feature = "native")]
+mod runtime_error;
+#[cfg(feature = "native")]
+mod runtime_metrics;
+#[cfg(feature = "native")]
+mod scenario;
+#[cfg(feature = "native")]
+mod sink;
+#[cfg(feature = "native")]
+mod topology;
+#[cfg(feature = "native")]
+mod variables;
+#[cfg(feature = "native")]
+mod zipkin;
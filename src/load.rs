@@ -0,0 +1,243 @@
+//! Load shaping: paces the loops of all services of a run along a [`LoadProfile`], e.g. a ramp
+//! from 0 to 500 iterations per second, and a [`TrafficPattern`] like day and night, so capacity
+//! and autoscaling demos see the load change.
+
+use std::f64::consts::TAU;
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(feature = "native")]
+use std::time::SystemTime;
+
+use crate::parser::{LoadProfile, LoadStage, TrafficPattern};
+
+/// How long a loop waits before it asks again while the rate is zero
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+
+const DAY: Duration = Duration::from_secs(24 * 3600);
+
+#[cfg(feature = "native")]
+/// The time of day of `now`, after midnight UTC
+pub fn time_of_day(now: SystemTime) -> Duration {
+    let since_epoch = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    Duration::from_millis((since_epoch.as_millis() % DAY.as_millis()) as u64)
+}
+
+impl TrafficPattern {
+    /// The share of the full rate at `time_of_day`, after midnight UTC
+    pub fn factor_at(&self, time_of_day: Duration) -> f64 {
+        match self {
+            TrafficPattern::Diurnal {
+                peak, amplitude, ..
+            } => {
+                let trough = 1.0 / amplitude.max(1.0);
+                let phase = (time_of_day.as_secs_f64() - peak.as_secs_f64()) / DAY.as_secs_f64();
+                trough + (1.0 - trough) * (1.0 + (phase * TAU).cos()) / 2.0
+            }
+        }
+    }
+
+    /// How many days of the pattern pass per day of the run
+    fn speed(&self) -> f64 {
+        match self {
+            TrafficPattern::Diurnal { day: Some(day), .. } if !day.is_zero() => {
+                DAY.as_secs_f64() / day.as_secs_f64()
+            }
+            TrafficPattern::Diurnal { .. } => 1.0,
+        }
+    }
+}
+
+impl LoadProfile {
+    /// The iterations per second at `elapsed` after the start of the run
+    pub fn rate_at(&self, elapsed: Duration) -> f64 {
+        self.integrate(elapsed).0
+    }
+
+    /// How many iterations the profile hands out from the start of the run until `elapsed`
+    fn iterations_until(&self, elapsed: Duration) -> f64 {
+        self.integrate(elapsed).1
+    }
+
+    // The rate at `elapsed` and the iterations until then. The rate changes evenly within a
+    // stage, so the iterations of a stage are its mean rate times its length
+    fn integrate(&self, elapsed: Duration) -> (f64, f64) {
+        let mut rate = 0.0;
+        let mut iterations = 0.0;
+        let mut start = Duration::ZERO;
+        for stage in &self.stages {
+            let (length, end_rate) = match stage {
+                LoadStage::Ramp { from, to, over } => {
+                    rate = *from as f64;
+                    (*over, *to as f64)
+                }
+                LoadStage::Hold { duration } => (*duration, rate),
+                LoadStage::RampDown { over } => (*over, 0.0),
+            };
+            if elapsed < start + length {
+                let into_stage = (elapsed - start).as_secs_f64();
+                let rate_now = rate + (end_rate - rate) * into_stage / length.as_secs_f64();
+                return (rate_now, iterations + (rate + rate_now) / 2.0 * into_stage);
+            }
+            iterations += (rate + end_rate) / 2.0 * length.as_secs_f64();
+            rate = end_rate;
+            start += length;
+        }
+        (rate, iterations + rate * (elapsed - start).as_secs_f64())
+    }
+}
+
+/// Hands out the iterations of the loops of all services, at the rate of the profile scaled by
+/// the pattern. Iterations that no loop took within a second are dropped, so a slow service does
+/// not cause a burst later. Without a profile, the pattern slows every loop down on its own.
+/// Times are counted from the start of the run, so a simulation paces its loops by its own clock
+#[derive(Debug, Default)]
+pub struct LoadShaper {
+    profile: Option<LoadProfile>,
+    /// The pattern, with the time of day the run started at
+    pattern: Option<(TrafficPattern, Duration)>,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    tokens: f64,
+    /// Since the start of the run
+    refilled_at: Duration,
+}
+
+impl LoadShaper {
+    /// A shaper without a profile or pattern
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hands out the iterations of the profile, without any to hand out yet
+    pub fn with_profile(mut self, profile: LoadProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Scales the rate along the pattern, from the time of day the run started at
+    pub fn with_pattern(mut self, pattern: TrafficPattern, time_of_day: Duration) -> Self {
+        self.pattern = Some((pattern, time_of_day));
+        self
+    }
+
+    /// The share of the full rate the pattern allows at `elapsed`
+    fn factor(&self, elapsed: Duration) -> f64 {
+        let Some((pattern, started_at)) = &self.pattern else {
+            return 1.0;
+        };
+        let into_pattern = started_at.as_secs_f64() + elapsed.as_secs_f64() * pattern.speed();
+        pattern.factor_at(Duration::from_secs_f64(into_pattern % DAY.as_secs_f64()))
+    }
+
+    /// How long a loop pauses `elapsed` after the start of the run after an iteration that took
+    /// `iteration`, to run slower by the pattern. With a profile, the pattern scales the rate of
+    /// the profile instead
+    pub fn pause(&self, iteration: Duration, elapsed: Duration) -> Duration {
+        if self.profile.is_some() {
+            return Duration::ZERO;
+        }
+        let factor = self.factor(elapsed);
+        iteration.mul_f64(1.0 / factor - 1.0)
+    }
+
+    /// Takes an iteration for a loop `elapsed` after the start of the run, or returns how long to
+    /// wait before asking again
+    pub fn try_acquire(&self, elapsed: Duration) -> Result<(), Duration> {
+        let Some(profile) = &self.profile else {
+            return Ok(());
+        };
+        let factor = self.factor(elapsed);
+        let rate = profile.rate_at(elapsed) * factor;
+        let mut bucket = self.bucket.lock().unwrap();
+        if elapsed > bucket.refilled_at {
+            // The pattern changes slowly, so its share at the end holds for the whole refill
+            let refill = (profile.iterations_until(elapsed)
+                - profile.iterations_until(bucket.refilled_at))
+                * factor;
+            bucket.tokens = (bucket.tokens + refill).min(rate.max(1.0));
+            bucket.refilled_at = elapsed;
+        }
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        if rate <= 0.0 {
+            return Err(IDLE_WAIT);
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+// `time_of_day` is only needed by the native build
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn test_rate_follows_the_stages() {
+        let profile =
+            parser::parse_load_profile("ramp 0->500 rps over 5m, hold 10m, ramp down 2m").unwrap();
+        let rate_at = |secs| profile.rate_at(Duration::from_secs(secs));
+        assert_eq!(rate_at(0), 0.0);
+        assert_eq!(rate_at(150), 250.0);
+        assert_eq!(rate_at(300), 500.0);
+        assert_eq!(rate_at(899), 500.0);
+        assert_eq!(rate_at(960), 250.0);
+        // The rate stays where the last stage ended
+        assert_eq!(rate_at(3600), 0.0);
+        // 300s at a mean of 250 per second
+        assert_eq!(profile.iterations_until(Duration::from_secs(300)), 75_000.0);
+
+        let profile = parser::parse_load_profile("ramp 10->20 rps over 10s").unwrap();
+        assert_eq!(profile.rate_at(Duration::from_secs(60)), 20.0);
+    }
+
+    #[test]
+    fn test_shaper_paces_the_iterations() {
+        let profile = parser::parse_load_profile("hold 1s, ramp 10->10 rps over 1m").unwrap();
+        let shaper = LoadShaper::new().with_profile(profile);
+        let at = Duration::from_millis;
+        // Nothing runs while the rate is zero
+        assert_eq!(shaper.try_acquire(at(0)), Err(IDLE_WAIT));
+        assert_eq!(
+            shaper.try_acquire(at(1000)),
+            Err(Duration::from_millis(100))
+        );
+        assert_eq!(shaper.try_acquire(at(1100)), Ok(()));
+        assert!(shaper.try_acquire(at(1100)).is_err());
+        // At 10 per second, at most 10 are handed out after a pause
+        let handed_out = (0..20)
+            .filter(|_| shaper.try_acquire(at(30_000)).is_ok())
+            .count();
+        assert_eq!(handed_out, 10);
+    }
+
+    #[test]
+    fn test_diurnal_pattern_slows_the_loops_down_at_night() {
+        let pattern = parser::parse_traffic_pattern("diurnal(peak: 14:00, amplitude: 5x)").unwrap();
+        let hours = |hours: u64| Duration::from_secs(hours * 3600);
+        assert_eq!(pattern.factor_at(hours(14)), 1.0);
+        assert!((pattern.factor_at(hours(2)) - 0.2).abs() < 1e-9);
+        assert!((pattern.factor_at(hours(8)) - 0.6).abs() < 1e-9);
+
+        // A day per hour, starting at the trough
+        let pattern = parser::parse_traffic_pattern("diurnal(peak: 12:00, day: 60m)").unwrap();
+        let shaper = LoadShaper::new().with_pattern(pattern, Duration::ZERO);
+        let iteration = Duration::from_millis(100);
+        assert_eq!(shaper.try_acquire(Duration::ZERO), Ok(()));
+        assert_eq!(shaper.pause(iteration, Duration::ZERO), iteration);
+        let half_an_hour = Duration::from_secs(1800);
+        assert_eq!(shaper.pause(iteration, half_an_hour), Duration::ZERO);
+
+        assert_eq!(
+            time_of_day(SystemTime::UNIX_EPOCH + hours(24 * 365 + 3)),
+            hours(3)
+        );
+    }
+}
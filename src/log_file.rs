@@ -0,0 +1,190 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// When to start a new log file, and how many of the old ones to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    /// The size in bytes a file may grow to
+    pub max_size: u64,
+    /// How many rotated files are kept next to the current one, as `app.log.1` to `app.log.<keep>`
+    pub keep: usize,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self {
+            max_size: 100 * 1024 * 1024,
+            keep: 5,
+        }
+    }
+}
+
+/// One setting of `--rotate`, e.g. `size=100MB` or `keep=5`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationSetting {
+    Size(u64),
+    Keep(usize),
+}
+
+impl Rotation {
+    pub fn from_settings(settings: &[RotationSetting]) -> Self {
+        let mut rotation = Self::default();
+        for setting in settings {
+            match setting {
+                RotationSetting::Size(max_size) => rotation.max_size = *max_size,
+                RotationSetting::Keep(keep) => rotation.keep = *keep,
+            }
+        }
+        rotation
+    }
+}
+
+pub fn parse_rotation_setting(setting: &str) -> Result<RotationSetting, String> {
+    match setting.split_once('=') {
+        Some(("size", size)) => parse_size(size).map(RotationSetting::Size),
+        Some(("keep", keep)) => keep
+            .parse()
+            .map(RotationSetting::Keep)
+            .map_err(|_| format!("Expected a number of files, got {}", keep)),
+        _ => Err(format!(
+            "Expected size=<size> or keep=<files>, got {}",
+            setting
+        )),
+    }
+}
+
+// Sizes like 500KB, 100MB or 1GB, in multiples of 1024
+fn parse_size(size: &str) -> Result<u64, String> {
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(digits);
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1024,
+        "MB" => 1024 * 1024,
+        "GB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Unknown size unit {} in {}", unit, size)),
+    };
+    match number.parse::<u64>() {
+        Ok(number) if number > 0 => Ok(number * multiplier),
+        _ => Err(format!("Expected a positive size, got {}", size)),
+    }
+}
+
+/// A log file that is appended to, and rotated once it grows too big
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotation: Option<Rotation>,
+}
+
+impl LogFile {
+    /// Opens the file for appending, creating it and its directory if needed
+    pub fn open(path: impl AsRef<Path>, rotation: Option<Rotation>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            rotation,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    // Shifts app.log.1 to app.log.2 and so on, dropping the oldest, and starts a new app.log
+    fn rotate(&mut self, keep: usize) -> io::Result<()> {
+        self.file.flush()?;
+        if keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..keep).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    // Every log line arrives in one write, so lines are never split across files
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(rotation) = self.rotation {
+            if self.size > 0 && self.size + buf.len() as u64 > rotation.max_size {
+                self.rotate(rotation.keep)?;
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_file_rotates_and_keeps_the_newest_files() {
+        let settings = ["size=20".to_string(), "keep=2".to_string()]
+            .iter()
+            .map(|setting| parse_rotation_setting(setting).unwrap())
+            .collect::<Vec<_>>();
+        let rotation = Rotation::from_settings(&settings);
+        assert_eq!(
+            rotation,
+            Rotation {
+                max_size: 20,
+                keep: 2
+            }
+        );
+        assert_eq!(
+            parse_rotation_setting("size=100MB"),
+            Ok(RotationSetting::Size(100 * 1024 * 1024))
+        );
+        assert!(parse_rotation_setting("size=100XB").is_err());
+
+        let dir = std::env::temp_dir().join(format!("mustermann-log-{}", std::process::id()));
+        let path = dir.join("app.log");
+        let mut file = LogFile::open(&path, Some(rotation)).unwrap();
+        for line in [
+            "first line\n",
+            "second line\n",
+            "third line\n",
+            "fourth line\n",
+        ] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "fourth line\n");
+        assert_eq!(read(dir.join("app.log.1")), "third line\n");
+        assert_eq!(read(dir.join("app.log.2")), "second line\n");
+        assert!(!dir.join("app.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
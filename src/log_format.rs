@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::{self, FormatEvent, FormatFields};
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::FmtContext;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::log_file::LogFile;
+
+/// The target of the logs the services print, to tell them apart from the logs of mustermann itself
+pub const SERVICE_TARGET: &str = "service";
+
+/// The field with the attributes of a service, which logfmt and ECS split into a field each
+const ATTRIBUTES_FIELD: &str = "attributes";
+
+/// Joins the attributes of a service into one field, e.g. `env=production,pod=products-7d4b9`.
+/// Field names are fixed when mustermann is built, so the attributes cannot be a field each
+pub fn join_attributes(attributes: &BTreeMap<String, String>) -> String {
+    attributes
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn split_attributes(value: &str) -> impl Iterator<Item = (&str, &str)> {
+    value
+        .split(',')
+        .filter_map(|attribute| attribute.split_once('='))
+}
+
+/// Which logs are written: `expression` if given, else the level picked with -q and -v, else
+/// RUST_LOG. The logs of the services stay at info whatever -q and -v say.
+pub fn filter(verbosity: i8, expression: Option<&str>) -> EnvFilter {
+    match expression {
+        Some(expression) => EnvFilter::new(expression),
+        None if verbosity != 0 => EnvFilter::new(directives(verbosity)),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| directives(0).into()),
+    }
+}
+
+fn directives(verbosity: i8) -> String {
+    let level = match verbosity {
+        ..=-2 => "error",
+        -1 => "warn",
+        0 => "info",
+        1 => "info,mustermann=debug",
+        2.. => "debug,mustermann=trace",
+    };
+    format!("{},{}=info", level, SERVICE_TARGET)
+}
+
+pub fn parse_filter(expression: &str) -> Result<String, String> {
+    EnvFilter::try_new(expression)
+        .map(|_| expression.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// How logs are written to the console when they are not sent to an OpenTelemetry endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// One line per event, with colors on a terminal
+    #[default]
+    Pretty,
+    /// One JSON object per line
+    Json,
+    /// `key=value` pairs, one line per event
+    Logfmt,
+    /// Like pretty, but shorter
+    Compact,
+    /// One JSON object per line with Elastic Common Schema fields
+    Ecs,
+}
+
+/// The layer that writes the logs in `format`, to `output` or else to stdout
+pub fn layer<S>(format: LogFormat, output: Option<LogFile>) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let mut layer = tracing_subscriber::fmt::layer();
+    // Files get no colors
+    if output.is_some() || matches!(format, LogFormat::Logfmt | LogFormat::Ecs) {
+        layer = layer.with_ansi(false);
+    }
+    let layer = layer.with_writer(writer(output));
+    match format {
+        LogFormat::Pretty => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+        LogFormat::Logfmt => layer.event_format(Logfmt).boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Ecs => layer.event_format(Ecs).boxed(),
+    }
+}
+
+/// Writes to `output`, or else to stdout
+pub fn writer(output: Option<LogFile>) -> BoxMakeWriter {
+    match output {
+        Some(file) => BoxMakeWriter::new(std::sync::Mutex::new(file)),
+        None => BoxMakeWriter::new(std::io::stdout),
+    }
+}
+
+/// Writes events as logfmt, e.g. `ts=2025-01-01T00:00:00Z level=info target=mustermann msg="Service stopped"`
+struct Logfmt;
+
+impl<S, N> FormatEvent<S, N> for Logfmt
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        write!(writer, "ts=")?;
+        SystemTime.format_time(&mut writer)?;
+        write!(
+            writer,
+            " level={} target={}",
+            metadata.level().as_str().to_lowercase(),
+            metadata.target()
+        )?;
+        let mut visitor = LogfmtVisitor {
+            writer: &mut writer,
+            result: Ok(()),
+        };
+        event.record(&mut visitor);
+        visitor.result?;
+        writeln!(writer)
+    }
+}
+
+struct LogfmtVisitor<'a, 'w> {
+    writer: &'a mut format::Writer<'w>,
+    result: fmt::Result,
+}
+
+impl Visit for LogfmtVisitor<'_, '_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == ATTRIBUTES_FIELD {
+            for (key, value) in split_attributes(value) {
+                if self.result.is_ok() {
+                    self.result = write_pair(self.writer, key, value);
+                }
+            }
+        } else if self.result.is_ok() {
+            self.result = write_pair(self.writer, field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+fn write_pair(writer: &mut impl Write, key: &str, value: &str) -> fmt::Result {
+    let key = if key == "message" { "msg" } else { key };
+    write!(writer, " {}={}", key, quote(value))
+}
+
+/// Quotes a value if it would not survive as a bare logfmt value
+fn quote(value: &str) -> String {
+    let bare = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '=' || c == '"' || c.is_control());
+    if bare {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The version of the Elastic Common Schema the ECS logs follow
+const ECS_VERSION: &str = "8.11.0";
+
+/// Writes events as Elastic Common Schema JSON, e.g.
+/// `{"@timestamp":"2025-01-01T00:00:00Z","log.level":"info","message":"Service stopped",...}`
+struct Ecs;
+
+impl<S, N> FormatEvent<S, N> for Ecs
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut format::Writer::new(&mut timestamp))?;
+        let mut visitor = EcsVisitor(Vec::new());
+        event.record(&mut visitor);
+        let record = ecs_record(
+            &timestamp,
+            &metadata.level().as_str().to_lowercase(),
+            metadata.target(),
+            visitor.0,
+        );
+        writeln!(writer, "{}", serde_json::Value::Object(record))
+    }
+}
+
+struct EcsVisitor(Vec<(String, String)>);
+
+impl Visit for EcsVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name().to_string(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+/// Maps the fields of an event to ECS. Fields without an ECS counterpart go to `labels`.
+fn ecs_record(
+    timestamp: &str,
+    level: &str,
+    target: &str,
+    fields: Vec<(String, String)>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let mut record = serde_json::Map::new();
+    record.insert("@timestamp".into(), timestamp.into());
+    record.insert("log.level".into(), level.into());
+    record.insert("log.logger".into(), target.into());
+    record.insert("service.name".into(), "mustermann".into());
+    record.insert("ecs.version".into(), ECS_VERSION.into());
+    for (name, value) in fields {
+        if name == ATTRIBUTES_FIELD {
+            for (key, value) in split_attributes(&value) {
+                record.insert(format!("labels.{}", key), value.into());
+            }
+            continue;
+        }
+        let key = match name.as_str() {
+            "message" => "message".to_string(),
+            "app_name" => "service.name".to_string(),
+            "instance" => "service.node.name".to_string(),
+            "trace_id" => "trace.id".to_string(),
+            "span_id" => "span.id".to_string(),
+            _ => format!("labels.{}", name),
+        };
+        record.insert(key, value.into());
+    }
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verbosity_keeps_the_logs_of_the_services() {
+        assert_eq!(directives(-1), "warn,service=info");
+        assert_eq!(directives(1), "info,mustermann=debug,service=info");
+        assert_eq!(directives(5), directives(2));
+        assert!(parse_filter("service=off,mustermann=debug").is_ok());
+        assert!(parse_filter("service=loud").is_err());
+    }
+
+    #[test]
+    fn test_ecs_fields() {
+        let fields = [
+            ("message", "Fetching products"),
+            ("app_name", "products"),
+            ("instance", "products-0"),
+            ("trace_id", "4bf92f3577b34da6a3ce929d0e0e4736"),
+            ("pid", "42"),
+            ("attributes", "env=production,k8s.pod.name=products-7d4b9"),
+        ];
+        let record = ecs_record(
+            "2025-01-01T00:00:00Z",
+            "info",
+            SERVICE_TARGET,
+            fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        );
+        assert_eq!(
+            serde_json::Value::Object(record).to_string(),
+            "{\"@timestamp\":\"2025-01-01T00:00:00Z\",\"ecs.version\":\"8.11.0\",\"labels.env\":\"production\",\
+             \"labels.k8s.pod.name\":\"products-7d4b9\",\"labels.pid\":\"42\",\
+             \"log.level\":\"info\",\"log.logger\":\"service\",\"message\":\"Fetching products\",\
+             \"service.name\":\"products\",\"service.node.name\":\"products-0\",\
+             \"trace.id\":\"4bf92f3577b34da6a3ce929d0e0e4736\"}"
+        );
+    }
+
+    #[test]
+    fn test_attributes_split_into_fields() {
+        let attributes = BTreeMap::from([
+            ("pod".to_string(), "products-7d4b9".to_string()),
+            ("env".to_string(), "production".to_string()),
+        ]);
+        let joined = join_attributes(&attributes);
+        assert_eq!(joined, "env=production,pod=products-7d4b9");
+        assert_eq!(
+            split_attributes(&joined).collect::<Vec<_>>(),
+            [("env", "production"), ("pod", "products-7d4b9")]
+        );
+    }
+
+    #[test]
+    fn test_logfmt_pairs() {
+        let mut line = String::new();
+        write_pair(&mut line, "message", "Service stopped").unwrap();
+        write_pair(&mut line, "app_name", "products").unwrap();
+        write_pair(&mut line, "error", "said \"no\"\n").unwrap();
+        write_pair(&mut line, "empty", "").unwrap();
+        assert_eq!(
+            line,
+            " msg=\"Service stopped\" app_name=products error=\"said \\\"no\\\"\\n\" empty=\"\""
+        );
+    }
+}
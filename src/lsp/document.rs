@@ -0,0 +1,417 @@
+use std::ops::Range;
+
+use pest::iterators::Pair;
+use pest::Parser;
+
+use crate::check::{self, Severity, Subject};
+use crate::parser::{self, MustermannParser, ParseError, Rule};
+
+/// A name in the file, with its position in bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Symbol {
+    name: String,
+    span: Range<usize>,
+}
+
+impl Symbol {
+    fn new(pair: &Pair<Rule>) -> Self {
+        let span = pair.as_span();
+        Self {
+            name: span.as_str().to_string(),
+            span: span.start()..span.end(),
+        }
+    }
+
+    fn contains(&self, offset: usize) -> bool {
+        // The end counts too, the cursor is often right behind the name
+        self.span.start <= offset && offset <= self.span.end
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ServiceSymbol {
+    name: Symbol,
+    methods: Vec<Symbol>,
+}
+
+#[derive(Debug, Clone)]
+struct CallSymbol {
+    /// The index of the calling service
+    service: usize,
+    callee: Option<Symbol>,
+    method: Symbol,
+}
+
+/// Where the services, methods and calls of a file are
+#[derive(Debug, Clone, Default)]
+struct Index {
+    services: Vec<ServiceSymbol>,
+    calls: Vec<CallSymbol>,
+    /// Services named by settings and chaos actions
+    settings: Vec<Symbol>,
+}
+
+impl Index {
+    fn new(text: &str) -> Result<Self, ParseError> {
+        let program = MustermannParser::parse(Rule::program, text)?
+            .next()
+            .unwrap();
+        let mut index = Self::default();
+        for pair in program.into_inner() {
+            match pair.as_rule() {
+                Rule::service_def => index.add_service(pair),
+                Rule::EOI => {}
+                _ => index.settings.extend(
+                    pair.into_inner()
+                        .flatten()
+                        .filter(|pair| pair.as_rule() == Rule::identifier)
+                        .map(|pair| Symbol::new(&pair)),
+                ),
+            }
+        }
+        Ok(index)
+    }
+
+    fn add_service(&mut self, pair: Pair<Rule>) {
+        let service = self.services.len();
+        let mut inner = pair.into_inner();
+        let name = Symbol::new(&inner.next().unwrap());
+        let mut methods = Vec::new();
+        for pair in inner.flatten() {
+            match pair.as_rule() {
+                Rule::method_def => methods.push(Symbol::new(&pair.into_inner().next().unwrap())),
+                Rule::call_stmt => {
+                    let mut names: Vec<Symbol> =
+                        pair.into_inner().map(|pair| Symbol::new(&pair)).collect();
+                    let method = names.pop().unwrap();
+                    self.calls.push(CallSymbol {
+                        service,
+                        callee: names.pop(),
+                        method,
+                    });
+                }
+                _ => {}
+            }
+        }
+        self.services.push(ServiceSymbol { name, methods });
+    }
+
+    fn services_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a ServiceSymbol> {
+        self.services
+            .iter()
+            .filter(move |service| service.name.name == name)
+    }
+
+    /// Where the diagnostics about `subject` belong
+    fn locate(&self, subject: &Subject) -> Vec<Range<usize>> {
+        match subject {
+            Subject::Service(name) => self
+                .services_named(name)
+                .map(|service| service.name.span.clone())
+                .collect(),
+            Subject::Call {
+                service,
+                callee,
+                method,
+            } => self
+                .calls
+                .iter()
+                .filter(|call| {
+                    self.services[call.service].name.name == *service
+                        && call.callee.as_ref().map(|callee| &callee.name) == callee.as_ref()
+                        && call.method.name == *method
+                })
+                .map(|call| {
+                    let start = call.callee.as_ref().unwrap_or(&call.method).span.start;
+                    start..call.method.span.end
+                })
+                .collect(),
+            Subject::Setting(name) => self
+                .settings
+                .iter()
+                .filter(|setting| setting.name == *name)
+                .map(|setting| setting.span.clone())
+                .collect(),
+        }
+    }
+}
+
+/// A problem in the file, with its position in bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub span: Range<usize>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Service,
+    Method,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+}
+
+/// A file open in the editor
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    pub text: String,
+    /// Of the last version that parsed, so completion keeps working while a line is half written
+    index: Index,
+    problems: Vec<Problem>,
+}
+
+impl Document {
+    pub fn new(text: String) -> Self {
+        Self::default().update(text)
+    }
+
+    /// The next version of the document
+    pub fn update(self, text: String) -> Self {
+        let (index, problems) = match parser::parse(&text) {
+            Ok(program) => {
+                let index = Index::new(&text).expect("The file parsed already");
+                let mut problems = Vec::new();
+                for diagnostic in check::check(&program) {
+                    let mut spans = index.locate(&diagnostic.subject);
+                    spans.dedup();
+                    if spans.is_empty() {
+                        spans.push(0..0);
+                    }
+                    problems.extend(spans.into_iter().map(|span| Problem {
+                        span,
+                        severity: diagnostic.severity,
+                        message: diagnostic.message.clone(),
+                    }));
+                }
+                problems.dedup();
+                (index, problems)
+            }
+            Err(e) => (self.index, vec![parse_problem(&e, text.len())]),
+        };
+        Self {
+            text,
+            index,
+            problems,
+        }
+    }
+
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    /// Where the service or method at `offset` is defined
+    pub fn definition(&self, offset: usize) -> Option<Range<usize>> {
+        let index = &self.index;
+        let service_span = |name: &str| {
+            index
+                .services_named(name)
+                .next()
+                .map(|service| service.name.span.clone())
+        };
+        if let Some(setting) = index.settings.iter().find(|s| s.contains(offset)) {
+            return service_span(&setting.name);
+        }
+        let call = index.calls.iter().find(|call| {
+            call.callee
+                .iter()
+                .chain([&call.method])
+                .any(|s| s.contains(offset))
+        })?;
+        match &call.callee {
+            Some(callee) if callee.contains(offset) => service_span(&callee.name),
+            callee => {
+                let service = match callee {
+                    Some(callee) => index.services_named(&callee.name).next()?,
+                    None => &index.services[call.service],
+                };
+                service
+                    .methods
+                    .iter()
+                    .find(|method| method.name == call.method.name)
+                    .map(|method| method.span.clone())
+            }
+        }
+    }
+
+    /// What may follow a `call` at `offset`: the methods of a service after `call service.`,
+    /// the methods of the enclosing service in a loop, and the services anywhere else
+    pub fn completions(&self, offset: usize) -> Vec<Completion> {
+        let before = &self.text[..offset.min(self.text.len())];
+        let word_start = before
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .map_or(0, |i| i + 1);
+        let keyword = before[..word_start].trim_end();
+        let is_call = keyword.ends_with("call")
+            && !keyword[..keyword.len() - 4]
+                .ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+        if !is_call {
+            return Vec::new();
+        }
+        let methods = |service: &ServiceSymbol| {
+            service
+                .methods
+                .iter()
+                .map(|method| Completion {
+                    label: method.name.clone(),
+                    kind: CompletionKind::Method,
+                })
+                .collect::<Vec<_>>()
+        };
+        if let Some((callee, _)) = before[word_start..].split_once('.') {
+            return self
+                .index
+                .services_named(callee)
+                .next()
+                .map(methods)
+                .unwrap_or_default();
+        }
+        // Loops call the methods of their service, methods and shutdown blocks call other services
+        match enclosing_blocks(before) {
+            (Some("loop"), Some(service)) => self
+                .index
+                .services_named(service)
+                .next()
+                .map(methods)
+                .unwrap_or_default(),
+            _ => self
+                .index
+                .services
+                .iter()
+                .map(|service| Completion {
+                    label: service.name.name.clone(),
+                    kind: CompletionKind::Service,
+                })
+                .collect(),
+        }
+    }
+}
+
+fn parse_problem(error: &ParseError, len: usize) -> Problem {
+    let (span, message) = match error {
+        ParseError::PestError(e) => {
+            let span = match e.location {
+                pest::error::InputLocation::Pos(pos) => pos..pos.saturating_add(1).min(len),
+                pest::error::InputLocation::Span((start, end)) => start..end,
+            };
+            (span, e.variant.message().to_string())
+        }
+        ParseError::InvalidInput(message) => (0..0, message.clone()),
+    };
+    Problem {
+        span,
+        severity: Severity::Error,
+        message,
+    }
+}
+
+// Blocks nest only once, so the last `loop`, `method` or `shutdown` and the last `service name`
+// before the cursor enclose it. Returns the kind of the block and the name of the service.
+fn enclosing_blocks(before: &str) -> (Option<&str>, Option<&str>) {
+    let mut words = before
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .rev()
+        .peekable();
+    let mut block = None;
+    while let Some(word) = words.next() {
+        if words.peek() == Some(&"service") {
+            return (block, Some(word));
+        }
+        if block.is_none() && matches!(word, "loop" | "method" | "shutdown") {
+            block = Some(word);
+        }
+    }
+    (block, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "service products {
+    method get_products {
+        print \"Fetching products\";
+    }
+}
+service frontend {
+    method main_page {
+        call products.get_products;
+        call products.get_stock;
+    }
+    loop {
+        call main_page;
+    }
+}
+inject latency frontend->payments 100ms;
+";
+
+    #[test]
+    fn test_document_points_at_definitions_and_problems() {
+        let document = Document::new(FILE.to_string());
+        let offset = |text: &str| FILE.find(text).unwrap();
+
+        let problems: Vec<(&str, &str)> = document
+            .problems()
+            .iter()
+            .map(|problem| (&FILE[problem.span.clone()], problem.message.as_str()))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                ("products.get_stock", "services[1].methods[0].statements[1].call.method: frontend.main_page calls products.get_stock, but products has no method get_stock"),
+                ("payments", "injections[0].latency.route.to: An injection names payments, but there is no service payments"),
+            ]
+        );
+
+        let definition = |text: &str| {
+            document
+                .definition(offset(text))
+                .map(|span| &FILE[span.start..])
+                .map(|rest| rest.split_whitespace().next().unwrap())
+        };
+        assert_eq!(definition("products.get_products"), Some("products"));
+        assert_eq!(definition("get_products;"), Some("get_products"));
+        assert_eq!(
+            document.definition(offset("main_page;")),
+            Some(offset("main_page {")..offset("main_page {") + "main_page".len())
+        );
+        assert_eq!(definition("frontend->"), Some("frontend"));
+    }
+
+    #[test]
+    fn test_document_completes_calls_while_the_file_does_not_parse() {
+        let document = Document::new(FILE.to_string());
+        let typing = FILE.replace("call main_page;", "call products.");
+        let document = document.update(typing.clone());
+        assert_eq!(document.problems().len(), 1);
+
+        let at = typing.find("products.\n").unwrap() + "products.".len();
+        assert_eq!(
+            document.completions(at),
+            [Completion {
+                label: "get_products".to_string(),
+                kind: CompletionKind::Method,
+            }]
+        );
+        let labels = |offset: usize| {
+            document
+                .completions(offset)
+                .into_iter()
+                .map(|completion| completion.label)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(at - "products.".len()), ["main_page"]);
+        assert_eq!(
+            labels(typing.find("products.get_stock").unwrap()),
+            ["products", "frontend"]
+        );
+        assert!(document
+            .completions(typing.find("Fetching").unwrap())
+            .is_empty());
+    }
+}
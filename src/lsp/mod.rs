@@ -0,0 +1,379 @@
+//! A language server for config files, spoken over stdin and stdout. It reports parse errors and
+//! the problems `check` finds, jumps to the services and methods that calls name and completes
+//! call targets.
+mod document;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::check::Severity;
+use document::{CompletionKind, Document};
+
+/// JSON-RPC error code for requests the server does not know
+const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code for requests with parameters the server cannot read
+const INVALID_PARAMS: i64 = -32602;
+
+/// A position in a document. Characters are counted in UTF-16 code units, like editors do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Position {
+    line: usize,
+    character: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+struct LspRange {
+    start: Position,
+    end: Position,
+}
+
+#[derive(Deserialize)]
+struct TextDocumentIdentifier {
+    uri: String,
+}
+
+#[derive(Deserialize)]
+struct TextDocumentItem {
+    uri: String,
+    text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidOpenParams {
+    text_document: TextDocumentItem,
+}
+
+#[derive(Deserialize)]
+struct ContentChange {
+    text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidChangeParams {
+    text_document: TextDocumentIdentifier,
+    content_changes: Vec<ContentChange>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidCloseParams {
+    text_document: TextDocumentIdentifier,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextDocumentPositionParams {
+    text_document: TextDocumentIdentifier,
+    position: Position,
+}
+
+/// Answers the requests of an editor until it sends `exit` or closes the input
+pub fn serve(mut reader: impl BufRead, writer: impl Write) -> io::Result<()> {
+    let mut server = Server {
+        writer,
+        documents: HashMap::new(),
+    };
+    while let Some(message) = read_message(&mut reader)? {
+        if !server.handle(message)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+struct Server<W> {
+    writer: W,
+    documents: HashMap<String, Document>,
+}
+
+impl<W: Write> Server<W> {
+    /// Returns false once the editor asks the server to exit
+    fn handle(&mut self, message: Value) -> io::Result<bool> {
+        let id = message.get("id").cloned();
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    // The whole document is sent on every change
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "completionProvider": { "triggerCharacters": ["."] },
+                },
+                "serverInfo": { "name": "mustermann", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "textDocument/didOpen" => {
+                if let Ok(params) = serde_json::from_value::<DidOpenParams>(params) {
+                    let document = Document::new(params.text_document.text);
+                    self.open(params.text_document.uri, document)?;
+                }
+                return Ok(true);
+            }
+            "textDocument/didChange" => {
+                if let Ok(params) = serde_json::from_value::<DidChangeParams>(params) {
+                    let uri = params.text_document.uri;
+                    if let Some(change) = params.content_changes.into_iter().last() {
+                        let document = self.documents.remove(&uri).unwrap_or_default();
+                        self.open(uri, document.update(change.text))?;
+                    }
+                }
+                return Ok(true);
+            }
+            "textDocument/didClose" => {
+                if let Ok(params) = serde_json::from_value::<DidCloseParams>(params) {
+                    let uri = params.text_document.uri;
+                    self.documents.remove(&uri);
+                    self.publish_diagnostics(&uri, Vec::new())?;
+                }
+                return Ok(true);
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/completion" => self.completion(params),
+            "shutdown" => Ok(Value::Null),
+            "exit" => return Ok(false),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        };
+        // Notifications have no id and get no reply
+        if let Some(id) = id {
+            let reply = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": code, "message": message },
+                }),
+            };
+            write_message(&mut self.writer, &reply)?;
+        }
+        Ok(true)
+    }
+
+    fn open(&mut self, uri: String, document: Document) -> io::Result<()> {
+        let diagnostics = document
+            .problems()
+            .iter()
+            .map(|problem| {
+                json!({
+                    "range": range(&document.text, &problem.span),
+                    "severity": match problem.severity {
+                        Severity::Error => 1,
+                        Severity::Warning => 2,
+                    },
+                    "source": "mustermann",
+                    "message": problem.message,
+                })
+            })
+            .collect();
+        self.publish_diagnostics(&uri, diagnostics)?;
+        self.documents.insert(uri, document);
+        Ok(())
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: Vec<Value>) -> io::Result<()> {
+        write_message(
+            &mut self.writer,
+            &json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": { "uri": uri, "diagnostics": diagnostics },
+            }),
+        )
+    }
+
+    fn document_at(&self, params: Value) -> Result<(&str, &Document, usize), (i64, String)> {
+        let params: TextDocumentPositionParams =
+            serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+        let (uri, document) = self
+            .documents
+            .get_key_value(&params.text_document.uri)
+            .ok_or_else(|| {
+                (
+                    INVALID_PARAMS,
+                    format!("{} is not open", params.text_document.uri),
+                )
+            })?;
+        Ok((uri, document, offset(&document.text, params.position)))
+    }
+
+    fn definition(&self, params: Value) -> Result<Value, (i64, String)> {
+        let (uri, document, offset) = self.document_at(params)?;
+        Ok(match document.definition(offset) {
+            Some(span) => json!({ "uri": uri, "range": range(&document.text, &span) }),
+            None => Value::Null,
+        })
+    }
+
+    fn completion(&self, params: Value) -> Result<Value, (i64, String)> {
+        let (_, document, offset) = self.document_at(params)?;
+        let items: Vec<Value> = document
+            .completions(offset)
+            .into_iter()
+            .map(|completion| {
+                json!({
+                    "label": completion.label,
+                    // The completion item kinds of the protocol
+                    "kind": match completion.kind {
+                        CompletionKind::Method => 2,
+                        CompletionKind::Service => 9,
+                    },
+                })
+            })
+            .collect();
+        Ok(json!(items))
+    }
+}
+
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            );
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Message without Content-Length")
+    })?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+fn position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: before.matches('\n').count(),
+        character: before[line_start..].encode_utf16().count(),
+    }
+}
+
+fn range(text: &str, span: &Range<usize>) -> LspRange {
+    LspRange {
+        start: position(text, span.start),
+        end: position(text, span.end),
+    }
+}
+
+// Positions past the end of a line or the text end up at the end of it
+fn offset(text: &str, position: Position) -> usize {
+    let Some(line_start) = text
+        .split_inclusive('\n')
+        .map(str::len)
+        .scan(0, |start, len| {
+            let line_start = *start;
+            *start += len;
+            Some(line_start)
+        })
+        .chain([text.len()])
+        .nth(position.line)
+    else {
+        return text.len();
+    };
+    let line = text[line_start..].split('\n').next().unwrap_or_default();
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= position.character {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message: Value) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn replies(output: &[u8]) -> Vec<Value> {
+        let mut reader = output;
+        std::iter::from_fn(|| read_message(&mut reader).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_server_answers_an_editor_session() {
+        let uri = "file:///scenario.muster";
+        let text = "service products {
+    method get_products {
+        print \"Ünïcode\"; call payments.charge;
+    }
+    loop {
+        call get_products;
+    }
+}
+inject latency products->payments 100ms;
+";
+        let input = [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "mustermann", "version": 1, "text": text } },
+            }),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "textDocument/definition",
+                "params": { "textDocument": { "uri": uri }, "position": { "line": 5, "character": 13 } },
+            }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "textDocument/hover", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]
+        .map(frame)
+        .concat();
+
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+        let replies = replies(&output);
+        assert_eq!(replies.len(), 5);
+        assert_eq!(
+            replies[0]["result"]["capabilities"]["definitionProvider"],
+            true
+        );
+        let diagnostics = &replies[1]["params"]["diagnostics"];
+        // Characters are counted in UTF-16, the text is indexed in bytes
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({ "start": { "line": 2, "character": 30 }, "end": { "line": 2, "character": 45 } })
+        );
+        let charge = text.find("charge").unwrap();
+        assert_eq!(offset(text, position(text, charge)), charge);
+        assert_eq!(diagnostics[1]["severity"], 2);
+        assert_eq!(
+            replies[2]["result"],
+            json!({ "uri": uri, "range": { "start": { "line": 1, "character": 11 }, "end": { "line": 1, "character": 23 } } })
+        );
+        assert_eq!(replies[3]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(replies[4]["result"], Value::Null);
+    }
+}
@@ -2,7 +2,8 @@ use std::collections::HashMap;
 
 use opentelemetry::propagation::{Extractor, Injector};
 
-pub struct MetadataMap<'a>(&'a mut HashMap<String, String>);
+/// Carries serialized trace context headers between services
+pub struct MetadataMap<'a>(pub &'a mut HashMap<String, String>);
 
 impl Injector for MetadataMap<'_> {
     /// Set a key and value in the MetadataMap.  Does nothing if the key or value are not valid inputs
@@ -1,41 +1,535 @@
 use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
-use opentelemetry_otlp::{LogExporter, WithExportConfig, WithTonicConfig};
-use opentelemetry_sdk::logs::SdkLoggerProvider;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use opentelemetry_otlp::{
+    ExporterBuildError, LogExporter, WithExportConfig, WithHttpConfig, WithTonicConfig,
+};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::logs::{SdkLogger, SdkLoggerProvider};
+use opentelemetry_sdk::metrics::Temporality;
 use opentelemetry_sdk::Resource;
 use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
-use tonic::metadata::MetadataMap;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::prelude::*;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::log_format;
+use crate::parser;
+
+/// Which format traces are sent in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraceExporter {
+    /// OTLP, to the OpenTelemetry endpoint like logs and metrics
+    #[default]
+    Otlp,
+    /// Zipkin v2 JSON, to --zipkin-endpoint
+    Zipkin,
+}
+
+/// How telemetry is sent to an OpenTelemetry endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Protocol {
+    /// OTLP over gRPC, usually on port 4317
+    #[default]
+    Grpc,
+    /// OTLP over HTTP with protobuf bodies, usually on port 4318
+    Http,
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Grpc => write!(f, "gRPC"),
+            Protocol::Http => write!(f, "HTTP"),
+        }
+    }
+}
+
+/// How telemetry sent over gRPC is secured. https:// endpoints are trusted if the system or
+/// --otel-ca-cert trusts their certificate
+#[derive(clap::Args, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tls {
+    /// Also trust endpoints whose certificate is signed by this CA, a PEM file
+    #[arg(long = "otel-ca-cert", value_name = "PATH")]
+    pub ca_cert: Option<String>,
+    /// Authenticate with this certificate, a PEM file, at endpoints that require mutual TLS
+    #[arg(
+        long = "otel-client-cert",
+        value_name = "PATH",
+        requires = "client_key"
+    )]
+    pub client_cert: Option<String>,
+    /// The private key of --otel-client-cert, a PEM file
+    #[arg(
+        long = "otel-client-key",
+        value_name = "PATH",
+        requires = "client_cert"
+    )]
+    pub client_key: Option<String>,
+    /// Send telemetry without TLS, even to an https:// endpoint
+    #[arg(long = "otel-insecure", conflicts_with_all = ["ca_cert", "client_cert"])]
+    pub insecure: bool,
+}
+
+/// How metrics are aggregated between exports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MetricsTemporality {
+    /// What happened since the last export
+    #[default]
+    Delta,
+    /// Everything since the start, as Prometheus-compatible backends expect
+    Cumulative,
+}
+
+impl From<MetricsTemporality> for Temporality {
+    fn from(temporality: MetricsTemporality) -> Self {
+        match temporality {
+            MetricsTemporality::Delta => Temporality::Delta,
+            MetricsTemporality::Cumulative => Temporality::Cumulative,
+        }
+    }
+}
+
+/// How often and in which temporality metrics are exported
+#[derive(clap::Args, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsExport {
+    /// Export metrics at this interval, e.g. "10s". Defaults to once a minute, or to
+    /// OTEL_METRIC_EXPORT_INTERVAL
+    #[arg(long = "metrics-interval", value_parser = parser::parse_duration)]
+    pub interval: Option<Duration>,
+    /// Export what happened since the last export, or everything since the start
+    #[arg(long = "metrics-temporality", value_enum, default_value_t = MetricsTemporality::Delta)]
+    pub temporality: MetricsTemporality,
+}
+
+/// Where the logs, traces and metrics are sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub url: String,
+    pub protocol: Protocol,
+    /// Sent along with every export, e.g. the API key of a backend
+    pub headers: Vec<(String, String)>,
+    pub tls: Tls,
+    /// Traces go to this Zipkin URL instead of the endpoint
+    pub zipkin: Option<String>,
+    pub metrics: MetricsExport,
+}
+
+impl Endpoint {
+    /// A collector on this host, on the usual port of `protocol`
+    pub fn local(protocol: Protocol) -> Self {
+        let url = match protocol {
+            Protocol::Grpc => "http://localhost:4317",
+            Protocol::Http => "http://localhost:4318",
+        };
+        Self {
+            url: url.to_string(),
+            protocol,
+            headers: Vec::new(),
+            tls: Tls::default(),
+            zipkin: None,
+            metrics: MetricsExport::default(),
+        }
+    }
+
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    pub fn with_tls(mut self, tls: Tls) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    pub fn with_zipkin(mut self, url: String) -> Self {
+        self.zipkin = Some(url);
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: MetricsExport) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Checks that the TLS options fit the endpoint
+    pub fn check_tls(&self) -> Result<(), String> {
+        if self.tls == Tls::default() {
+            return Ok(());
+        }
+        if self.protocol == Protocol::Http {
+            return Err(
+                "--otel-ca-cert, --otel-client-cert and --otel-insecure only apply to --otel-protocol grpc"
+                    .to_string(),
+            );
+        }
+        if !self.tls.insecure && !self.url.starts_with("https://") {
+            return Err(format!(
+                "--otel-ca-cert and --otel-client-cert need an https:// endpoint, got {}",
+                self.url
+            ));
+        }
+        Ok(())
+    }
+
+    /// The TLS settings of the gRPC exporters, if the endpoint is reached over TLS
+    pub fn tls_config(&self) -> Result<Option<ClientTlsConfig>, ExporterBuildError> {
+        if !self.signal_url("traces").starts_with("https://") {
+            return Ok(None);
+        }
+        let mut config = ClientTlsConfig::new().with_enabled_roots();
+        if let Some(path) = &self.tls.ca_cert {
+            config = config.ca_certificate(Certificate::from_pem(read_pem(path)?));
+        }
+        if let (Some(cert), Some(key)) = (&self.tls.client_cert, &self.tls.client_key) {
+            config = config.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+        }
+        Ok(Some(config))
+    }
+
+    /// The headers for gRPC exporters
+    pub fn metadata(&self) -> MetadataMap {
+        let mut metadata = MetadataMap::with_capacity(self.headers.len());
+        for (key, value) in &self.headers {
+            // parse_header checked them already
+            if let (Ok(key), Ok(value)) = (MetadataKey::from_bytes(key.as_bytes()), value.parse()) {
+                metadata.insert(key, value);
+            }
+        }
+        metadata
+    }
+
+    /// The headers for HTTP exporters
+    pub fn http_headers(&self) -> HashMap<String, String> {
+        self.headers.iter().cloned().collect()
+    }
+
+    /// Where one kind of telemetry goes, e.g. "traces". OTLP/HTTP takes each kind on a path of its own
+    pub fn signal_url(&self, signal: &str) -> String {
+        match self.protocol {
+            Protocol::Grpc if self.tls.insecure => self.url.replacen("https://", "http://", 1),
+            Protocol::Grpc => self.url.clone(),
+            Protocol::Http => format!("{}/v1/{}", self.url.trim_end_matches('/'), signal),
+        }
+    }
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, ExporterBuildError> {
+    std::fs::read(path).map_err(|e| ExporterBuildError::InternalFailure(format!("{}: {}", path, e)))
+}
+
+/// Parses a header of `--otel-header`, e.g. `x-honeycomb-team=<api key>`
+pub fn parse_header(header: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = header.split_once('=') else {
+        return Err(format!("Expected key=value, got {}", header));
+    };
+    let key = key.trim();
+    MetadataKey::<tonic::metadata::Ascii>::from_bytes(key.as_bytes())
+        .map_err(|_| format!("{} is not a valid header name", key))?;
+    MetadataValue::<tonic::metadata::Ascii>::try_from(value)
+        .map_err(|_| format!("The value of header {} is not valid", key))?;
+    Ok((key.to_string(), value.to_string()))
+}
 
-pub fn setup_otlp(
-    endpoint: &str,
+/// Parses a baggage entry like "tenant=acme"
+pub fn parse_baggage(entry: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = entry.split_once('=') else {
+        return Err(format!("Expected key=value, got {}", entry));
+    };
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("Expected a key in {}", entry));
+    }
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
+/// Sends the logs that pass `filter` to `endpoint`, and writes them as JSON to `output` or else to stdout.
+/// The logs of the services are exported under their own service name, the rest under `service_name`
+/// Loggers that export to `endpoint`, mustermann's own logs under `service_name`
+pub fn service_loggers(
+    endpoint: &Endpoint,
+    service_name: &str,
+) -> Result<ServiceLoggers, opentelemetry_otlp::ExporterBuildError> {
+    let default = logger_provider(endpoint, service_name)?;
+    let endpoint = endpoint.clone();
+    Ok(ServiceLoggers::new(default, move |service_name| {
+        logger_provider(&endpoint, service_name)
+    }))
+}
+
+type Bridge = OpenTelemetryTracingBridge<SdkLoggerProvider, SdkLogger>;
+type Logger = (SdkLoggerProvider, Arc<Bridge>);
+type ProviderFactory = dyn Fn(&str) -> Result<SdkLoggerProvider, ExporterBuildError> + Send + Sync;
+
+/// A logger provider per simulated service, so that logs name the same service as its traces.
+/// Providers are created when a service logs for the first time
+#[derive(Clone)]
+pub struct ServiceLoggers {
+    default: Logger,
+    services: Arc<Mutex<HashMap<String, Logger>>>,
+    factory: Arc<ProviderFactory>,
+}
+
+impl ServiceLoggers {
+    pub fn new(
+        default: SdkLoggerProvider,
+        factory: impl Fn(&str) -> Result<SdkLoggerProvider, ExporterBuildError> + Send + Sync + 'static,
+    ) -> Self {
+        let bridge = Arc::new(Bridge::new(&default));
+        Self {
+            default: (default, bridge),
+            services: Arc::default(),
+            factory: Arc::new(factory),
+        }
+    }
+
+    /// Sends the logs with the `service` target to the provider of their `app_name`, and all
+    /// other logs to the default provider
+    pub fn layer(&self) -> ServiceLogsLayer {
+        ServiceLogsLayer {
+            loggers: self.clone(),
+        }
+    }
+
+    fn bridge(&self, service_name: Option<&str>) -> Arc<Bridge> {
+        let Some(service_name) = service_name else {
+            return self.default.1.clone();
+        };
+        let mut services = self.services.lock().unwrap();
+        if let Some((_, bridge)) = services.get(service_name) {
+            return bridge.clone();
+        }
+        match (self.factory)(service_name) {
+            Ok(provider) => {
+                let bridge = Arc::new(Bridge::new(&provider));
+                services.insert(service_name.to_string(), (provider, bridge.clone()));
+                bridge
+            }
+            // Logging the error here would end up in this layer again
+            Err(_) => self.default.1.clone(),
+        }
+    }
+
+    /// Flushes and stops all providers
+    pub fn shutdown(&self) -> OTelSdkResult {
+        let services = std::mem::take(&mut *self.services.lock().unwrap());
+        for (provider, _) in services.values() {
+            provider.shutdown()?;
+        }
+        self.default.0.shutdown()
+    }
+}
+
+pub struct ServiceLogsLayer {
+    loggers: ServiceLoggers,
+}
+
+impl<S> Layer<S> for ServiceLogsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut app_name = None;
+        if event.metadata().target() == log_format::SERVICE_TARGET {
+            event.record(&mut AppNameVisitor(&mut app_name));
+        }
+        self.loggers
+            .bridge(app_name.as_deref())
+            .on_event(event, ctx);
+    }
+}
+
+/// Reads the `app_name` field of a service log
+struct AppNameVisitor<'a>(&'a mut Option<String>);
+
+impl Visit for AppNameVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "app_name" {
+            *self.0 = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "app_name" {
+            *self.0 = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Exports log records to `endpoint` in batches
+pub fn logger_provider(
+    endpoint: &Endpoint,
     service_name: &str,
 ) -> Result<SdkLoggerProvider, opentelemetry_otlp::ExporterBuildError> {
-    let mut metadata = MetadataMap::new();
-    metadata.insert(SERVICE_NAME, service_name.parse().unwrap());
-    let exporter = LogExporter::builder()
-        .with_tonic()
-        .with_endpoint(endpoint)
-        .with_metadata(metadata)
-        .build()?;
-
-    let provider: SdkLoggerProvider = SdkLoggerProvider::builder()
+    let exporter = match endpoint.protocol {
+        Protocol::Grpc => {
+            let mut metadata = endpoint.metadata();
+            metadata.insert(SERVICE_NAME, service_name.parse().unwrap());
+            let mut builder = LogExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint.signal_url("logs"))
+                .with_metadata(metadata);
+            if let Some(tls_config) = endpoint.tls_config()? {
+                builder = builder.with_tls_config(tls_config);
+            }
+            builder.build()?
+        }
+        Protocol::Http => LogExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint.signal_url("logs"))
+            .with_headers(
+                endpoint
+                    .http_headers()
+                    .into_iter()
+                    .chain([(SERVICE_NAME.to_string(), service_name.to_string())])
+                    .collect(),
+            )
+            .build()?,
+    };
+
+    Ok(SdkLoggerProvider::builder()
         .with_resource(
             Resource::builder()
                 .with_service_name(service_name.to_string())
                 .build(),
         )
         .with_batch_exporter(exporter)
-        .build();
+        .build())
+}
 
-    let layer = OpenTelemetryTracingBridge::new(&provider);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_sdk::logs::InMemoryLogExporter;
+    use tracing_subscriber::layer::SubscriberExt;
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "INFO".into()),
-        )
-        .with(tracing_subscriber::fmt::layer().json())
-        .with(layer)
-        .init();
-    Ok(provider)
+    #[test]
+    fn test_http_endpoints_take_a_path_per_signal() {
+        let endpoint = Endpoint {
+            url: "https://otlp.example.com/".to_string(),
+            protocol: Protocol::Http,
+            headers: Vec::new(),
+            tls: Tls::default(),
+            zipkin: None,
+            metrics: MetricsExport::default(),
+        };
+        assert_eq!(
+            endpoint.signal_url("traces"),
+            "https://otlp.example.com/v1/traces"
+        );
+        let local = Endpoint::local(Protocol::Grpc);
+        assert_eq!(local.signal_url("metrics"), "http://localhost:4317");
+        assert!(local.metadata().is_empty());
+        assert_eq!(
+            Endpoint::local(Protocol::Http).signal_url("logs"),
+            "http://localhost:4318/v1/logs"
+        );
+    }
+
+    #[test]
+    fn test_service_logs_are_exported_under_their_service_name() {
+        fn provider(service_name: &str, exporter: &InMemoryLogExporter) -> SdkLoggerProvider {
+            SdkLoggerProvider::builder()
+                .with_resource(
+                    Resource::builder()
+                        .with_service_name(service_name.to_string())
+                        .build(),
+                )
+                .with_simple_exporter(exporter.clone())
+                .build()
+        }
+        let own_logs = InMemoryLogExporter::default();
+        let service_logs = InMemoryLogExporter::default();
+        let exporter = service_logs.clone();
+        let loggers = ServiceLoggers::new(provider("mustermann", &own_logs), move |service_name| {
+            Ok(provider(service_name, &exporter))
+        });
+
+        let subscriber = tracing_subscriber::registry().with(loggers.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: log_format::SERVICE_TARGET, app_name = %"products", "Fetching products");
+            tracing::info!("Service started");
+        });
+
+        let service_name =
+            |log: &opentelemetry_sdk::logs::in_memory_exporter::LogDataWithResource| {
+                log.resource.get(&SERVICE_NAME.into()).unwrap().to_string()
+            };
+        let service_logs = service_logs.get_emitted_logs().unwrap();
+        assert_eq!(service_logs.len(), 1);
+        assert_eq!(service_name(&service_logs[0]), "products");
+        let own_logs = own_logs.get_emitted_logs().unwrap();
+        assert_eq!(own_logs.len(), 1);
+        assert_eq!(service_name(&own_logs[0]), "mustermann");
+        loggers.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_headers_reach_grpc_and_http_exporters() {
+        let header = parse_header("X-Honeycomb-Team=abc=123").unwrap();
+        assert_eq!(
+            header,
+            ("X-Honeycomb-Team".to_string(), "abc=123".to_string())
+        );
+        assert!(parse_header("x-api-key").is_err());
+        assert!(parse_header("bad header=1").is_err());
+
+        let endpoint = Endpoint::local(Protocol::Grpc).with_headers(vec![header]);
+        assert_eq!(
+            endpoint.metadata().get("x-honeycomb-team").unwrap(),
+            "abc=123"
+        );
+        assert_eq!(endpoint.http_headers()["X-Honeycomb-Team"], "abc=123");
+    }
+
+    #[test]
+    fn test_tls_options_apply_to_grpc_over_https() {
+        let endpoint = Endpoint {
+            url: "https://otlp.example.com:4317".to_string(),
+            protocol: Protocol::Grpc,
+            headers: Vec::new(),
+            tls: Tls::default(),
+            zipkin: None,
+            metrics: MetricsExport::default(),
+        };
+        assert!(endpoint.tls_config().unwrap().is_some());
+        assert!(Endpoint::local(Protocol::Grpc)
+            .tls_config()
+            .unwrap()
+            .is_none());
+
+        let insecure = endpoint.clone().with_tls(Tls {
+            insecure: true,
+            ..Tls::default()
+        });
+        assert_eq!(
+            insecure.signal_url("traces"),
+            "http://otlp.example.com:4317"
+        );
+        assert!(insecure.tls_config().unwrap().is_none());
+
+        let with_ca = Tls {
+            ca_cert: Some("ca.pem".to_string()),
+            ..Tls::default()
+        };
+        assert!(endpoint
+            .clone()
+            .with_tls(with_ca.clone())
+            .check_tls()
+            .is_ok());
+        assert!(Endpoint::local(Protocol::Grpc)
+            .with_tls(with_ca.clone())
+            .check_tls()
+            .is_err());
+        assert!(Endpoint::local(Protocol::Http)
+            .with_tls(with_ca)
+            .check_tls()
+            .is_err());
+    }
 }
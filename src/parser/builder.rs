@@ -0,0 +1,301 @@
+//! Builds a [`Program`] in code instead of parsing it, e.g. to generate scenarios in tests:
+//!
+//! ```
+//! use mustermann::parser::Program;
+//!
+//! let program = Program::builder()
+//!     .service("products")
+//!     .method("get_products", |m| m.print("Fetching products").sleep_ms(100))
+//!     .service("frontend")
+//!     .method("main_page", |m| m.call("products", "get_products"))
+//!     .loop_call("main_page")
+//!     .build();
+//! assert_eq!(program.services.len(), 2);
+//! ```
+
+use std::time::Duration;
+
+use super::{
+    AccessLogFormat, Loop, Method, Program, Service, ServiceConfig, SeverityMix, Shutdown,
+    Statement, Template,
+};
+
+impl Program {
+    pub fn builder() -> ProgramBuilder {
+        ProgramBuilder::default()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProgramBuilder {
+    program: Program,
+}
+
+impl ProgramBuilder {
+    /// Starts a service. Its methods follow, until the next service or `build`
+    pub fn service(self, name: &str) -> ServiceBuilder {
+        ServiceBuilder {
+            program: self,
+            service: Service {
+                name: name.to_string(),
+                methods: Vec::new(),
+                loops: Vec::new(),
+                shutdown: None,
+                config: ServiceConfig::default(),
+            },
+        }
+    }
+
+    /// Changes the settings that have no builder of their own, like injections or chaos actions
+    pub fn with(mut self, settings: impl FnOnce(&mut Program)) -> Self {
+        settings(&mut self.program);
+        self
+    }
+
+    pub fn build(self) -> Program {
+        self.program
+    }
+}
+
+#[derive(Debug)]
+pub struct ServiceBuilder {
+    program: ProgramBuilder,
+    service: Service,
+}
+
+impl ServiceBuilder {
+    pub fn method(mut self, name: &str, body: impl FnOnce(MethodBuilder) -> MethodBuilder) -> Self {
+        self.service.methods.push(Method {
+            name: name.to_string(),
+            statements: body(MethodBuilder::default()).statements,
+        });
+        self
+    }
+
+    /// Calls a method of the service over and over, like `loop { call main_page; }`
+    pub fn loop_call(mut self, method: &str) -> Self {
+        self.service.loops = vec![Loop {
+            statements: vec![Statement::Call {
+                service: None,
+                method: method.to_string(),
+            }],
+        }];
+        self
+    }
+
+    /// What the service runs once the simulation shuts down
+    pub fn shutdown(mut self, body: impl FnOnce(MethodBuilder) -> MethodBuilder) -> Self {
+        self.service.shutdown = Some(Shutdown {
+            statements: body(MethodBuilder::default()).statements,
+        });
+        self
+    }
+
+    pub fn with_config(mut self, config: ServiceConfig) -> Self {
+        self.service.config = config;
+        self
+    }
+
+    /// Finishes this service and starts the next one
+    pub fn service(self, name: &str) -> ServiceBuilder {
+        self.finish().service(name)
+    }
+
+    /// Finishes this service, e.g. to change settings with `with`
+    pub fn finish(mut self) -> ProgramBuilder {
+        self.program.program.services.push(self.service);
+        self.program
+    }
+
+    pub fn build(self) -> Program {
+        self.finish().build()
+    }
+}
+
+/// The statements of a method or of a shutdown block
+#[derive(Debug, Default)]
+pub struct MethodBuilder {
+    statements: Vec<Statement>,
+}
+
+impl MethodBuilder {
+    pub fn print(self, message: &str) -> Self {
+        self.statement(Statement::Stdout {
+            message: message.to_string(),
+            args: None,
+            weights: None,
+            severity_mix: None,
+            templates: None,
+        })
+    }
+
+    /// Prints `message` with one of `args` in place of `%s`, like `print "..." with [...]`
+    pub fn print_with(self, message: &str, args: &[&str]) -> Self {
+        self.statement(Statement::Stdout {
+            message: message.to_string(),
+            args: Some(args.iter().map(|arg| arg.to_string()).collect()),
+            weights: None,
+            severity_mix: None,
+            templates: None,
+        })
+    }
+
+    /// Prints `message` with one of `args`, picked by weight, like `print "..." with ["a": 80, "b": 20]`
+    pub fn print_weighted(self, message: &str, args: &[(&str, u64)]) -> Self {
+        self.statement(Statement::Stdout {
+            message: message.to_string(),
+            args: Some(args.iter().map(|(arg, _)| arg.to_string()).collect()),
+            weights: Some(args.iter().map(|(_, weight)| *weight).collect()),
+            severity_mix: None,
+            templates: None,
+        })
+    }
+
+    /// Prints one of `templates`, picked by weight, like `print ["a": 80, "b": 20]`
+    pub fn print_templates(self, templates: &[(&str, u64)]) -> Self {
+        let templates: Vec<Template> = templates
+            .iter()
+            .map(|(message, weight)| Template {
+                message: message.to_string(),
+                weight: *weight,
+            })
+            .collect();
+        self.statement(Statement::Stdout {
+            message: templates
+                .first()
+                .map(|template| template.message.clone())
+                .unwrap_or_default(),
+            args: None,
+            weights: None,
+            severity_mix: None,
+            templates: Some(templates),
+        })
+    }
+
+    /// Prints `message` to stdout or stderr, picked by weight, like `print "..." severity [...]`
+    pub fn print_mixed(self, message: &str, mix: SeverityMix) -> Self {
+        self.statement(Statement::Stdout {
+            message: message.to_string(),
+            args: None,
+            weights: None,
+            severity_mix: Some(mix),
+            templates: None,
+        })
+    }
+
+    pub fn stderr(self, message: &str) -> Self {
+        self.statement(Statement::Stderr {
+            message: message.to_string(),
+            args: None,
+            weights: None,
+            templates: None,
+        })
+    }
+
+    pub fn sleep(self, duration: Duration) -> Self {
+        self.statement(Statement::Sleep {
+            duration,
+            jitter: Duration::ZERO,
+        })
+    }
+
+    pub fn sleep_ms(self, ms: u64) -> Self {
+        self.sleep(Duration::from_millis(ms))
+    }
+
+    /// Calls a method of another service
+    pub fn call(self, service: &str, method: &str) -> Self {
+        self.statement(Statement::Call {
+            service: Some(service.to_string()),
+            method: method.to_string(),
+        })
+    }
+
+    pub fn baggage(self, key: &str, value: &str) -> Self {
+        self.statement(Statement::Baggage {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    pub fn access_log(self, format: AccessLogFormat) -> Self {
+        self.statement(Statement::AccessLog { format })
+    }
+
+    /// Runs an extension instruction, like `ext hl7 "ADT^A01";`
+    pub fn ext(self, name: &str, operand: &str) -> Self {
+        self.statement(Statement::Extension {
+            name: name.to_string(),
+            operand: operand.to_string(),
+        })
+    }
+
+    pub fn statement(mut self, statement: Statement) -> Self {
+        self.statements.push(statement);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_built_program_matches_parsed_program() {
+        let source = r#"
+        service products {
+            config {
+                replicas 2;
+            }
+
+            method get_products {
+                print "Fetching product %s" with ["12345", "67890"];
+                baggage tenant = "acme";
+                sleep 100ms;
+                stderr "Out of stock";
+            }
+
+            shutdown {
+                print "Bye";
+            }
+        }
+
+        service frontend {
+            method main_page {
+                call products.get_products;
+                access_log alb;
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+        "#;
+        let built = Program::builder()
+            .service("products")
+            .with_config(ServiceConfig {
+                replicas: Some(2),
+                ..ServiceConfig::default()
+            })
+            .method("get_products", |m| {
+                m.print_with("Fetching product %s", &["12345", "67890"])
+                    .baggage("tenant", "acme")
+                    .sleep_ms(100)
+                    .stderr("Out of stock")
+            })
+            .shutdown(|s| s.print("Bye"))
+            .service("frontend")
+            .method("main_page", |m| {
+                m.call("products", "get_products")
+                    .access_log(AccessLogFormat::Alb)
+            })
+            .loop_call("main_page")
+            .build();
+
+        assert_eq!(
+            serde_json::to_value(&built).unwrap(),
+            serde_json::to_value(parse(source).unwrap()).unwrap()
+        );
+    }
+}
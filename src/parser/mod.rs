@@ -1,66 +1,485 @@
+//! Parses the scenario language into a [`Program`], the services with their config and methods.
+
 use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::Parser;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
 use std::time::Duration;
 
+pub mod builder;
+pub mod source;
+
 #[derive(Parser)]
 #[grammar = "parser/grammar.pest"]
 pub struct MustermannParser;
 
 // AST structures for the program elements
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
 pub struct Program {
     pub services: Vec<Service>,
+    pub injections: Vec<Injection>,
+    pub circuit_breakers: Vec<CircuitBreakerConfig>,
+    pub retry_policies: Vec<RetryPolicy>,
+    pub priorities: Vec<CallPriority>,
+    pub rate_limits: Vec<RateLimit>,
+    pub chaos: Vec<ChaosAction>,
+    pub metric_views: Vec<MetricView>,
+    pub load: Option<LoadProfile>,
+    pub pattern: Option<TrafficPattern>,
+    pub incidents: Vec<Incident>,
+    pub timeline: Vec<TimelineEvent>,
+}
+
+impl Program {
+    /// Adds the services and settings of another file
+    pub fn extend(&mut self, other: Program) {
+        self.services.extend(other.services);
+        self.injections.extend(other.injections);
+        self.circuit_breakers.extend(other.circuit_breakers);
+        self.retry_policies.extend(other.retry_policies);
+        self.priorities.extend(other.priorities);
+        self.rate_limits.extend(other.rate_limits);
+        self.chaos.extend(other.chaos);
+        self.metric_views.extend(other.metric_views);
+        self.incidents.extend(other.incidents);
+        self.timeline.extend(other.timeline);
+        // There is one load profile per run, the one of a later file wins
+        if other.load.is_some() {
+            self.load = other.load;
+        }
+        if other.pattern.is_some() {
+            self.pattern = other.pattern;
+        }
+    }
+}
+
+/// The calls from one service to another. `*` on either side matches any service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Route {
+    pub from: String,
+    pub to: String,
+}
+
+impl Route {
+    pub fn matches(&self, from: &str, to: &str) -> bool {
+        (self.from == "*" || self.from == from) && (self.to == "*" || self.to == to)
+    }
+}
+
+impl std::fmt::Display for Route {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}->{}", self.from, self.to)
+    }
+}
+
+/// A circuit breaker on a route. With a wildcard route every matching route gets its own breaker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CircuitBreakerConfig {
+    pub route: Route,
+    /// The share of failed calls, between 0 and 1, that opens the circuit
+    pub failure_threshold: f64,
+    /// How many of the most recent calls the failure share is computed over
+    pub window: usize,
+    /// How long the circuit stays open before a trial call is let through
+    #[serde(rename = "cooldown_ms", with = "ms")]
+    #[schemars(with = "u64")]
+    pub cooldown: Duration,
+}
+
+/// Retries calls on a route that fail with a retryable error
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RetryPolicy {
+    pub route: Route,
+    /// The maximum number of attempts, including the first one
+    pub attempts: usize,
+    /// The wait before the first retry, doubling with every further retry
+    #[serde(rename = "backoff_ms", with = "ms")]
+    #[schemars(with = "u64")]
+    pub backoff: Duration,
+}
+
+/// The priority of the calls on a route. When the queue of a service is full,
+/// calls with a higher priority are delivered first. Calls without one have priority 0.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CallPriority {
+    pub route: Route,
+    pub priority: usize,
+}
+
+/// Caps the rate of calls a service accepts, rejecting the calls above it.
+/// With `*` every service gets a limit of its own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RateLimit {
+    pub service: String,
+    /// How many calls the service accepts `per` period
+    pub calls: usize,
+    #[serde(rename = "per_ms", with = "ms")]
+    #[schemars(with = "u64")]
+    pub per: Duration,
+    /// How many calls the service accepts at once after a quiet period
+    pub burst: usize,
+}
+
+/// Changes how the coordinator delivers the calls of a route, without touching the services
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Injection {
+    /// Delays every call by `delay`, plus or minus up to `jitter`
+    Latency {
+        route: Route,
+        #[serde(rename = "delay_ms", with = "ms")]
+        #[schemars(with = "u64")]
+        delay: Duration,
+        #[serde(rename = "jitter_ms", default, with = "ms")]
+        #[schemars(with = "u64")]
+        jitter: Duration,
+    },
+    /// Drops a share of the calls without delivering them and fails another share.
+    /// Rates are fractions between 0 and 1.
+    Faults {
+        route: Route,
+        drop_rate: f64,
+        error_rate: f64,
+    },
+}
+
+/// Changes how an instrument is exported, to match the names of existing dashboards
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MetricView {
+    pub instrument: String,
+    pub action: MetricViewAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricViewAction {
+    /// Exports the instrument under another name, and in another unit if given
+    Rename { name: String, unit: Option<String> },
+    /// Exports nothing for the instrument
+    Drop,
+}
+
+/// An incident staged at a known time after the start of the run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ChaosAction {
+    #[serde(rename = "at_ms", with = "ms")]
+    #[schemars(with = "u64")]
+    pub at: Duration,
+    pub kind: ChaosKind,
+}
+
+/// A degradation of a service that builds up gradually, for alerts to catch: the share of its
+/// calls that fail, and of its prints that go to stderr, rises from `from` to `to`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Incident {
+    #[serde(rename = "at_ms", with = "ms")]
+    #[schemars(with = "u64")]
+    pub at: Duration,
+    pub service: String,
+    /// The error rate at the start of the incident, between 0 and 1
+    pub from: f64,
+    /// The error rate once the incident built up
+    pub to: f64,
+    /// How long the incident takes to build up
+    #[serde(rename = "over_ms", with = "ms")]
+    #[schemars(with = "u64")]
+    pub over: Duration,
+    /// How long the error rate then takes to fall back to `from`, after which the incident ends.
+    /// Without it, the error rate stays at `to`
+    #[serde(rename = "recover_ms", default, with = "optional_ms")]
+    #[schemars(with = "Option<u64>")]
+    pub recover: Option<Duration>,
+}
+
+/// A change to the services at a known time after the start of the run. Together, the events of
+/// a timeline tell the story of an incident, e.g. a bad deploy and its rollback
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TimelineEvent {
+    #[serde(rename = "at_ms", with = "ms")]
+    #[schemars(with = "u64")]
+    pub at: Duration,
+    pub change: TimelineChange,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineChange {
+    Deploy(Deploy),
+    /// Rolls back the latest deploy of the service that is still live, or of any service without
+    /// one. The deploy before it is live again
+    Rollback {
+        service: Option<String>,
+    },
+}
+
+/// A new version of a service, live until it is rolled back or replaced by the next deploy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Deploy {
+    pub service: String,
+    /// Logged as the `service.version` attribute of the service
+    pub version: String,
+    /// Added to every call to the service
+    #[serde(rename = "latency_ms", default, with = "optional_ms")]
+    #[schemars(with = "Option<u64>")]
+    pub latency: Option<Duration>,
+    /// The share of the calls to the service that fail, between 0 and 1
+    #[serde(default)]
+    pub error_rate: Option<f64>,
+    /// Added to the attributes of the service, replacing those with the same key
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChaosKind {
+    /// Stops a service as if all of its instances died
+    Kill(String),
+    /// Brings a killed service back
+    Restart(String),
+    /// Injects latency or faults, until the given time if there is one
+    Inject {
+        injection: Injection,
+        #[serde(rename = "until_ms", default, with = "optional_ms")]
+        #[schemars(with = "Option<u64>")]
+        until: Option<Duration>,
+    },
+}
+
+/// How many loop iterations per second all services run together, stage by stage. After the last
+/// stage, the rate stays where it ended
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct LoadProfile {
+    pub stages: Vec<LoadStage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadStage {
+    /// Changes the rate evenly from `from` to `to` iterations per second
+    Ramp {
+        from: usize,
+        to: usize,
+        #[serde(rename = "over_ms", with = "ms")]
+        #[schemars(with = "u64")]
+        over: Duration,
+    },
+    /// Keeps the rate the previous stage ended with
+    Hold {
+        #[serde(rename = "duration_ms", with = "ms")]
+        #[schemars(with = "u64")]
+        duration: Duration,
+    },
+    /// Brings the rate down to zero evenly, so the loops stop
+    RampDown {
+        #[serde(rename = "over_ms", with = "ms")]
+        #[schemars(with = "u64")]
+        over: Duration,
+    },
 }
 
-#[derive(Debug, Clone)]
+/// Scales the rate of the loops over time, on top of the load profile if there is one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TrafficPattern {
+    /// Day and night as a sine curve: the loops run at full rate at `peak` and `amplitude` times
+    /// slower twelve hours later
+    Diurnal {
+        /// The time of day of the peak, after midnight UTC
+        #[serde(rename = "peak_ms", with = "ms")]
+        #[schemars(with = "u64")]
+        peak: Duration,
+        amplitude: f64,
+        /// How long a day lasts in the run, a real day if not set
+        #[serde(rename = "day_ms", default, with = "optional_ms")]
+        #[schemars(with = "Option<u64>")]
+        day: Option<Duration>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Service {
     pub name: String,
+    #[serde(default)]
     pub methods: Vec<Method>,
+    #[serde(default)]
     pub loops: Vec<Loop>,
+    #[serde(default)]
+    pub shutdown: Option<Shutdown>,
+    #[serde(default)]
+    pub config: ServiceConfig,
+}
+
+/// Per-service settings from the `config` block. Settings that are set override the global CLI flags.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ServiceConfig {
+    pub max_instructions: Option<usize>,
+    pub remote_call_limit: Option<usize>,
+    /// Maximum number of printed messages per second
+    pub print_rate_limit: Option<usize>,
+    /// Print only one in this many messages
+    pub print_sample_rate: Option<usize>,
+    /// Run this many instances of the service
+    pub replicas: Option<usize>,
+    /// How the spans of the service are named, e.g. `{service}.{method}`
+    pub span_name: Option<String>,
+    /// The service starts this long after the run, like a cron job or a deploy
+    #[serde(rename = "start_after_ms", default, with = "optional_ms")]
+    #[schemars(with = "Option<u64>")]
+    pub start_after: Option<Duration>,
+    /// The service stops this long after the start of the run
+    #[serde(rename = "stop_after_ms", default, with = "optional_ms")]
+    #[schemars(with = "Option<u64>")]
+    pub stop_after: Option<Duration>,
+    /// Attached to every log of the service, e.g. `env` or `pod`
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
+    /// Makes a share of the sleeps and handled calls of the service extremely slow
+    #[serde(default)]
+    pub spike: Option<LatencySpike>,
 }
 
-#[derive(Debug, Clone)]
+/// Occasional extreme outliers, for the long tail of latency histograms
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct LatencySpike {
+    /// The share of sleeps and calls that spike, between 0 and 1
+    pub rate: f64,
+    /// How long a spiked sleep or call takes at least
+    #[serde(rename = "to_ms", with = "ms")]
+    #[schemars(with = "u64")]
+    pub to: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Method {
     pub name: String,
     pub statements: Vec<Statement>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Loop {
     pub statements: Vec<Statement>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Statements a service runs once when the simulation shuts down
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Shutdown {
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum Statement {
     Stdout {
         message: String,
         args: Option<Vec<String>>,
+        /// How often each of `args` is picked, if one is picked per print instead of printing
+        /// all of them
+        #[serde(default)]
+        weights: Option<Vec<u64>>,
+        /// Prints to stdout or stderr at random, instead of always to stdout
+        #[serde(default)]
+        severity_mix: Option<SeverityMix>,
+        /// Templates to pick one of per print instead of always printing `message`, which is
+        /// the first of them
+        #[serde(default)]
+        templates: Option<Vec<Template>>,
     },
     Stderr {
         message: String,
         args: Option<Vec<String>>,
+        #[serde(default)]
+        weights: Option<Vec<u64>>,
+        #[serde(default)]
+        templates: Option<Vec<Template>>,
     },
     Sleep {
+        #[serde(rename = "duration_ms", with = "ms")]
+        #[schemars(with = "u64")]
         duration: Duration,
+        /// Each sleep lasts up to this much shorter or longer than `duration`
+        #[serde(
+            rename = "jitter_ms",
+            default,
+            skip_serializing_if = "Duration::is_zero",
+            with = "ms"
+        )]
+        #[schemars(with = "u64")]
+        jitter: Duration,
     },
     Call {
         service: Option<String>,
         method: String,
     },
+    /// Sets a baggage entry for the rest of the method and the calls it makes
+    Baggage { key: String, value: String },
+    /// Prints a made-up access log line in the format of a cloud load balancer or CDN
+    AccessLog { format: AccessLogFormat },
+    /// Runs an instruction an embedder registered, see [`crate::extension`]
+    Extension { name: String, operand: String },
+}
+
+/// One of the templates of a print, with how often it is picked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Template {
+    pub message: String,
+    pub weight: u64,
+}
+
+/// How often a print logs at info, to stdout, and how often at error, to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SeverityMix {
+    #[serde(default)]
+    pub info: u64,
+    #[serde(default)]
+    pub error: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogFormat {
+    /// AWS Application Load Balancer
+    Alb,
+    /// AWS CloudFront standard logs
+    Cloudfront,
+}
+
+impl AccessLogFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccessLogFormat::Alb => "alb",
+            AccessLogFormat::Cloudfront => "cloudfront",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "alb" => Some(AccessLogFormat::Alb),
+            "cloudfront" => Some(AccessLogFormat::Cloudfront),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for Statement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Statement::Stdout { message, args } => {
+            Statement::Stdout { message, args, .. } => {
                 write!(f, "Print({})", message)?;
                 if let Some(args) = args {
                     write!(f, "({:?})", args)?;
                 }
                 Ok(())
             }
-            Statement::Sleep { duration } => write!(f, "Sleep({:?})", duration),
+            Statement::Sleep { duration, jitter } if jitter.is_zero() => {
+                write!(f, "Sleep({:?})", duration)
+            }
+            Statement::Sleep { duration, jitter } => {
+                write!(f, "Sleep({:?}±{:?})", duration, jitter)
+            }
             Statement::Call { service, method } => {
                 write!(
                     f,
@@ -69,16 +488,52 @@ impl std::fmt::Display for Statement {
                     method
                 )
             }
-            Statement::Stderr { message, args } => {
+            Statement::Stderr { message, args, .. } => {
                 write!(f, "Stderr({})", message)?;
                 if let Some(args) = args {
                     write!(f, "({:?})", args)?;
                 }
                 Ok(())
             }
+            Statement::Baggage { key, value } => write!(f, "Baggage({} = {})", key, value),
+            Statement::AccessLog { format } => write!(f, "AccessLog({})", format.name()),
+            Statement::Extension { name, operand } => write!(f, "Ext({} {:?})", name, operand),
         }
     }
 }
+// Durations are written as milliseconds, like most of them are given in the DSL
+mod ms {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+mod optional_ms {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => ms::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<u64>::deserialize(deserializer).map(|ms| ms.map(Duration::from_millis))
+    }
+}
+
 #[derive(Debug)]
 pub enum ParseError {
     PestError(Box<pest::error::Error<Rule>>),
@@ -102,21 +557,209 @@ impl std::fmt::Display for ParseError {
 
 impl std::error::Error for ParseError {}
 
-// Main parsing function
+/// Parses a scenario, e.g. the contents of a `.muster` file
 pub fn parse(input: &str) -> Result<Program, ParseError> {
     let mut pairs = MustermannParser::parse(Rule::program, input)?;
     parse_program(pairs.next().unwrap().into_inner())
 }
 
+/// The JSON Schema of a serialized [`Program`], as shipped in `schema/program.schema.json`
+pub fn json_schema() -> serde_json::Value {
+    serde_json::to_value(schemars::schema_for!(Program)).expect("a schema is valid JSON")
+}
+
+/// Parses a single injection without the `inject` keyword, as passed on the command line,
+/// e.g. `latency frontend->products 200ms±50ms`
+pub fn parse_injection(input: &str) -> Result<Injection, ParseError> {
+    parse_injection_pair(parse_whole(Rule::injection, input)?)
+}
+
+/// Parses a single retry policy without the `retry` keyword, as passed on the command line,
+/// e.g. `frontend->products attempts 3 backoff 100ms`
+pub fn parse_retry_policy(input: &str) -> Result<RetryPolicy, ParseError> {
+    parse_retry_policy_pair(parse_whole(Rule::retry_policy, input)?)
+}
+
+/// Parses a single call priority without the `priority` keyword, as passed on the command line,
+/// e.g. `*->health 10`
+pub fn parse_call_priority(input: &str) -> Result<CallPriority, ParseError> {
+    parse_call_priority_pair(parse_whole(Rule::call_priority, input)?)
+}
+
+/// Parses a single rate limit without the `limit` keyword, as passed on the command line,
+/// e.g. `products 50/s burst 10`
+pub fn parse_rate_limit(input: &str) -> Result<RateLimit, ParseError> {
+    parse_rate_limit_pair(parse_whole(Rule::rate_limit, input)?)
+}
+
+/// Parses a single metric view without the `metric` keyword, as passed on the command line,
+/// e.g. `remote_invocation_counter rename rpc.client.calls`
+pub fn parse_metric_view(input: &str) -> Result<MetricView, ParseError> {
+    parse_metric_view_pair(parse_whole(Rule::metric_view, input)?)
+}
+
+/// Parses the stages of a load profile without the `load` block, as passed on the command line,
+/// e.g. `ramp 0->500 rps over 5m, hold 10m`
+pub fn parse_load_profile(input: &str) -> Result<LoadProfile, ParseError> {
+    parse_load_profile_pair(parse_whole(Rule::load_profile, input)?)
+}
+
+/// Parses a traffic pattern without the `pattern` keyword, as passed on the command line,
+/// e.g. `diurnal(peak: 14:00, amplitude: 5x, day: 60m)`
+pub fn parse_traffic_pattern(input: &str) -> Result<TrafficPattern, ParseError> {
+    parse_traffic_pattern_pair(parse_whole(Rule::traffic_pattern, input)?)
+}
+
+/// Checks a span name template, which may use the `{service}` and `{method}` placeholders
+pub fn parse_span_name(input: &str) -> Result<String, ParseError> {
+    let mut rest = input;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| {
+            ParseError::InvalidInput(format!("Unclosed placeholder in span name: {}", input))
+        })?;
+        let placeholder = &rest[start..start + end + 1];
+        if placeholder != "{service}" && placeholder != "{method}" {
+            return Err(ParseError::InvalidInput(format!(
+                "Unknown placeholder {} in span name, expected {{service}} or {{method}}",
+                placeholder
+            )));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(input.to_string())
+}
+
+/// Parses a duration as passed on the command line, e.g. `5s`
+pub fn parse_duration(input: &str) -> Result<Duration, ParseError> {
+    parse_time_value(parse_whole(Rule::time_value, input)?)
+}
+
+/// Parses a file with chaos actions only, e.g. `at 2m kill products;`
+pub fn parse_chaos(input: &str) -> Result<Vec<ChaosAction>, ParseError> {
+    let mut pairs = MustermannParser::parse(Rule::chaos_file, input)?;
+    pairs
+        .next()
+        .unwrap()
+        .into_inner()
+        .filter(|pair| pair.as_rule() == Rule::chaos_def)
+        .map(parse_chaos_def)
+        .collect()
+}
+
+// Parses `input` as `rule`, failing if anything is left over
+fn parse_whole(rule: Rule, input: &str) -> Result<Pair<'_, Rule>, ParseError> {
+    let input = input.trim();
+    let pair = MustermannParser::parse(rule, input)?.next().unwrap();
+    if pair.as_span().end() != input.len() {
+        return Err(ParseError::InvalidInput(format!(
+            "Unexpected input: {}",
+            &input[pair.as_span().end()..]
+        )));
+    }
+    Ok(pair)
+}
+
+/// Parses a single circuit breaker without the `circuit_breaker` keyword, as passed on the command line,
+/// e.g. `frontend->products threshold 50% window 10 cooldown 5s`
+pub fn parse_circuit_breaker(input: &str) -> Result<CircuitBreakerConfig, ParseError> {
+    parse_circuit_breaker_pair(parse_whole(Rule::circuit_breaker, input)?)
+}
+
 // Parse the entire program
 fn parse_program(pairs: Pairs<Rule>) -> Result<Program, ParseError> {
     let mut services = Vec::new();
+    let mut injections = Vec::new();
+    let mut circuit_breakers = Vec::new();
+    let mut retry_policies = Vec::new();
+    let mut priorities = Vec::new();
+    let mut rate_limits = Vec::new();
+    let mut chaos = Vec::new();
+    let mut metric_views = Vec::new();
+    let mut load = None;
+    let mut pattern = None;
+    let mut incidents = Vec::new();
+    let mut timeline = Vec::new();
 
     for pair in pairs {
         match pair.as_rule() {
             Rule::service_def => {
                 services.push(parse_service(pair)?);
             }
+            Rule::inject_def => {
+                let injection = pair
+                    .into_inner()
+                    .next()
+                    .ok_or_else(|| ParseError::InvalidInput("Expected injection".to_string()))?;
+                injections.push(parse_injection_pair(injection)?);
+            }
+            Rule::circuit_breaker_def => {
+                let circuit_breaker = pair.into_inner().next().ok_or_else(|| {
+                    ParseError::InvalidInput("Expected circuit breaker".to_string())
+                })?;
+                circuit_breakers.push(parse_circuit_breaker_pair(circuit_breaker)?);
+            }
+            Rule::retry_def => {
+                let retry_policy = pair
+                    .into_inner()
+                    .next()
+                    .ok_or_else(|| ParseError::InvalidInput("Expected retry policy".to_string()))?;
+                retry_policies.push(parse_retry_policy_pair(retry_policy)?);
+            }
+            Rule::priority_def => {
+                let priority = pair
+                    .into_inner()
+                    .next()
+                    .ok_or_else(|| ParseError::InvalidInput("Expected priority".to_string()))?;
+                priorities.push(parse_call_priority_pair(priority)?);
+            }
+            Rule::limit_def => {
+                let rate_limit = pair
+                    .into_inner()
+                    .next()
+                    .ok_or_else(|| ParseError::InvalidInput("Expected rate limit".to_string()))?;
+                rate_limits.push(parse_rate_limit_pair(rate_limit)?);
+            }
+            Rule::chaos_def => {
+                chaos.push(parse_chaos_def(pair)?);
+            }
+            Rule::metric_def => {
+                let metric_view = pair
+                    .into_inner()
+                    .next()
+                    .ok_or_else(|| ParseError::InvalidInput("Expected metric view".to_string()))?;
+                metric_views.push(parse_metric_view_pair(metric_view)?);
+            }
+            Rule::load_def => {
+                if load.is_some() {
+                    return Err(ParseError::InvalidInput(
+                        "There is more than one load block".to_string(),
+                    ));
+                }
+                let profile = pair
+                    .into_inner()
+                    .next()
+                    .ok_or_else(|| ParseError::InvalidInput("Expected load profile".to_string()))?;
+                load = Some(parse_load_profile_pair(profile)?);
+            }
+            Rule::pattern_def => {
+                if pattern.is_some() {
+                    return Err(ParseError::InvalidInput(
+                        "There is more than one traffic pattern".to_string(),
+                    ));
+                }
+                let traffic_pattern = pair.into_inner().next().ok_or_else(|| {
+                    ParseError::InvalidInput("Expected traffic pattern".to_string())
+                })?;
+                pattern = Some(parse_traffic_pattern_pair(traffic_pattern)?);
+            }
+            Rule::incident_def => {
+                incidents.push(parse_incident(pair)?);
+            }
+            Rule::timeline_def => {
+                for event in pair.into_inner() {
+                    timeline.push(parse_timeline_event(event)?);
+                }
+            }
             Rule::EOI => {}
             _ => {
                 return Err(ParseError::InvalidInput(format!(
@@ -127,7 +770,560 @@ fn parse_program(pairs: Pairs<Rule>) -> Result<Program, ParseError> {
         }
     }
 
-    Ok(Program { services })
+    Ok(Program {
+        services,
+        injections,
+        circuit_breakers,
+        retry_policies,
+        priorities,
+        rate_limits,
+        chaos,
+        metric_views,
+        load,
+        pattern,
+        incidents,
+        timeline,
+    })
+}
+
+// Parse `at <time>: deploy <service> <version> (<changes>)` or `at <time>: rollback <service>`
+fn parse_timeline_event(pair: Pair<Rule>) -> Result<TimelineEvent, ParseError> {
+    let mut inner_pairs = pair.into_inner();
+    let mut next = || {
+        inner_pairs
+            .next()
+            .ok_or_else(|| ParseError::InvalidInput("Incomplete timeline event".to_string()))
+    };
+    let at = parse_time_value(next()?)?;
+    let action = next()?;
+    let change = match action.as_rule() {
+        Rule::deploy_action => TimelineChange::Deploy(parse_deploy(action)?),
+        Rule::rollback_action => TimelineChange::Rollback {
+            service: action
+                .into_inner()
+                .next()
+                .map(|service| service.as_str().to_string()),
+        },
+        rule => {
+            return Err(ParseError::InvalidInput(format!(
+                "Unexpected timeline action: {:?}",
+                rule
+            )))
+        }
+    };
+    Ok(TimelineEvent { at, change })
+}
+
+fn parse_deploy(pair: Pair<Rule>) -> Result<Deploy, ParseError> {
+    let mut inner_pairs = pair.into_inner();
+    let mut next = || {
+        inner_pairs
+            .next()
+            .ok_or_else(|| ParseError::InvalidInput("Incomplete deploy".to_string()))
+    };
+    let mut deploy = Deploy {
+        service: next()?.as_str().to_string(),
+        version: next()?.as_str().to_string(),
+        latency: None,
+        error_rate: None,
+        attributes: BTreeMap::new(),
+    };
+    let changes = inner_pairs
+        .next()
+        .map(Pair::into_inner)
+        .into_iter()
+        .flatten();
+    for change in changes {
+        let change = setting_value(change)?;
+        match change.as_rule() {
+            Rule::latency_change => {
+                deploy.latency = Some(parse_time_value(setting_value(change)?)?);
+            }
+            Rule::error_rate_change => {
+                deploy.error_rate = Some(parse_percentage(setting_value(change)?)?);
+            }
+            Rule::attribute_change => {
+                let mut inner_pairs = change.into_inner();
+                let (Some(key), Some(value)) = (inner_pairs.next(), inner_pairs.next()) else {
+                    return Err(ParseError::InvalidInput(
+                        "Expected key and value in attribute change".to_string(),
+                    ));
+                };
+                let raw_value = value.as_str();
+                let value = &raw_value[1..raw_value.len() - 1];
+                if value.contains(',') {
+                    return Err(ParseError::InvalidInput(format!(
+                        "The value of attribute {} must not contain a comma",
+                        key.as_str()
+                    )));
+                }
+                deploy
+                    .attributes
+                    .insert(key.as_str().to_string(), value.to_string());
+            }
+            rule => {
+                return Err(ParseError::InvalidInput(format!(
+                    "Unexpected deploy change: {:?}",
+                    rule
+                )))
+            }
+        }
+    }
+    Ok(deploy)
+}
+
+// Parse `incident at <time>: <service> error_rate <from>-><to> over <time>, recover over <time>;`
+fn parse_incident(pair: Pair<Rule>) -> Result<Incident, ParseError> {
+    let mut inner_pairs = pair.into_inner();
+    let mut next = || {
+        inner_pairs
+            .next()
+            .ok_or_else(|| ParseError::InvalidInput("Incomplete incident".to_string()))
+    };
+    let at = parse_time_value(next()?)?;
+    let service = next()?.as_str().to_string();
+    let from = parse_percentage(next()?)?;
+    let to = parse_percentage(next()?)?;
+    let over = parse_time_value(next()?)?;
+    let recover = match inner_pairs.next() {
+        Some(setting) => Some(parse_time_value(setting_value(setting)?)?),
+        None => None,
+    };
+    Ok(Incident {
+        at,
+        service,
+        from,
+        to,
+        over,
+        recover,
+    })
+}
+
+// Parse `diurnal(...)`. The peak defaults to 14:00 and the amplitude to 2x
+fn parse_traffic_pattern_pair(pair: Pair<Rule>) -> Result<TrafficPattern, ParseError> {
+    let diurnal = pair
+        .into_inner()
+        .next()
+        .ok_or_else(|| ParseError::InvalidInput("Expected traffic pattern".to_string()))?;
+    let mut peak = Duration::from_secs(14 * 3600);
+    let mut amplitude = 2.0;
+    let mut day = None;
+    for setting in diurnal.into_inner() {
+        let setting = setting_value(setting)?;
+        let rule = setting.as_rule();
+        let value = setting_value(setting)?;
+        match rule {
+            Rule::peak_setting => peak = parse_time_of_day(value)?,
+            Rule::amplitude_setting => {
+                let text = value.as_str().trim_end_matches('x');
+                amplitude = text.parse().map_err(|_| {
+                    ParseError::InvalidInput(format!("Invalid amplitude: {}", value.as_str()))
+                })?;
+                if amplitude < 1.0 {
+                    return Err(ParseError::InvalidInput(format!(
+                        "Amplitude below 1x: {}",
+                        value.as_str()
+                    )));
+                }
+            }
+            _ => day = Some(parse_time_value(value)?),
+        }
+    }
+    Ok(TrafficPattern::Diurnal {
+        peak,
+        amplitude,
+        day,
+    })
+}
+
+// Parse a time of day like `14:00` into the time since midnight
+fn parse_time_of_day(pair: Pair<Rule>) -> Result<Duration, ParseError> {
+    let text = pair.as_str();
+    let (hours, minutes) = text.split_once(':').unwrap_or((text, ""));
+    match (hours.parse::<u64>(), minutes.parse::<u64>()) {
+        (Ok(hours), Ok(minutes)) if hours < 24 && minutes < 60 && text.len() <= 5 => {
+            Ok(Duration::from_secs(hours * 3600 + minutes * 60))
+        }
+        _ => Err(ParseError::InvalidInput(format!(
+            "Invalid time of day: {}",
+            text
+        ))),
+    }
+}
+
+// Parse the comma separated stages of a load profile
+fn parse_load_profile_pair(pair: Pair<Rule>) -> Result<LoadProfile, ParseError> {
+    let stages = pair
+        .into_inner()
+        .map(|stage| {
+            let stage = stage
+                .into_inner()
+                .next()
+                .ok_or_else(|| ParseError::InvalidInput("Expected load stage".to_string()))?;
+            let rule = stage.as_rule();
+            let mut inner_pairs = stage.into_inner();
+            let mut next = || {
+                inner_pairs
+                    .next()
+                    .ok_or_else(|| ParseError::InvalidInput(format!("Incomplete {:?}", rule)))
+            };
+            match rule {
+                Rule::ramp_stage => {
+                    let rate = |pair: Pair<Rule>| {
+                        pair.as_str().parse().map_err(|_| {
+                            ParseError::InvalidInput(format!("Invalid number: {}", pair.as_str()))
+                        })
+                    };
+                    Ok(LoadStage::Ramp {
+                        from: rate(next()?)?,
+                        to: rate(next()?)?,
+                        over: parse_time_value(next()?)?,
+                    })
+                }
+                Rule::hold_stage => Ok(LoadStage::Hold {
+                    duration: parse_time_value(next()?)?,
+                }),
+                Rule::ramp_down_stage => Ok(LoadStage::RampDown {
+                    over: parse_time_value(next()?)?,
+                }),
+                rule => Err(ParseError::InvalidInput(format!(
+                    "Unexpected load stage: {:?}",
+                    rule
+                ))),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(LoadProfile { stages })
+}
+
+// Parse `at <time> <action>` or `from <time> to <time> <injection>`
+fn parse_chaos_def(pair: Pair<Rule>) -> Result<ChaosAction, ParseError> {
+    let action = pair
+        .into_inner()
+        .next()
+        .ok_or_else(|| ParseError::InvalidInput("Expected chaos action".to_string()))?;
+    let rule = action.as_rule();
+    let mut inner_pairs = action.into_inner();
+    let at =
+        parse_time_value(inner_pairs.next().ok_or_else(|| {
+            ParseError::InvalidInput("Expected time of chaos action".to_string())
+        })?)?;
+    match rule {
+        Rule::scheduled_action => {
+            let action = inner_pairs
+                .next()
+                .ok_or_else(|| ParseError::InvalidInput("Expected chaos action".to_string()))?;
+            let kind = match action.as_rule() {
+                Rule::kill_action => ChaosKind::Kill(setting_value(action)?.as_str().to_string()),
+                Rule::restart_action => {
+                    ChaosKind::Restart(setting_value(action)?.as_str().to_string())
+                }
+                _ => ChaosKind::Inject {
+                    injection: parse_injection_pair(action)?,
+                    until: None,
+                },
+            };
+            Ok(ChaosAction { at, kind })
+        }
+        Rule::chaos_window => {
+            let (Some(until), Some(injection)) = (inner_pairs.next(), inner_pairs.next()) else {
+                return Err(ParseError::InvalidInput(
+                    "Expected end time and injection of chaos window".to_string(),
+                ));
+            };
+            let until = parse_time_value(until)?;
+            if until <= at {
+                return Err(ParseError::InvalidInput(format!(
+                    "Chaos window ends at {:?}, before it starts at {:?}",
+                    until, at
+                )));
+            }
+            Ok(ChaosAction {
+                at,
+                kind: ChaosKind::Inject {
+                    injection: parse_injection_pair(injection)?,
+                    until: Some(until),
+                },
+            })
+        }
+        rule => Err(ParseError::InvalidInput(format!(
+            "Unexpected chaos action: {:?}",
+            rule
+        ))),
+    }
+}
+
+// Parse a rate limit, the burst defaults to the calls per period
+fn parse_metric_view_pair(pair: Pair<Rule>) -> Result<MetricView, ParseError> {
+    let mut inner_pairs = pair.into_inner();
+    let instrument = inner_pairs
+        .next()
+        .ok_or_else(|| ParseError::InvalidInput("Expected instrument in metric view".to_string()))?
+        .as_str()
+        .to_string();
+    let action = inner_pairs.next().ok_or_else(|| {
+        ParseError::InvalidInput("Expected drop or rename in metric view".to_string())
+    })?;
+    let action = match action.as_rule() {
+        Rule::metric_drop => MetricViewAction::Drop,
+        _ => {
+            let mut rename = action.into_inner();
+            let name = rename
+                .next()
+                .ok_or_else(|| ParseError::InvalidInput("Expected new metric name".to_string()))?
+                .as_str()
+                .to_string();
+            let unit = rename.next().map(|unit| {
+                let unit = unit.as_str();
+                unit[1..unit.len() - 1].to_string()
+            });
+            MetricViewAction::Rename { name, unit }
+        }
+    };
+    Ok(MetricView { instrument, action })
+}
+
+fn parse_rate_limit_pair(pair: Pair<Rule>) -> Result<RateLimit, ParseError> {
+    let mut inner_pairs = pair.into_inner();
+    let service = inner_pairs
+        .next()
+        .ok_or_else(|| ParseError::InvalidInput("Expected service in rate limit".to_string()))?
+        .as_str()
+        .to_string();
+    let mut rate = inner_pairs
+        .next()
+        .ok_or_else(|| ParseError::InvalidInput("Expected rate like 50/s".to_string()))?
+        .into_inner();
+    let (Some(calls), Some(unit)) = (rate.next(), rate.next()) else {
+        return Err(ParseError::InvalidInput(
+            "Expected rate like 50/s".to_string(),
+        ));
+    };
+    let calls: usize = calls
+        .as_str()
+        .parse()
+        .map_err(|_| ParseError::InvalidInput(format!("Invalid number: {}", calls.as_str())))?;
+    let per = match unit.as_str() {
+        "ms" => Duration::from_millis(1),
+        "s" => Duration::from_secs(1),
+        "m" => Duration::from_secs(60),
+        unit => {
+            return Err(ParseError::InvalidInput(format!(
+                "Invalid time unit: {}",
+                unit
+            )))
+        }
+    };
+    let burst = match inner_pairs.next() {
+        Some(setting) => parse_number(setting)?,
+        None => calls,
+    };
+
+    if calls == 0 || burst == 0 {
+        return Err(ParseError::InvalidInput(
+            "Rate limits must allow at least 1 call".to_string(),
+        ));
+    }
+    Ok(RateLimit {
+        service,
+        calls,
+        per,
+        burst,
+    })
+}
+
+// Parse the route and priority of a call priority
+fn parse_call_priority_pair(pair: Pair<Rule>) -> Result<CallPriority, ParseError> {
+    let mut inner_pairs = pair.into_inner();
+    let route =
+        parse_route(inner_pairs.next().ok_or_else(|| {
+            ParseError::InvalidInput("Expected route in call priority".to_string())
+        })?)?;
+    let number = inner_pairs
+        .next()
+        .ok_or_else(|| ParseError::InvalidInput("Expected priority of the route".to_string()))?
+        .as_str();
+    let priority = number
+        .parse()
+        .map_err(|_| ParseError::InvalidInput(format!("Invalid number: {}", number)))?;
+    Ok(CallPriority { route, priority })
+}
+
+// Parse a retry policy, settings that are left out keep their defaults
+fn parse_retry_policy_pair(pair: Pair<Rule>) -> Result<RetryPolicy, ParseError> {
+    let mut inner_pairs = pair.into_inner();
+    let route =
+        parse_route(inner_pairs.next().ok_or_else(|| {
+            ParseError::InvalidInput("Expected route in retry policy".to_string())
+        })?)?;
+    let mut policy = RetryPolicy {
+        route,
+        attempts: 3,
+        backoff: Duration::from_millis(100),
+    };
+
+    for setting in inner_pairs {
+        let setting = setting
+            .into_inner()
+            .next()
+            .ok_or_else(|| ParseError::InvalidInput("Empty retry setting".to_string()))?;
+        match setting.as_rule() {
+            Rule::attempts_setting => policy.attempts = parse_number(setting)?,
+            Rule::backoff_setting => policy.backoff = parse_time_value(setting_value(setting)?)?,
+            rule => {
+                return Err(ParseError::InvalidInput(format!(
+                    "Unexpected retry setting: {:?}",
+                    rule
+                )))
+            }
+        }
+    }
+
+    if policy.attempts == 0 {
+        return Err(ParseError::InvalidInput(
+            "Retry attempts must be at least 1".to_string(),
+        ));
+    }
+    Ok(policy)
+}
+
+// Parse a circuit breaker, settings that are left out keep their defaults
+fn parse_circuit_breaker_pair(pair: Pair<Rule>) -> Result<CircuitBreakerConfig, ParseError> {
+    let mut inner_pairs = pair.into_inner();
+    let route = parse_route(inner_pairs.next().ok_or_else(|| {
+        ParseError::InvalidInput("Expected route in circuit breaker".to_string())
+    })?)?;
+    let mut config = CircuitBreakerConfig {
+        route,
+        failure_threshold: 0.5,
+        window: 10,
+        cooldown: Duration::from_secs(5),
+    };
+
+    for setting in inner_pairs {
+        let setting = setting
+            .into_inner()
+            .next()
+            .ok_or_else(|| ParseError::InvalidInput("Empty circuit breaker setting".to_string()))?;
+        let rule = setting.as_rule();
+        match rule {
+            Rule::threshold_setting => {
+                config.failure_threshold = parse_percentage(setting_value(setting)?)?
+            }
+            Rule::window_setting => config.window = parse_number(setting)?,
+            Rule::cooldown_setting => config.cooldown = parse_time_value(setting_value(setting)?)?,
+            _ => {
+                return Err(ParseError::InvalidInput(format!(
+                    "Unexpected circuit breaker setting: {:?}",
+                    rule
+                )))
+            }
+        }
+    }
+
+    if config.window == 0 {
+        return Err(ParseError::InvalidInput(
+            "Circuit breaker window must be at least 1".to_string(),
+        ));
+    }
+    Ok(config)
+}
+
+// Parse an injection
+fn parse_injection_pair(pair: Pair<Rule>) -> Result<Injection, ParseError> {
+    let injection = pair
+        .into_inner()
+        .next()
+        .ok_or_else(|| ParseError::InvalidInput("Expected injection".to_string()))?;
+
+    match injection.as_rule() {
+        Rule::latency_injection => {
+            let mut inner_pairs = injection.into_inner();
+            let route = parse_route(inner_pairs.next().ok_or_else(|| {
+                ParseError::InvalidInput("Expected route in latency injection".to_string())
+            })?)?;
+            let delay = parse_time_value(inner_pairs.next().ok_or_else(|| {
+                ParseError::InvalidInput("Expected delay in latency injection".to_string())
+            })?)?;
+            let jitter = match inner_pairs.next() {
+                Some(pair) => parse_time_value(pair)?,
+                None => Duration::ZERO,
+            };
+            Ok(Injection::Latency {
+                route,
+                delay,
+                jitter,
+            })
+        }
+        Rule::fault_injection => {
+            let mut inner_pairs = injection.into_inner();
+            let route = parse_route(inner_pairs.next().ok_or_else(|| {
+                ParseError::InvalidInput("Expected route in fault injection".to_string())
+            })?)?;
+            let mut drop_rate = 0.0;
+            let mut error_rate = 0.0;
+            for fault_rate in inner_pairs {
+                let mut fault_rate = fault_rate.into_inner();
+                let (Some(kind), Some(percentage)) = (fault_rate.next(), fault_rate.next()) else {
+                    return Err(ParseError::InvalidInput(
+                        "Expected a fault kind and a percentage".to_string(),
+                    ));
+                };
+                let rate = parse_percentage(percentage)?;
+                match kind.as_str() {
+                    "drop" => drop_rate = rate,
+                    "error" => error_rate = rate,
+                    kind => {
+                        return Err(ParseError::InvalidInput(format!("Unknown fault: {}", kind)))
+                    }
+                }
+            }
+            Ok(Injection::Faults {
+                route,
+                drop_rate,
+                error_rate,
+            })
+        }
+        rule => Err(ParseError::InvalidInput(format!(
+            "Unexpected injection: {:?}",
+            rule
+        ))),
+    }
+}
+
+// The value of a `keyword value` setting
+fn setting_value(setting: Pair<Rule>) -> Result<Pair<Rule>, ParseError> {
+    let rule = setting.as_rule();
+    setting
+        .into_inner()
+        .next()
+        .ok_or_else(|| ParseError::InvalidInput(format!("Expected a value for {:?}", rule)))
+}
+
+// Parse a percentage like `5%` or `0.5%` into a fraction between 0 and 1
+fn parse_percentage(pair: Pair<Rule>) -> Result<f64, ParseError> {
+    let text = pair.as_str().trim_end_matches('%');
+    let percent: f64 = text
+        .parse()
+        .map_err(|_| ParseError::InvalidInput(format!("Invalid percentage: {}", pair.as_str())))?;
+    if percent > 100.0 {
+        return Err(ParseError::InvalidInput(format!(
+            "Percentage above 100%: {}",
+            pair.as_str()
+        )));
+    }
+    Ok(percent / 100.0)
+}
+
+// Parse a route like `frontend->products`
+fn parse_route(pair: Pair<Rule>) -> Result<Route, ParseError> {
+    let mut endpoints = pair.into_inner().map(|p| p.as_str().to_string());
+    match (endpoints.next(), endpoints.next()) {
+        (Some(from), Some(to)) => Ok(Route { from, to }),
+        _ => Err(ParseError::InvalidInput(
+            "Expected a route like from->to".to_string(),
+        )),
+    }
 }
 
 // Parse a service definition
@@ -148,6 +1344,8 @@ fn parse_service(pair: Pair<Rule>) -> Result<Service, ParseError> {
 
     let mut methods = Vec::new();
     let mut loops = Vec::new();
+    let mut shutdown = None;
+    let mut config = None;
 
     // Parse method and loop definitions
     for pair in inner_pairs {
@@ -158,6 +1356,24 @@ fn parse_service(pair: Pair<Rule>) -> Result<Service, ParseError> {
             Rule::loop_def => {
                 loops.push(parse_loop(pair)?);
             }
+            Rule::config_def => {
+                if config.is_some() {
+                    return Err(ParseError::InvalidInput(format!(
+                        "Service {} has more than one config block",
+                        name
+                    )));
+                }
+                config = Some(parse_config(pair)?);
+            }
+            Rule::shutdown_def => {
+                if shutdown.is_some() {
+                    return Err(ParseError::InvalidInput(format!(
+                        "Service {} has more than one shutdown block",
+                        name
+                    )));
+                }
+                shutdown = Some(parse_shutdown(pair)?);
+            }
             _ => {}
         }
     }
@@ -166,9 +1382,114 @@ fn parse_service(pair: Pair<Rule>) -> Result<Service, ParseError> {
         name,
         methods,
         loops,
+        shutdown,
+        config: config.unwrap_or_default(),
     })
 }
 
+// Parse a config block
+fn parse_config(pair: Pair<Rule>) -> Result<ServiceConfig, ParseError> {
+    let mut config = ServiceConfig::default();
+
+    for entry in pair.into_inner() {
+        let setting = entry
+            .into_inner()
+            .next()
+            .ok_or_else(|| ParseError::InvalidInput("Empty config entry".to_string()))?;
+        let rule = setting.as_rule();
+        if rule == Rule::span_name_entry {
+            let template = setting.into_inner().next().ok_or_else(|| {
+                ParseError::InvalidInput("Expected span name template".to_string())
+            })?;
+            let template = template.as_str();
+            config.span_name = Some(parse_span_name(&template[1..template.len() - 1])?);
+            continue;
+        }
+        if rule == Rule::spike_entry {
+            let mut inner_pairs = setting.into_inner();
+            let (Some(rate), Some(to)) = (inner_pairs.next(), inner_pairs.next()) else {
+                return Err(ParseError::InvalidInput(
+                    "Expected rate and time in spike entry".to_string(),
+                ));
+            };
+            config.spike = Some(LatencySpike {
+                rate: parse_percentage(rate)?,
+                to: parse_time_value(to)?,
+            });
+            continue;
+        }
+        if matches!(rule, Rule::start_after_entry | Rule::stop_after_entry) {
+            let time_value = setting
+                .into_inner()
+                .next()
+                .ok_or_else(|| ParseError::InvalidInput("Expected time value".to_string()))?;
+            let time = Some(parse_time_value(time_value)?);
+            match rule {
+                Rule::start_after_entry => config.start_after = time,
+                _ => config.stop_after = time,
+            }
+            continue;
+        }
+        if rule == Rule::attribute_entry {
+            let mut inner_pairs = setting.into_inner();
+            let key = inner_pairs.next().ok_or_else(|| {
+                ParseError::InvalidInput("Expected key in attribute entry".to_string())
+            })?;
+            let value = inner_pairs.next().ok_or_else(|| {
+                ParseError::InvalidInput("Expected string literal in attribute entry".to_string())
+            })?;
+            let raw_value = value.as_str();
+            let value = &raw_value[1..raw_value.len() - 1];
+            if value.contains(',') {
+                return Err(ParseError::InvalidInput(format!(
+                    "The value of attribute {} must not contain a comma",
+                    key.as_str()
+                )));
+            }
+            config
+                .attributes
+                .insert(key.as_str().to_string(), value.to_string());
+            continue;
+        }
+        let value = parse_number(setting)?;
+        match rule {
+            Rule::max_instructions_entry => config.max_instructions = Some(value),
+            Rule::remote_call_limit_entry => config.remote_call_limit = Some(value),
+            Rule::print_rate_limit_entry => config.print_rate_limit = Some(value),
+            Rule::print_sample_rate_entry => config.print_sample_rate = Some(value),
+            Rule::replicas_entry => config.replicas = Some(value),
+            _ => {
+                return Err(ParseError::InvalidInput(format!(
+                    "Unexpected config entry: {:?}",
+                    rule
+                )))
+            }
+        }
+    }
+    if let (Some(start_after), Some(stop_after)) = (config.start_after, config.stop_after) {
+        if stop_after <= start_after {
+            return Err(ParseError::InvalidInput(
+                "stop_after must be later than start_after".to_string(),
+            ));
+        }
+    }
+
+    Ok(config)
+}
+
+// Parse the number value of a config entry
+fn parse_number(pair: Pair<Rule>) -> Result<usize, ParseError> {
+    let number_str = pair
+        .into_inner()
+        .find(|p| p.as_rule() == Rule::number)
+        .map(|p| p.as_str())
+        .ok_or_else(|| ParseError::InvalidInput("Expected number in config entry".to_string()))?;
+
+    number_str
+        .parse()
+        .map_err(|_| ParseError::InvalidInput(format!("Invalid number: {}", number_str)))
+}
+
 // Parse a method definition
 fn parse_method(pair: Pair<Rule>) -> Result<Method, ParseError> {
     let mut inner_pairs = pair.into_inner();
@@ -211,6 +1532,19 @@ fn parse_loop(pair: Pair<Rule>) -> Result<Loop, ParseError> {
     Ok(Loop { statements })
 }
 
+// Parse a shutdown block
+fn parse_shutdown(pair: Pair<Rule>) -> Result<Shutdown, ParseError> {
+    let mut statements = Vec::new();
+
+    for pair in pair.into_inner() {
+        if pair.as_rule() == Rule::statement {
+            statements.push(parse_statement(pair)?);
+        }
+    }
+
+    Ok(Shutdown { statements })
+}
+
 // Parse a statement
 fn parse_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
     let inner = pair
@@ -222,6 +1556,9 @@ fn parse_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
         Rule::print_stmt => parse_print_statement(inner),
         Rule::sleep_stmt => parse_sleep_statement(inner),
         Rule::call_stmt => parse_call_statement(inner),
+        Rule::baggage_stmt => parse_baggage_statement(inner),
+        Rule::access_log_stmt => parse_access_log_statement(inner),
+        Rule::extension_stmt => parse_extension_statement(inner),
         _ => Err(ParseError::InvalidInput(format!(
             "Unexpected statement type: {:?}",
             inner.as_rule()
@@ -230,8 +1567,71 @@ fn parse_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
 }
 
 // Parse a print statement
+// Parse templates like `["Cache hit": 80, "Cache miss": 20]`, a template without a weight has
+// weight 1
+fn parse_template_list(pair: Pair<Rule>) -> Result<Vec<Template>, ParseError> {
+    let mut templates = Vec::new();
+    for template_pair in pair.into_inner() {
+        let mut inner_pairs = template_pair.into_inner();
+        let raw_str = inner_pairs
+            .next()
+            .ok_or_else(|| ParseError::InvalidInput("Expected a template".to_string()))?
+            .as_str();
+        let weight = match inner_pairs.next() {
+            Some(weight) => weight.as_str().parse().map_err(|_| {
+                ParseError::InvalidInput(format!("Invalid number: {}", weight.as_str()))
+            })?,
+            None => 1,
+        };
+        templates.push(Template {
+            message: raw_str[1..raw_str.len() - 1].to_string(),
+            weight,
+        });
+    }
+    if templates.iter().all(|template| template.weight == 0) {
+        return Err(ParseError::InvalidInput(
+            "At least one weight must be above 0".to_string(),
+        ));
+    }
+    Ok(templates)
+}
+
+// Parse a severity mix like `severity [info: 95, error: 5]`
+fn parse_severity_mix(pair: Pair<Rule>) -> Result<SeverityMix, ParseError> {
+    let mut mix = SeverityMix { info: 0, error: 0 };
+    let mut seen = Vec::new();
+    for weight_pair in pair.into_inner() {
+        let mut inner_pairs = weight_pair.into_inner();
+        let (Some(level), Some(weight)) = (inner_pairs.next(), inner_pairs.next()) else {
+            return Err(ParseError::InvalidInput(
+                "Expected a severity and its weight".to_string(),
+            ));
+        };
+        let weight = weight.as_str().parse().map_err(|_| {
+            ParseError::InvalidInput(format!("Invalid number: {}", weight.as_str()))
+        })?;
+        if seen.contains(&level.as_str()) {
+            return Err(ParseError::InvalidInput(format!(
+                "Severity {} appears twice",
+                level.as_str()
+            )));
+        }
+        seen.push(level.as_str());
+        match level.as_str() {
+            "info" => mix.info = weight,
+            _ => mix.error = weight,
+        }
+    }
+    if mix.info == 0 && mix.error == 0 {
+        return Err(ParseError::InvalidInput(
+            "At least one weight must be above 0".to_string(),
+        ));
+    }
+    Ok(mix)
+}
+
 fn parse_print_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
-    let mut inner_pairs = pair.into_inner();
+    let mut inner_pairs = pair.into_inner().peekable();
 
     // Get the print channel (print or stderr)
     let channel_pair = inner_pairs.next().ok_or_else(|| {
@@ -240,47 +1640,100 @@ fn parse_print_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
 
     let is_stderr = channel_pair.as_str() == "stderr";
 
-    // Get the message string
+    // Get the message string, or the templates to pick one of
     let message_pair = inner_pairs.next().ok_or_else(|| {
         ParseError::InvalidInput("Expected string literal in print statement".to_string())
     })?;
 
-    let message = if message_pair.as_rule() == Rule::string_literal {
-        // Remove quotes from the string literal
-        let raw_str = message_pair.as_str();
-        raw_str[1..raw_str.len() - 1].to_string()
-    } else {
-        return Err(ParseError::InvalidInput(
-            "Expected string literal in print statement".to_string(),
-        ));
+    let (message, templates) = match message_pair.as_rule() {
+        Rule::string_literal => {
+            // Remove quotes from the string literal
+            let raw_str = message_pair.as_str();
+            (raw_str[1..raw_str.len() - 1].to_string(), None)
+        }
+        Rule::template_list => {
+            let templates = parse_template_list(message_pair)?;
+            (templates[0].message.clone(), Some(templates))
+        }
+        _ => {
+            return Err(ParseError::InvalidInput(
+                "Expected string literal in print statement".to_string(),
+            ))
+        }
     };
 
-    // Parse optional array literal for arguments
-    let args = if let Some(array_pair) = inner_pairs.find(|p| p.as_rule() == Rule::array_literal) {
-        let mut args = Vec::new();
-
-        for str_pair in array_pair.into_inner() {
-            if str_pair.as_rule() == Rule::string_literal {
-                let raw_str = str_pair.as_str();
+    // Parse optional array literal for arguments, with a weight per argument or without
+    let (args, weights) = match inner_pairs.next_if(|pair| pair.as_rule() != Rule::severity_mix) {
+        Some(array_pair) if array_pair.as_rule() == Rule::weighted_array_literal => {
+            let mut args = Vec::new();
+            let mut weights = Vec::new();
+            for value_pair in array_pair.into_inner() {
+                let mut value = value_pair.into_inner();
+                let (Some(arg), Some(weight)) = (value.next(), value.next()) else {
+                    return Err(ParseError::InvalidInput(
+                        "Expected a value and its weight".to_string(),
+                    ));
+                };
+                let raw_str = arg.as_str();
                 args.push(raw_str[1..raw_str.len() - 1].to_string());
+                weights.push(weight.as_str().parse().map_err(|_| {
+                    ParseError::InvalidInput(format!("Invalid number: {}", weight.as_str()))
+                })?);
+            }
+            if weights.iter().all(|weight| *weight == 0) {
+                return Err(ParseError::InvalidInput(
+                    "At least one weight must be above 0".to_string(),
+                ));
             }
+            (Some(args), Some(weights))
+        }
+        Some(array_pair) => {
+            let mut args = Vec::new();
+
+            for str_pair in array_pair.into_inner() {
+                if str_pair.as_rule() == Rule::string_literal {
+                    let raw_str = str_pair.as_str();
+                    args.push(raw_str[1..raw_str.len() - 1].to_string());
+                }
+            }
+
+            (Some(args), None)
         }
+        None => (None, None),
+    };
 
-        Some(args)
-    } else {
-        None
+    let severity_mix = match inner_pairs.next() {
+        Some(mix_pair) => Some(parse_severity_mix(mix_pair)?),
+        None => None,
     };
 
     if is_stderr {
-        Ok(Statement::Stderr { message, args })
+        if severity_mix.is_some() {
+            return Err(ParseError::InvalidInput(
+                "stderr always logs errors, use print for a severity mix".to_string(),
+            ));
+        }
+        Ok(Statement::Stderr {
+            message,
+            args,
+            weights,
+            templates,
+        })
     } else {
-        Ok(Statement::Stdout { message, args })
+        Ok(Statement::Stdout {
+            message,
+            args,
+            weights,
+            severity_mix,
+            templates,
+        })
     }
 }
 
 // Parse a sleep statement
 fn parse_sleep_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
-    let time_value_pair = pair.into_inner().next().ok_or_else(|| {
+    let mut inner_pairs = pair.into_inner();
+    let time_value_pair = inner_pairs.next().ok_or_else(|| {
         ParseError::InvalidInput("Expected time value in sleep statement".to_string())
     })?;
 
@@ -290,6 +1743,20 @@ fn parse_sleep_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
         ));
     }
 
+    let duration = parse_time_value(time_value_pair)?;
+    let jitter = match inner_pairs.next() {
+        Some(pair) if pair.as_rule() == Rule::percentage => {
+            let share = parse_percentage(pair)?;
+            Duration::from_millis((duration.as_millis() as f64 * share).round() as u64)
+        }
+        Some(pair) => parse_time_value(pair)?,
+        None => Duration::ZERO,
+    };
+    Ok(Statement::Sleep { duration, jitter })
+}
+
+// Parse a time value like `500ms` or `1s`
+fn parse_time_value(time_value_pair: Pair<Rule>) -> Result<Duration, ParseError> {
     let mut inner_pairs = time_value_pair.into_inner();
 
     let number_str = inner_pairs
@@ -318,18 +1785,15 @@ fn parse_sleep_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
         })
         .ok_or_else(|| ParseError::InvalidInput("Expected time unit in time value".to_string()))?;
 
-    let duration = match unit {
-        "ms" => Duration::from_millis(number),
-        "s" => Duration::from_secs(number),
-        _ => {
-            return Err(ParseError::InvalidInput(format!(
-                "Invalid time unit: {}",
-                unit
-            )))
-        }
-    };
-
-    Ok(Statement::Sleep { duration })
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        _ => Err(ParseError::InvalidInput(format!(
+            "Invalid time unit: {}",
+            unit
+        ))),
+    }
 }
 
 // Parse a call statement
@@ -367,6 +1831,51 @@ fn parse_call_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
     })
 }
 
+// Parse a baggage statement like `baggage tenant = "acme"`
+fn parse_baggage_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
+    let mut inner_pairs = pair.into_inner();
+    let key = inner_pairs
+        .next()
+        .ok_or_else(|| ParseError::InvalidInput("Expected key in baggage statement".to_string()))?;
+    let value = inner_pairs.next().ok_or_else(|| {
+        ParseError::InvalidInput("Expected string literal in baggage statement".to_string())
+    })?;
+    let raw_value = value.as_str();
+    Ok(Statement::Baggage {
+        key: key.as_str().to_string(),
+        value: raw_value[1..raw_value.len() - 1].to_string(),
+    })
+}
+
+// Parse an access log statement like `access_log alb`
+fn parse_access_log_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
+    let format = pair
+        .into_inner()
+        .next()
+        .and_then(|format| AccessLogFormat::from_name(format.as_str()))
+        .ok_or_else(|| ParseError::InvalidInput("Expected alb or cloudfront".to_string()))?;
+    Ok(Statement::AccessLog { format })
+}
+
+// Parse an extension statement like `ext hl7 "ADT^A01"`
+fn parse_extension_statement(pair: Pair<Rule>) -> Result<Statement, ParseError> {
+    let mut inner = pair.into_inner();
+    let name = inner
+        .next()
+        .ok_or_else(|| ParseError::InvalidInput("Expected extension name".to_string()))?;
+    let operand = inner
+        .next()
+        .map(|operand| {
+            let raw = operand.as_str();
+            raw[1..raw.len() - 1].to_string()
+        })
+        .unwrap_or_default();
+    Ok(Statement::Extension {
+        name: name.as_str().to_string(),
+        operand,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -388,6 +1897,32 @@ mod tests {
         assert_eq!(ast.services[0].methods[0].name, "get_products");
     }
 
+    #[test]
+    fn test_parse_access_log_statements() {
+        let service = "
+        service edge {
+            method serve {
+                access_log alb;
+                access_log cloudfront;
+            }
+        }
+        ";
+        let ast = parse(service).unwrap();
+
+        assert_eq!(
+            ast.services[0].methods[0].statements,
+            vec![
+                Statement::AccessLog {
+                    format: AccessLogFormat::Alb
+                },
+                Statement::AccessLog {
+                    format: AccessLogFormat::Cloudfront
+                },
+            ]
+        );
+        assert!(parse("service edge { method serve { access_log nginx; } }").is_err());
+    }
+
     #[test]
     fn test_parse_service_with_empty_var_list() {
         let service = "
@@ -409,6 +1944,9 @@ mod tests {
             Statement::Stdout {
                 message: "Fetching product orders %s".to_string(),
                 args: Some(vec![]),
+                weights: None,
+                severity_mix: None,
+                templates: None,
             }
         );
     }
@@ -435,12 +1973,16 @@ mod tests {
             Statement::Stdout {
                 message: "Fetching product orders %s".to_string(),
                 args: Some(vec![]),
+                weights: None,
+                severity_mix: None,
+                templates: None,
             }
         );
         assert_eq!(
             ast.services[0].methods[0].statements[1],
             Statement::Sleep {
                 duration: Duration::from_secs(1),
+                jitter: Duration::ZERO,
             }
         );
     }
@@ -467,6 +2009,9 @@ mod tests {
             Statement::Stdout {
                 message: "Fetching product orders %s".to_string(),
                 args: Some(vec![]),
+                weights: None,
+                severity_mix: None,
+                templates: None,
             }
         );
         assert_eq!(
@@ -474,8 +2019,526 @@ mod tests {
             Statement::Stderr {
                 message: "Error fetching product orders".to_string(),
                 args: None,
+                weights: None,
+                templates: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_service_with_shutdown_block() {
+        let service = "
+        service products {
+            method get_products {
+                print \"Fetching product orders\";
+            }
+
+            shutdown {
+                print \"Shutting down\";
+            }
+        }
+        ";
+        let ast = parse(service).unwrap();
+
+        let shutdown = ast.services[0].shutdown.as_ref().unwrap();
+        assert_eq!(
+            shutdown.statements,
+            vec![Statement::Stdout {
+                message: "Shutting down".to_string(),
+                args: None,
+                weights: None,
+                severity_mix: None,
+                templates: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_service_with_two_shutdown_blocks() {
+        let service = "
+        service products {
+            shutdown {
+                print \"Shutting down\";
+            }
+            shutdown {
+                print \"Shutting down again\";
+            }
+        }
+        ";
+        assert!(parse(service).is_err());
+    }
+
+    #[test]
+    fn test_parse_service_with_config() {
+        let service = "
+        service products {
+            config {
+                max_instructions 5000;
+                remote_call_limit 10;
+                print_rate_limit 100;
+                print_sample_rate 5;
+                replicas 3;
+                span_name \"HTTP GET /{method}\";
+                start_after 60s;
+                stop_after 10m;
+                attribute env = \"production\";
+                attribute k8s.pod.name = \"products-7d4b9\";
+                spike 1% of calls to 5s;
+            }
+
+            method get_products {
+                print \"Fetching product orders\";
+            }
+        }
+        ";
+        let ast = parse(service).unwrap();
+
+        assert_eq!(
+            ast.services[0].config,
+            ServiceConfig {
+                max_instructions: Some(5000),
+                remote_call_limit: Some(10),
+                print_rate_limit: Some(100),
+                print_sample_rate: Some(5),
+                replicas: Some(3),
+                span_name: Some("HTTP GET /{method}".to_string()),
+                start_after: Some(Duration::from_secs(60)),
+                stop_after: Some(Duration::from_secs(600)),
+                attributes: BTreeMap::from([
+                    ("env".to_string(), "production".to_string()),
+                    ("k8s.pod.name".to_string(), "products-7d4b9".to_string()),
+                ]),
+                spike: Some(LatencySpike {
+                    rate: 0.01,
+                    to: Duration::from_secs(5),
+                }),
+            }
+        );
+        assert!(parse(
+            "service products { config { attribute zones = \"a,b\"; } method get { print \"x\"; } }"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_span_name_rejects_unknown_placeholders() {
+        assert!(parse_span_name("{service}.{method}").is_ok());
+        assert!(parse_span_name("{service}.{function}").is_err());
+        assert!(parse_span_name("{service").is_err());
+    }
+
+    #[test]
+    fn test_parse_latency_injection() {
+        let program = "
+        inject latency frontend->products 200ms±50ms;
+        inject latency *->payments 1s;
+
+        service products {
+            method get_products {
+                print \"Fetching product orders\";
+            }
+        }
+        ";
+        let ast = parse(program).unwrap();
+
+        assert_eq!(
+            ast.injections,
+            vec![
+                Injection::Latency {
+                    route: Route {
+                        from: "frontend".to_string(),
+                        to: "products".to_string(),
+                    },
+                    delay: Duration::from_millis(200),
+                    jitter: Duration::from_millis(50),
+                },
+                Injection::Latency {
+                    route: Route {
+                        from: "*".to_string(),
+                        to: "payments".to_string(),
+                    },
+                    delay: Duration::from_secs(1),
+                    jitter: Duration::ZERO,
+                },
+            ]
+        );
+        assert_eq!(
+            parse_injection("latency *->payments 1s").unwrap(),
+            ast.injections[1]
+        );
+        assert!(parse_injection("latency frontend->products 1s extra").is_err());
+    }
+
+    #[test]
+    fn test_parse_fault_injection() {
+        assert_eq!(
+            parse_injection("faults frontend->payments drop 5% error 12.5%").unwrap(),
+            Injection::Faults {
+                route: Route {
+                    from: "frontend".to_string(),
+                    to: "payments".to_string(),
+                },
+                drop_rate: 0.05,
+                error_rate: 0.125,
+            }
+        );
+        assert!(parse_injection("faults frontend->payments error 150%").is_err());
+        assert!(parse_injection("faults frontend->payments").is_err());
+    }
+
+    #[test]
+    fn test_parse_circuit_breaker() {
+        let program = "
+        circuit_breaker frontend->products threshold 25% window 20 cooldown 500ms;
+        circuit_breaker *->payments;
+        ";
+        let ast = parse(program).unwrap();
+
+        assert_eq!(
+            ast.circuit_breakers,
+            vec![
+                CircuitBreakerConfig {
+                    route: Route {
+                        from: "frontend".to_string(),
+                        to: "products".to_string(),
+                    },
+                    failure_threshold: 0.25,
+                    window: 20,
+                    cooldown: Duration::from_millis(500),
+                },
+                CircuitBreakerConfig {
+                    route: Route {
+                        from: "*".to_string(),
+                        to: "payments".to_string(),
+                    },
+                    failure_threshold: 0.5,
+                    window: 10,
+                    cooldown: Duration::from_secs(5),
+                },
+            ]
+        );
+        assert!(parse_circuit_breaker("frontend->products window 0").is_err());
+    }
+
+    #[test]
+    fn test_parse_retry_policy() {
+        let ast = parse("retry frontend->products attempts 5 backoff 50ms;").unwrap();
+        assert_eq!(
+            ast.retry_policies,
+            vec![RetryPolicy {
+                route: Route {
+                    from: "frontend".to_string(),
+                    to: "products".to_string(),
+                },
+                attempts: 5,
+                backoff: Duration::from_millis(50),
+            }]
+        );
+        assert_eq!(
+            parse_retry_policy("*->products").unwrap(),
+            RetryPolicy {
+                route: Route {
+                    from: "*".to_string(),
+                    to: "products".to_string(),
+                },
+                attempts: 3,
+                backoff: Duration::from_millis(100),
+            }
+        );
+        assert!(parse_retry_policy("frontend->products attempts 0").is_err());
+    }
+
+    #[test]
+    fn test_parse_call_priority() {
+        let ast = parse("priority *->health 10;").unwrap();
+        assert_eq!(
+            ast.priorities,
+            vec![CallPriority {
+                route: Route {
+                    from: "*".to_string(),
+                    to: "health".to_string(),
+                },
+                priority: 10,
+            }]
+        );
+        assert_eq!(
+            parse_call_priority("frontend->products 2")
+                .unwrap()
+                .priority,
+            2
+        );
+        assert!(parse_call_priority("frontend->products").is_err());
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        let ast = parse("limit products 50/s;").unwrap();
+        assert_eq!(
+            ast.rate_limits,
+            vec![RateLimit {
+                service: "products".to_string(),
+                calls: 50,
+                per: Duration::from_secs(1),
+                burst: 50,
+            }]
+        );
+        assert_eq!(
+            parse_rate_limit("* 1/ms burst 5").unwrap(),
+            RateLimit {
+                service: "*".to_string(),
+                calls: 1,
+                per: Duration::from_millis(1),
+                burst: 5,
+            }
+        );
+        assert!(parse_rate_limit("products 50 / s").is_err());
+        assert!(parse_rate_limit("products 0/s").is_err());
+    }
+
+    #[test]
+    fn test_parse_metric_view() {
+        let ast = parse(
+            "
+            metric remote_invocation_counter rename rpc.client.calls unit \"{call}\";
+            metric instruction.duration drop;
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            ast.metric_views,
+            vec![
+                MetricView {
+                    instrument: "remote_invocation_counter".to_string(),
+                    action: MetricViewAction::Rename {
+                        name: "rpc.client.calls".to_string(),
+                        unit: Some("{call}".to_string()),
+                    },
+                },
+                MetricView {
+                    instrument: "instruction.duration".to_string(),
+                    action: MetricViewAction::Drop,
+                },
+            ]
+        );
+        assert_eq!(
+            parse_metric_view("local_invocation_counter rename calls").unwrap(),
+            MetricView {
+                instrument: "local_invocation_counter".to_string(),
+                action: MetricViewAction::Rename {
+                    name: "calls".to_string(),
+                    unit: None,
+                },
+            }
+        );
+        assert!(parse_metric_view("local_invocation_counter rename \"calls\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_chaos() {
+        let ast = parse(
+            "
+            at 2m kill products;
+            at 5m restart products;
+            from 3m to 4m latency frontend->api 1s;
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            ast.chaos,
+            vec![
+                ChaosAction {
+                    at: Duration::from_secs(120),
+                    kind: ChaosKind::Kill("products".to_string()),
+                },
+                ChaosAction {
+                    at: Duration::from_secs(300),
+                    kind: ChaosKind::Restart("products".to_string()),
+                },
+                ChaosAction {
+                    at: Duration::from_secs(180),
+                    kind: ChaosKind::Inject {
+                        injection: Injection::Latency {
+                            route: Route {
+                                from: "frontend".to_string(),
+                                to: "api".to_string(),
+                            },
+                            delay: Duration::from_secs(1),
+                            jitter: Duration::ZERO,
+                        },
+                        until: Some(Duration::from_secs(240)),
+                    },
+                },
+            ]
+        );
+        let chaos = parse_chaos("at 10s faults *->payments error 50%;").unwrap();
+        assert!(matches!(
+            chaos[0].kind,
+            ChaosKind::Inject { until: None, .. }
+        ));
+        assert!(parse_chaos("from 4m to 3m latency a->b 1s;").is_err());
+        assert!(parse_chaos("service products {}").is_err());
+    }
+
+    #[test]
+    fn test_parse_load_profile() {
+        let ast = parse("load { ramp 0->500 rps over 5m, hold 10m, ramp down 2m }").unwrap();
+        assert_eq!(
+            ast.load,
+            Some(LoadProfile {
+                stages: vec![
+                    LoadStage::Ramp {
+                        from: 0,
+                        to: 500,
+                        over: Duration::from_secs(300),
+                    },
+                    LoadStage::Hold {
+                        duration: Duration::from_secs(600),
+                    },
+                    LoadStage::RampDown {
+                        over: Duration::from_secs(120),
+                    },
+                ],
+            })
+        );
+        assert_eq!(
+            parse_load_profile("hold 1m").unwrap().stages,
+            vec![LoadStage::Hold {
+                duration: Duration::from_secs(60),
+            }]
+        );
+        assert!(parse("load { }").is_err());
+        assert!(parse("load { hold 1m } load { hold 2m }").is_err());
+        assert!(parse_load_profile("ramp 500 rps over 5m").is_err());
+    }
+
+    #[test]
+    fn test_parse_traffic_pattern() {
+        let ast = parse("pattern diurnal(peak: 14:00, amplitude: 5x);").unwrap();
+        assert_eq!(
+            ast.pattern,
+            Some(TrafficPattern::Diurnal {
+                peak: Duration::from_secs(14 * 3600),
+                amplitude: 5.0,
+                day: None,
+            })
+        );
+        assert_eq!(
+            parse_traffic_pattern("diurnal(day: 60m, peak: 9:30, amplitude: 1.5x)").unwrap(),
+            TrafficPattern::Diurnal {
+                peak: Duration::from_secs(9 * 3600 + 30 * 60),
+                amplitude: 1.5,
+                day: Some(Duration::from_secs(3600)),
+            }
+        );
+        assert_eq!(
+            parse_traffic_pattern("diurnal()").unwrap(),
+            TrafficPattern::Diurnal {
+                peak: Duration::from_secs(14 * 3600),
+                amplitude: 2.0,
+                day: None,
+            }
+        );
+        assert!(parse_traffic_pattern("diurnal(peak: 24:00)").is_err());
+        assert!(parse_traffic_pattern("diurnal(peak: 14:60)").is_err());
+        assert!(parse_traffic_pattern("diurnal(amplitude: 0.5x)").is_err());
+    }
+
+    #[test]
+    fn test_parse_incident() {
+        let ast = parse(
+            "
+            incident at 10m: products error_rate 0%->40% over 2m, recover over 5m;
+            incident at 30s: payments error_rate 5%->10% over 1s;
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            ast.incidents,
+            vec![
+                Incident {
+                    at: Duration::from_secs(600),
+                    service: "products".to_string(),
+                    from: 0.0,
+                    to: 0.4,
+                    over: Duration::from_secs(120),
+                    recover: Some(Duration::from_secs(300)),
+                },
+                Incident {
+                    at: Duration::from_secs(30),
+                    service: "payments".to_string(),
+                    from: 0.05,
+                    to: 0.1,
+                    over: Duration::from_secs(1),
+                    recover: None,
+                },
+            ]
+        );
+        assert!(parse("incident at 1m: products error_rate 0%->140% over 2m;").is_err());
+    }
+
+    #[test]
+    fn test_parse_timeline() {
+        let ast = parse(
+            r#"
+            timeline {
+                at 1m: deploy products v2.1 (latency +100ms, error_rate 5%, attribute region = "eu");
+                at 2m: deploy payments v7;
+                at 4m: rollback products;
+                at 5m: rollback;
             }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            ast.timeline,
+            vec![
+                TimelineEvent {
+                    at: Duration::from_secs(60),
+                    change: TimelineChange::Deploy(Deploy {
+                        service: "products".to_string(),
+                        version: "v2.1".to_string(),
+                        latency: Some(Duration::from_millis(100)),
+                        error_rate: Some(0.05),
+                        attributes: BTreeMap::from([("region".to_string(), "eu".to_string())]),
+                    }),
+                },
+                TimelineEvent {
+                    at: Duration::from_secs(120),
+                    change: TimelineChange::Deploy(Deploy {
+                        service: "payments".to_string(),
+                        version: "v7".to_string(),
+                        latency: None,
+                        error_rate: None,
+                        attributes: BTreeMap::new(),
+                    }),
+                },
+                TimelineEvent {
+                    at: Duration::from_secs(240),
+                    change: TimelineChange::Rollback {
+                        service: Some("products".to_string()),
+                    },
+                },
+                TimelineEvent {
+                    at: Duration::from_secs(300),
+                    change: TimelineChange::Rollback { service: None },
+                },
+            ]
         );
+        assert!(parse("timeline { at 1m: deploy products (latency +1s); }").is_err());
+    }
+
+    #[test]
+    fn test_parse_service_without_config() {
+        let service = "
+        service products {
+            method get_products {
+                print \"Fetching product orders\";
+            }
+        }
+        ";
+        let ast = parse(service).unwrap();
+
+        assert_eq!(ast.services[0].config, ServiceConfig::default());
     }
 
     #[test]
@@ -510,4 +2573,193 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_parse_sleep_with_jitter() {
+        let ast = parse(
+            "
+        service payments {
+            method charge {
+                sleep 1s±200ms;
+                sleep 500ms+-10%;
+            }
+        }
+        ",
+        )
+        .unwrap();
+        assert_eq!(
+            ast.services[0].methods[0].statements,
+            [
+                Statement::Sleep {
+                    duration: Duration::from_secs(1),
+                    jitter: Duration::from_millis(200),
+                },
+                Statement::Sleep {
+                    duration: Duration::from_millis(500),
+                    jitter: Duration::from_millis(50),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_severity_mix() {
+        let ast = parse(
+            r#"
+        service payments {
+            method charge {
+                print "Charged order %s" with ["12345"] severity [info: 95, error: 5];
+                print "Charged" severity [error: 1];
+            }
+        }
+        "#,
+        )
+        .unwrap();
+        let statements = &ast.services[0].methods[0].statements;
+        assert_eq!(
+            statements[0],
+            Statement::Stdout {
+                message: "Charged order %s".to_string(),
+                args: Some(vec!["12345".to_string()]),
+                weights: None,
+                severity_mix: Some(SeverityMix { info: 95, error: 5 }),
+                templates: None,
+            }
+        );
+        assert_eq!(
+            statements[1],
+            Statement::Stdout {
+                message: "Charged".to_string(),
+                args: None,
+                weights: None,
+                severity_mix: Some(SeverityMix { info: 0, error: 1 }),
+                templates: None,
+            }
+        );
+
+        for invalid in [
+            r#"stderr "Charged" severity [info: 95, error: 5];"#,
+            r#"print "Charged" severity [info: 1, info: 2];"#,
+            r#"print "Charged" severity [info: 0];"#,
+        ] {
+            let source = format!("service payments {{ method charge {{ {} }} }}", invalid);
+            assert!(parse(&source).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_weighted_print() {
+        let ast = parse(
+            r#"
+        service payments {
+            method charge {
+                stderr "Connection %s" with ["timeout": 80, "refused": 20];
+            }
+        }
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            ast.services[0].methods[0].statements[0],
+            Statement::Stderr {
+                message: "Connection %s".to_string(),
+                args: Some(vec!["timeout".to_string(), "refused".to_string()]),
+                weights: Some(vec![80, 20]),
+                templates: None,
+            }
+        );
+
+        let zero =
+            parse(r#"service payments { method charge { print "%s" with ["timeout": 0]; } }"#);
+        assert!(zero.is_err());
+    }
+
+    #[test]
+    fn test_program_serializes_to_json() {
+        let ast = parse(
+            "
+            service products {
+                method get_products {
+                    sleep 200ms;
+                    call inventory.count;
+                }
+            }
+            inject latency frontend->products 100ms;
+            ",
+        )
+        .unwrap();
+        let json = serde_json::to_value(&ast).unwrap();
+        assert_eq!(
+            json["services"][0]["methods"][0]["statements"],
+            serde_json::json!([
+                { "sleep": { "duration_ms": 200 } },
+                { "call": { "service": "inventory", "method": "count" } },
+            ])
+        );
+        assert_eq!(
+            json["injections"][0]["latency"]["route"],
+            serde_json::json!({ "from": "frontend", "to": "products" })
+        );
+        assert_eq!(json["injections"][0]["latency"]["delay_ms"], 100);
+    }
+
+    #[test]
+    fn test_program_round_trips_through_json() {
+        let ast = parse(
+            r#"
+            service products {
+                config {
+                    replicas 2;
+                }
+
+                method get_products {
+                    print "Fetching product %s" with ["12345"];
+                    sleep 200ms;
+                    access_log alb;
+                }
+
+                shutdown {
+                    print "Bye";
+                }
+            }
+            retry frontend->products attempts 3 backoff 50ms;
+            from 3m to 4m latency frontend->products 1s;
+            "#,
+        )
+        .unwrap();
+        let json = serde_json::to_value(&ast).unwrap();
+        let deserialized: Program = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&deserialized).unwrap(), json);
+
+        // Settings a generator leaves out are empty
+        let minimal: Program = serde_json::from_value(serde_json::json!({
+            "services": [{
+                "name": "products",
+                "methods": [{ "name": "get_products", "statements": [{ "sleep": { "duration_ms": 5 } }] }],
+                "loops": [],
+                "shutdown": null,
+                "config": {}
+            }]
+        }))
+        .unwrap();
+        assert_eq!(
+            minimal.services[0].methods[0].statements,
+            [Statement::Sleep {
+                duration: Duration::from_millis(5),
+                jitter: Duration::ZERO,
+            }]
+        );
+        assert!(minimal.injections.is_empty());
+    }
+
+    #[test]
+    fn test_shipped_schema_is_up_to_date() {
+        let shipped: serde_json::Value =
+            serde_json::from_str(include_str!("../../schema/program.schema.json")).unwrap();
+        assert_eq!(
+            shipped,
+            json_schema(),
+            "regenerate it with `mustermann schema > schema/program.schema.json`"
+        );
+    }
 }
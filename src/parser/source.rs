@@ -0,0 +1,624 @@
+//! Writes a [`Program`] back as DSL source, e.g. to migrate YAML scenarios to `.muster` files.
+//! Parsing the source gives the same program.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use super::{
+    AccessLogFormat, ChaosAction, ChaosKind, Injection, LoadStage, MetricViewAction, Program,
+    Route, ServiceConfig, Statement, Template, TimelineChange, TimelineEvent, TrafficPattern,
+};
+
+/// The program holds something the DSL cannot express, like a quote in a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceError(pub String);
+
+impl std::error::Error for SourceError {}
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Program {
+    /// The program as DSL source, with the settings after the services
+    pub fn to_source(&self) -> Result<String, SourceError> {
+        let mut out = String::new();
+        for service in &self.services {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            writeln!(out, "service {} {{", identifier(&service.name)?).unwrap();
+            let mut blocks = Vec::new();
+            if service.config != ServiceConfig::default() {
+                blocks.push(config(&service.config)?);
+            }
+            for method in &service.methods {
+                let header = format!("method {}", identifier(&method.name)?);
+                blocks.push(block(&header, &method.statements)?);
+            }
+            for service_loop in &service.loops {
+                blocks.push(block("loop", &service_loop.statements)?);
+            }
+            if let Some(shutdown) = &service.shutdown {
+                blocks.push(block("shutdown", &shutdown.statements)?);
+            }
+            out.push_str(&blocks.join("\n"));
+            out.push_str("}\n");
+        }
+
+        let mut settings = Vec::new();
+        for injection in &self.injections {
+            settings.push(format!("inject {};", self::injection(injection)?));
+        }
+        for breaker in &self.circuit_breakers {
+            settings.push(format!(
+                "circuit_breaker {} threshold {} window {} cooldown {};",
+                route(&breaker.route)?,
+                percentage(breaker.failure_threshold),
+                breaker.window,
+                time(breaker.cooldown)?
+            ));
+        }
+        for policy in &self.retry_policies {
+            settings.push(format!(
+                "retry {} attempts {} backoff {};",
+                route(&policy.route)?,
+                policy.attempts,
+                time(policy.backoff)?
+            ));
+        }
+        for priority in &self.priorities {
+            settings.push(format!(
+                "priority {} {};",
+                route(&priority.route)?,
+                priority.priority
+            ));
+        }
+        for limit in &self.rate_limits {
+            let unit = match limit.per.as_millis() {
+                1 => "ms",
+                1000 => "s",
+                60_000 => "m",
+                _ => {
+                    return Err(SourceError(format!(
+                        "Rate limit of {} is per {:?}, but only per ms, s or m can be written",
+                        limit.service, limit.per
+                    )))
+                }
+            };
+            let mut setting = format!(
+                "limit {} {}/{}",
+                endpoint(&limit.service)?,
+                limit.calls,
+                unit
+            );
+            if limit.burst != limit.calls {
+                write!(setting, " burst {}", limit.burst).unwrap();
+            }
+            settings.push(setting + ";");
+        }
+        for action in &self.chaos {
+            settings.push(chaos(action)?);
+        }
+        for incident in &self.incidents {
+            let mut setting = format!(
+                "incident at {}: {} error_rate {}->{} over {}",
+                time(incident.at)?,
+                identifier(&incident.service)?,
+                percentage(incident.from),
+                percentage(incident.to),
+                time(incident.over)?
+            );
+            if let Some(recover) = incident.recover {
+                write!(setting, ", recover over {}", time(recover)?).unwrap();
+            }
+            settings.push(setting + ";");
+        }
+        if !self.timeline.is_empty() {
+            let mut setting = "timeline {\n".to_string();
+            for event in &self.timeline {
+                writeln!(setting, "    {};", timeline_event(event)?).unwrap();
+            }
+            settings.push(setting + "}");
+        }
+        for view in &self.metric_views {
+            let action = match &view.action {
+                MetricViewAction::Drop => "drop".to_string(),
+                MetricViewAction::Rename { name, unit } => {
+                    let mut rename = format!("rename {}", instrument(name)?);
+                    if let Some(unit) = unit {
+                        write!(rename, " unit {}", string(unit)?).unwrap();
+                    }
+                    rename
+                }
+            };
+            settings.push(format!(
+                "metric {} {};",
+                instrument(&view.instrument)?,
+                action
+            ));
+        }
+        if let Some(load) = &self.load {
+            let stages = load
+                .stages
+                .iter()
+                .map(|stage| {
+                    Ok(match stage {
+                        LoadStage::Ramp { from, to, over } => {
+                            format!("ramp {}->{} rps over {}", from, to, time(*over)?)
+                        }
+                        LoadStage::Hold { duration } => format!("hold {}", time(*duration)?),
+                        LoadStage::RampDown { over } => format!("ramp down {}", time(*over)?),
+                    })
+                })
+                .collect::<Result<Vec<_>, SourceError>>()?;
+            if stages.is_empty() {
+                return Err(SourceError("The load profile has no stages".to_string()));
+            }
+            settings.push(format!("load {{ {} }}", stages.join(", ")));
+        }
+        if let Some(TrafficPattern::Diurnal {
+            peak,
+            amplitude,
+            day,
+        }) = &self.pattern
+        {
+            let minutes = peak.as_secs() / 60;
+            if Duration::from_secs(minutes * 60) != *peak || minutes >= 24 * 60 {
+                return Err(SourceError(format!(
+                    "The peak at {:?} is not a time of day in whole minutes",
+                    peak
+                )));
+            }
+            let mut pattern = format!(
+                "pattern diurnal(peak: {}:{:02}, amplitude: {}x",
+                minutes / 60,
+                minutes % 60,
+                amplitude
+            );
+            if let Some(day) = day {
+                write!(pattern, ", day: {}", time(*day)?).unwrap();
+            }
+            settings.push(pattern + ");");
+        }
+        if !settings.is_empty() {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            for setting in settings {
+                writeln!(out, "{}", setting).unwrap();
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn config(config: &ServiceConfig) -> Result<String, SourceError> {
+    let mut entries = Vec::new();
+    let numbers = [
+        ("max_instructions", config.max_instructions),
+        ("remote_call_limit", config.remote_call_limit),
+        ("print_rate_limit", config.print_rate_limit),
+        ("print_sample_rate", config.print_sample_rate),
+        ("replicas", config.replicas),
+    ];
+    for (name, value) in numbers {
+        if let Some(value) = value {
+            entries.push(format!("{} {};", name, value));
+        }
+    }
+    if let Some(span_name) = &config.span_name {
+        entries.push(format!("span_name {};", string(span_name)?));
+    }
+    let windows = [
+        ("start_after", config.start_after),
+        ("stop_after", config.stop_after),
+    ];
+    for (name, window) in windows {
+        if let Some(window) = window {
+            entries.push(format!("{} {};", name, time(window)?));
+        }
+    }
+    for (key, value) in &config.attributes {
+        if !is_name(key, &['_', '.', '-']) {
+            return Err(SourceError(format!(
+                "{:?} is not a valid attribute key",
+                key
+            )));
+        }
+        entries.push(format!("attribute {} = {};", key, string(value)?));
+    }
+    if let Some(spike) = &config.spike {
+        entries.push(format!(
+            "spike {} of calls to {};",
+            percentage(spike.rate),
+            time(spike.to)?
+        ));
+    }
+    Ok(indented("config", &entries))
+}
+
+fn block(header: &str, statements: &[Statement]) -> Result<String, SourceError> {
+    let lines = statements
+        .iter()
+        .map(|s| statement(s).map(|s| s + ";"))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(indented(header, &lines))
+}
+
+fn indented(header: &str, lines: &[String]) -> String {
+    let mut out = format!("    {} {{\n", header);
+    for line in lines {
+        writeln!(out, "        {}", line).unwrap();
+    }
+    out + "    }\n"
+}
+
+fn statement(statement: &Statement) -> Result<String, SourceError> {
+    Ok(match statement {
+        Statement::Stdout {
+            message,
+            args,
+            weights,
+            severity_mix,
+            templates,
+        } => {
+            let mut out = print("print", message, templates, args, weights)?;
+            if let Some(mix) = severity_mix {
+                write!(out, " severity [info: {}, error: {}]", mix.info, mix.error).unwrap();
+            }
+            out
+        }
+        Statement::Stderr {
+            message,
+            args,
+            weights,
+            templates,
+        } => print("stderr", message, templates, args, weights)?,
+        Statement::Sleep { duration, jitter } if jitter.is_zero() => {
+            format!("sleep {}", time(*duration)?)
+        }
+        Statement::Sleep { duration, jitter } => {
+            format!("sleep {}±{}", time(*duration)?, time(*jitter)?)
+        }
+        Statement::Call {
+            service: Some(service),
+            method,
+        } => format!("call {}.{}", identifier(service)?, identifier(method)?),
+        Statement::Call {
+            service: None,
+            method,
+        } => format!("call {}", identifier(method)?),
+        Statement::Baggage { key, value } => {
+            if !is_name(key, &['_', '.', '-']) {
+                return Err(SourceError(format!("{:?} is not a valid baggage key", key)));
+            }
+            format!("baggage {} = {}", key, string(value)?)
+        }
+        Statement::AccessLog { format } => match format {
+            AccessLogFormat::Alb => "access_log alb".to_string(),
+            AccessLogFormat::Cloudfront => "access_log cloudfront".to_string(),
+        },
+        Statement::Extension { name, operand } if operand.is_empty() => {
+            format!("ext {}", identifier(name)?)
+        }
+        Statement::Extension { name, operand } => {
+            format!("ext {} {}", identifier(name)?, string(operand)?)
+        }
+    })
+}
+
+fn print(
+    channel: &str,
+    message: &str,
+    templates: &Option<Vec<Template>>,
+    args: &Option<Vec<String>>,
+    weights: &Option<Vec<u64>>,
+) -> Result<String, SourceError> {
+    let mut out = match templates {
+        Some(templates) => {
+            let templates = templates
+                .iter()
+                .map(|template| {
+                    string(&template.message)
+                        .map(|message| format!("{}: {}", message, template.weight))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            format!("{} [{}]", channel, templates.join(", "))
+        }
+        None => format!("{} {}", channel, string(message)?),
+    };
+    if let (Some(args), Some(weights)) = (args, weights) {
+        if args.len() != weights.len() {
+            return Err(SourceError(format!(
+                "{:?} has {} values and {} weights",
+                message,
+                args.len(),
+                weights.len()
+            )));
+        }
+        let args = args
+            .iter()
+            .zip(weights)
+            .map(|(arg, weight)| string(arg).map(|arg| format!("{}: {}", arg, weight)))
+            .collect::<Result<Vec<_>, _>>()?;
+        write!(out, " with [{}]", args.join(", ")).unwrap();
+    } else if let Some(args) = args {
+        let args = args
+            .iter()
+            .map(|arg| string(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        write!(out, " with [{}]", args.join(", ")).unwrap();
+    }
+    Ok(out)
+}
+
+fn injection(injection: &Injection) -> Result<String, SourceError> {
+    Ok(match injection {
+        Injection::Latency {
+            route: r,
+            delay,
+            jitter,
+        } if jitter.is_zero() => format!("latency {} {}", route(r)?, time(*delay)?),
+        Injection::Latency {
+            route: r,
+            delay,
+            jitter,
+        } => format!("latency {} {}±{}", route(r)?, time(*delay)?, time(*jitter)?),
+        Injection::Faults {
+            route: r,
+            drop_rate,
+            error_rate,
+        } => {
+            let mut out = format!("faults {}", route(r)?);
+            // One rate is needed, even if the faults do nothing
+            if *drop_rate > 0.0 || *error_rate == 0.0 {
+                write!(out, " drop {}", percentage(*drop_rate)).unwrap();
+            }
+            if *error_rate > 0.0 {
+                write!(out, " error {}", percentage(*error_rate)).unwrap();
+            }
+            out
+        }
+    })
+}
+
+fn chaos(action: &ChaosAction) -> Result<String, SourceError> {
+    let at = time(action.at)?;
+    Ok(match &action.kind {
+        ChaosKind::Kill(service) => format!("at {} kill {};", at, identifier(service)?),
+        ChaosKind::Restart(service) => format!("at {} restart {};", at, identifier(service)?),
+        ChaosKind::Inject {
+            injection: i,
+            until: None,
+        } => format!("at {} {};", at, injection(i)?),
+        ChaosKind::Inject {
+            injection: i,
+            until: Some(until),
+        } => format!("from {} to {} {};", at, time(*until)?, injection(i)?),
+    })
+}
+
+fn timeline_event(event: &TimelineEvent) -> Result<String, SourceError> {
+    let deploy = match &event.change {
+        TimelineChange::Deploy(deploy) => deploy,
+        TimelineChange::Rollback { service: None } => {
+            return Ok(format!("at {}: rollback", time(event.at)?))
+        }
+        TimelineChange::Rollback {
+            service: Some(service),
+        } => {
+            return Ok(format!(
+                "at {}: rollback {}",
+                time(event.at)?,
+                identifier(service)?
+            ))
+        }
+    };
+    let version = &deploy.version;
+    if version.is_empty()
+        || !version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ['.', '-', '_'].contains(&c))
+    {
+        return Err(SourceError(format!("{:?} is not a valid version", version)));
+    }
+    let mut changes = Vec::new();
+    if let Some(latency) = deploy.latency {
+        changes.push(format!("latency +{}", time(latency)?));
+    }
+    if let Some(error_rate) = deploy.error_rate {
+        changes.push(format!("error_rate {}", percentage(error_rate)));
+    }
+    for (key, value) in &deploy.attributes {
+        if !is_name(key, &['_', '.', '-']) {
+            return Err(SourceError(format!(
+                "{:?} is not a valid attribute key",
+                key
+            )));
+        }
+        changes.push(format!("attribute {} = {}", key, string(value)?));
+    }
+    let mut line = format!(
+        "at {}: deploy {} {}",
+        time(event.at)?,
+        identifier(&deploy.service)?,
+        version
+    );
+    if !changes.is_empty() {
+        write!(line, " ({})", changes.join(", ")).unwrap();
+    }
+    Ok(line)
+}
+
+fn route(route: &Route) -> Result<String, SourceError> {
+    Ok(format!(
+        "{}->{}",
+        endpoint(&route.from)?,
+        endpoint(&route.to)?
+    ))
+}
+
+fn endpoint(name: &str) -> Result<&str, SourceError> {
+    if name == "*" {
+        Ok(name)
+    } else {
+        identifier(name)
+    }
+}
+
+fn identifier(name: &str) -> Result<&str, SourceError> {
+    if is_name(name, &['_']) {
+        Ok(name)
+    } else {
+        Err(SourceError(format!("{:?} is not a valid name", name)))
+    }
+}
+
+fn instrument(name: &str) -> Result<&str, SourceError> {
+    if is_name(name, &['_', '.', '-', '/']) {
+        Ok(name)
+    } else {
+        Err(SourceError(format!(
+            "{:?} is not a valid instrument name",
+            name
+        )))
+    }
+}
+
+// A letter, then letters, digits and `extra`
+fn is_name(name: &str, extra: &[char]) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || extra.contains(&c))
+}
+
+// String literals have no escapes, so a quote cannot be written
+fn string(s: &str) -> Result<String, SourceError> {
+    if s.contains('"') {
+        return Err(SourceError(format!("{:?} contains a quote", s)));
+    }
+    Ok(format!("\"{}\"", s))
+}
+
+// In the largest unit that divides the duration
+fn time(duration: Duration) -> Result<String, SourceError> {
+    let ms = duration.as_millis();
+    if Duration::from_millis(ms as u64) != duration {
+        return Err(SourceError(format!(
+            "{:?} is not a whole number of milliseconds",
+            duration
+        )));
+    }
+    Ok(match ms {
+        0 => "0ms".to_string(),
+        ms if ms % 60_000 == 0 => format!("{}m", ms / 60_000),
+        ms if ms % 1000 == 0 => format!("{}s", ms / 1000),
+        ms => format!("{}ms", ms),
+    })
+}
+
+// A fraction as a percentage, rounded to hide floating point noise like 7.000000000000001%
+fn percentage(fraction: f64) -> String {
+    format!("{}%", (fraction * 100.0 * 1e6).round() / 1e6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn test_source_parses_to_the_same_program() {
+        let source = r#"
+        service products {
+            config {
+                replicas 2;
+                span_name "{service}.{method}";
+                start_after 1m;
+                stop_after 90s;
+                attribute env = "production";
+                spike 0.5% of calls to 5s;
+            }
+
+            method get_products {
+                print "Fetching product %s" with ["12345", "67890"];
+                print "Checked stock" severity [info: 95, error: 5];
+                print ["Cache hit for %s": 9, "Cache miss for %s": 1] with ["12345"];
+                baggage tenant.id = "acme";
+                sleep 1500ms;
+                sleep 1s±200ms;
+                stderr "Out of stock";
+                stderr "Connection %s" with ["timeout": 80, "refused": 20];
+                access_log cloudfront;
+                ext hl7 "ADT^A01";
+            }
+
+            shutdown {
+                print "Bye";
+            }
+        }
+
+        service frontend {
+            method main_page {
+                call products.get_products;
+                sleep 2m;
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+
+        inject latency frontend->products 200ms±50ms;
+        inject faults *->products drop 5% error 0.5%;
+        circuit_breaker frontend->products threshold 50% window 20 cooldown 10s;
+        retry frontend->products attempts 3 backoff 50ms;
+        priority frontend->products 2;
+        limit products 50/s burst 10;
+        limit * 100/m;
+        at 1m kill products;
+        at 90s restart products;
+        from 3m to 4m faults frontend->products error 7%;
+        incident at 10m: products error_rate 0%->40% over 2m, recover over 5m;
+        incident at 20m: products error_rate 5%->10% over 30s;
+        timeline {
+            at 1m: deploy products v2.1 (latency +100ms, error_rate 5%, attribute region = "eu");
+            at 2m: deploy frontend v7;
+            at 4m: rollback products;
+            at 5m: rollback;
+        }
+        metric http.server.duration rename http_duration unit "ms";
+        metric vm.instructions drop;
+        load { ramp 0->500 rps over 5m, hold 10m, ramp down 90s }
+        pattern diurnal(peak: 9:30, amplitude: 2.5x, day: 60m);
+        "#;
+        let program = parse(source).unwrap();
+        let written = program.to_source().unwrap();
+        assert_eq!(
+            serde_json::to_value(parse(&written).unwrap()).unwrap(),
+            serde_json::to_value(&program).unwrap(),
+            "{}",
+            written
+        );
+        assert!(written.contains("    method main_page {\n        call products.get_products;\n        sleep 2m;\n    }\n"));
+    }
+
+    #[test]
+    fn test_what_the_dsl_cannot_express_is_an_error() {
+        let program = Program::builder()
+            .service("products")
+            .method("get_products", |m| m.print("Say \"cheese\""))
+            .build();
+        assert_eq!(
+            program.to_source(),
+            Err(SourceError(
+                "\"Say \\\"cheese\\\"\" contains a quote".to_string()
+            ))
+        );
+        let program = Program::builder().service("product service").build();
+        assert!(program.to_source().is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::{Duration, Instant};
+
+/// Decides which print messages of a service make it to the output.
+/// Sampling keeps every k-th message, the rate limit caps the messages per second that pass sampling.
+#[derive(Debug, Clone, Default)]
+pub struct PrintLimiter {
+    rate_limit: Option<usize>,
+    sample_rate: Option<usize>,
+    window_start: Option<Instant>,
+    window_count: usize,
+    seen: usize,
+    dropped: usize,
+}
+
+impl PrintLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lets at most `limit` messages per second through
+    pub fn with_rate_limit(mut self, limit: usize) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Keeps one in `rate` messages
+    pub fn with_sample_rate(mut self, rate: usize) -> Self {
+        self.sample_rate = Some(rate.max(1));
+        self
+    }
+
+    /// Returns whether the message arriving at `now` should be printed
+    pub fn allow(&mut self, now: Instant) -> bool {
+        self.seen += 1;
+        if let Some(sample_rate) = self.sample_rate {
+            if !(self.seen - 1).is_multiple_of(sample_rate) {
+                self.dropped += 1;
+                return false;
+            }
+        }
+
+        if let Some(rate_limit) = self.rate_limit {
+            match self.window_start {
+                Some(start) if now.duration_since(start) < Duration::from_secs(1) => {}
+                _ => {
+                    self.window_start = Some(now);
+                    self.window_count = 0;
+                }
+            }
+            if self.window_count >= rate_limit {
+                self.dropped += 1;
+                return false;
+            }
+            self.window_count += 1;
+        }
+
+        true
+    }
+
+    /// The number of messages that were not printed
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_allows_everything() {
+        let mut limiter = PrintLimiter::new();
+        let now = Instant::now();
+        assert!((0..100).all(|_| limiter.allow(now)));
+        assert_eq!(limiter.dropped(), 0);
+    }
+
+    #[test]
+    fn test_rate_limit_per_second() {
+        let mut limiter = PrintLimiter::new().with_rate_limit(2);
+        let now = Instant::now();
+        assert!(limiter.allow(now));
+        assert!(limiter.allow(now));
+        assert!(!limiter.allow(now + Duration::from_millis(500)));
+        assert!(limiter.allow(now + Duration::from_secs(1)));
+        assert_eq!(limiter.dropped(), 1);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut limiter = PrintLimiter::new().with_sample_rate(3);
+        let now = Instant::now();
+        let allowed: Vec<bool> = (0..6).map(|_| limiter.allow(now)).collect();
+        assert_eq!(allowed, vec![true, false, false, true, false, false]);
+        assert_eq!(limiter.dropped(), 4);
+    }
+}
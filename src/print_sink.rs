@@ -0,0 +1,173 @@
+//! Where a [`crate::vm::VM`] sends what its service prints. The CLI queues the prints and logs
+//! them with [`TracingSink`], embedders pass a sink of their own to capture them.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+
+use crate::log_format;
+use crate::vm::{Print, PrintMessage};
+
+/// The sink did not take a print, e.g. because nobody receives from it anymore
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintSinkError(pub String);
+
+impl std::error::Error for PrintSinkError {}
+
+impl std::fmt::Display for PrintSinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Takes everything a VM prints. The VM waits for `print`, so a slow sink slows the service down
+#[tonic::async_trait]
+pub trait PrintSink: Send + Sync {
+    async fn print(&self, print: Print) -> Result<(), PrintSinkError>;
+}
+
+/// Queues the prints for a task that handles them apart from the VM
+#[tonic::async_trait]
+impl PrintSink for mpsc::Sender<Print> {
+    async fn print(&self, print: Print) -> Result<(), PrintSinkError> {
+        self.send(print)
+            .await
+            .map_err(|e| PrintSinkError(e.to_string()))
+    }
+}
+
+/// Logs prints under the `service` target, stdout at info and stderr at error level. Prints in a
+/// span name its trace and span, to jump from the log to the trace
+pub struct TracingSink {
+    app_name: String,
+    instance: String,
+    /// The attributes of the service, joined as the `attributes` field
+    attributes: Option<String>,
+    /// The attributes from each time after `started` on, as deploys change them
+    attribute_changes: Vec<(Duration, Option<String>)>,
+    started: Instant,
+}
+
+impl TracingSink {
+    pub fn new(app_name: &str, instance: &str) -> Self {
+        Self {
+            app_name: app_name.to_string(),
+            instance: instance.to_string(),
+            attributes: None,
+            attribute_changes: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Adds the attributes to every log, see [`log_format::join_attributes`]
+    pub fn with_attributes(mut self, attributes: &BTreeMap<String, String>) -> Self {
+        if !attributes.is_empty() {
+            self.attributes = Some(log_format::join_attributes(attributes));
+        }
+        self
+    }
+
+    /// Changes the attributes at the given times after `started`, the start of the run. The
+    /// changes must be in the order they are due
+    pub fn with_attribute_changes(
+        mut self,
+        changes: Vec<(Duration, BTreeMap<String, String>)>,
+        started: Instant,
+    ) -> Self {
+        self.attribute_changes = changes
+            .into_iter()
+            .map(|(at, attributes)| {
+                let joined =
+                    (!attributes.is_empty()).then(|| log_format::join_attributes(&attributes));
+                (at, joined)
+            })
+            .collect();
+        self.started = started;
+        self
+    }
+
+    fn current_attributes(&self) -> Option<&str> {
+        let elapsed = self.started.elapsed();
+        let due = self
+            .attribute_changes
+            .partition_point(|(at, _)| *at <= elapsed);
+        match due.checked_sub(1) {
+            Some(latest) => self.attribute_changes[latest].1.as_deref(),
+            None => self.attributes.as_deref(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl PrintSink for TracingSink {
+    async fn print(&self, print: Print) -> Result<(), PrintSinkError> {
+        let (app_name, instance_id) = (&self.app_name, &self.instance);
+        let attributes = self.current_attributes();
+        match (print.message, print.span_context) {
+            (PrintMessage::Stdout(message), Some(span)) => {
+                tracing::info!(target: log_format::SERVICE_TARGET, app_name = %app_name, instance = %instance_id, attributes, trace_id = %span.trace_id(), span_id = %span.span_id(), "{}", message);
+            }
+            (PrintMessage::Stdout(message), None) => {
+                tracing::info!(target: log_format::SERVICE_TARGET, app_name = %app_name, instance = %instance_id, attributes, "{}", message);
+            }
+            (PrintMessage::Stderr(message), Some(span)) => {
+                tracing::error!(target: log_format::SERVICE_TARGET, app_name = %app_name, instance = %instance_id, attributes, trace_id = %span.trace_id(), span_id = %span.span_id(), "{}", message);
+            }
+            (PrintMessage::Stderr(message), None) => {
+                tracing::error!(target: log_format::SERVICE_TARGET, app_name = %app_name, instance = %instance_id, attributes, "{}", message);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{code_gen::CodeGenerator, parser, vm::VM};
+    use std::sync::{Arc, Mutex};
+
+    /// Collects the prints, like a test harness asserting on the output
+    #[derive(Clone, Default)]
+    struct MemorySink(Arc<Mutex<Vec<PrintMessage>>>);
+
+    #[tonic::async_trait]
+    impl PrintSink for MemorySink {
+        async fn print(&self, print: Print) -> Result<(), PrintSinkError> {
+            self.0.lock().unwrap().push(print.message);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vm_prints_to_a_custom_sink() {
+        let ast = parser::parse(
+            r#"
+            service payments {
+                method charge {
+                    print "Processing payment";
+                    stderr "Card declined";
+                }
+
+                loop {
+                    call charge;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+        let sink = MemorySink::default();
+        let mut vm = VM::new(code, "payments", sink.clone()).with_max_execution_counter(10);
+        vm.run().await.unwrap_err();
+
+        assert_eq!(
+            sink.0.lock().unwrap()[..2],
+            [
+                PrintMessage::Stdout("Processing payment".to_string()),
+                PrintMessage::Stderr("Card declined".to_string()),
+            ]
+        );
+    }
+}
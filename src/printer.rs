@@ -1,29 +1,136 @@
+use std::collections::{BTreeSet, HashMap};
+
 use tabled::Tabled;
 
 use crate::code_gen::instruction::Instruction;
+use crate::parser::{Program, Statement};
 
+/// A service of a file at a glance
 #[derive(Tabled)]
-pub struct AnnotatedInstruction {
-    instruction: String,
-    description: String,
+pub struct ServiceSummary {
+    service: String,
+    replicas: usize,
+    methods: String,
+    loops: usize,
+    /// The methods of other services it calls
+    calls: String,
+}
+
+/// Summarizes every service of the program, in the order of the file
+pub fn summarize(program: &Program) -> Vec<ServiceSummary> {
+    program
+        .services
+        .iter()
+        .map(|service| {
+            let calls: BTreeSet<String> = service
+                .methods
+                .iter()
+                .flat_map(|method| &method.statements)
+                .chain(service.loops.iter().flat_map(|l| &l.statements))
+                .chain(service.shutdown.iter().flat_map(|s| &s.statements))
+                .filter_map(|statement| match statement {
+                    Statement::Call {
+                        service: Some(callee),
+                        method,
+                    } => Some(format!("{}.{}", callee, method)),
+                    _ => None,
+                })
+                .collect();
+            ServiceSummary {
+                service: service.name.clone(),
+                replicas: service.config.replicas.unwrap_or(1),
+                methods: service
+                    .methods
+                    .iter()
+                    .map(|method| method.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                loops: service.loops.len(),
+                calls: calls.into_iter().collect::<Vec<_>>().join(", "),
+            }
+        })
+        .collect()
+}
+
+/// An instruction with its position in the bytecode
+#[derive(Tabled)]
+pub struct DisassembledInstruction {
+    offset: String,
+    label: String,
+    #[tabled(inline)]
+    instruction: AnnotatedInstruction,
+    /// Where a jump or call goes to
+    target: String,
 }
 
-impl AnnotatedInstruction {
-    pub fn new(instruction: String, description: String) -> Self {
-        Self {
-            instruction,
-            description,
+/// Lists the instructions with their byte offsets, and the offsets of the labels that jumps and calls go to
+pub fn disassemble(code: &[Instruction]) -> Vec<DisassembledInstruction> {
+    let mut offsets = Vec::with_capacity(code.len());
+    let mut labels = HashMap::new();
+    let mut offset = 0;
+    for instruction in code {
+        if let Instruction::Label(label) = instruction {
+            labels.insert(label.as_str(), offset);
         }
+        offsets.push(offset);
+        offset += instruction.to_bytes().len();
     }
+    code.iter()
+        .zip(offsets)
+        .map(|(instruction, offset)| {
+            let (label, target) = match instruction {
+                Instruction::Label(label) => (label.clone(), String::new()),
+                Instruction::JmpIfZero(label)
+                | Instruction::Jump(label)
+                | Instruction::Call(label) => {
+                    let target = match labels.get(label.as_str()) {
+                        Some(target) => format!("{:#06x}", target),
+                        None => format!("missing label {}", label),
+                    };
+                    (String::new(), target)
+                }
+                _ => (String::new(), String::new()),
+            };
+            DisassembledInstruction {
+                offset: format!("{:#06x}", offset),
+                label,
+                instruction: instruction.into(),
+                target,
+            }
+        })
+        .collect()
 }
 
-impl Into<AnnotatedInstruction> for &Instruction {
-    fn into(self) -> AnnotatedInstruction {
-        match self {
+#[derive(Tabled)]
+pub struct AnnotatedInstruction {
+    instruction: String,
+    description: String,
+}
+
+impl From<&Instruction> for AnnotatedInstruction {
+    fn from(instruction: &Instruction) -> Self {
+        match instruction {
             Instruction::Push(stack_value) => AnnotatedInstruction {
                 instruction: "Push".to_string(),
                 description: format!("Push {:?}", stack_value),
             },
+            Instruction::PushWeighted(values) => AnnotatedInstruction {
+                instruction: "PushWeighted".to_string(),
+                description: format!("Push one of {:?}, picked by weight", values),
+            },
+            Instruction::SleepJittered(ms, jitter_ms) => AnnotatedInstruction {
+                instruction: "SleepJittered".to_string(),
+                description: format!("Sleep for {}ms, plus or minus up to {}ms", ms, jitter_ms),
+            },
+            Instruction::PrintMixed(info, error) => AnnotatedInstruction {
+                instruction: "PrintMixed".to_string(),
+                description: format!(
+                    "Print the top of the stack to stdout {} times and to stderr {} times in {}",
+                    info,
+                    error,
+                    info + error
+                ),
+            },
             Instruction::Pop => AnnotatedInstruction {
                 instruction: "Pop".to_string(),
                 description: "Pop the top of the stack".to_string(),
@@ -98,6 +205,65 @@ impl Into<AnnotatedInstruction> for &Instruction {
                 instruction: "Ret".to_string(),
                 description: "Return from the current function".to_string(),
             },
+            Instruction::SetBaggage(key, value) => AnnotatedInstruction {
+                instruction: "SetBaggage".to_string(),
+                description: format!("Set the baggage entry {} to {}", key, value),
+            },
+            Instruction::AccessLog(format) => AnnotatedInstruction {
+                instruction: "AccessLog".to_string(),
+                description: format!("Push a {} access log line", format.name()),
+            },
+            Instruction::Extension(code, operand) => AnnotatedInstruction {
+                instruction: "Extension".to_string(),
+                description: format!("Run extension {:#04x} with {:?}", code, operand),
+            },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_resolves_labels() {
+        let rows = disassemble(&[
+            Instruction::Label("start".to_string()),
+            Instruction::Sleep(100),
+            Instruction::Jump("start".to_string()),
+            Instruction::Call("checkout".to_string()),
+        ]);
+        let offsets: Vec<&str> = rows.iter().map(|row| row.offset.as_str()).collect();
+        // A label is 1 + 8 + 5 bytes, a sleep 1 + 8 + 8 and a jump 1 + 8 + 5
+        assert_eq!(offsets, ["0x0000", "0x000e", "0x001f", "0x002d"]);
+        assert_eq!(rows[0].label, "start");
+        assert_eq!(rows[2].target, "0x0000");
+        assert_eq!(rows[3].target, "missing label checkout");
+    }
+
+    #[test]
+    fn test_summarize_lists_methods_and_calls() {
+        let program = crate::parser::parse(
+            "
+            service frontend {
+                method main_page {
+                    call products.get_products;
+                    call products.get_products;
+                    call payments.charge;
+                }
+                method about {
+                    print \"About\";
+                }
+                loop {
+                    call main_page;
+                }
+            }
+            ",
+        )
+        .unwrap();
+        let summary = summarize(&program);
+        assert_eq!(summary[0].methods, "main_page, about");
+        assert_eq!(summary[0].loops, 1);
+        assert_eq!(summary[0].calls, "payments.charge, products.get_products");
+    }
+}
@@ -0,0 +1,514 @@
+use std::collections::BTreeMap;
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+use crate::value::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrintfError {
+    InvalidTemplate(String),
+    /// The template expects the first number of values, but got the second
+    ArityMismatch(usize, usize),
+    /// The value does not fit the placeholder, e.g. `abc` for `%d`
+    InvalidValue,
+}
+
+impl std::error::Error for PrintfError {}
+
+impl std::fmt::Display for PrintfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrintfError::InvalidTemplate(template) => write!(f, "Invalid template: {}", template),
+            PrintfError::ArityMismatch(expected, found) => write!(
+                f,
+                "Template expects {} values but {} were given",
+                expected, found
+            ),
+            PrintfError::InvalidValue => write!(f, "Value does not fit the placeholder"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conversion {
+    /// `%s`, formats any value
+    String,
+    /// `%d`, formats an integer or a string holding an integer
+    Int,
+    /// `%f`, formats a number with six decimal places unless a precision is given
+    Float,
+}
+
+/// A placeholder like `%-20s`, `%5d` or `%.2f`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placeholder {
+    pub conversion: Conversion,
+    /// Pad on the right instead of the left (`-` flag)
+    pub left_align: bool,
+    /// Pad numbers with zeros instead of spaces (`0` flag)
+    pub zero_pad: bool,
+    /// Minimum width of the formatted value
+    pub width: Option<usize>,
+    /// Decimal places for `%f`, minimum digits for `%d`, maximum characters for `%s`
+    pub precision: Option<usize>,
+}
+
+/// A generator like `%gauss(mean: 200, stddev: 20, drift: +5/min)`, a number from a normal
+/// distribution whose mean drifts over the run, so metrics taken from the logs trend
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gauss {
+    /// The flags, width and precision, e.g. `%.1gauss(...)`. Without a precision, whole numbers
+    pub placeholder: Placeholder,
+    pub mean: f64,
+    /// A tenth of the mean unless given
+    pub stddev: f64,
+    /// How much the mean changes per second of the run
+    pub drift: f64,
+}
+
+impl Gauss {
+    /// The mean at `elapsed` after the start of the run
+    pub fn mean_at(&self, elapsed: Duration) -> f64 {
+        self.mean + self.drift * elapsed.as_secs_f64()
+    }
+
+    /// Draws a number at `elapsed` after the start of the run from two rolls between 0 and 1
+    pub fn sample(&self, elapsed: Duration, rolls: (f64, f64)) -> f64 {
+        // Box-Muller, with 1 - roll so the logarithm never sees 0
+        let radius = (-2.0 * (1.0 - rolls.0).ln()).sqrt();
+        self.mean_at(elapsed) + self.stddev * radius * (TAU * rolls.1).cos()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+    /// `%{key}`, the value of a baggage entry
+    Baggage(String),
+    Gauss(Gauss),
+}
+
+/// A Printf template split into literal text and placeholders. Parsed once, e.g. when the
+/// bytecode is decoded, and formatted on every print
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+/// A template with what it reads besides its values
+#[derive(Debug, Clone, Copy)]
+pub struct Fill<'a> {
+    template: &'a Template,
+    baggage: Option<&'a BTreeMap<String, String>>,
+    samples: &'a [f64],
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, PrintfError> {
+        let invalid = || PrintfError::InvalidTemplate(template.to_string());
+        let mut segments = Vec::new();
+        let mut rest = template;
+
+        while let Some(position) = rest.find('%') {
+            if position > 0 {
+                segments.push(Segment::Literal(rest[..position].to_string()));
+            }
+            rest = &rest[position + 1..];
+            if let Some(after) = rest.strip_prefix('%') {
+                segments.push(Segment::Literal("%".to_string()));
+                rest = after;
+                continue;
+            }
+            if let Some(after) = rest.strip_prefix('{') {
+                let end = after.find('}').ok_or_else(invalid)?;
+                segments.push(Segment::Baggage(after[..end].to_string()));
+                rest = &after[end + 1..];
+                continue;
+            }
+
+            let mut left_align = false;
+            let mut zero_pad = false;
+            loop {
+                if let Some(after) = rest.strip_prefix('-') {
+                    left_align = true;
+                    rest = after;
+                } else if let Some(after) = rest.strip_prefix('0') {
+                    zero_pad = true;
+                    rest = after;
+                } else {
+                    break;
+                }
+            }
+            let width = take_number(&mut rest);
+            let precision = match rest.strip_prefix('.') {
+                Some(after) => {
+                    rest = after;
+                    Some(take_number(&mut rest).unwrap_or(0))
+                }
+                None => None,
+            };
+
+            let placeholder = |conversion| Placeholder {
+                conversion,
+                left_align,
+                zero_pad,
+                width,
+                precision,
+            };
+            if let Some(after) = rest.strip_prefix("gauss(") {
+                let end = after.find(')').ok_or_else(invalid)?;
+                let gauss = parse_gauss(&after[..end], placeholder(Conversion::Float))
+                    .ok_or_else(invalid)?;
+                segments.push(Segment::Gauss(gauss));
+                rest = &after[end + 1..];
+                continue;
+            }
+
+            let conversion = match rest.chars().next() {
+                Some('s') => Conversion::String,
+                Some('d') => Conversion::Int,
+                Some('f') => Conversion::Float,
+                _ => return Err(invalid()),
+            };
+            rest = &rest[1..];
+            segments.push(Segment::Placeholder(placeholder(conversion)));
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Formats the template, without baggage or samples until they are given
+    pub fn fill(&self) -> Fill<'_> {
+        Fill {
+            template: self,
+            baggage: None,
+            samples: &[],
+        }
+    }
+
+    /// Whether the template has anything to fill in, so printing it as it is would be wrong
+    pub fn has_placeholders(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|segment| !matches!(segment, Segment::Literal(_)))
+    }
+
+    /// The generators of the template, in order
+    pub fn generators(&self) -> impl Iterator<Item = &Gauss> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Gauss(gauss) => Some(gauss),
+            _ => None,
+        })
+    }
+
+    /// The number of values the template consumes
+    pub fn arity(&self) -> usize {
+        self.segments
+            .iter()
+            .filter(|segment| matches!(segment, Segment::Placeholder(_)))
+            .count()
+    }
+}
+
+impl<'a> Fill<'a> {
+    /// Fills the `%{key}` placeholders from `baggage`. Keys that are not set become empty
+    pub fn with_baggage(mut self, baggage: &'a BTreeMap<String, String>) -> Self {
+        self.baggage = Some(baggage);
+        self
+    }
+
+    /// Fills the generators in order with `samples`, drawn with [`Gauss::sample`]. Generators
+    /// without a sample print their mean at the start of the run
+    pub fn with_samples(mut self, samples: &'a [f64]) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Replaces the placeholders left-to-right with the given values
+    pub fn format(&self, values: &[Value]) -> Result<String, PrintfError> {
+        let arity = self.template.arity();
+        let mismatch = PrintfError::ArityMismatch(arity, values.len());
+        if values.len() != arity {
+            return Err(mismatch);
+        }
+
+        let mut values = values.iter();
+        let mut samples = self.samples.iter();
+        let mut formatted = String::new();
+        for segment in &self.template.segments {
+            match segment {
+                Segment::Literal(text) => formatted.push_str(text),
+                Segment::Placeholder(placeholder) => {
+                    // The arity check above guarantees a value for every placeholder
+                    let value = values.next().ok_or_else(|| mismatch.clone())?;
+                    formatted.push_str(&format_value(placeholder, value)?);
+                }
+                Segment::Baggage(key) => {
+                    if let Some(value) = self.baggage.and_then(|baggage| baggage.get(key)) {
+                        formatted.push_str(value);
+                    }
+                }
+                Segment::Gauss(gauss) => {
+                    let sample = samples.next().copied().unwrap_or(gauss.mean);
+                    let precision = gauss.placeholder.precision.unwrap_or(0);
+                    let number = format!("{:.precision$}", sample, precision = precision);
+                    formatted.push_str(&pad(&gauss.placeholder, number));
+                }
+            }
+        }
+        Ok(formatted)
+    }
+}
+
+// Reads the leading digits of `rest`, if any
+fn take_number(rest: &mut &str) -> Option<usize> {
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let number = rest[..digits].parse().ok();
+    *rest = &rest[digits..];
+    number
+}
+
+// Reads the arguments of `%gauss(...)`, e.g. `mean: 200, drift: -5/min`
+fn parse_gauss(arguments: &str, placeholder: Placeholder) -> Option<Gauss> {
+    let mut mean = None;
+    let mut stddev = None;
+    let mut drift = 0.0;
+    for argument in arguments.split(',') {
+        let (key, value) = argument.split_once(':')?;
+        let value = value.trim();
+        match key.trim() {
+            "mean" => mean = Some(value.parse::<f64>().ok()?),
+            "stddev" => stddev = Some(value.parse::<f64>().ok().filter(|s| *s >= 0.0)?),
+            "drift" => {
+                let (amount, unit) = value.split_once('/')?;
+                let per = match unit.trim() {
+                    "s" => 1.0,
+                    "min" => 60.0,
+                    "h" => 3600.0,
+                    _ => return None,
+                };
+                drift = amount.trim().parse::<f64>().ok()? / per;
+            }
+            _ => return None,
+        }
+    }
+    let mean = mean.filter(|mean: &f64| mean.is_finite())?;
+    Some(Gauss {
+        placeholder,
+        mean,
+        stddev: stddev.unwrap_or(mean.abs() / 10.0),
+        drift,
+    })
+}
+
+fn format_value(placeholder: &Placeholder, value: &Value) -> Result<String, PrintfError> {
+    let formatted = match (placeholder.conversion, value) {
+        (Conversion::String, value) => {
+            let s = value.to_string();
+            match placeholder.precision {
+                Some(precision) => s.chars().take(precision).collect(),
+                None => s,
+            }
+        }
+        (Conversion::Int, value) => {
+            let i = match value {
+                Value::Int(i) => *i as i64,
+                Value::String(s) => s.parse::<i64>().map_err(|_| PrintfError::InvalidValue)?,
+            };
+            let digits = format!(
+                "{:0width$}",
+                i.unsigned_abs(),
+                width = placeholder.precision.unwrap_or(0)
+            );
+            if i < 0 {
+                format!("-{}", digits)
+            } else {
+                digits
+            }
+        }
+        (Conversion::Float, value) => {
+            let f = match value {
+                Value::Int(i) => *i as f64,
+                Value::String(s) => s.parse::<f64>().map_err(|_| PrintfError::InvalidValue)?,
+            };
+            format!(
+                "{:.precision$}",
+                f,
+                precision = placeholder.precision.unwrap_or(6)
+            )
+        }
+    };
+
+    Ok(pad(placeholder, formatted))
+}
+
+fn pad(placeholder: &Placeholder, formatted: String) -> String {
+    let width = placeholder.width.unwrap_or(0);
+    let len = formatted.chars().count();
+    if len >= width {
+        return formatted;
+    }
+    let padding = width - len;
+    if placeholder.left_align {
+        return format!("{}{}", formatted, " ".repeat(padding));
+    }
+    // Zero padding goes between the sign and the digits, and only applies to numbers
+    let is_number = placeholder.conversion != Conversion::String;
+    if placeholder.zero_pad && is_number {
+        return match formatted.strip_prefix('-') {
+            Some(digits) => format!("-{}{}", "0".repeat(padding), digits),
+            None => format!("{}{}", "0".repeat(padding), formatted),
+        };
+    }
+    format!("{}{}", " ".repeat(padding), formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.into())
+    }
+
+    #[test]
+    fn test_format_mixed_placeholders() {
+        let template = Template::parse("GET %s %d %fms").unwrap();
+        assert_eq!(template.arity(), 3);
+        let formatted = template
+            .fill()
+            .format(&[string("/products"), Value::Int(200), string("1.5")])
+            .unwrap();
+        assert_eq!(formatted, "GET /products 200 1.500000ms");
+    }
+
+    #[test]
+    fn test_format_escaped_percent() {
+        let template = Template::parse("%d%% done").unwrap();
+        assert_eq!(
+            template.fill().format(&[Value::Int(50)]).unwrap(),
+            "50% done"
+        );
+    }
+
+    #[test]
+    fn test_format_width_and_alignment() {
+        let template = Template::parse("[%5d] [%-8s] [%8s]").unwrap();
+        let formatted = template
+            .fill()
+            .format(&[Value::Int(42), string("GET"), string("/home")])
+            .unwrap();
+        assert_eq!(formatted, "[   42] [GET     ] [   /home]");
+    }
+
+    #[test]
+    fn test_format_precision() {
+        let template = Template::parse("%.2f %.3s %.3d %.0f").unwrap();
+        let formatted = template
+            .fill()
+            .format(&[
+                string("3.14159"),
+                string("abcdef"),
+                Value::Int(7),
+                Value::Int(2),
+            ])
+            .unwrap();
+        assert_eq!(formatted, "3.14 abc 007 2");
+    }
+
+    #[test]
+    fn test_format_zero_padding() {
+        let template = Template::parse("%05d %08.2f %05s").unwrap();
+        let formatted = template
+            .fill()
+            .format(&[string("-42"), string("3.14159"), string("ab")])
+            .unwrap();
+        assert_eq!(formatted, "-0042 00003.14    ab");
+    }
+
+    #[test]
+    fn test_format_arity_mismatch() {
+        let template = Template::parse("%s and %s").unwrap();
+        assert_eq!(
+            template.fill().format(&[string("one")]),
+            Err(PrintfError::ArityMismatch(2, 1))
+        );
+    }
+
+    #[test]
+    fn test_format_invalid_int() {
+        let template = Template::parse("%d").unwrap();
+        assert_eq!(
+            template.fill().format(&[string("abc")]),
+            Err(PrintfError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn test_format_baggage() {
+        let baggage = BTreeMap::from([("tenant".to_string(), "acme".to_string())]);
+        let template = Template::parse("%{tenant}: GET %s%{region}").unwrap();
+        assert_eq!(template.arity(), 1);
+        assert!(template.has_placeholders());
+        assert_eq!(
+            template
+                .fill()
+                .with_baggage(&baggage)
+                .format(&[string("/cart")])
+                .unwrap(),
+            "acme: GET /cart"
+        );
+        assert!(Template::parse("%{tenant").is_err());
+    }
+
+    #[test]
+    fn test_format_drifting_gauss() {
+        let template =
+            Template::parse("latency=%gauss(mean: 200, drift: +5/min)ms %5.1gauss(mean: 1, stddev: 0, drift: -1/h) %s")
+                .unwrap();
+        assert_eq!(template.arity(), 1);
+        let generators: Vec<&Gauss> = template.generators().collect();
+        assert_eq!(generators[0].stddev, 20.0);
+        assert_eq!(generators[0].mean_at(Duration::from_secs(120)), 210.0);
+        assert_eq!(generators[1].mean_at(Duration::from_secs(1800)), 0.5);
+        // A first roll of 1 - e^-0.5 is one standard deviation away from the mean
+        let rolls = (1.0 - (-0.5f64).exp(), 0.0);
+        assert!((generators[0].sample(Duration::ZERO, rolls) - 220.0).abs() < 1e-9);
+        assert_eq!(
+            template.fill().format(&[string("GET")]).unwrap(),
+            "latency=200ms   1.0 GET"
+        );
+        let samples = [213.6, 0.75];
+        assert_eq!(
+            template
+                .fill()
+                .with_samples(&samples)
+                .format(&[string("GET")])
+                .unwrap(),
+            "latency=214ms   0.8 GET"
+        );
+        assert!(Template::parse("%gauss(stddev: 5)").is_err());
+        assert!(Template::parse("%gauss(mean: 5, drift: 1/day)").is_err());
+        assert!(Template::parse("%gauss(mean: 5").is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_template() {
+        assert_eq!(
+            Template::parse("Hello, %!"),
+            Err(PrintfError::InvalidTemplate("Hello, %!".to_string()))
+        );
+        assert_eq!(
+            Template::parse("Trailing %"),
+            Err(PrintfError::InvalidTemplate("Trailing %".to_string()))
+        );
+        assert_eq!(
+            Template::parse("%5"),
+            Err(PrintfError::InvalidTemplate("%5".to_string()))
+        );
+    }
+}
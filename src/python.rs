@@ -0,0 +1,184 @@
+//! The `mustermann` Python module, to generate and check scenarios from scripts and notebooks:
+//!
+//! ```text
+//! import mustermann
+//!
+//! program = mustermann.parse(source)
+//! compiled = mustermann.compile(program)
+//! async for record in mustermann.run(compiled, duration_ms=5000):
+//!     print(record.service, record.stream, record.message)
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use pyo3::exceptions::{PyRuntimeError, PyStopAsyncIteration, PyValueError};
+use pyo3::prelude::*;
+use tokio::sync::Mutex;
+
+use crate::artifact::{self, CompiledService};
+use crate::embedded;
+use crate::events::{RunEvent, RunEvents};
+use crate::parser;
+use crate::scenario::Scenario;
+use crate::vm::PrintMessage;
+
+/// A parsed scenario
+#[pyclass(name = "Program", frozen)]
+struct PyProgram {
+    program: parser::Program,
+}
+
+#[pymethods]
+impl PyProgram {
+    /// The names of the services
+    #[getter]
+    fn services(&self) -> Vec<String> {
+        self.program
+            .services
+            .iter()
+            .map(|service| service.name.clone())
+            .collect()
+    }
+}
+
+/// The code of every service of a scenario
+#[pyclass(name = "CompiledProgram", frozen)]
+struct PyCompiledProgram {
+    services: Vec<CompiledService>,
+    /// The injections, policies, chaos and timeline of the program, which the run applies
+    scenario: Scenario,
+}
+
+#[pymethods]
+impl PyCompiledProgram {
+    /// The names of the services
+    #[getter]
+    fn services(&self) -> Vec<String> {
+        self.services
+            .iter()
+            .map(|service| service.name.clone())
+            .collect()
+    }
+}
+
+/// A line a service printed, `stream` is `stdout` or `stderr`
+#[pyclass(frozen, get_all)]
+struct LogRecord {
+    service: String,
+    stream: &'static str,
+    message: String,
+}
+
+#[pymethods]
+impl LogRecord {
+    fn __repr__(&self) -> String {
+        format!(
+            "LogRecord(service={:?}, stream={:?}, message={:?})",
+            self.service, self.stream, self.message
+        )
+    }
+}
+
+/// The records of a run, ends once the services stopped
+#[pyclass]
+struct LogStream {
+    run: Arc<Mutex<Run>>,
+}
+
+struct Run {
+    events: RunEvents,
+    /// Raised once the services stopped
+    error: Option<String>,
+}
+
+#[pymethods]
+impl LogStream {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let run = self.run.clone();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let mut run = run.lock().await;
+            loop {
+                match run.events.next().await {
+                    Some(RunEvent::LogEmitted {
+                        service, message, ..
+                    }) => {
+                        let (stream, message) = match message {
+                            PrintMessage::Stdout(message) => ("stdout", message),
+                            PrintMessage::Stderr(message) => ("stderr", message),
+                        };
+                        return Ok(LogRecord {
+                            service,
+                            stream,
+                            message,
+                        });
+                    }
+                    Some(RunEvent::Error { error, .. }) => {
+                        run.error.get_or_insert(error);
+                    }
+                    Some(_) => {}
+                    None => {
+                        return Err(match run.error.take() {
+                            Some(error) => PyRuntimeError::new_err(error),
+                            None => PyStopAsyncIteration::new_err(()),
+                        })
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Parses a scenario, raises ValueError if it does not parse
+#[pyfunction]
+fn parse(source: &str) -> PyResult<PyProgram> {
+    parser::parse(source)
+        .map(|program| PyProgram { program })
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Compiles every service, raises ValueError if a service does not compile
+#[pyfunction]
+fn compile(program: &PyProgram) -> PyResult<PyCompiledProgram> {
+    artifact::compile(&program.program)
+        .map(|services| PyCompiledProgram {
+            services,
+            scenario: Scenario::new(&program.program),
+        })
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Runs the services for `duration_ms` in the background. The returned stream yields what they
+/// print, and raises RuntimeError at the end if a service failed or called one that does not exist
+#[pyfunction]
+fn run(program: &PyCompiledProgram, duration_ms: u64) -> PyResult<LogStream> {
+    let events = embedded::start(
+        program.scenario.clone(),
+        program.services.clone(),
+        Duration::from_millis(duration_ms),
+    )
+    .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    Ok(LogStream {
+        run: Arc::new(Mutex::new(Run {
+            events,
+            error: None,
+        })),
+    })
+}
+
+#[pymodule]
+fn mustermann(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(compile, m)?)?;
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    m.add_class::<PyProgram>()?;
+    m.add_class::<PyCompiledProgram>()?;
+    m.add_class::<LogRecord>()?;
+    m.add_class::<LogStream>()?;
+    Ok(())
+}
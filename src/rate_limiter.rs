@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+/// Admits up to `burst` calls at once and refills at `calls` per `per`
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    /// Tokens added per second
+    refill_rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Starts out full
+    pub fn new(calls: usize, per: Duration, burst: usize, now: Instant) -> Self {
+        let capacity = burst.max(1) as f64;
+        Self {
+            capacity,
+            refill_rate: calls as f64 / per.as_secs_f64().max(f64::EPSILON),
+            tokens: capacity,
+            refilled_at: now,
+        }
+    }
+
+    /// Takes a token for a call arriving at `now`, or returns false if there is none left
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.refill_rate).min(self.capacity);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_calls_beyond_burst() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10, Duration::from_secs(1), 3, now);
+        assert!(bucket.try_acquire(now));
+        assert!(bucket.try_acquire(now));
+        assert!(bucket.try_acquire(now));
+        assert!(!bucket.try_acquire(now));
+    }
+
+    #[test]
+    fn test_refills_over_time() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10, Duration::from_secs(1), 10, now);
+        for _ in 0..10 {
+            assert!(bucket.try_acquire(now));
+        }
+        assert!(!bucket.try_acquire(now));
+        // 10 calls per second is one every 100ms
+        let later = now + Duration::from_millis(250);
+        assert!(bucket.try_acquire(later));
+        assert!(bucket.try_acquire(later));
+        assert!(!bucket.try_acquire(later));
+        // Never more than the burst, however long it stays unused
+        let much_later = now + Duration::from_secs(60);
+        for _ in 0..10 {
+            assert!(bucket.try_acquire(much_later));
+        }
+        assert!(!bucket.try_acquire(much_later));
+    }
+}
@@ -0,0 +1,382 @@
+//! Records the decisions a run leaves to chance, to replay them and repeat the run: the random
+//! numbers of the VMs and the coordinator, the timestamps of access logs, and which call a VM took
+//! at CheckInterrupt. A replayed
+//! VM takes the calls in the recorded order and waits for a call that has not arrived yet, so the
+//! services print the same as in the recorded run, as long as they get the same calls.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+#[cfg(any(feature = "golden", test))]
+use rand::rngs::StdRng;
+#[cfg(any(feature = "golden", test))]
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// The source of the decisions of the coordinator. VMs record under their instance ID
+pub const COORDINATOR: &str = "coordinator";
+
+/// A decision that could have gone another way
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    /// A random number, e.g. for a weighted value, jitter or a fault
+    Random(u64),
+    /// CheckInterrupt took a call to this function
+    Call(String),
+    /// CheckInterrupt took no call
+    NoCall,
+    /// The wall-clock time of an access log, in microseconds since the epoch
+    Timestamp(u64),
+}
+
+/// One line of a recording
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    source: String,
+    decision: Decision,
+}
+
+/// A replay asked for another decision than the one recorded next, e.g. because the scenario changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diverged(pub String);
+
+impl std::error::Error for Diverged {}
+
+impl std::fmt::Display for Diverged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The replay diverged from the recording: {}", self.0)
+    }
+}
+
+/// Appends the decisions of every source to a file, as newline-delimited JSON
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<RecorderFile>>);
+
+struct RecorderFile {
+    writer: BufWriter<File>,
+    /// Set after the first failed write, so a full disk is reported once
+    failed: bool,
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Recorder")
+    }
+}
+
+impl Recorder {
+    /// Truncates the file at `path`, creating it if needed
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self(Arc::new(Mutex::new(RecorderFile {
+            writer: BufWriter::new(File::create(path)?),
+            failed: false,
+        }))))
+    }
+
+    fn record(&self, source: &str, decision: Decision) {
+        let entry = Entry {
+            source: source.to_string(),
+            decision,
+        };
+        let mut file = self.0.lock().unwrap();
+        let result = serde_json::to_writer(&mut file.writer, &entry)
+            .map_err(std::io::Error::from)
+            .and_then(|_| file.writer.write_all(b"\n"));
+        if let Err(e) = result {
+            if !file.failed {
+                tracing::warn!("Cannot write to the recording: {}", e);
+            }
+            file.failed = true;
+        }
+    }
+
+    pub fn flush(&self) {
+        self.0.lock().unwrap().flush();
+    }
+}
+
+impl RecorderFile {
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            tracing::warn!("Cannot write to the recording: {}", e);
+        }
+    }
+}
+
+/// A run that stops early with an error still leaves the decisions it made in the file
+impl Drop for RecorderFile {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// The decisions of a recorded run, by source
+#[derive(Debug, Clone, Default)]
+pub struct Recording(Arc<Mutex<HashMap<String, VecDeque<Decision>>>>);
+
+impl Recording {
+    /// Reads the recording at `path`
+    pub fn read(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut sources: HashMap<String, VecDeque<Decision>> = HashMap::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(&line).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid recording entry on line {}: {}", index + 1, e),
+                )
+            })?;
+            sources
+                .entry(entry.source)
+                .or_default()
+                .push_back(entry.decision);
+        }
+        Ok(Self(Arc::new(Mutex::new(sources))))
+    }
+
+    /// The decisions of `source`, to replay them
+    pub fn decisions(&self, source: &str) -> Decisions {
+        let decisions = self.0.lock().unwrap().remove(source).unwrap_or_default();
+        Decisions {
+            source: source.to_string(),
+            mode: Mode::Replay(Mutex::new(decisions)),
+        }
+    }
+}
+
+/// Hands out the decisions of every VM and of the coordinator of a run
+#[derive(Debug, Clone, Default)]
+pub enum Decider {
+    #[default]
+    Live,
+    Record(Recorder),
+    Replay(Recording),
+    /// Decides by chance, but every source draws from a sequence fixed by the seed, for golden files
+    #[cfg(any(feature = "golden", test))]
+    Seeded(u64),
+}
+
+impl Decider {
+    pub fn decisions(&self, source: &str) -> Decisions {
+        match self {
+            Decider::Live => Decisions::default(),
+            Decider::Record(recorder) => Decisions::record(source, recorder.clone()),
+            Decider::Replay(recording) => recording.decisions(source),
+            #[cfg(any(feature = "golden", test))]
+            Decider::Seeded(seed) => Decisions::seeded(source, *seed),
+        }
+    }
+}
+
+/// Where a VM or the coordinator gets its decisions from: by chance, by chance while recording
+/// them, by a seeded sequence, or from a recording
+#[derive(Debug, Default)]
+pub struct Decisions {
+    source: String,
+    mode: Mode,
+}
+
+#[derive(Default)]
+enum Mode {
+    #[default]
+    Live,
+    Record(Recorder),
+    Replay(Mutex<VecDeque<Decision>>),
+    #[cfg(any(feature = "golden", test))]
+    Seeded(Box<Mutex<StdRng>>),
+}
+
+impl std::fmt::Debug for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Live => write!(f, "Live"),
+            Mode::Record(recorder) => recorder.fmt(f),
+            Mode::Replay(decisions) => f
+                .debug_tuple("Replay")
+                .field(&decisions.lock().unwrap().len())
+                .finish(),
+            #[cfg(any(feature = "golden", test))]
+            Mode::Seeded(_) => write!(f, "Seeded"),
+        }
+    }
+}
+
+impl Decisions {
+    /// Decides by chance and records the decisions of `source`
+    pub fn record(source: &str, recorder: Recorder) -> Self {
+        Self {
+            source: source.to_string(),
+            mode: Mode::Record(recorder),
+        }
+    }
+
+    /// Decides by a sequence that `seed` and `source` fix, so every source draws other numbers
+    #[cfg(any(feature = "golden", test))]
+    pub fn seeded(source: &str, seed: u64) -> Self {
+        // FNV-1a, which unlike the hasher of std is the same in every build
+        let source_hash = source
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            });
+        Self {
+            source: source.to_string(),
+            mode: Mode::Seeded(Box::new(Mutex::new(StdRng::seed_from_u64(
+                seed ^ source_hash,
+            )))),
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(self.mode, Mode::Replay(_))
+    }
+
+    /// A random number in `range`, which must not be empty
+    pub fn random_range(&self, range: Range<u64>) -> Result<u64, Diverged> {
+        match &self.mode {
+            Mode::Live => Ok(rand::random_range(range)),
+            Mode::Record(recorder) => {
+                let value = rand::random_range(range);
+                recorder.record(&self.source, Decision::Random(value));
+                Ok(value)
+            }
+            #[cfg(any(feature = "golden", test))]
+            Mode::Seeded(rng) => Ok(rng.lock().unwrap().random_range(range)),
+            Mode::Replay(_) => match self.next()? {
+                Decision::Random(value) if range.contains(&value) => Ok(value),
+                decision => {
+                    Err(self.diverged(&format!("a random number in {:?}", range), decision))
+                }
+            },
+        }
+    }
+
+    /// A random number in `0.0..1.0`
+    pub fn random_f64(&self) -> Result<f64, Diverged> {
+        const RESOLUTION: u64 = 1 << 53;
+        Ok(self.random_range(0..RESOLUTION)? as f64 / RESOLUTION as f64)
+    }
+
+    /// The wall-clock time `now`, to the microsecond, or the one recorded next when replaying
+    pub fn timestamp(&self, now: SystemTime) -> Result<SystemTime, Diverged> {
+        let micros = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64
+        };
+        match &self.mode {
+            Mode::Record(recorder) => {
+                let micros = micros(now);
+                recorder.record(&self.source, Decision::Timestamp(micros));
+                Ok(SystemTime::UNIX_EPOCH + Duration::from_micros(micros))
+            }
+            Mode::Replay(_) => match self.next()? {
+                Decision::Timestamp(micros) => {
+                    Ok(SystemTime::UNIX_EPOCH + Duration::from_micros(micros))
+                }
+                decision => Err(self.diverged("a timestamp", decision)),
+            },
+            _ => Ok(now),
+        }
+    }
+
+    /// Records which call CheckInterrupt took, if any
+    pub fn record_call(&self, function: Option<&str>) {
+        if let Mode::Record(recorder) = &self.mode {
+            let decision = match function {
+                Some(function) => Decision::Call(function.to_string()),
+                None => Decision::NoCall,
+            };
+            recorder.record(&self.source, decision);
+        }
+    }
+
+    /// The function of the call CheckInterrupt took next in the recording, `None` if it took none
+    pub fn replayed_call(&self) -> Result<Option<String>, Diverged> {
+        match self.next()? {
+            Decision::Call(function) => Ok(Some(function)),
+            Decision::NoCall => Ok(None),
+            decision => Err(self.diverged("CheckInterrupt", decision)),
+        }
+    }
+
+    fn next(&self) -> Result<Decision, Diverged> {
+        let Mode::Replay(decisions) = &self.mode else {
+            return Err(Diverged(format!("{} is not replaying", self.source)));
+        };
+        decisions
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| Diverged(format!("the recording of {} ended", self.source)))
+    }
+
+    fn diverged(&self, expected: &str, decision: Decision) -> Diverged {
+        Diverged(format!(
+            "{} asked for {}, but {:?} was recorded",
+            self.source, expected, decision
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_repeats_the_recorded_decisions() {
+        let path =
+            std::env::temp_dir().join(format!("mustermann-recording-{}.jsonl", std::process::id()));
+        let recorder = Recorder::create(&path).unwrap();
+        let products = Decisions::record("products-0", recorder.clone());
+        let frontend = Decisions::record("frontend-0", recorder.clone());
+        let first = products.random_range(0..100).unwrap();
+        frontend.record_call(None);
+        products.record_call(Some("get_products"));
+        let roll = products.random_f64().unwrap();
+        let timestamp = products.timestamp(SystemTime::now()).unwrap();
+        recorder.flush();
+
+        let recording = Recording::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let products = recording.decisions("products-0");
+        assert!(products.is_replaying());
+        assert_eq!(products.random_range(0..100), Ok(first));
+        assert_eq!(
+            products.replayed_call(),
+            Ok(Some("get_products".to_string()))
+        );
+        assert_eq!(products.random_f64(), Ok(roll));
+        assert_eq!(products.timestamp(SystemTime::UNIX_EPOCH), Ok(timestamp));
+        assert_eq!(
+            products.random_range(0..100),
+            Err(Diverged("the recording of products-0 ended".to_string()))
+        );
+
+        let frontend = recording.decisions("frontend-0");
+        assert!(frontend.random_range(0..100).is_err());
+    }
+
+    #[test]
+    fn test_seeded_decisions_repeat_per_source() {
+        let draw = |source: &str, seed: u64| -> Vec<u64> {
+            let decisions = Decider::Seeded(seed).decisions(source);
+            (0..8)
+                .map(|_| decisions.random_range(0..1000).unwrap())
+                .collect()
+        };
+        assert_eq!(draw("products-0", 7), draw("products-0", 7));
+        assert_ne!(draw("products-0", 7), draw("frontend-0", 7));
+        assert_ne!(draw("products-0", 7), draw("products-0", 8));
+    }
+}
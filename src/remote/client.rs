@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use super::proto::coordinator_client::CoordinatorClient;
+use super::proto::{self, error_to_outcome, outcome_to_error};
+use crate::vm_coordinator::{CallError, CoordinatorHandle, IncomingCall, ServiceMessage};
+
+/// Connects to the coordinator served at `url`, e.g. "http://10.0.0.1:7000" or "10.0.0.1:7000".
+/// The handle works like the one of a local coordinator, the task relays its messages until the
+/// handle is told to shut down.
+pub async fn connect(
+    url: &str,
+) -> Result<(CoordinatorHandle, JoinHandle<()>), tonic::transport::Error> {
+    let client = CoordinatorClient::connect(endpoint(url)).await?;
+    let (tx, rx) = mpsc::channel(100);
+    Ok((
+        CoordinatorHandle::from_sender(tx),
+        tokio::spawn(relay(client, rx)),
+    ))
+}
+
+/// Waits until every service of the simulation registered with the coordinator at `url`
+pub async fn wait_for_services(url: &str) -> Result<(), tonic::Status> {
+    let mut client = CoordinatorClient::connect(endpoint(url))
+        .await
+        .map_err(|e| tonic::Status::unavailable(e.to_string()))?;
+    client.wait_for_services().await?;
+    Ok(())
+}
+
+// Coordinators on other hosts are usually given as an address only
+fn endpoint(url: &str) -> String {
+    if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("http://{}", url)
+    }
+}
+
+// Relays the messages of the local services to the coordinator
+async fn relay(client: CoordinatorClient, mut rx: mpsc::Receiver<ServiceMessage>) {
+    // The stream of calls to each instance, keyed by service and instance
+    let mut registrations: HashMap<(String, String), JoinHandle<()>> = HashMap::new();
+    while let Some(msg) = rx.recv().await {
+        match msg {
+            ServiceMessage::Call {
+                id,
+                from,
+                to,
+                function,
+                context,
+                reply,
+            } => {
+                let mut client = client.clone();
+                tokio::spawn(async move {
+                    let request = proto::CallRequest {
+                        from,
+                        to: to.clone(),
+                        function,
+                        context,
+                    };
+                    let outcome = match client.call(request).await {
+                        Ok(response) => error_to_outcome(response.into_inner().error),
+                        Err(status) => {
+                            tracing::error!(call_id = id, "Error sending message: {}", status);
+                            Err(CallError::ServiceUnavailable(to))
+                        }
+                    };
+                    if let Some(reply) = reply {
+                        // The caller may have stopped waiting for the reply
+                        let _ = reply.send(outcome);
+                    }
+                });
+            }
+            ServiceMessage::RegisterService {
+                name,
+                instance,
+                sender,
+            } => {
+                let calls = tokio::spawn(receive_calls(
+                    client.clone(),
+                    name.clone(),
+                    instance.clone(),
+                    sender,
+                ));
+                if let Some(previous) = registrations.insert((name, instance), calls) {
+                    previous.abort();
+                }
+            }
+            ServiceMessage::DeregisterService { name, instance } => {
+                // Closing the stream removes the instance from the coordinator
+                registrations.retain(|(service, registered), calls| {
+                    let matches = *service == name
+                        && instance
+                            .as_ref()
+                            .is_none_or(|instance| instance == registered);
+                    if matches {
+                        calls.abort();
+                    }
+                    !matches
+                });
+            }
+            // Only a coordinator sends retries, to itself, and only the process of the coordinator
+            // controls it
+            ServiceMessage::Retry { .. }
+            | ServiceMessage::Chaos(_)
+            | ServiceMessage::Stats { .. } => {}
+            ServiceMessage::Shutdown => break,
+        }
+    }
+    for calls in registrations.into_values() {
+        calls.abort();
+    }
+}
+
+// Registers the instance and hands the calls the coordinator streams to it to the VM
+async fn receive_calls(
+    mut client: CoordinatorClient,
+    name: String,
+    instance: String,
+    sender: mpsc::Sender<IncomingCall>,
+) {
+    let request = proto::RegisterRequest {
+        name: name.clone(),
+        instance: instance.clone(),
+    };
+    let mut calls = match client.register(request).await {
+        Ok(response) => response.into_inner(),
+        Err(status) => {
+            tracing::error!(instance, "Cannot register with the coordinator: {}", status);
+            return;
+        }
+    };
+    while let Ok(Some(call)) = calls.message().await {
+        let id = call.id;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let incoming_call = IncomingCall {
+            id,
+            instance: call.instance,
+            function: call.function,
+            context: call.context,
+            reply: Some(reply_tx),
+        };
+        if sender.send(incoming_call).await.is_err() {
+            let error = outcome_to_error(Err(CallError::ServiceUnavailable(name.clone())));
+            let _ = client.reply(proto::ReplyRequest { id, error }).await;
+            continue;
+        }
+        let mut client = client.clone();
+        tokio::spawn(async move {
+            let outcome = reply_rx.await.unwrap_or(Err(CallError::Dropped));
+            let error = outcome_to_error(outcome);
+            if let Err(status) = client.reply(proto::ReplyRequest { id, error }).await {
+                tracing::debug!(call_id = id, "Cannot reply to the coordinator: {}", status);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_defaults_to_http() {
+        assert_eq!(endpoint("10.0.0.1:7000"), "http://10.0.0.1:7000");
+        assert_eq!(
+            endpoint("https://coordinator:7000"),
+            "https://coordinator:7000"
+        );
+    }
+}
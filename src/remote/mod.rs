@@ -0,0 +1,88 @@
+//! Runs the services of a simulation in processes of their own, possibly on other hosts. One
+//! process runs the coordinator and serves it over gRPC, the service processes reach it through
+//! a `CoordinatorHandle` that works like the one of a local coordinator.
+mod client;
+mod proto;
+mod server;
+
+pub use client::{connect, wait_for_services};
+pub use server::{serve, RemoteCoordinator};
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::net::TcpListener;
+    use tokio::sync::{mpsc, oneshot};
+
+    use super::*;
+    use crate::vm_coordinator::{CallError, ServiceCoordinator, ServiceMessage};
+
+    async fn call(
+        handle: &crate::vm_coordinator::CoordinatorHandle,
+        to: &str,
+    ) -> oneshot::Receiver<Result<(), CallError>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        handle
+            .sender()
+            .send(ServiceMessage::Call {
+                id: 0,
+                from: "frontend".to_string(),
+                to: to.to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        reply_rx
+    }
+
+    #[tokio::test]
+    async fn test_calls_between_processes_go_through_the_coordinator() {
+        let coordinator = ServiceCoordinator::new();
+        let coordinator_handle = coordinator.handle();
+        let coordinator_task = tokio::spawn(coordinator.run());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let remote_coordinator = RemoteCoordinator::new(coordinator_handle.clone(), 1, 10);
+        let stopped = remote_coordinator.stopped();
+        let server = tokio::spawn(serve(listener, remote_coordinator, async {
+            let _ = stop_rx.await;
+        }));
+
+        let (products, products_relay) = connect(&url).await.unwrap();
+        let (frontend, frontend_relay) = connect(&url).await.unwrap();
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        products
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+        wait_for_services(&url).await.unwrap();
+
+        let reply = call(&frontend, "products").await;
+        let incoming_call = products_rx.recv().await.unwrap();
+        assert_eq!(incoming_call.function, "get_products");
+        assert_eq!(incoming_call.instance, "products-0");
+        incoming_call.reply.unwrap().send(Ok(())).unwrap();
+        assert_eq!(reply.await.unwrap(), Ok(()));
+
+        let reply = call(&frontend, "payments").await;
+        assert_eq!(
+            reply.await.unwrap(),
+            Err(CallError::ServiceNotFound("payments".to_string()))
+        );
+
+        for (handle, relay) in [(products, products_relay), (frontend, frontend_relay)] {
+            handle.shutdown().await.unwrap();
+            relay.await.unwrap();
+        }
+        // The products instance closed its stream of calls along with its connection
+        stopped.await;
+        coordinator_handle.shutdown().await.unwrap();
+        coordinator_task.await.unwrap();
+        stop_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
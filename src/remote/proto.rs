@@ -0,0 +1,376 @@
+//! Messages and gRPC stubs of `proto/coordinator.proto`, written along the lines of what
+//! tonic-build generates, so building does not depend on protoc.
+use std::collections::HashMap;
+
+use crate::vm_coordinator;
+
+pub const SERVICE_NAME: &str = "mustermann.coordinator.Coordinator";
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RegisterRequest {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub instance: String,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct IncomingCall {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    #[prost(string, tag = "2")]
+    pub instance: String,
+    #[prost(string, tag = "3")]
+    pub function: String,
+    #[prost(map = "string, string", tag = "4")]
+    pub context: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WaitForServicesRequest {}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct WaitForServicesResponse {}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CallRequest {
+    #[prost(string, tag = "1")]
+    pub from: String,
+    #[prost(string, tag = "2")]
+    pub to: String,
+    #[prost(string, tag = "3")]
+    pub function: String,
+    #[prost(map = "string, string", tag = "4")]
+    pub context: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CallResponse {
+    #[prost(message, optional, tag = "1")]
+    pub error: Option<CallError>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReplyRequest {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    #[prost(message, optional, tag = "2")]
+    pub error: Option<CallError>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReplyResponse {}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CallError {
+    #[prost(enumeration = "CallErrorKind", tag = "1")]
+    pub kind: i32,
+    #[prost(string, tag = "2")]
+    pub detail: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum CallErrorKind {
+    Unspecified = 0,
+    ServiceNotFound = 1,
+    ServiceUnavailable = 2,
+    UnknownFunction = 3,
+    Dropped = 4,
+    InjectedFault = 5,
+    CircuitOpen = 6,
+    RateLimited = 7,
+    ShuttingDown = 8,
+}
+
+impl From<vm_coordinator::CallError> for CallError {
+    fn from(error: vm_coordinator::CallError) -> Self {
+        use vm_coordinator::CallError as E;
+        let (kind, detail) = match error {
+            E::ServiceNotFound(service) => (CallErrorKind::ServiceNotFound, service),
+            E::ServiceUnavailable(service) => (CallErrorKind::ServiceUnavailable, service),
+            E::UnknownFunction(function) => (CallErrorKind::UnknownFunction, function),
+            E::Dropped => (CallErrorKind::Dropped, String::new()),
+            E::InjectedFault(route) => (CallErrorKind::InjectedFault, route),
+            E::CircuitOpen(route) => (CallErrorKind::CircuitOpen, route),
+            E::RateLimited(service) => (CallErrorKind::RateLimited, service),
+            E::ShuttingDown => (CallErrorKind::ShuttingDown, String::new()),
+        };
+        Self {
+            kind: kind as i32,
+            detail,
+        }
+    }
+}
+
+impl From<CallError> for vm_coordinator::CallError {
+    fn from(error: CallError) -> Self {
+        use vm_coordinator::CallError as E;
+        let kind = CallErrorKind::try_from(error.kind).unwrap_or(CallErrorKind::Unspecified);
+        match kind {
+            CallErrorKind::ServiceNotFound => E::ServiceNotFound(error.detail),
+            CallErrorKind::ServiceUnavailable => E::ServiceUnavailable(error.detail),
+            CallErrorKind::UnknownFunction => E::UnknownFunction(error.detail),
+            // A newer coordinator may send kinds this one does not know
+            CallErrorKind::Dropped | CallErrorKind::Unspecified => E::Dropped,
+            CallErrorKind::InjectedFault => E::InjectedFault(error.detail),
+            CallErrorKind::CircuitOpen => E::CircuitOpen(error.detail),
+            CallErrorKind::RateLimited => E::RateLimited(error.detail),
+            CallErrorKind::ShuttingDown => E::ShuttingDown,
+        }
+    }
+}
+
+/// Turns the outcome of a call into the optional error of a response
+pub fn outcome_to_error(outcome: Result<(), vm_coordinator::CallError>) -> Option<CallError> {
+    outcome.err().map(CallError::from)
+}
+
+/// Turns the optional error of a response back into the outcome of a call
+pub fn error_to_outcome(error: Option<CallError>) -> Result<(), vm_coordinator::CallError> {
+    match error {
+        Some(error) => Err(error.into()),
+        None => Ok(()),
+    }
+}
+
+pub mod coordinator_client {
+    use tonic::codegen::http::uri::PathAndQuery;
+
+    #[derive(Debug, Clone)]
+    pub struct CoordinatorClient {
+        inner: tonic::client::Grpc<tonic::transport::Channel>,
+    }
+
+    impl CoordinatorClient {
+        pub async fn connect(url: String) -> Result<Self, tonic::transport::Error> {
+            let channel = tonic::transport::Endpoint::new(url)?.connect().await?;
+            Ok(Self {
+                inner: tonic::client::Grpc::new(channel),
+            })
+        }
+
+        async fn ready(&mut self) -> Result<(), tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| tonic::Status::unknown(format!("Service was not ready: {}", e)))
+        }
+
+        fn path(method: &str) -> PathAndQuery {
+            PathAndQuery::try_from(format!("/{}/{}", super::SERVICE_NAME, method))
+                .expect("Method paths are valid")
+        }
+
+        pub async fn register(
+            &mut self,
+            request: super::RegisterRequest,
+        ) -> Result<tonic::Response<tonic::codec::Streaming<super::IncomingCall>>, tonic::Status>
+        {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            self.inner
+                .server_streaming(tonic::Request::new(request), Self::path("Register"), codec)
+                .await
+        }
+
+        pub async fn wait_for_services(
+            &mut self,
+        ) -> Result<tonic::Response<super::WaitForServicesResponse>, tonic::Status> {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            self.inner
+                .unary(
+                    tonic::Request::new(super::WaitForServicesRequest {}),
+                    Self::path("WaitForServices"),
+                    codec,
+                )
+                .await
+        }
+
+        pub async fn call(
+            &mut self,
+            request: super::CallRequest,
+        ) -> Result<tonic::Response<super::CallResponse>, tonic::Status> {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            self.inner
+                .unary(tonic::Request::new(request), Self::path("Call"), codec)
+                .await
+        }
+
+        pub async fn reply(
+            &mut self,
+            request: super::ReplyRequest,
+        ) -> Result<tonic::Response<super::ReplyResponse>, tonic::Status> {
+            self.ready().await?;
+            let codec = tonic::codec::ProstCodec::default();
+            self.inner
+                .unary(tonic::Request::new(request), Self::path("Reply"), codec)
+                .await
+        }
+    }
+}
+
+pub mod coordinator_server {
+    use futures::stream::BoxStream;
+    use tonic::codegen::*;
+
+    pub type IncomingCallStream = BoxStream<'static, Result<super::IncomingCall, tonic::Status>>;
+
+    #[async_trait]
+    pub trait Coordinator: Send + Sync + 'static {
+        async fn register(
+            &self,
+            request: tonic::Request<super::RegisterRequest>,
+        ) -> Result<tonic::Response<IncomingCallStream>, tonic::Status>;
+
+        async fn wait_for_services(
+            &self,
+            request: tonic::Request<super::WaitForServicesRequest>,
+        ) -> Result<tonic::Response<super::WaitForServicesResponse>, tonic::Status>;
+
+        async fn call(
+            &self,
+            request: tonic::Request<super::CallRequest>,
+        ) -> Result<tonic::Response<super::CallResponse>, tonic::Status>;
+
+        async fn reply(
+            &self,
+            request: tonic::Request<super::ReplyRequest>,
+        ) -> Result<tonic::Response<super::ReplyResponse>, tonic::Status>;
+    }
+
+    #[derive(Debug)]
+    pub struct CoordinatorServer<T> {
+        inner: Arc<T>,
+    }
+
+    impl<T> CoordinatorServer<T> {
+        pub fn new(inner: T) -> Self {
+            Self {
+                inner: Arc::new(inner),
+            }
+        }
+    }
+
+    impl<T> Clone for CoordinatorServer<T> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+            }
+        }
+    }
+
+    struct RegisterSvc<T>(Arc<T>);
+
+    impl<T: Coordinator> tonic::server::ServerStreamingService<super::RegisterRequest>
+        for RegisterSvc<T>
+    {
+        type Response = super::IncomingCall;
+        type ResponseStream = IncomingCallStream;
+        type Future = BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+
+        fn call(&mut self, request: tonic::Request<super::RegisterRequest>) -> Self::Future {
+            let inner = self.0.clone();
+            Box::pin(async move { inner.register(request).await })
+        }
+    }
+
+    struct WaitForServicesSvc<T>(Arc<T>);
+
+    impl<T: Coordinator> tonic::server::UnaryService<super::WaitForServicesRequest>
+        for WaitForServicesSvc<T>
+    {
+        type Response = super::WaitForServicesResponse;
+        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+        fn call(&mut self, request: tonic::Request<super::WaitForServicesRequest>) -> Self::Future {
+            let inner = self.0.clone();
+            Box::pin(async move { inner.wait_for_services(request).await })
+        }
+    }
+
+    struct CallSvc<T>(Arc<T>);
+
+    impl<T: Coordinator> tonic::server::UnaryService<super::CallRequest> for CallSvc<T> {
+        type Response = super::CallResponse;
+        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+        fn call(&mut self, request: tonic::Request<super::CallRequest>) -> Self::Future {
+            let inner = self.0.clone();
+            Box::pin(async move { inner.call(request).await })
+        }
+    }
+
+    struct ReplySvc<T>(Arc<T>);
+
+    impl<T: Coordinator> tonic::server::UnaryService<super::ReplyRequest> for ReplySvc<T> {
+        type Response = super::ReplyResponse;
+        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+
+        fn call(&mut self, request: tonic::Request<super::ReplyRequest>) -> Self::Future {
+            let inner = self.0.clone();
+            Box::pin(async move { inner.reply(request).await })
+        }
+    }
+
+    impl<T, B> Service<http::Request<B>> for CoordinatorServer<T>
+    where
+        T: Coordinator,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = std::convert::Infallible;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            let inner = self.inner.clone();
+            let method = req
+                .uri()
+                .path()
+                .strip_prefix(&format!("/{}/", super::SERVICE_NAME))
+                .map(str::to_string);
+            Box::pin(async move {
+                let response = match method.as_deref() {
+                    Some("Register") => grpc().server_streaming(RegisterSvc(inner), req).await,
+                    Some("WaitForServices") => grpc().unary(WaitForServicesSvc(inner), req).await,
+                    Some("Call") => grpc().unary(CallSvc(inner), req).await,
+                    Some("Reply") => grpc().unary(ReplySvc(inner), req).await,
+                    _ => {
+                        let mut response = http::Response::new(empty_body());
+                        let headers = response.headers_mut();
+                        headers.insert(
+                            tonic::Status::GRPC_STATUS,
+                            (tonic::Code::Unimplemented as i32).into(),
+                        );
+                        headers.insert(
+                            http::header::CONTENT_TYPE,
+                            tonic::metadata::GRPC_CONTENT_TYPE,
+                        );
+                        response
+                    }
+                };
+                Ok(response)
+            })
+        }
+    }
+
+    // Every method encodes other messages, so each gets a codec of its own
+    fn grpc<T, U>() -> tonic::server::Grpc<tonic::codec::ProstCodec<T, U>>
+    where
+        T: prost::Message + Send + 'static,
+        U: prost::Message + Default + Send + 'static,
+    {
+        tonic::server::Grpc::new(tonic::codec::ProstCodec::default())
+    }
+
+    impl<T> tonic::server::NamedService for CoordinatorServer<T> {
+        const NAME: &'static str = super::SERVICE_NAME;
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot, watch};
+use tonic::{Request, Response, Status};
+
+use super::proto::coordinator_server::{Coordinator, CoordinatorServer, IncomingCallStream};
+use super::proto::{self, outcome_to_error};
+use crate::vm_coordinator::{
+    self, CallError, CallId, CallReply, CoordinatorHandle, ServiceMessage,
+};
+
+/// Calls delivered to instances in other processes that have yet to reply, with the instance
+type PendingReplies = Arc<Mutex<HashMap<CallId, (String, CallReply)>>>;
+
+/// Makes a local coordinator reachable for services in other processes
+pub struct RemoteCoordinator {
+    handle: CoordinatorHandle,
+    pending: PendingReplies,
+    /// How many calls an instance queues
+    queue_size: usize,
+    /// Services wait for this many instances before they start
+    expected_instances: usize,
+    instances: watch::Sender<Instances>,
+}
+
+/// The instances that registered with a remote coordinator so far
+#[derive(Debug, Default, Clone, Copy)]
+struct Instances {
+    registered: usize,
+    /// Instances that did not stop yet
+    running: usize,
+}
+
+impl RemoteCoordinator {
+    pub fn new(handle: CoordinatorHandle, expected_instances: usize, queue_size: usize) -> Self {
+        Self {
+            handle,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            queue_size: queue_size.max(1),
+            expected_instances,
+            instances: watch::Sender::new(Instances::default()),
+        }
+    }
+
+    /// Completes once every expected instance registered and all of them stopped again
+    pub fn stopped(&self) -> impl Future<Output = ()> + 'static {
+        let mut instances = self.instances.subscribe();
+        let expected_instances = self.expected_instances;
+        async move {
+            let _ = instances
+                .wait_for(|instances| {
+                    instances.registered >= expected_instances && instances.running == 0
+                })
+                .await;
+        }
+    }
+}
+
+/// Serves the coordinator on `listener` until `shutdown` completes
+pub async fn serve(
+    listener: TcpListener,
+    coordinator: RemoteCoordinator,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    let incoming = tonic::transport::server::TcpIncoming::from_listener(listener, true, None)
+        .expect("A bound listener accepts connections");
+    tonic::transport::Server::builder()
+        .add_service(CoordinatorServer::new(coordinator))
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await
+}
+
+/// Removes the instance from the coordinator once its stream of calls closes
+struct Registration {
+    handle: CoordinatorHandle,
+    name: String,
+    instance: String,
+    pending: PendingReplies,
+    instances: watch::Sender<Instances>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // Dropping the replies tells the callers the calls never completed
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|_, (instance, _)| *instance != self.instance);
+        self.instances
+            .send_modify(|instances| instances.running -= 1);
+        let handle = self.handle.clone();
+        let name = self.name.clone();
+        let instance = self.instance.clone();
+        tokio::spawn(async move {
+            if handle
+                .deregister_service(&name, Some(&instance))
+                .await
+                .is_err()
+            {
+                tracing::debug!(instance, "Coordinator stopped before the remote instance");
+            }
+        });
+    }
+}
+
+#[tonic::async_trait]
+impl Coordinator for RemoteCoordinator {
+    async fn register(
+        &self,
+        request: Request<proto::RegisterRequest>,
+    ) -> Result<Response<IncomingCallStream>, Status> {
+        let proto::RegisterRequest { name, instance } = request.into_inner();
+        let (incoming_tx, incoming_rx) = mpsc::channel(self.queue_size);
+        self.handle
+            .register_service(&name, &instance, incoming_tx)
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        tracing::debug!(instance, "Remote instance of {} registered", name);
+        self.instances.send_modify(|instances| {
+            instances.registered += 1;
+            instances.running += 1;
+        });
+
+        let registration = Registration {
+            handle: self.handle.clone(),
+            name,
+            instance,
+            pending: self.pending.clone(),
+            instances: self.instances.clone(),
+        };
+        let calls = futures::stream::unfold(
+            (incoming_rx, registration),
+            |(mut incoming_rx, registration)| async move {
+                let call: vm_coordinator::IncomingCall = incoming_rx.recv().await?;
+                if let Some(reply) = call.reply {
+                    registration
+                        .pending
+                        .lock()
+                        .unwrap()
+                        .insert(call.id, (registration.instance.clone(), reply));
+                }
+                let call = proto::IncomingCall {
+                    id: call.id,
+                    instance: call.instance,
+                    function: call.function,
+                    context: call.context,
+                };
+                Some((Ok(call), (incoming_rx, registration)))
+            },
+        );
+        Ok(Response::new(Box::pin(calls)))
+    }
+
+    async fn wait_for_services(
+        &self,
+        _request: Request<proto::WaitForServicesRequest>,
+    ) -> Result<Response<proto::WaitForServicesResponse>, Status> {
+        let expected_instances = self.expected_instances;
+        self.instances
+            .subscribe()
+            .wait_for(|instances| instances.registered >= expected_instances)
+            .await
+            .map_err(|_| Status::unavailable("Coordinator stopped"))?;
+        Ok(Response::new(proto::WaitForServicesResponse {}))
+    }
+
+    async fn call(
+        &self,
+        request: Request<proto::CallRequest>,
+    ) -> Result<Response<proto::CallResponse>, Status> {
+        let proto::CallRequest {
+            from,
+            to,
+            function,
+            context,
+        } = request.into_inner();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        // Call IDs are only unique within a process, so the call gets a new one here
+        self.handle
+            .sender()
+            .send(ServiceMessage::Call {
+                id: vm_coordinator::next_call_id(),
+                from,
+                to,
+                function,
+                context,
+                reply: Some(reply_tx),
+            })
+            .await
+            .map_err(|_| Status::unavailable("Coordinator stopped"))?;
+        let outcome = reply_rx.await.unwrap_or(Err(CallError::Dropped));
+        Ok(Response::new(proto::CallResponse {
+            error: outcome_to_error(outcome),
+        }))
+    }
+
+    async fn reply(
+        &self,
+        request: Request<proto::ReplyRequest>,
+    ) -> Result<Response<proto::ReplyResponse>, Status> {
+        let proto::ReplyRequest { id, error } = request.into_inner();
+        let pending = self.pending.lock().unwrap().remove(&id);
+        match pending {
+            // The caller may have stopped waiting for the reply
+            Some((_, reply)) => {
+                let _ = reply.send(proto::error_to_outcome(error));
+            }
+            None => tracing::debug!(call_id = id, "Reply to an unknown call"),
+        }
+        Ok(Response::new(proto::ReplyResponse {}))
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::future::join_all;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::journal::JournalEntry;
+use crate::vm_coordinator::{self, ServiceMessage};
+
+/// How a replay went
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplaySummary {
+    pub calls: usize,
+    pub failed: usize,
+}
+
+/// Re-issues the calls of a journal through the coordinator, with the time between them as it was
+/// recorded, divided by `speed`. Retries are left out, the retry policies of the routes make them again.
+/// Returns once every call completed or failed.
+pub async fn replay(
+    mut entries: Vec<JournalEntry>,
+    speed: f64,
+    coordinator: mpsc::Sender<ServiceMessage>,
+) -> ReplaySummary {
+    entries.retain(|entry| entry.attempt == 1);
+    entries.sort_by_key(|entry| entry.timestamp_ms);
+    let Some(first_timestamp_ms) = entries.first().map(|entry| entry.timestamp_ms) else {
+        return ReplaySummary::default();
+    };
+    let start = Instant::now();
+    let mut replies = Vec::new();
+    for entry in entries {
+        let offset_ms = (entry.timestamp_ms - first_timestamp_ms) as f64 / speed;
+        tokio::time::sleep_until(start + Duration::from_secs_f64(offset_ms / 1000.0)).await;
+        tracing::debug!(
+            call_id = entry.call_id,
+            trace_id = entry.trace_id.as_deref(),
+            "Replaying call from {} to {}.{}",
+            entry.from,
+            entry.to,
+            entry.function
+        );
+        let (reply_tx, reply_rx) = oneshot::channel();
+        // Replayed calls start new traces
+        let call = ServiceMessage::Call {
+            id: vm_coordinator::next_call_id(),
+            from: entry.from,
+            to: entry.to,
+            function: entry.function,
+            context: HashMap::new(),
+            reply: Some(reply_tx),
+        };
+        if coordinator.send(call).await.is_err() {
+            tracing::warn!("Coordinator stopped before the replay finished");
+            break;
+        }
+        replies.push(reply_rx);
+    }
+    let outcomes = join_all(replies).await;
+    ReplaySummary {
+        calls: outcomes.len(),
+        failed: outcomes
+            .iter()
+            .filter(|outcome| !matches!(outcome, Ok(Ok(()))))
+            .count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm_coordinator::{CallError, ServiceCoordinator};
+
+    fn entry(timestamp_ms: u64, to: &str, function: &str, attempt: usize) -> JournalEntry {
+        JournalEntry {
+            timestamp_ms,
+            call_id: timestamp_ms,
+            from: "frontend".to_string(),
+            to: to.to_string(),
+            function: function.to_string(),
+            instance: format!("{}-0", to),
+            attempt,
+            trace_id: None,
+            span_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_reissues_first_attempts_in_order() {
+        let coordinator = ServiceCoordinator::new();
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+        let products = tokio::spawn(async move {
+            let mut functions = Vec::new();
+            while let Some(call) = products_rx.recv().await {
+                functions.push(call.function);
+                let outcome = match functions.len() {
+                    1 => Ok(()),
+                    _ => Err(CallError::InjectedFault("frontend->products".to_string())),
+                };
+                call.reply.unwrap().send(outcome).unwrap();
+            }
+            functions
+        });
+
+        let entries = vec![
+            entry(1_000_200, "products", "get_stock", 1),
+            entry(1_000_000, "products", "get_products", 1),
+            entry(1_000_100, "products", "get_products", 2),
+        ];
+        let summary = replay(entries, 10.0, coordinator_handle.sender()).await;
+        assert_eq!(
+            summary,
+            ReplaySummary {
+                calls: 2,
+                failed: 1
+            }
+        );
+
+        coordinator_handle
+            .deregister_service("products", None)
+            .await
+            .unwrap();
+        drop(coordinator_handle);
+        handle.await.unwrap();
+        assert_eq!(products.await.unwrap(), vec!["get_products", "get_stock"]);
+    }
+}
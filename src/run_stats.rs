@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tabled::Tabled;
+
+use crate::vm::VmStats;
+
+/// What the instances of a service did during a run
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct ServiceRunStats {
+    instances: usize,
+    instructions: usize,
+    info_logs: usize,
+    error_logs: usize,
+    dropped_logs: usize,
+    remote_calls: usize,
+    incoming_calls: usize,
+    failed_remote_calls: usize,
+    /// Of the instance that ran the longest
+    wall_time: Duration,
+}
+
+/// One of the counters of a service, named like the columns of the `--stats` table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Counter {
+    Instructions,
+    #[serde(alias = "stdout")]
+    InfoLogs,
+    #[serde(alias = "stderr")]
+    ErrorLogs,
+    DroppedLogs,
+    CallsMade,
+    CallsReceived,
+    CallsFailed,
+}
+
+impl std::fmt::Display for Counter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Counter::Instructions => "instructions",
+            Counter::InfoLogs => "info_logs",
+            Counter::ErrorLogs => "error_logs",
+            Counter::DroppedLogs => "dropped_logs",
+            Counter::CallsMade => "calls_made",
+            Counter::CallsReceived => "calls_received",
+            Counter::CallsFailed => "calls_failed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Collects what every instance did during a run, for the table of `--stats`
+#[derive(Debug, Clone, Default)]
+pub struct RunStats {
+    services: Arc<Mutex<BTreeMap<String, ServiceRunStats>>>,
+}
+
+impl RunStats {
+    /// Adds the counters of an instance that stopped after running for `wall_time`
+    pub fn record_instance(&self, service: &str, stats: &VmStats, wall_time: Duration) {
+        let mut services = self.services.lock().unwrap();
+        let service = services.entry(service.to_string()).or_default();
+        service.instances += 1;
+        service.instructions += stats.instructions;
+        service.remote_calls += stats.remote_calls;
+        service.incoming_calls += stats.incoming_calls;
+        service.wall_time = service.wall_time.max(wall_time);
+    }
+
+    /// Adds the logs an instance emitted, and the ones its print limits dropped
+    pub fn record_logs(&self, service: &str, info: usize, error: usize, dropped: usize) {
+        let mut services = self.services.lock().unwrap();
+        let service = services.entry(service.to_string()).or_default();
+        service.info_logs += info;
+        service.error_logs += error;
+        service.dropped_logs += dropped;
+    }
+
+    /// Adds the failed calls of an instance, once the replies to all of its calls arrived
+    pub fn record_failed_calls(&self, service: &str, failed: usize) {
+        let mut services = self.services.lock().unwrap();
+        services
+            .entry(service.to_string())
+            .or_default()
+            .failed_remote_calls += failed;
+    }
+
+    /// The counter of all instances of a service, `None` if no instance of it ran
+    pub fn count(&self, service: &str, counter: Counter) -> Option<usize> {
+        let services = self.services.lock().unwrap();
+        let stats = services.get(service)?;
+        Some(match counter {
+            Counter::Instructions => stats.instructions,
+            Counter::InfoLogs => stats.info_logs,
+            Counter::ErrorLogs => stats.error_logs,
+            Counter::DroppedLogs => stats.dropped_logs,
+            Counter::CallsMade => stats.remote_calls,
+            Counter::CallsReceived => stats.incoming_calls,
+            Counter::CallsFailed => stats.failed_remote_calls,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.services.lock().unwrap().is_empty()
+    }
+
+    /// One row per service, ordered by name
+    pub fn rows(&self) -> Vec<StatsRow> {
+        self.services
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, stats)| StatsRow {
+                service: name.clone(),
+                instances: stats.instances,
+                instructions: stats.instructions,
+                info_logs: stats.info_logs,
+                error_logs: stats.error_logs,
+                dropped_logs: stats.dropped_logs,
+                calls_made: stats.remote_calls,
+                calls_received: stats.incoming_calls,
+                calls_failed: stats.failed_remote_calls,
+                wall_time: format!("{:.1?}", stats.wall_time),
+            })
+            .collect()
+    }
+}
+
+#[derive(Tabled)]
+pub struct StatsRow {
+    service: String,
+    instances: usize,
+    instructions: usize,
+    #[tabled(rename = "info logs")]
+    info_logs: usize,
+    #[tabled(rename = "error logs")]
+    error_logs: usize,
+    #[tabled(rename = "dropped logs")]
+    dropped_logs: usize,
+    #[tabled(rename = "calls made")]
+    calls_made: usize,
+    #[tabled(rename = "calls received")]
+    calls_received: usize,
+    #[tabled(rename = "calls failed")]
+    calls_failed: usize,
+    #[tabled(rename = "wall time")]
+    wall_time: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_stats_add_up_the_instances_of_a_service() {
+        let run_stats = RunStats::default();
+        let stats = VmStats {
+            instructions: 100,
+            stdout: 10,
+            stderr: 2,
+            remote_calls: 5,
+            incoming_calls: 3,
+            failed_remote_calls: 0,
+        };
+        run_stats.record_instance("products", &stats, Duration::from_millis(1500));
+        run_stats.record_instance("products", &stats, Duration::from_millis(2000));
+        run_stats.record_logs("products", 10, 2, 0);
+        run_stats.record_logs("products", 8, 3, 2);
+        run_stats.record_logs("frontend", 1, 0, 0);
+        run_stats.record_failed_calls("products", 1);
+        run_stats.record_failed_calls("products", 1);
+
+        let rows = run_stats.rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].service, "frontend");
+        let products = &rows[1];
+        assert_eq!(products.instances, 2);
+        assert_eq!(products.instructions, 200);
+        assert_eq!(
+            (
+                products.info_logs,
+                products.error_logs,
+                products.dropped_logs
+            ),
+            (18, 5, 2)
+        );
+        assert_eq!(
+            (
+                products.calls_made,
+                products.calls_received,
+                products.calls_failed
+            ),
+            (10, 6, 2)
+        );
+        assert_eq!(products.wall_time, "2.0s");
+    }
+}
@@ -1,13 +1,17 @@
 use tokio::task::JoinError;
 
-use crate::vm;
+use crate::{vm, vm_builder, vm_coordinator};
 
 #[derive(Debug)]
 pub enum RuntimeError {
     VMError(vm::VMError),
+    VmConfigError(vm_builder::VmConfigError),
+    CoordinatorStopped(vm_coordinator::CoordinatorStopped),
     ServiceError(JoinError),
     InitTraceError(opentelemetry_otlp::ExporterBuildError),
     InitMeterError(opentelemetry_otlp::ExporterBuildError),
+    /// A call went to an unknown service while running with `--strict`
+    DeadLetter(String),
 }
 
 impl std::error::Error for RuntimeError {}
@@ -16,9 +20,12 @@ impl std::fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             RuntimeError::VMError(e) => write!(f, "VM error: {}", e),
+            RuntimeError::VmConfigError(e) => write!(f, "VM configuration error: {}", e),
+            RuntimeError::CoordinatorStopped(e) => write!(f, "{}", e),
             RuntimeError::ServiceError(e) => write!(f, "Service error: {}", e),
             RuntimeError::InitTraceError(e) => write!(f, "Init trace error: {}", e),
             RuntimeError::InitMeterError(e) => write!(f, "Init meter error: {}", e),
+            RuntimeError::DeadLetter(error) => write!(f, "Aborted in strict mode: {}", error),
         }
     }
 }
@@ -29,6 +36,18 @@ impl From<JoinError> for RuntimeError {
     }
 }
 
+impl From<vm_builder::VmConfigError> for RuntimeError {
+    fn from(e: vm_builder::VmConfigError) -> Self {
+        RuntimeError::VmConfigError(e)
+    }
+}
+
+impl From<vm_coordinator::CoordinatorStopped> for RuntimeError {
+    fn from(e: vm_coordinator::CoordinatorStopped) -> Self {
+        RuntimeError::CoordinatorStopped(e)
+    }
+}
+
 impl From<vm::VMError> for RuntimeError {
     fn from(e: vm::VMError) -> Self {
         RuntimeError::VMError(e)
@@ -0,0 +1,172 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use opentelemetry::metrics::{Counter, Gauge, MeterProvider, UpDownCounter};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+
+use crate::vm_coordinator::CoordinatorHandle;
+
+/// Metrics about mustermann itself, under `mustermann.runtime.*`, to tell when the generator
+/// is the bottleneck rather than the simulated services
+#[derive(Clone)]
+pub struct RuntimeMetrics {
+    active_vms: UpDownCounter<i64>,
+    print_queue_depth: Gauge<u64>,
+    dropped_messages: Counter<u64>,
+    /// Executed by all VMs, for the instructions per second
+    instructions: Arc<AtomicU64>,
+}
+
+impl RuntimeMetrics {
+    pub fn new(meter_provider: &SdkMeterProvider, coordinator: &CoordinatorHandle) -> Self {
+        let meter = meter_provider.meter("mustermann.runtime");
+        let instructions = Arc::new(AtomicU64::new(0));
+
+        // The gauge must not keep the coordinator running
+        let coordinator = coordinator.sender().downgrade();
+        meter
+            .u64_observable_gauge("mustermann.runtime.coordinator.queue_depth")
+            .with_unit("{message}")
+            .with_description("The number of messages waiting for the coordinator")
+            .with_callback(move |observer| {
+                if let Some(tx) = coordinator.upgrade() {
+                    observer.observe((tx.max_capacity() - tx.capacity()) as u64, &[]);
+                }
+            })
+            .build();
+
+        let executed = instructions.clone();
+        let last_sample = Mutex::new((Instant::now(), 0));
+        meter
+            .f64_observable_gauge("mustermann.runtime.instructions_per_second")
+            .with_unit("{instruction}/s")
+            .with_description("The number of instructions all services executed per second")
+            .with_callback(move |observer| {
+                let now = Instant::now();
+                let total = executed.load(Ordering::Relaxed);
+                let mut last_sample = last_sample.lock().unwrap();
+                let elapsed = now.duration_since(last_sample.0).as_secs_f64();
+                if elapsed > 0.0 {
+                    observer.observe((total - last_sample.1) as f64 / elapsed, &[]);
+                }
+                *last_sample = (now, total);
+            })
+            .build();
+
+        Self {
+            active_vms: meter
+                .i64_up_down_counter("mustermann.runtime.active_vms")
+                .with_unit("{vm}")
+                .with_description("The number of VMs running a service instance")
+                .build(),
+            print_queue_depth: meter
+                .u64_gauge("mustermann.runtime.print_queue.depth")
+                .with_unit("{message}")
+                .with_description("The number of prints of an instance waiting to be logged")
+                .build(),
+            dropped_messages: meter
+                .u64_counter("mustermann.runtime.dropped_messages")
+                .with_unit("{message}")
+                .with_description("The number of prints dropped by rate limiting or sampling")
+                .build(),
+            instructions,
+        }
+    }
+
+    /// Counts the instructions of a VM, shared with the VM
+    pub fn instructions(&self) -> Arc<AtomicU64> {
+        self.instructions.clone()
+    }
+
+    pub fn vm_started(&self, service: &str) {
+        self.active_vms
+            .add(1, &[KeyValue::new("service", service.to_string())]);
+    }
+
+    pub fn vm_stopped(&self, service: &str) {
+        self.active_vms
+            .add(-1, &[KeyValue::new("service", service.to_string())]);
+    }
+
+    pub fn record_print_queue_depth(&self, depth: usize, service: &str, instance: &str) {
+        self.print_queue_depth.record(
+            depth as u64,
+            &[
+                KeyValue::new("service", service.to_string()),
+                KeyValue::new("instance", instance.to_string()),
+            ],
+        );
+    }
+
+    pub fn message_dropped(&self, service: &str) {
+        self.dropped_messages
+            .add(1, &[KeyValue::new("service", service.to_string())]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm_coordinator::ServiceMessage;
+    use opentelemetry_sdk::metrics::data::{Gauge, Sum};
+    use opentelemetry_sdk::metrics::{InMemoryMetricExporter, PeriodicReader};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_runtime_metrics_report_the_coordinator_queue_and_active_vms() {
+        let exporter = InMemoryMetricExporter::default();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter.clone()).build())
+            .build();
+        let (tx, _rx) = mpsc::channel(10);
+        let coordinator = CoordinatorHandle::from_sender(tx.clone());
+        let metrics = RuntimeMetrics::new(&meter_provider, &coordinator);
+
+        for _ in 0..2 {
+            tx.send(ServiceMessage::DeregisterService {
+                name: "products".to_string(),
+                instance: None,
+            })
+            .await
+            .unwrap();
+        }
+        metrics.vm_started("products");
+        metrics.vm_started("frontend");
+        metrics.vm_stopped("frontend");
+        meter_provider.force_flush().unwrap();
+
+        let resource_metrics = exporter.get_finished_metrics().unwrap();
+        let metrics: Vec<_> = resource_metrics
+            .iter()
+            .flat_map(|resource| &resource.scope_metrics)
+            .flat_map(|scope| &scope.metrics)
+            .collect();
+        let metric = |name: &str| {
+            metrics
+                .iter()
+                .find(|metric| metric.name == name)
+                .unwrap()
+                .data
+                .as_any()
+        };
+        let queue_depth = metric("mustermann.runtime.coordinator.queue_depth")
+            .downcast_ref::<Gauge<u64>>()
+            .unwrap();
+        assert_eq!(queue_depth.data_points[0].value, 2);
+        let active_vms = metric("mustermann.runtime.active_vms")
+            .downcast_ref::<Sum<i64>>()
+            .unwrap();
+        let mut active_vms: Vec<(String, i64)> = active_vms
+            .data_points
+            .iter()
+            .map(|point| (point.attributes[0].value.to_string(), point.value))
+            .collect();
+        active_vms.sort();
+        assert_eq!(
+            active_vms,
+            [("frontend".to_string(), 0), ("products".to_string(), 1)]
+        );
+    }
+}
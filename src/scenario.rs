@@ -0,0 +1,166 @@
+//! Wires a program up to run: the coordinator with the faults, policies and chaos of the program,
+//! and the VM of every replica with the config of its service. The CLI, golden files and embedders
+//! all set up their runs here, so a file behaves the same in each of them.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::artifact::CompiledService;
+use crate::chaos;
+use crate::load::LoadShaper;
+use crate::parser::{
+    CallPriority, ChaosAction, CircuitBreakerConfig, Incident, Injection, LoadProfile, Program,
+    RateLimit, RetryPolicy, ServiceConfig, TimelineEvent, TrafficPattern,
+};
+use crate::print_limiter::PrintLimiter;
+use crate::recording::{self, Decider};
+use crate::timeline;
+use crate::vm_builder::VmBuilder;
+use crate::vm_coordinator::{CoordinatorHandle, ServiceCoordinator};
+
+/// The settings of a program that apply to the whole run rather than to one service
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub injections: Vec<Injection>,
+    pub incidents: Vec<Incident>,
+    /// The first matching circuit breaker wins
+    pub circuit_breakers: Vec<CircuitBreakerConfig>,
+    pub retry_policies: Vec<RetryPolicy>,
+    pub priorities: Vec<CallPriority>,
+    pub rate_limits: Vec<RateLimit>,
+    /// The chaos of the program, without the windows of the services and the deploys of the timeline
+    pub chaos: Vec<ChaosAction>,
+    pub timeline: Vec<TimelineEvent>,
+    pub load: Option<LoadProfile>,
+    pub pattern: Option<TrafficPattern>,
+}
+
+/// What the VMs of a run share
+pub struct Run {
+    pub coordinator: CoordinatorHandle,
+    pub decider: Decider,
+    /// Set to run the shutdown blocks and stop
+    pub shutdown: Arc<AtomicBool>,
+    /// Paces the loops of all services along the load profile and traffic pattern
+    pub load: Option<Arc<LoadShaper>>,
+    /// The start of the run, that incidents and the load profile are timed from
+    pub started: std::time::Instant,
+    /// The wall-clock time at `started`, that access logs are timestamped from
+    pub started_at: SystemTime,
+}
+
+impl Scenario {
+    pub fn new(program: &Program) -> Self {
+        Self {
+            injections: program.injections.clone(),
+            incidents: program.incidents.clone(),
+            circuit_breakers: program.circuit_breakers.clone(),
+            retry_policies: program.retry_policies.clone(),
+            priorities: program.priorities.clone(),
+            rate_limits: program.rate_limits.clone(),
+            chaos: program.chaos.clone(),
+            timeline: program.timeline.clone(),
+            load: program.load.clone(),
+            pattern: program.pattern.clone(),
+        }
+    }
+
+    /// A coordinator for `services` that applies the scenario. Services with a `start_after` or
+    /// `stop_after` are killed outside of their window
+    pub fn coordinator(
+        &self,
+        services: &[CompiledService],
+        decider: &Decider,
+    ) -> ServiceCoordinator {
+        let mut chaos = self.chaos.clone();
+        chaos.extend(chaos::service_windows(
+            services
+                .iter()
+                .map(|service| (service.name.as_str(), &service.config)),
+        ));
+        chaos.extend(timeline::chaos_actions(&self.timeline));
+        ServiceCoordinator::new()
+            .with_decisions(decider.decisions(recording::COORDINATOR))
+            .with_injections(self.injections.clone())
+            .with_incidents(self.incidents.clone())
+            .with_circuit_breakers(self.circuit_breakers.clone())
+            .with_retry_policies(self.retry_policies.clone())
+            .with_priorities(self.priorities.clone())
+            .with_rate_limits(self.rate_limits.clone())
+            .with_chaos(&chaos)
+    }
+
+    /// Paces the loops along the load profile and traffic pattern, if the scenario has one.
+    /// `time_of_day` is when the run starts, for the pattern
+    pub fn load_shaper(&self, time_of_day: Duration) -> Option<Arc<LoadShaper>> {
+        if self.load.is_none() && self.pattern.is_none() {
+            return None;
+        }
+        let mut shaper = LoadShaper::new();
+        if let Some(profile) = &self.load {
+            shaper = shaper.with_profile(profile.clone());
+        }
+        if let Some(pattern) = &self.pattern {
+            shaper = shaper.with_pattern(pattern.clone(), time_of_day);
+        }
+        Some(Arc::new(shaper))
+    }
+
+    /// The VM of the replica `instance` of `service`, with the config of the service, the
+    /// incidents of the scenario and what the VMs of `run` share. `defaults` fill in the limits
+    /// the config of the service leaves open
+    pub fn vm_builder(
+        &self,
+        service: &CompiledService,
+        instance: &str,
+        run: &Run,
+        defaults: &ServiceConfig,
+    ) -> VmBuilder {
+        let config = &service.config;
+        let mut builder = VmBuilder::new(service.code.clone(), &service.name)
+            .with_remote_call_tx(run.coordinator.sender())
+            .with_decisions(run.decider.decisions(instance))
+            .with_shutdown_flag(run.shutdown.clone())
+            .with_started(run.started)
+            .with_started_at(run.started_at);
+        if let Some(suspension) = run.coordinator.suspension(&service.name) {
+            builder = builder.with_suspension(suspension);
+        }
+        if let Some(load) = &run.load {
+            builder = builder.with_load(load.clone());
+        }
+        if self
+            .incidents
+            .iter()
+            .any(|incident| incident.service == service.name)
+        {
+            builder = builder.with_incidents(self.incidents.clone(), run.started);
+        }
+        if let Some(spike) = &config.spike {
+            builder = builder.with_spike(spike.clone());
+        }
+        if let Some(limit) = config.remote_call_limit.or(defaults.remote_call_limit) {
+            builder = builder.with_remote_call_limit(limit);
+        }
+        if let Some(max_instructions) = config.max_instructions.or(defaults.max_instructions) {
+            builder = builder.with_max_execution_counter(max_instructions);
+        }
+        if let Some(span_name) = config.span_name.as_ref().or(defaults.span_name.as_ref()) {
+            builder = builder.with_span_name(span_name.clone());
+        }
+        builder
+    }
+}
+
+/// Drops the prints of a service above the rate limit and outside the sample of its config
+pub fn print_limiter(config: &ServiceConfig) -> PrintLimiter {
+    let mut print_limiter = PrintLimiter::new();
+    if let Some(print_rate_limit) = config.print_rate_limit {
+        print_limiter = print_limiter.with_rate_limit(print_rate_limit);
+    }
+    if let Some(print_sample_rate) = config.print_sample_rate {
+        print_limiter = print_limiter.with_sample_rate(print_sample_rate);
+    }
+    print_limiter
+}
@@ -0,0 +1,1038 @@
+//! Runs all services of a program on one thread, without tokio, OpenTelemetry or a network, e.g.
+//! in a browser. The services run their code on the same interpreter as the [`crate::vm::VM`],
+//! but time is simulated: a sleep advances the clock of its service instead of blocking, and the
+//! service with the earliest clock runs next. Remote calls queue up at the callee until it waits
+//! for calls, like the VM does. Incidents, the timeline, the chaos schedule and the load profile
+//! play out on the simulated clock.
+//!
+//! ```
+//! use mustermann::{parser, simulation::{Simulation, Stream}};
+//!
+//! let program = parser::parse(
+//!     "service products {
+//!         method get_products { print \"Fetching products\"; }
+//!     }
+//!     service frontend {
+//!         method main_page { sleep 10ms; call products.get_products; }
+//!         loop { call main_page; }
+//!     }",
+//! )
+//! .unwrap();
+//! let mut simulation = Simulation::from_program(&program).unwrap();
+//! simulation.run(100).unwrap();
+//! let output = simulation.take_output();
+//! assert_eq!(output[0].service, "products");
+//! assert_eq!(output[0].stream, Stream::Stdout);
+//! assert_eq!(output[0].message, "Fetching products");
+//! ```
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::chaos::{self, ChaosEvent, ChaosSchedule, Fault};
+use crate::code_gen::error::CodeGenError;
+use crate::code_gen::instruction::Instruction;
+use crate::code_gen::CodeGenerator;
+use crate::decoder::{decode, DecodeError, DecodedInstr};
+use crate::extension::ExtensionRegistry;
+pub use crate::interpreter::Stream;
+use crate::interpreter::{Host, Interpreter, InterpreterError, Step};
+use crate::load::LoadShaper;
+use crate::parser::{Incident, Injection, LatencySpike, Program};
+use crate::printf::PrintfError;
+use crate::timeline;
+pub use crate::value::Value;
+
+/// How long a service waits for an incoming call before it checks again, like the VM does by default
+const INTERRUPT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimulationError {
+    StackUnderflow,
+    InvalidStackValue,
+    MissingVar(String),
+    MissingLabel(String),
+    MissingStackFrame,
+    Printf(PrintfError),
+    InvalidBytecode(DecodeError),
+    /// No handler is registered for the extension code
+    UnknownExtension(u8),
+    /// The handler of an extension failed
+    ExtensionError(String),
+    /// The instruction needs the native VM, e.g. access logs
+    Unsupported(&'static str),
+    /// The weights of a `PushWeighted` add up to 0, so there is no value to pick
+    ZeroTotalWeight,
+}
+
+impl std::error::Error for SimulationError {}
+
+impl std::fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimulationError::StackUnderflow => write!(f, "Stack underflow"),
+            SimulationError::InvalidStackValue => write!(f, "Invalid stack value"),
+            SimulationError::MissingVar(var) => write!(f, "Missing variable: {}", var),
+            SimulationError::MissingLabel(label) => write!(f, "Missing label: {}", label),
+            SimulationError::MissingStackFrame => write!(f, "Missing stack frame"),
+            SimulationError::Printf(err) => write!(f, "{}", err),
+            SimulationError::InvalidBytecode(err) => write!(f, "Invalid bytecode: {}", err),
+            SimulationError::UnknownExtension(code) => {
+                write!(f, "Unknown extension {:#04x}", code)
+            }
+            SimulationError::ExtensionError(msg) => write!(f, "Extension error: {}", msg),
+            SimulationError::Unsupported(instruction) => {
+                write!(f, "{} is not supported in a simulation", instruction)
+            }
+            SimulationError::ZeroTotalWeight => write!(f, "Weights add up to 0"),
+        }
+    }
+}
+
+impl From<InterpreterError> for SimulationError {
+    fn from(e: InterpreterError) -> Self {
+        match e {
+            InterpreterError::StackUnderflow => SimulationError::StackUnderflow,
+            InterpreterError::InvalidStackValue => SimulationError::InvalidStackValue,
+            InterpreterError::MissingVar(var) => SimulationError::MissingVar(var),
+            InterpreterError::MissingLabel(label) => SimulationError::MissingLabel(label),
+            InterpreterError::MissingStackFrame => SimulationError::MissingStackFrame,
+            InterpreterError::Printf(e) => SimulationError::Printf(e),
+            InterpreterError::UnknownExtension(code) => SimulationError::UnknownExtension(code),
+            InterpreterError::ExtensionError(msg) => SimulationError::ExtensionError(msg),
+            InterpreterError::ZeroTotalWeight => SimulationError::ZeroTotalWeight,
+        }
+    }
+}
+
+/// A line a service printed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub service: String,
+    pub stream: Stream,
+    pub message: String,
+    /// The simulated time since the start
+    pub at: Duration,
+}
+
+struct IncomingCall {
+    /// The index of the calling service, which prints it if the call fails
+    caller: usize,
+    method: String,
+    baggage: BTreeMap<String, String>,
+    /// When the call arrives, after the latency injected into its route
+    at: Duration,
+}
+
+/// The state of one service, like a VM without telemetry
+struct Machine {
+    name: String,
+    interpreter: Interpreter,
+    incoming_calls: VecDeque<IncomingCall>,
+    clock: Duration,
+    /// The `stop_after` of the service, it stops once its clock reaches it
+    stop_after: Option<Duration>,
+    /// The latency spike of the service, for its sleeps and the calls it takes
+    spike: Option<LatencySpike>,
+    /// When the current iteration of the loop started, to pause after it for the traffic pattern
+    loop_iteration_started: Option<Duration>,
+    stopped: bool,
+}
+
+/// Lends the interpreter the random numbers, the clock and the extensions of a simulation
+struct SimulationHost<'a> {
+    seed: &'a mut u64,
+    clock: Duration,
+    service_name: &'a str,
+    extensions: &'a ExtensionRegistry,
+}
+
+impl Host for SimulationHost<'_> {
+    type Error = SimulationError;
+
+    fn random_range(&mut self, range: Range<u64>) -> Result<u64, SimulationError> {
+        Ok(random_range(self.seed, range))
+    }
+
+    fn random_f64(&mut self) -> Result<f64, SimulationError> {
+        Ok(random_f64(self.seed))
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.clock
+    }
+
+    fn service_name(&self) -> &str {
+        self.service_name
+    }
+
+    fn extensions(&self) -> &ExtensionRegistry {
+        self.extensions
+    }
+}
+
+/// The services of a program, run one instruction at a time
+#[derive(Default)]
+pub struct Simulation {
+    services: Vec<Machine>,
+    extensions: ExtensionRegistry,
+    output: Vec<Output>,
+    /// The service that ran last, so services with the same clock take turns
+    last: usize,
+    /// The state of the random numbers for weighted values, jitter and severity mixes, so a run
+    /// can be repeated
+    seed: u64,
+    /// The latency and faults injected into the calls between services
+    injections: Vec<Injection>,
+    /// Fail calls to their services and turn their prints to stdout into errors while they last
+    incidents: Vec<Incident>,
+    /// The chaos schedule of the program, with the service windows and the timeline
+    chaos: ChaosSchedule,
+    /// Services the chaos schedule killed and did not restart yet
+    killed: HashSet<String>,
+    /// Paces the loops of all services along the load profile and traffic pattern
+    load: Option<LoadShaper>,
+}
+
+impl Simulation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compiles every service of the program. A service with a `start_after` starts with its
+    /// clock at that time. The simulated run starts at midnight, for the traffic pattern
+    pub fn from_program(program: &Program) -> Result<Self, CodeGenError> {
+        let mut simulation = Self::new();
+        for service in &program.services {
+            let code = CodeGenerator::new(service).process()?;
+            simulation = simulation
+                .with_service(&service.name, code)
+                .expect("Bytecode generated from instructions is always valid");
+            if let Some(machine) = simulation.services.last_mut() {
+                machine.clock = service.config.start_after.unwrap_or_default();
+                machine.stop_after = service.config.stop_after;
+                machine.spike = service.config.spike.clone();
+            }
+        }
+        simulation.injections = program.injections.clone();
+        simulation.incidents = program.incidents.clone();
+        let mut chaos = program.chaos.clone();
+        chaos.extend(chaos::service_windows(
+            program
+                .services
+                .iter()
+                .map(|service| (service.name.as_str(), &service.config)),
+        ));
+        chaos.extend(timeline::chaos_actions(&program.timeline));
+        simulation.chaos = ChaosSchedule::new(&chaos);
+        if program.load.is_some() || program.pattern.is_some() {
+            let mut shaper = LoadShaper::new();
+            if let Some(profile) = &program.load {
+                shaper = shaper.with_profile(profile.clone());
+            }
+            if let Some(pattern) = &program.pattern {
+                shaper = shaper.with_pattern(pattern.clone(), Duration::ZERO);
+            }
+            simulation.load = Some(shaper);
+        }
+        Ok(simulation)
+    }
+
+    /// Adds a service, with code compiled by [`CodeGenerator`]
+    pub fn with_service(
+        mut self,
+        name: &str,
+        code: Vec<Instruction>,
+    ) -> Result<Self, SimulationError> {
+        let bytecode: Vec<u8> = code
+            .iter()
+            .flat_map(|instruction| instruction.to_bytes())
+            .collect();
+        let program = decode(&bytecode).map_err(SimulationError::InvalidBytecode)?;
+        self.services.push(Machine {
+            name: name.to_string(),
+            interpreter: Interpreter::new(program),
+            incoming_calls: VecDeque::new(),
+            clock: Duration::ZERO,
+            stop_after: None,
+            spike: None,
+            loop_iteration_started: None,
+            stopped: false,
+        });
+        Ok(self)
+    }
+
+    /// The handlers of the extensions the services were compiled with
+    pub fn with_extensions(mut self, extensions: ExtensionRegistry) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Where the random numbers for weighted values, jitter and severity mixes start, runs with
+    /// the same seed print the same
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The simulated time of the service that is furthest behind
+    pub fn clock(&self) -> Duration {
+        self.services
+            .iter()
+            .filter(|service| !service.stopped)
+            .map(|service| service.clock)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// The lines printed since the last call
+    pub fn take_output(&mut self) -> Vec<Output> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Runs up to `max_steps` instructions. Returns how many ran, fewer once all services stopped
+    pub fn run(&mut self, max_steps: usize) -> Result<usize, SimulationError> {
+        for steps in 0..max_steps {
+            if !self.step()? {
+                return Ok(steps);
+            }
+        }
+        Ok(max_steps)
+    }
+
+    /// Runs one instruction of the service with the earliest clock. Returns false once all
+    /// services stopped. A service that fails stops, the others keep running
+    pub fn step(&mut self) -> Result<bool, SimulationError> {
+        let count = self.services.len();
+        let Some(index) = (1..=count)
+            .map(|offset| (self.last + offset) % count)
+            .filter(|&index| !self.services[index].stopped)
+            .min_by_key(|&index| self.services[index].clock)
+        else {
+            return Ok(false);
+        };
+        self.last = index;
+        // No service is behind this one, so everything due by its clock has happened
+        while let Some(event) = self.chaos.pop_due(self.services[index].clock) {
+            self.apply_chaos(event);
+        }
+        if let Err(e) = self.execute_instruction(index) {
+            self.services[index].stopped = true;
+            return Err(e);
+        }
+        let machine = &mut self.services[index];
+        let stop_reached = machine.stop_after.is_some_and(|stop| machine.clock >= stop);
+        if machine.interpreter.is_done() || stop_reached {
+            machine.stopped = true;
+        }
+        Ok(true)
+    }
+
+    fn execute_instruction(&mut self, index: usize) -> Result<(), SimulationError> {
+        let machine = &mut self.services[index];
+        let interpreter = &machine.interpreter;
+        // A killed service waits for its restart where the VM does
+        if self.killed.contains(&machine.name)
+            && matches!(
+                interpreter.instructions[interpreter.ip],
+                DecodedInstr::CheckInterrupt | DecodedInstr::Ret
+            )
+        {
+            machine.clock += INTERRUPT_INTERVAL;
+            return Ok(());
+        }
+        let mut host = SimulationHost {
+            seed: &mut self.seed,
+            clock: machine.clock,
+            service_name: &machine.name,
+            extensions: &self.extensions,
+        };
+        match machine.interpreter.step(&mut host)? {
+            Step::Continue
+            | Step::JumpedBack
+            | Step::StartContext
+            | Step::EndContext
+            | Step::Returned => {}
+            Step::Print(stream, message) => self.print(index, stream, message),
+            Step::Sleep(sleep) => {
+                let spiked = spiked(machine.spike.as_ref(), &mut self.seed);
+                machine.clock += spiked.map_or(sleep, |spike| spike.max(sleep));
+            }
+            Step::AccessLog(_) => return Err(SimulationError::Unsupported("access_log")),
+            Step::RemoteCall { service, method } => self.remote_call(index, service, method),
+            Step::CheckInterrupt => self.check_interrupt(index)?,
+            Step::Call(label) => {
+                if !self.wait_for_load(index) {
+                    self.services[index].interpreter.call(label)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues the call at the callee, unless a fault, the chaos schedule or a missing service
+    /// fails it. Calls fail without stopping the caller, like on the VM
+    fn remote_call(&mut self, index: usize, service: String, method: String) {
+        let caller = &self.services[index];
+        let (from, clock) = (caller.name.clone(), caller.clock);
+        let route = (from.as_str(), service.as_str());
+        let seed = &mut self.seed;
+        let fault = chaos::injected_fault(&self.injections, &self.incidents, route, clock, || {
+            random_f64(seed)
+        });
+        let error = match fault {
+            Some(Fault::Drop) => Some("Call dropped before it completed".to_string()),
+            Some(Fault::Error) => Some(format!("Injected fault on {}->{}", from, service)),
+            None if self.killed.contains(&service) => {
+                Some(format!("Service unavailable: {}", service))
+            }
+            None => None,
+        };
+        if let Some(error) = error {
+            return self.call_failed(index, &service, &method, error);
+        }
+        let delay =
+            chaos::injected_latency(&self.injections, route, |range| random_range(seed, range));
+        let call = IncomingCall {
+            caller: index,
+            method,
+            baggage: self.services[index].interpreter.baggage.clone(),
+            at: clock + delay,
+        };
+        match self
+            .services
+            .iter_mut()
+            .find(|callee| callee.name == service)
+        {
+            Some(callee) if !callee.stopped => callee.incoming_calls.push_back(call),
+            Some(_) => {
+                let error = format!("Service unavailable: {}", service);
+                self.call_failed(index, &service, &call.method, error)
+            }
+            None => {
+                let error = format!("Service not found: {}", service);
+                self.call_failed(index, &service, &call.method, error)
+            }
+        }
+    }
+
+    /// Takes the first call that arrived, or waits until the next one arrives, at most for the
+    /// interval of the VM
+    fn check_interrupt(&mut self, index: usize) -> Result<(), SimulationError> {
+        let machine = &mut self.services[index];
+        let clock = machine.clock;
+        let Some(position) = machine
+            .incoming_calls
+            .iter()
+            .position(|call| call.at <= clock)
+        else {
+            let next_call = machine.incoming_calls.iter().map(|call| call.at).min();
+            let wait_until = clock + INTERRUPT_INTERVAL;
+            machine.clock = next_call.map_or(wait_until, |at| at.min(wait_until));
+            return Ok(());
+        };
+        let call = machine.incoming_calls.remove(position).unwrap();
+        let label_name = format!("start_{}", call.method);
+        let label = machine
+            .interpreter
+            .strings
+            .lookup(&label_name)
+            .ok_or(SimulationError::MissingLabel(label_name))?;
+        machine.interpreter.call(label)?;
+        // The baggage of the caller goes on top of the service's own until the call returns
+        machine.interpreter.baggage.extend(call.baggage);
+        // A spiked call is slow before its method runs
+        if let Some(spike) = spiked(machine.spike.as_ref(), &mut self.seed) {
+            machine.clock += spike;
+        }
+        Ok(())
+    }
+
+    /// Paces the loop of the service along the load profile and traffic pattern, like the VM
+    /// does. Returns true while the service waits for its next iteration
+    fn wait_for_load(&mut self, index: usize) -> bool {
+        let Some(load) = &self.load else {
+            return false;
+        };
+        let machine = &mut self.services[index];
+        if machine.interpreter.loop_call() != Some(machine.interpreter.ip) {
+            return false;
+        }
+        if let Some(started) = machine.loop_iteration_started.take() {
+            let pause = load.pause(machine.clock - started, machine.clock);
+            if !pause.is_zero() {
+                machine.clock += pause;
+                return true;
+            }
+        }
+        match load.try_acquire(machine.clock) {
+            Ok(()) => {
+                machine.loop_iteration_started = Some(machine.clock);
+                false
+            }
+            Err(wait) => {
+                machine.clock += wait;
+                true
+            }
+        }
+    }
+
+    fn apply_chaos(&mut self, event: ChaosEvent) {
+        match event {
+            ChaosEvent::Kill(service) => {
+                // Calls that are queued when the service is killed fail
+                let rejected: Vec<IncomingCall> = self
+                    .services
+                    .iter_mut()
+                    .filter(|machine| machine.name == service)
+                    .flat_map(|machine| std::mem::take(&mut machine.incoming_calls))
+                    .collect();
+                for call in rejected {
+                    let error = format!("Service unavailable: {}", service);
+                    self.call_failed(call.caller, &service, &call.method, error);
+                }
+                self.killed.insert(service);
+            }
+            ChaosEvent::Restart(service) => {
+                self.killed.remove(&service);
+            }
+            ChaosEvent::Inject(injection) => self.injections.push(injection),
+            ChaosEvent::Lift(injection) => {
+                if let Some(index) = self.injections.iter().rposition(|i| *i == injection) {
+                    self.injections.remove(index);
+                }
+            }
+        }
+    }
+
+    fn call_failed(&mut self, index: usize, service: &str, method: &str, error: String) {
+        let message = format!("Call to {}.{} failed: {}", service, method, error);
+        self.print(index, Stream::Stderr, message);
+    }
+
+    /// Prints a line of the service. Prints to stdout go to stderr at the error rate of the
+    /// incidents of the service
+    fn print(&mut self, index: usize, stream: Stream, message: String) {
+        let machine = &self.services[index];
+        let error_rate = chaos::incident_error_rate(&self.incidents, &machine.name, machine.clock);
+        let stream = match (stream, error_rate) {
+            (Stream::Stdout, Some(error_rate)) if random_f64(&mut self.seed) < error_rate => {
+                Stream::Stderr
+            }
+            _ => stream,
+        };
+        self.output.push(Output {
+            service: machine.name.clone(),
+            stream,
+            message,
+            at: machine.clock,
+        });
+    }
+}
+
+/// How long a sleep or call takes at least if the dice make it an outlier of `spike`
+fn spiked(spike: Option<&LatencySpike>, seed: &mut u64) -> Option<Duration> {
+    let spike = spike?;
+    (random_f64(seed) < spike.rate).then_some(spike.to)
+}
+
+/// The next number of the sequence in `range`, which must not be empty
+fn random_range(state: &mut u64, range: Range<u64>) -> u64 {
+    range.start + next_random(state) % (range.end - range.start)
+}
+
+/// The next number of the sequence as a fraction between 0 and 1
+fn random_f64(state: &mut u64) -> f64 {
+    // The upper 53 bits, as many as an f64 holds
+    (next_random(state) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// The next number of a splitmix64 sequence
+fn next_random(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn test_simulation_runs_remote_calls_on_the_callee() {
+        let program = parser::parse(
+            r#"
+            service products {
+                method get_products {
+                    print "Fetching products for %{tenant}";
+                    sleep 50ms;
+                    stderr "Out of stock";
+                }
+            }
+
+            service frontend {
+                method main_page {
+                    baggage tenant = "acme";
+                    print "Loading main page";
+                    call products.get_products;
+                    call inventory.count;
+                    sleep 1s;
+                }
+
+                loop {
+                    call main_page;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        assert_eq!(simulation.run(200), Ok(200));
+
+        let output: Vec<(String, Stream, String, u128)> = simulation
+            .take_output()
+            .into_iter()
+            .take(4)
+            .map(|line| (line.service, line.stream, line.message, line.at.as_millis()))
+            .collect();
+        assert_eq!(
+            output,
+            [
+                (
+                    "frontend".to_string(),
+                    Stream::Stdout,
+                    "Loading main page".to_string(),
+                    0
+                ),
+                (
+                    "frontend".to_string(),
+                    Stream::Stderr,
+                    "Call to inventory.count failed: Service not found: inventory".to_string(),
+                    0
+                ),
+                (
+                    "products".to_string(),
+                    Stream::Stdout,
+                    "Fetching products for acme".to_string(),
+                    100
+                ),
+                (
+                    "products".to_string(),
+                    Stream::Stderr,
+                    "Out of stock".to_string(),
+                    150
+                ),
+            ]
+        );
+        assert!(simulation.take_output().is_empty());
+    }
+
+    #[test]
+    fn test_failing_service_stops_alone() {
+        let program = parser::parse(
+            r#"
+            service logs {
+                method write {
+                    access_log alb;
+                }
+
+                loop {
+                    call write;
+                }
+            }
+
+            service frontend {
+                method main_page {
+                    print "Loading main page";
+                    sleep 1s;
+                }
+
+                loop {
+                    call main_page;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        assert_eq!(
+            simulation.run(100),
+            Err(SimulationError::Unsupported("access_log"))
+        );
+        assert_eq!(simulation.run(100), Ok(100));
+        assert!(simulation
+            .take_output()
+            .iter()
+            .all(|line| line.service == "frontend"));
+    }
+
+    #[test]
+    fn test_weighted_values_follow_their_weights() {
+        let program = parser::parse(
+            r#"
+            service payments {
+                method charge {
+                    print "Connection %s" with ["timeout": 3, "refused": 1, "reset": 0];
+                    sleep 1s;
+                }
+
+                loop {
+                    call charge;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let run = |seed| {
+            let mut simulation = Simulation::from_program(&program).unwrap().with_seed(seed);
+            simulation.run(4000).unwrap();
+            simulation
+                .take_output()
+                .into_iter()
+                .map(|line| line.message)
+                .collect::<Vec<_>>()
+        };
+        let messages = run(7);
+        let timeouts = messages
+            .iter()
+            .filter(|message| *message == "Connection timeout")
+            .count();
+
+        assert!(messages.len() > 300);
+        assert!(!messages.contains(&"Connection reset".to_string()));
+        assert!((0.65..0.85).contains(&(timeouts as f64 / messages.len() as f64)));
+        assert_eq!(messages, run(7));
+    }
+
+    #[test]
+    fn test_jittered_sleeps_vary_within_the_jitter() {
+        let program = parser::parse(
+            r#"
+            service payments {
+                method charge {
+                    print "Charging";
+                    sleep 1s±200ms;
+                }
+
+                loop {
+                    call charge;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        simulation.run(1000).unwrap();
+        let at: Vec<Duration> = simulation
+            .take_output()
+            .iter()
+            .map(|line| line.at)
+            .collect();
+        let intervals: Vec<Duration> = at.windows(2).map(|pair| pair[1] - pair[0]).collect();
+
+        assert!(intervals.len() > 50);
+        assert!(intervals
+            .iter()
+            .all(|interval| (800..=1200).contains(&interval.as_millis())));
+        assert!(intervals.iter().any(|interval| *interval != intervals[0]));
+    }
+
+    #[test]
+    fn test_severity_mix_prints_to_both_streams() {
+        let program = parser::parse(
+            r#"
+            service payments {
+                method charge {
+                    print "Charging" severity [info: 9, error: 1];
+                    sleep 1s;
+                }
+
+                loop {
+                    call charge;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        simulation.run(4000).unwrap();
+        let output = simulation.take_output();
+        let errors = output
+            .iter()
+            .filter(|line| line.stream == Stream::Stderr)
+            .count();
+
+        assert!(output.len() > 300);
+        assert!((0.05..0.15).contains(&(errors as f64 / output.len() as f64)));
+    }
+
+    #[test]
+    fn test_print_picks_one_of_its_templates() {
+        let program = parser::parse(
+            r#"
+            service products {
+                method get_products {
+                    print ["Cache hit for %s", "Cache miss for %s"] with ["12345"];
+                    sleep 1s;
+                }
+
+                loop {
+                    call get_products;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        simulation.run(1000).unwrap();
+        let messages: Vec<String> = simulation
+            .take_output()
+            .into_iter()
+            .map(|line| line.message)
+            .collect();
+
+        assert!(messages.contains(&"Cache hit for 12345".to_string()));
+        assert!(messages.contains(&"Cache miss for 12345".to_string()));
+        assert!(messages
+            .iter()
+            .all(|message| message.ends_with("for 12345")));
+    }
+
+    #[test]
+    fn test_spike_makes_a_share_of_the_sleeps_outliers() {
+        let program = parser::parse(
+            r#"
+            service payments {
+                config {
+                    spike 10% of calls to 5s;
+                }
+
+                method charge {
+                    print "Charging";
+                    sleep 100ms;
+                }
+
+                loop {
+                    call charge;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        simulation.run(10_000).unwrap();
+        let at: Vec<Duration> = simulation
+            .take_output()
+            .iter()
+            .map(|line| line.at)
+            .collect();
+        let intervals: Vec<u128> = at
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).as_millis())
+            .collect();
+        let outliers = intervals.iter().filter(|&&ms| ms == 5000).count();
+
+        assert!(intervals.iter().all(|ms| [100, 5000].contains(ms)));
+        assert!(outliers > 0);
+        assert!(outliers * 4 < intervals.len());
+    }
+
+    #[test]
+    fn test_generators_drift_over_the_run() {
+        let program = parser::parse(
+            r#"
+            service products {
+                method get_products {
+                    print "latency=%gauss(mean: 200, stddev: 5, drift: +10/min)ms";
+                    sleep 1s;
+                }
+
+                loop {
+                    call get_products;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        simulation.run(10_000).unwrap();
+        let latencies: Vec<f64> = simulation
+            .take_output()
+            .iter()
+            .map(|line| {
+                let latency = line.message.strip_prefix("latency=").unwrap();
+                latency.strip_suffix("ms").unwrap().parse().unwrap()
+            })
+            .collect();
+        let mean = |minute: usize| latencies[minute * 60..][..60].iter().sum::<f64>() / 60.0;
+
+        assert!(latencies.len() > 600);
+        assert!((mean(0) - 205.0).abs() < 3.0);
+        assert!((mean(9) - 295.0).abs() < 3.0);
+    }
+
+    #[test]
+    fn test_services_print_only_within_their_window() {
+        let program = parser::parse(
+            r#"
+            service backup {
+                config {
+                    start_after 5s;
+                    stop_after 10s;
+                }
+
+                method run {
+                    print "Backing up";
+                    sleep 1s;
+                }
+
+                loop {
+                    call run;
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        simulation.run(1000).unwrap();
+        let at: Vec<u128> = simulation
+            .take_output()
+            .iter()
+            .map(|line| line.at.as_millis())
+            .collect();
+
+        assert_eq!(at, [5000, 6000, 7000, 8000, 9000]);
+        assert_eq!(simulation.run(10).unwrap(), 0);
+    }
+
+    fn lines(simulation: &mut Simulation) -> Vec<(u128, Stream, String)> {
+        simulation
+            .take_output()
+            .into_iter()
+            .map(|line| (line.at.as_millis(), line.stream, line.message))
+            .collect()
+    }
+
+    const SHOP: &str = r#"
+        service products {
+            method get_products {
+                print "Fetching products";
+            }
+        }
+
+        service frontend {
+            method main_page {
+                call products.get_products;
+                sleep 1s;
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+    "#;
+
+    #[test]
+    fn test_incidents_turn_prints_into_errors() {
+        let program = parser::parse(&format!(
+            "{}\nincident at 2s: products error_rate 100%->100% over 1s;",
+            SHOP
+        ))
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        simulation.run(200).unwrap();
+        let streams: Vec<(u128, Stream)> = lines(&mut simulation)
+            .into_iter()
+            .filter(|(at, _, _)| *at < 4000)
+            .map(|(at, stream, _)| (at, stream))
+            .collect();
+
+        assert_eq!(
+            streams,
+            [
+                (100, Stream::Stdout),
+                (1100, Stream::Stdout),
+                (2000, Stream::Stderr),
+                (3000, Stream::Stderr),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chaos_kills_and_restarts_services() {
+        let program = parser::parse(&format!(
+            "{}\nat 2s kill products;\nat 4s restart products;",
+            SHOP
+        ))
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        simulation.run(200).unwrap();
+        let output: Vec<(u128, Stream, String)> = lines(&mut simulation)
+            .into_iter()
+            .filter(|(at, _, _)| *at < 5000)
+            .collect();
+        let unavailable = "Call to products.get_products failed: Service unavailable: products";
+
+        assert_eq!(
+            output,
+            [
+                (100, Stream::Stdout, "Fetching products".to_string()),
+                (1100, Stream::Stdout, "Fetching products".to_string()),
+                (2000, Stream::Stderr, unavailable.to_string()),
+                (3000, Stream::Stderr, unavailable.to_string()),
+                (4100, Stream::Stdout, "Fetching products".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_timeline_deploys_inject_into_calls() {
+        let program = parser::parse(&format!(
+            "{}\ntimeline {{\nat 2s: deploy products v2 (latency +300ms);\nat 4s: rollback products;\n}}",
+            SHOP
+        ))
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        simulation.run(200).unwrap();
+        let at: Vec<u128> = lines(&mut simulation)
+            .into_iter()
+            .map(|(at, _, _)| at)
+            .filter(|at| *at < 5000)
+            .collect();
+
+        assert_eq!(at, [100, 1100, 2300, 3300, 4100]);
+    }
+
+    #[test]
+    fn test_load_paces_the_loop() {
+        let program = parser::parse(
+            r#"
+            service frontend {
+                method main_page {
+                    print "Rendering";
+                }
+
+                loop {
+                    call main_page;
+                }
+            }
+
+            load { ramp 2->2 rps over 1m }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_program(&program).unwrap();
+        simulation.run(200).unwrap();
+        let at: Vec<u128> = lines(&mut simulation)
+            .into_iter()
+            .map(|(at, _, _)| at)
+            .filter(|at| *at < 2000)
+            .collect();
+
+        assert_eq!(at, [500, 1000, 1500]);
+    }
+}
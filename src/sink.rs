@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+use crate::log_file::LogFile;
+use crate::log_format::{self, LogFormat};
+use crate::otel::{self, Endpoint, ServiceLoggers};
+
+pub type SinkLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Where logs are written. Every sink gets every event and filters them on its own
+pub trait Sink: Send + Sync {
+    /// The layer that writes the events. Called once, when the sinks are installed
+    fn layer(&mut self) -> anyhow::Result<SinkLayer>;
+
+    /// Flushes what the sink still holds once the run ends
+    fn shutdown(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// A sink as passed with --sink, e.g. `file:app.json format=json filter=service=info`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkSpec {
+    pub kind: String,
+    /// What follows the colon, like the path of a file
+    pub target: Option<String>,
+    pub format: Option<LogFormat>,
+    /// An env-filter expression. The sink falls back to the filter of -q, -v and --filter
+    pub filter: Option<String>,
+}
+
+pub fn parse_sink(input: &str) -> Result<SinkSpec, String> {
+    let mut parts = input.split_whitespace();
+    let sink = parts
+        .next()
+        .ok_or("Expected a sink like console or file:app.log")?;
+    let (kind, target) = match sink.split_once(':') {
+        Some((kind, target)) => (kind, Some(target.to_string())),
+        None => (sink, None),
+    };
+    let mut spec = SinkSpec {
+        kind: kind.to_string(),
+        target,
+        format: None,
+        filter: None,
+    };
+    for setting in parts {
+        match setting.split_once('=') {
+            Some(("format", format)) => {
+                spec.format = Some(clap::ValueEnum::from_str(format, true)?);
+            }
+            Some(("filter", filter)) => spec.filter = Some(log_format::parse_filter(filter)?),
+            _ => {
+                return Err(format!(
+                    "Unknown sink setting {}, expected format=<format> or filter=<expression>",
+                    setting
+                ))
+            }
+        }
+    }
+    Ok(spec)
+}
+
+/// What the sinks are built from
+pub struct SinkContext {
+    pub endpoint: Endpoint,
+    /// The name mustermann's own logs are exported under
+    pub service_name: String,
+    /// The format of console sinks without a format
+    pub log_format: LogFormat,
+}
+
+type Factory = fn(&SinkSpec, &SinkContext) -> anyhow::Result<Box<dyn Sink>>;
+
+/// The kinds of sinks --sink can name
+pub struct SinkRegistry {
+    factories: BTreeMap<&'static str, Factory>,
+}
+
+impl SinkRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self {
+            factories: BTreeMap::new(),
+        };
+        registry.register("console", |spec, context| {
+            Ok(Box::new(WriterSink {
+                format: spec.format.unwrap_or(context.log_format),
+                output: None,
+            }))
+        });
+        registry.register("file", |spec, _| {
+            let path = spec
+                .target
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("The file sink needs a path, e.g. file:app.log"))?;
+            Ok(Box::new(WriterSink {
+                format: spec.format.unwrap_or(LogFormat::Json),
+                output: Some(LogFile::open(path, None)?),
+            }))
+        });
+        registry.register("otlp", |_, context| {
+            Ok(Box::new(OtlpSink(otel::service_loggers(
+                &context.endpoint,
+                &context.service_name,
+            )?)))
+        });
+        registry
+    }
+
+    pub fn register(&mut self, kind: &'static str, factory: Factory) {
+        self.factories.insert(kind, factory);
+    }
+
+    pub fn build(&self, spec: &SinkSpec, context: &SinkContext) -> anyhow::Result<Box<dyn Sink>> {
+        let factory = self.factories.get(spec.kind.as_str()).ok_or_else(|| {
+            let kinds: Vec<&str> = self.factories.keys().copied().collect();
+            anyhow::anyhow!(
+                "Unknown sink {}, expected one of {}",
+                spec.kind,
+                kinds.join(", ")
+            )
+        })?;
+        factory(spec, context)
+    }
+}
+
+/// The sinks of a run, each with its own filter
+pub struct Sinks {
+    sinks: Vec<(Box<dyn Sink>, EnvFilter)>,
+}
+
+impl Sinks {
+    /// `default_filter` filters the sinks without a filter of their own
+    pub fn new(
+        registry: &SinkRegistry,
+        specs: &[SinkSpec],
+        context: &SinkContext,
+        default_filter: impl Fn() -> EnvFilter,
+    ) -> anyhow::Result<Self> {
+        let mut sinks = Vec::new();
+        for spec in specs {
+            let filter = match &spec.filter {
+                Some(filter) => EnvFilter::new(filter),
+                None => default_filter(),
+            };
+            sinks.push((registry.build(spec, context)?, filter));
+        }
+        Ok(Self { sinks })
+    }
+
+    /// Adds a sink that was not named with --sink
+    pub fn with_sink(mut self, sink: Box<dyn Sink>, filter: EnvFilter) -> Self {
+        self.sinks.push((sink, filter));
+        self
+    }
+
+    /// Fans every event out to all sinks
+    pub fn layer(&mut self) -> anyhow::Result<SinkLayer> {
+        let mut layers = Vec::new();
+        for (sink, filter) in &mut self.sinks {
+            let filter = std::mem::replace(filter, EnvFilter::new("off"));
+            layers.push(sink.layer()?.with_filter(filter).boxed());
+        }
+        Ok(layers.boxed())
+    }
+
+    /// Installs the sinks as the global subscriber
+    pub fn install(&mut self) -> anyhow::Result<()> {
+        let subscriber = tracing_subscriber::registry().with(self.layer()?);
+        // Not init(), which also turns `log` records into events. Every event is already written
+        // to `log` as well, so each line would show up twice.
+        tracing::subscriber::set_global_default(subscriber)?;
+        Ok(())
+    }
+
+    pub fn shutdown(&self) -> anyhow::Result<()> {
+        for (sink, _) in &self.sinks {
+            sink.shutdown()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes to a file, or else to stdout
+pub struct WriterSink {
+    pub format: LogFormat,
+    pub output: Option<LogFile>,
+}
+
+impl Sink for WriterSink {
+    fn layer(&mut self) -> anyhow::Result<SinkLayer> {
+        Ok(log_format::layer(self.format, self.output.take()))
+    }
+}
+
+/// Exports the logs of every service under its own service name
+pub struct OtlpSink(pub ServiceLoggers);
+
+impl Sink for OtlpSink {
+    fn layer(&mut self) -> anyhow::Result<SinkLayer> {
+        Ok(self.0.layer().boxed())
+    }
+
+    fn shutdown(&self) -> anyhow::Result<()> {
+        Ok(self.0.shutdown()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::Context;
+
+    /// Collects the targets of the events it gets
+    #[derive(Clone, Default)]
+    struct MemorySink(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> Layer<S> for MemorySink {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let target = event.metadata().target().to_string();
+            self.0.lock().unwrap().push(target);
+        }
+    }
+
+    impl Sink for MemorySink {
+        fn layer(&mut self) -> anyhow::Result<SinkLayer> {
+            Ok(self.clone().boxed())
+        }
+    }
+
+    #[test]
+    fn test_parse_sink() {
+        assert_eq!(
+            parse_sink("file:logs/app.json format=ecs filter=service=info").unwrap(),
+            SinkSpec {
+                kind: "file".to_string(),
+                target: Some("logs/app.json".to_string()),
+                format: Some(LogFormat::Ecs),
+                filter: Some("service=info".to_string()),
+            }
+        );
+        assert!(parse_sink("console format=xml").is_err());
+        assert!(parse_sink("console color=red").is_err());
+    }
+
+    #[test]
+    fn test_sinks_filter_events_on_their_own() {
+        let context = SinkContext {
+            endpoint: Endpoint::local(otel::Protocol::Grpc),
+            service_name: "mustermann".to_string(),
+            log_format: LogFormat::Pretty,
+        };
+        let registry = SinkRegistry::new();
+        let unknown = parse_sink("kafka:logs").unwrap();
+        assert!(registry.build(&unknown, &context).is_err());
+
+        let services = MemorySink::default();
+        let everything = MemorySink::default();
+        let mut sinks = Sinks::new(&registry, &[], &context, || EnvFilter::new("info"))
+            .unwrap()
+            .with_sink(
+                Box::new(services.clone()),
+                EnvFilter::new("off,service=info"),
+            )
+            .with_sink(Box::new(everything.clone()), EnvFilter::new("info"));
+        let subscriber = tracing_subscriber::registry().with(sinks.layer().unwrap());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: log_format::SERVICE_TARGET, "Fetching products");
+            tracing::info!(target: "mustermann", "Service started");
+        });
+
+        assert_eq!(*services.0.lock().unwrap(), [log_format::SERVICE_TARGET]);
+        assert_eq!(
+            *everything.0.lock().unwrap(),
+            [log_format::SERVICE_TARGET, "mustermann"]
+        );
+    }
+}
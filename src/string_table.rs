@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Index of an interned string inside a [`StringTable`]
+pub type Symbol = usize;
+
+/// Holds every string literal and label of a program exactly once.
+/// Strings are interned when the bytecode is loaded, so executing an instruction
+/// only has to clone an `Arc` instead of allocating a fresh `String`.
+#[derive(Debug, Default, Clone)]
+pub struct StringTable {
+    strings: Vec<Arc<str>>,
+    symbols: HashMap<Arc<str>, Symbol>,
+}
+
+impl StringTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Interns a string and returns its symbol. Interning the same string twice returns the same symbol.
+    pub fn intern(&mut self, value: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(value) {
+            return *symbol;
+        }
+        let value: Arc<str> = Arc::from(value);
+        let symbol = self.strings.len();
+        self.strings.push(value.clone());
+        self.symbols.insert(value, symbol);
+        symbol
+    }
+
+    /// Returns the symbol of an already interned string
+    pub fn lookup(&self, value: &str) -> Option<Symbol> {
+        self.symbols.get(value).copied()
+    }
+
+    pub fn get(&self, symbol: Symbol) -> &Arc<str> {
+        &self.strings[symbol]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_returns_same_symbol() {
+        let mut table = StringTable::new();
+        let first = table.intern("start_main_page");
+        let second = table.intern("start_main_page");
+        assert_eq!(first, second);
+        assert_eq!(table.intern("end_main_page"), first + 1);
+    }
+
+    #[test]
+    fn test_interned_strings_share_allocation() {
+        let mut table = StringTable::new();
+        let symbol = table.intern("Main page");
+        let a = table.get(symbol).clone();
+        let b = table.get(symbol).clone();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(&*a, "Main page");
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut table = StringTable::new();
+        let symbol = table.intern("products");
+        assert_eq!(table.lookup("products"), Some(symbol));
+        assert_eq!(table.lookup("features"), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! Carries out a timeline: every deploy is live from its time until it is rolled back or replaced
+//! by the next deploy of its service. While it is live, the chaos schedule injects its latency and
+//! errors into the calls to the service, and the service logs its version and attributes.
+
+#[cfg(feature = "native")]
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::parser::{
+    ChaosAction, ChaosKind, Deploy, Injection, Route, TimelineChange, TimelineEvent,
+};
+
+#[cfg(feature = "native")]
+/// The attribute that holds the version of a deployed service
+pub const VERSION_ATTRIBUTE: &str = "service.version";
+
+/// A time a deploy is live, counted from the start of the run. A deploy that is replaced and
+/// later live again after a rollback has a window for each time
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeployWindow<'a> {
+    pub deploy: &'a Deploy,
+    pub from: Duration,
+    pub until: Option<Duration>,
+}
+
+struct Walk<'a> {
+    windows: Vec<DeployWindow<'a>>,
+    /// The events that roll back while no deploy is live
+    #[cfg_attr(not(feature = "native"), allow(dead_code))]
+    stray_rollbacks: Vec<usize>,
+}
+
+// Goes through the events in the order they are due, keeping the deploys that are still live in
+// the order they were deployed. Only the latest of them per service is in effect
+fn walk(timeline: &[TimelineEvent]) -> Walk<'_> {
+    let mut order: Vec<usize> = (0..timeline.len()).collect();
+    // Stable, so events due at the same time happen in the order they were written
+    order.sort_by_key(|&i| timeline[i].at);
+    let mut windows = Vec::new();
+    let mut stray_rollbacks = Vec::new();
+    // The deploys that were not rolled back, with the time they took effect if they are in effect
+    let mut live: Vec<(&Deploy, Option<Duration>)> = Vec::new();
+    for i in order {
+        let at = timeline[i].at;
+        match &timeline[i].change {
+            TimelineChange::Deploy(deploy) => {
+                if let Some((replaced, from)) = live
+                    .iter_mut()
+                    .rev()
+                    .find(|(live, _)| live.service == deploy.service)
+                {
+                    if let Some(from) = from.take() {
+                        windows.push(DeployWindow {
+                            deploy: replaced,
+                            from,
+                            until: Some(at),
+                        });
+                    }
+                }
+                live.push((deploy, Some(at)));
+            }
+            TimelineChange::Rollback { service } => {
+                let rolled_back = live.iter().rposition(|(deploy, _)| {
+                    service
+                        .as_ref()
+                        .is_none_or(|service| deploy.service == *service)
+                });
+                let Some(index) = rolled_back else {
+                    stray_rollbacks.push(i);
+                    continue;
+                };
+                let (deploy, from) = live.remove(index);
+                if let Some(from) = from {
+                    windows.push(DeployWindow {
+                        deploy,
+                        from,
+                        until: Some(at),
+                    });
+                }
+                if let Some((_, from)) = live
+                    .iter_mut()
+                    .rev()
+                    .find(|(live, _)| live.service == deploy.service)
+                {
+                    *from = Some(at);
+                }
+            }
+        }
+    }
+    windows.extend(live.into_iter().filter_map(|(deploy, from)| {
+        Some(DeployWindow {
+            deploy,
+            from: from?,
+            until: None,
+        })
+    }));
+    Walk {
+        windows,
+        stray_rollbacks,
+    }
+}
+
+/// The times the deploys of the timeline are live. Rollbacks while no deploy is live are ignored
+pub fn deploy_windows(timeline: &[TimelineEvent]) -> Vec<DeployWindow<'_>> {
+    walk(timeline).windows
+}
+
+#[cfg(feature = "native")]
+/// The positions of the events that roll back while no deploy is live
+pub fn stray_rollbacks(timeline: &[TimelineEvent]) -> Vec<usize> {
+    walk(timeline).stray_rollbacks
+}
+
+/// Injects the latency and errors of the deploys into the calls to their services while the
+/// deploys are live
+pub fn chaos_actions(timeline: &[TimelineEvent]) -> Vec<ChaosAction> {
+    let mut actions = Vec::new();
+    for window in deploy_windows(timeline) {
+        let route = Route {
+            from: "*".to_string(),
+            to: window.deploy.service.clone(),
+        };
+        let mut injections = Vec::new();
+        if let Some(delay) = window.deploy.latency {
+            injections.push(Injection::Latency {
+                route: route.clone(),
+                delay,
+                jitter: Duration::ZERO,
+            });
+        }
+        if let Some(error_rate) = window.deploy.error_rate {
+            injections.push(Injection::Faults {
+                route,
+                drop_rate: 0.0,
+                error_rate,
+            });
+        }
+        actions.extend(injections.into_iter().map(|injection| ChaosAction {
+            at: window.from,
+            kind: ChaosKind::Inject {
+                injection,
+                until: window.until,
+            },
+        }));
+    }
+    actions
+}
+
+#[cfg(feature = "native")]
+/// The attributes `service` logs from each time on, as the deploys of the timeline change them.
+/// `attributes` are the ones of the service before the first deploy and after a rollback of all
+pub fn attribute_changes(
+    timeline: &[TimelineEvent],
+    service: &str,
+    attributes: &BTreeMap<String, String>,
+) -> Vec<(Duration, BTreeMap<String, String>)> {
+    let mut changes = Vec::new();
+    for window in deploy_windows(timeline) {
+        if window.deploy.service != service {
+            continue;
+        }
+        let mut deployed = attributes.clone();
+        deployed.insert(VERSION_ATTRIBUTE.to_string(), window.deploy.version.clone());
+        deployed.extend(window.deploy.attributes.clone());
+        // A deploy that takes effect when another one ends comes after the end
+        changes.push((window.from, 1, deployed));
+        if let Some(until) = window.until {
+            changes.push((until, 0, attributes.clone()));
+        }
+    }
+    changes.sort_by_key(|(at, order, _)| (*at, *order));
+    changes
+        .into_iter()
+        .map(|(at, _, attributes)| (at, attributes))
+        .collect()
+}
+
+// Stray rollbacks and attribute changes are only needed by the native build
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn test_rollback_brings_the_previous_deploy_back() {
+        let program = parser::parse(
+            r#"
+            timeline {
+                at 1m: deploy products v2 (latency +100ms, attribute region = "eu");
+                at 2m: deploy products v3 (error_rate 10%);
+                at 3m: deploy payments v5;
+                at 4m: rollback products;
+                at 5m: rollback;
+                at 6m: rollback;
+                at 7m: rollback;
+            }
+            "#,
+        )
+        .unwrap();
+        let timeline = &program.timeline;
+        let windows: Vec<(&str, u64, Option<u64>)> = deploy_windows(timeline)
+            .iter()
+            .map(|window| {
+                (
+                    window.deploy.version.as_str(),
+                    window.from.as_secs() / 60,
+                    window.until.map(|until| until.as_secs() / 60),
+                )
+            })
+            .collect();
+        assert_eq!(
+            windows,
+            [
+                ("v2", 1, Some(2)),
+                ("v3", 2, Some(4)),
+                ("v5", 3, Some(5)),
+                ("v2", 4, Some(6)),
+            ]
+        );
+        assert_eq!(stray_rollbacks(timeline), [6]);
+
+        let actions = chaos_actions(timeline);
+        assert_eq!(actions.len(), 3);
+        assert_eq!(
+            actions[1],
+            ChaosAction {
+                at: Duration::from_secs(120),
+                kind: ChaosKind::Inject {
+                    injection: Injection::Faults {
+                        route: Route {
+                            from: "*".to_string(),
+                            to: "products".to_string(),
+                        },
+                        drop_rate: 0.0,
+                        error_rate: 0.1,
+                    },
+                    until: Some(Duration::from_secs(240)),
+                },
+            }
+        );
+
+        let attributes = BTreeMap::from([("env".to_string(), "production".to_string())]);
+        let changes = attribute_changes(timeline, "products", &attributes);
+        assert_eq!(changes[0].1["env"], "production");
+        assert_eq!(changes[0].1["region"], "eu");
+        let versions: Vec<(u64, Option<&str>)> = changes
+            .iter()
+            .map(|(at, attributes)| {
+                (
+                    at.as_secs() / 60,
+                    attributes.get(VERSION_ATTRIBUTE).map(String::as_str),
+                )
+            })
+            .collect();
+        assert_eq!(
+            versions,
+            [
+                (1, Some("v2")),
+                (2, None),
+                (2, Some("v3")),
+                (4, None),
+                (4, Some("v2")),
+                (6, None),
+            ]
+        );
+    }
+}
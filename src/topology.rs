@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::parser::{Program, Statement};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    #[default]
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+}
+
+/// The calls from one service to another
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Edge {
+    /// The methods of the callee that the caller calls
+    methods: BTreeSet<String>,
+    /// How often the call happened at runtime, if calls are observed
+    observed_calls: Option<u64>,
+}
+
+/// The services of a program and the calls between them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Topology {
+    services: BTreeSet<String>,
+    edges: BTreeMap<(String, String), Edge>,
+}
+
+impl Topology {
+    /// The call graph as declared in the program
+    pub fn from_program(program: &Program) -> Self {
+        let mut topology = Self::default();
+        for service in &program.services {
+            topology.services.insert(service.name.clone());
+            let statements = service
+                .methods
+                .iter()
+                .flat_map(|method| &method.statements)
+                .chain(service.loops.iter().flat_map(|l| &l.statements))
+                .chain(service.shutdown.iter().flat_map(|s| &s.statements));
+            for statement in statements {
+                if let Statement::Call {
+                    service: Some(callee),
+                    method,
+                } = statement
+                {
+                    topology.services.insert(callee.clone());
+                    topology
+                        .edges
+                        .entry((service.name.clone(), callee.clone()))
+                        .or_default()
+                        .methods
+                        .insert(method.clone());
+                }
+            }
+        }
+        topology
+    }
+
+    /// Shows how often each call happened, starting from zero for the declared ones
+    pub fn with_observed_calls(mut self) -> Self {
+        for edge in self.edges.values_mut() {
+            edge.observed_calls = Some(0);
+        }
+        self
+    }
+
+    /// Counts a call observed at runtime
+    pub fn record_call(&mut self, from: &str, to: &str, method: &str) {
+        self.services.insert(from.to_string());
+        self.services.insert(to.to_string());
+        let edge = self
+            .edges
+            .entry((from.to_string(), to.to_string()))
+            .or_default();
+        edge.methods.insert(method.to_string());
+        *edge.observed_calls.get_or_insert(0) += 1;
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+        }
+    }
+
+    /// How often each service called the others, with a row per caller and a column per callee
+    pub fn call_matrix(&self) -> tabled::Table {
+        let callers: BTreeSet<&str> = self.edges.keys().map(|(from, _)| from.as_str()).collect();
+        let callees: BTreeSet<&str> = self.edges.keys().map(|(_, to)| to.as_str()).collect();
+        let mut builder = tabled::builder::Builder::default();
+        builder.push_record(std::iter::once("caller").chain(callees.iter().copied()));
+        for caller in callers {
+            let calls = callees.iter().map(|callee| {
+                self.edges
+                    .get(&(caller.to_string(), callee.to_string()))
+                    .map(|edge| edge.observed_calls.unwrap_or_default().to_string())
+                    .unwrap_or_default()
+            });
+            builder.push_record(std::iter::once(caller.to_string()).chain(calls));
+        }
+        builder.build()
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph services {\n");
+        for service in &self.services {
+            let _ = writeln!(dot, "  \"{}\";", service);
+        }
+        for ((from, to), edge) in &self.edges {
+            let _ = writeln!(
+                dot,
+                "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                from,
+                to,
+                edge_label(edge)
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn to_mermaid(&self) -> String {
+        let mut mermaid = String::from("graph LR\n");
+        for service in &self.services {
+            let _ = writeln!(mermaid, "  {}", service);
+        }
+        for ((from, to), edge) in &self.edges {
+            let _ = writeln!(mermaid, "  {} -->|\"{}\"| {}", from, edge_label(edge), to);
+        }
+        mermaid
+    }
+}
+
+fn edge_label(edge: &Edge) -> String {
+    let methods = edge
+        .methods
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    match edge.observed_calls {
+        Some(1) => format!("{} (1 call)", methods),
+        Some(calls) => format!("{} ({} calls)", methods, calls),
+        None => methods,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn topology() -> Topology {
+        let program = "
+        service products {
+            method get_products {
+                print \"Fetching products\";
+            }
+        }
+
+        service frontend {
+            method main_page {
+                call products.get_products;
+                call products.get_product;
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+        ";
+        Topology::from_program(&parser::parse(program).unwrap())
+    }
+
+    #[test]
+    fn test_render_declared_calls() {
+        let topology = topology();
+        assert_eq!(
+            topology.render(GraphFormat::Dot),
+            "digraph services {\n  \"frontend\";\n  \"products\";\n  \"frontend\" -> \"products\" [label=\"get_product, get_products\"];\n}\n"
+        );
+        assert_eq!(
+            topology.render(GraphFormat::Mermaid),
+            "graph LR\n  frontend\n  products\n  frontend -->|\"get_product, get_products\"| products\n"
+        );
+    }
+
+    #[test]
+    fn test_render_observed_calls() {
+        let mut topology = topology().with_observed_calls();
+        topology.record_call("frontend", "products", "get_products");
+        topology.record_call("frontend", "products", "get_products");
+        topology.record_call("frontend", "inventory", "get_stock");
+        assert_eq!(
+            topology.render(GraphFormat::Mermaid),
+            "graph LR\n  frontend\n  inventory\n  products\n  frontend -->|\"get_stock (1 call)\"| inventory\n  frontend -->|\"get_product, get_products (2 calls)\"| products\n"
+        );
+    }
+
+    #[test]
+    fn test_call_matrix() {
+        let mut topology = topology().with_observed_calls();
+        topology.record_call("frontend", "products", "get_products");
+        topology.record_call("products", "inventory", "get_stock");
+        let mut matrix = topology.call_matrix();
+        assert_eq!(
+            matrix.with(tabled::settings::Style::ascii()).to_string(),
+            [
+                "+----------+-----------+----------+",
+                "| caller   | inventory | products |",
+                "+----------+-----------+----------+",
+                "| frontend |           | 1        |",
+                "+----------+-----------+----------+",
+                "| products | 1         |          |",
+                "+----------+-----------+----------+",
+            ]
+            .join("\n")
+        );
+    }
+}
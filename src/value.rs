@@ -0,0 +1,18 @@
+use std::sync::Arc;
+
+/// A value on the VM stack. Strings point into the string table of the VM,
+/// so pushing a string literal does not allocate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(Arc<str>),
+    Int(u64),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            Value::Int(n) => write!(f, "{}", n),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fs;
+
+/// The values of `${NAME}` in config files and OTLP headers: the environment, and the variables of
+/// an env file
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    values: HashMap<String, String>,
+}
+
+impl Variables {
+    pub fn from_env() -> Self {
+        Self {
+            values: std::env::vars().collect(),
+        }
+    }
+
+    /// Adds the variables of an env file, one `NAME=value` per line. Variables that are set in the
+    /// environment already keep their value, like with other tools that read .env files
+    pub fn with_env_file(mut self, path: &str) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let Some((name, value)) = line.split_once('=') else {
+                anyhow::bail!("{}:{}: Expected NAME=value, got {}", path, number + 1, line);
+            };
+            self.values
+                .entry(name.trim().to_string())
+                .or_insert_with(|| unquote(value.trim()).to_string());
+        }
+        Ok(self)
+    }
+
+    /// Replaces every `${NAME}` with the value of the variable, or `${NAME:-default}` with
+    /// `default` if it is not set. `$${` stays as `${`
+    pub fn interpolate(&self, text: &str) -> Result<String, String> {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find("${") {
+            if rest[..start].ends_with('$') {
+                result.push_str(&rest[..start - 1]);
+                result.push_str("${");
+                rest = &rest[start + 2..];
+                continue;
+            }
+            result.push_str(&rest[..start]);
+            let Some(end) = rest[start..].find('}') else {
+                return Err(format!("Unclosed ${{ in {}", &rest[start..]));
+            };
+            let variable = &rest[start + 2..start + end];
+            let (name, default) = match variable.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (variable, None),
+            };
+            match (self.values.get(name), default) {
+                (Some(value), _) => result.push_str(value),
+                (None, Some(default)) => result.push_str(default),
+                (None, None) => return Err(format!("Variable {} is not set", name)),
+            }
+            rest = &rest[start + end + 1..];
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+}
+
+// Values may be quoted to keep spaces or a `#`, otherwise a ` #` starts a comment
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|value| value.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value.split(" #").next().unwrap_or_default().trim_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_file_variables_are_interpolated() {
+        let path = std::env::temp_dir().join(format!("mustermann-{}.env", std::process::id()));
+        fs::write(
+            &path,
+            "# Credentials\nexport API_KEY=\"abc #123\"\nREGION=eu # the closest one\nPATH=ignored\n",
+        )
+        .unwrap();
+        let variables = Variables::from_env()
+            .with_env_file(path.to_str().unwrap())
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            variables.interpolate("print \"${API_KEY} in ${REGION}\";"),
+            Ok("print \"abc #123 in eu\";".to_string())
+        );
+        assert_eq!(
+            variables.interpolate("${PATH}"),
+            Ok(std::env::var("PATH").unwrap())
+        );
+        assert_eq!(
+            variables.interpolate("${TIER:-free} costs $${PRICE}"),
+            Ok("free costs ${PRICE}".to_string())
+        );
+        assert_eq!(
+            variables.interpolate("${MISSING_VARIABLE}"),
+            Err("Variable MISSING_VARIABLE is not set".to_string())
+        );
+    }
+}
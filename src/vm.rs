@@ -1,32 +1,81 @@
-use std::collections::HashMap;
+//! Runs the compiled code of a service. Prints go to a channel, telemetry to the tracer and meter
+//! provider given to the VM, and remote calls to a [`crate::vm_coordinator`].
 
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use opentelemetry::baggage::BaggageExt;
 use opentelemetry::metrics::Counter;
 use opentelemetry::metrics::Gauge;
+use opentelemetry::metrics::Histogram;
 use opentelemetry::metrics::MeterProvider;
 use opentelemetry::propagation::TextMapPropagator;
-use opentelemetry::trace::{TraceContextExt, TracerProvider};
+use opentelemetry::trace::{SpanContext, TraceContextExt, TracerProvider};
 use opentelemetry::{global, KeyValue};
 use opentelemetry::{
-    trace::{SpanKind, Tracer},
+    trace::{SpanKind, Status, Tracer},
     Context,
 };
-use opentelemetry_otlp::{WithExportConfig, WithTonicConfig};
-use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_otlp::{WithExportConfig, WithHttpConfig, WithTonicConfig};
 use opentelemetry_sdk::metrics::Temporality;
-use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::metrics::{
+    Aggregation, Instrument, PeriodicReader, SdkMeterProvider, Stream, View,
+};
+use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
 use opentelemetry_sdk::trace::SdkTracerProvider;
 use opentelemetry_sdk::Resource;
 use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
-use tokio::sync::mpsc;
-use tonic::metadata::{MetadataMap, MetadataValue};
-
-use crate::code_gen::instruction::{
-    Instruction, StackValue, CALL_CODE, CHECK_INTERRUPT_CODE, DEC_CODE, DUP_CODE, END_CONTEXT_CODE,
-    JMP_IF_ZERO_CODE, JUMP_CODE, LABEL_CODE, LOAD_VAR_CODE, POP_CODE, PRINTF_CODE, PUSH_INT_CODE,
-    PUSH_STRING_CODE, REMOTE_CALL_CODE, RET_CODE, SLEEP_CODE, START_CONTEXT_CODE, STDERR_CODE,
-    STDOUT_CODE, STORE_VAR_CODE,
-};
-use crate::vm_coordinator::ServiceMessage;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Instant;
+use tonic::metadata::MetadataValue;
+
+use crate::access_log;
+use crate::chaos;
+use crate::circuit_breaker::CircuitState;
+use crate::code_gen::instruction::Instruction;
+use crate::decoder::{decode, DecodeError};
+use crate::dry_run::DryRun;
+use crate::extension::ExtensionRegistry;
+use crate::health::HEALTH_CHECK;
+use crate::interpreter::{self, Host, Interpreter, InterpreterError, Step};
+use crate::load::LoadShaper;
+use crate::metadata_map;
+use crate::otel::{Endpoint, Protocol};
+use crate::parser::{Incident, LatencySpike, MetricView, MetricViewAction};
+use crate::print_sink::{PrintSink, PrintSinkError};
+use crate::printf::PrintfError;
+use crate::recording::{Decisions, Diverged};
+use crate::string_table::Symbol;
+pub use crate::value::Value;
+use crate::vm_coordinator::{self, CallError, CallReply, IncomingCall, ServiceMessage};
+use crate::zipkin::ZipkinExporter;
+
+// Still experimental in opentelemetry-semantic-conventions, so it is not exported without a feature flag
+const SERVICE_INSTANCE_ID: &str = "service.instance.id";
+/// The index of a replica among the instances of its service
+const REPLICA: &str = "mustermann.replica";
+// RPC attributes are experimental as well, see https://opentelemetry.io/docs/specs/semconv/rpc/rpc-spans/
+const RPC_SYSTEM: &str = "rpc.system";
+const RPC_SERVICE: &str = "rpc.service";
+const RPC_METHOD: &str = "rpc.method";
+const PEER_SERVICE: &str = "peer.service";
+
+/// The attributes of both sides of a remote call, so backends can tell which method was called
+fn rpc_attributes(service: &str, method: &str) -> Vec<KeyValue> {
+    vec![
+        KeyValue::new(RPC_SYSTEM, "mustermann"),
+        KeyValue::new(RPC_SERVICE, service.to_string()),
+        KeyValue::new(RPC_METHOD, method.to_string()),
+    ]
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VMError {
     StackUnderflow,
@@ -35,14 +84,24 @@ pub enum VMError {
     RemoteCallError(String),
     MissingLabel(String),
     MissingSpan,
-    PrintError(mpsc::error::SendError<PrintMessage>),
+    PrintError(PrintSinkError),
     MaxExecutionCounterReached,
+    RemoteCallLimitReached,
     InvalidTemplate(String),
+    PrintfArityMismatch(usize, usize),
     IPOutOfBounds(usize, usize),
     MissingFunctionName,
     MissingContext,
-    InvalidInstruction(u8),
+    InvalidBytecode(DecodeError),
     MissingStackFrame,
+    /// No handler is registered for the extension code
+    UnknownExtension(u8),
+    /// The handler of an extension failed
+    ExtensionError(String),
+    /// The VM asked for another decision than the recording holds
+    ReplayDiverged(Diverged),
+    /// The weights of a `PushWeighted` add up to 0, so there is no value to pick
+    ZeroTotalWeight,
 }
 
 impl std::error::Error for VMError {}
@@ -58,7 +117,13 @@ impl std::fmt::Display for VMError {
             VMError::MissingSpan => write!(f, "Missing span"),
             VMError::PrintError(err) => write!(f, "Print error: {}", err),
             VMError::MaxExecutionCounterReached => write!(f, "Max execution counter reached"),
+            VMError::RemoteCallLimitReached => write!(f, "Remote call limit reached"),
             VMError::InvalidTemplate(template) => write!(f, "Invalid template: {}", template),
+            VMError::PrintfArityMismatch(expected, found) => write!(
+                f,
+                "Template expects {} values but {} were given",
+                expected, found
+            ),
             VMError::IPOutOfBounds(ip, len) => {
                 write!(
                     f,
@@ -68,76 +133,233 @@ impl std::fmt::Display for VMError {
             }
             VMError::MissingFunctionName => write!(f, "Missing function name"),
             VMError::MissingContext => write!(f, "Missing context"),
-            VMError::InvalidInstruction(instruction) => {
-                write!(f, "Invalid instruction: {}", instruction)
-            }
+            VMError::InvalidBytecode(err) => write!(f, "Invalid bytecode: {}", err),
             VMError::MissingStackFrame => write!(f, "Missing stack frame"),
+            VMError::UnknownExtension(code) => write!(f, "Unknown extension {:#04x}", code),
+            VMError::ExtensionError(msg) => write!(f, "Extension error: {}", msg),
+            VMError::ReplayDiverged(diverged) => write!(f, "{}", diverged),
+            VMError::ZeroTotalWeight => write!(f, "Weights add up to 0"),
+        }
+    }
+}
+
+impl From<PrintfError> for VMError {
+    fn from(e: PrintfError) -> Self {
+        match e {
+            PrintfError::InvalidTemplate(template) => VMError::InvalidTemplate(template),
+            PrintfError::ArityMismatch(expected, found) => {
+                VMError::PrintfArityMismatch(expected, found)
+            }
+            PrintfError::InvalidValue => VMError::InvalidStackValue,
+        }
+    }
+}
+
+impl From<InterpreterError> for VMError {
+    fn from(e: InterpreterError) -> Self {
+        match e {
+            InterpreterError::StackUnderflow => VMError::StackUnderflow,
+            InterpreterError::InvalidStackValue => VMError::InvalidStackValue,
+            InterpreterError::MissingVar(var) => VMError::MissingVar(var),
+            InterpreterError::MissingLabel(label) => VMError::MissingLabel(label),
+            InterpreterError::MissingStackFrame => VMError::MissingStackFrame,
+            InterpreterError::Printf(e) => e.into(),
+            InterpreterError::UnknownExtension(code) => VMError::UnknownExtension(code),
+            InterpreterError::ExtensionError(msg) => VMError::ExtensionError(msg),
+            InterpreterError::ZeroTotalWeight => VMError::ZeroTotalWeight,
         }
     }
 }
 
+/// Lends the interpreter the decisions, the start of the run and the extensions of a VM
+struct VmHost<'a> {
+    decisions: &'a Decisions,
+    started: Instant,
+    service_name: &'a str,
+    extensions: &'a ExtensionRegistry,
+}
+
+impl Host for VmHost<'_> {
+    type Error = VMError;
+
+    fn random_range(&mut self, range: Range<u64>) -> Result<u64, VMError> {
+        self.decisions
+            .random_range(range)
+            .map_err(VMError::ReplayDiverged)
+    }
+
+    fn random_f64(&mut self) -> Result<f64, VMError> {
+        self.decisions.random_f64().map_err(VMError::ReplayDiverged)
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    fn service_name(&self) -> &str {
+        self.service_name
+    }
+
+    fn extensions(&self) -> &ExtensionRegistry {
+        self.extensions
+    }
+}
+
+/// The name of a replica, like `products-1`
+pub fn instance_id(service_name: &str, replica: usize) -> String {
+    format!("{}-{}", service_name, replica)
+}
+
+/// Names the service in telemetry. Replicas also get their own instance ID, so backends show
+/// every instance of a service instead of merging them
+fn service_resource(service_name: &str, replica: Option<usize>) -> Resource {
+    let builder = Resource::builder().with_service_name(service_name.to_string());
+    match replica {
+        Some(replica) => builder
+            .with_attributes([
+                KeyValue::new(SERVICE_INSTANCE_ID, instance_id(service_name, replica)),
+                KeyValue::new(REPLICA, replica as i64),
+            ])
+            .build(),
+        None => builder.build(),
+    }
+}
+
 pub fn setup_tracer(
-    endpoint: &str,
+    endpoint: &Endpoint,
     service_name: &str,
+    replica: Option<usize>,
 ) -> Result<SdkTracerProvider, opentelemetry_otlp::ExporterBuildError> {
-    let mut map = MetadataMap::with_capacity(3);
-
-    map.insert("x-application", service_name.parse().unwrap());
-    map.insert_bin(
-        "trace-proto-bin",
-        MetadataValue::from_bytes(b"[binary data]"),
-    );
-    let otlp_exporter = opentelemetry_otlp::SpanExporter::builder()
-        .with_tonic()
-        .with_export_config(opentelemetry_otlp::ExportConfig {
-            endpoint: Some(endpoint.to_string()),
-            protocol: opentelemetry_otlp::Protocol::Grpc,
-            timeout: Some(std::time::Duration::from_secs(3)),
-        })
-        .with_metadata(map)
-        .build()?;
-
-    let resource = Resource::builder()
-        .with_attribute(KeyValue::new(SERVICE_NAME, service_name.to_string()))
-        .build();
-    let provider = SdkTracerProvider::builder()
-        .with_resource(resource)
-        .with_batch_exporter(otlp_exporter)
-        .build();
+    let builder =
+        SdkTracerProvider::builder().with_resource(service_resource(service_name, replica));
+    let provider = match &endpoint.zipkin {
+        Some(url) => builder.with_batch_exporter(ZipkinExporter::new(url)),
+        None => builder.with_batch_exporter(span_exporter(endpoint, service_name)?),
+    }
+    .build();
 
     // Then pass it into provider builder
     global::set_text_map_propagator(TraceContextPropagator::new());
     Ok(provider)
 }
 
+fn span_exporter(
+    endpoint: &Endpoint,
+    service_name: &str,
+) -> Result<opentelemetry_otlp::SpanExporter, opentelemetry_otlp::ExporterBuildError> {
+    let otlp_exporter = match endpoint.protocol {
+        Protocol::Grpc => {
+            let mut map = endpoint.metadata();
+
+            map.insert("x-application", service_name.parse().unwrap());
+            map.insert_bin(
+                "trace-proto-bin",
+                MetadataValue::from_bytes(b"[binary data]"),
+            );
+            let mut builder = opentelemetry_otlp::SpanExporter::builder()
+                .with_tonic()
+                .with_export_config(opentelemetry_otlp::ExportConfig {
+                    endpoint: Some(endpoint.signal_url("traces")),
+                    protocol: opentelemetry_otlp::Protocol::Grpc,
+                    timeout: Some(std::time::Duration::from_secs(3)),
+                })
+                .with_metadata(map);
+            if let Some(tls_config) = endpoint.tls_config()? {
+                builder = builder.with_tls_config(tls_config);
+            }
+            builder.build()?
+        }
+        Protocol::Http => opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_export_config(opentelemetry_otlp::ExportConfig {
+                endpoint: Some(endpoint.signal_url("traces")),
+                protocol: opentelemetry_otlp::Protocol::HttpBinary,
+                timeout: Some(std::time::Duration::from_secs(3)),
+            })
+            .with_headers(
+                endpoint
+                    .http_headers()
+                    .into_iter()
+                    .chain([("x-application".to_string(), service_name.to_string())])
+                    .collect(),
+            )
+            .build()?,
+    };
+    Ok(otlp_exporter)
+}
+
+pub(crate) fn metric_exporter(
+    endpoint: &Endpoint,
+) -> Result<opentelemetry_otlp::MetricExporter, opentelemetry_otlp::ExporterBuildError> {
+    let builder = opentelemetry_otlp::MetricExporter::builder()
+        .with_temporality(Temporality::from(endpoint.metrics.temporality));
+    match endpoint.protocol {
+        Protocol::Grpc => {
+            let mut builder = builder
+                .with_tonic()
+                .with_endpoint(endpoint.signal_url("metrics"))
+                .with_metadata(endpoint.metadata());
+            if let Some(tls_config) = endpoint.tls_config()? {
+                builder = builder.with_tls_config(tls_config);
+            }
+            builder.build()
+        }
+        Protocol::Http => builder
+            .with_http()
+            .with_endpoint(endpoint.signal_url("metrics"))
+            .with_headers(endpoint.http_headers())
+            .build(),
+    }
+}
+
+/// Renames or drops the instruments named in `views`. The first view of an instrument wins
+fn metric_view(views: Vec<MetricView>) -> impl View {
+    move |instrument: &Instrument| {
+        let view = views
+            .iter()
+            .find(|view| view.instrument == instrument.name)?;
+        let stream = Stream::new().description(instrument.description.clone());
+        Some(match &view.action {
+            MetricViewAction::Drop => stream
+                .name(instrument.name.clone())
+                .unit(instrument.unit.clone())
+                .aggregation(Aggregation::Drop),
+            MetricViewAction::Rename { name, unit } => stream.name(name.clone()).unit(
+                unit.clone()
+                    .map(Cow::from)
+                    .unwrap_or_else(|| instrument.unit.clone()),
+            ),
+        })
+    }
+}
+
 pub(crate) fn init_meter_provider(
-    endpoint: Option<&str>,
+    endpoint: Option<&Endpoint>,
     service_name: &str,
+    replica: Option<usize>,
+    views: &[MetricView],
 ) -> Result<opentelemetry_sdk::metrics::SdkMeterProvider, opentelemetry_otlp::ExporterBuildError> {
+    let resource = service_resource(service_name, replica);
+    let view = metric_view(views.to_vec());
     let provider = if let Some(endpoint) = endpoint {
-        let exporter = opentelemetry_otlp::MetricExporter::builder()
-            .with_temporality(Temporality::Delta)
-            .with_tonic()
-            .with_endpoint(endpoint.to_string())
-            .build()?;
-        let resource = Resource::builder()
-            .with_service_name(service_name.to_string())
-            .build();
+        let exporter = metric_exporter(endpoint)?;
+        let mut reader = PeriodicReader::builder(exporter);
+        if let Some(interval) = endpoint.metrics.interval {
+            reader = reader.with_interval(interval);
+        }
 
         SdkMeterProvider::builder()
-            .with_periodic_exporter(exporter)
+            .with_reader(reader.build())
             .with_resource(resource)
+            .with_view(view)
             .build()
     } else {
         let exporter = opentelemetry_stdout::MetricExporter::default();
 
-        let resource = Resource::builder()
-            .with_service_name(service_name.to_string())
-            .build();
-
         SdkMeterProvider::builder()
             .with_periodic_exporter(exporter)
             .with_resource(resource)
+            .with_view(view)
             .build()
     };
 
@@ -150,79 +372,179 @@ pub enum PrintMessage {
     Stderr(String),
 }
 
-///The length of the length byte array for a string
-const LENGTH_OFFSET: usize = std::mem::size_of::<usize>();
+/// A message printed by a service, with the span it was printed in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Print {
+    pub message: PrintMessage,
+    pub span_context: Option<SpanContext>,
+}
+
+/// Counters describing what a VM did during its run
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VmStats {
+    pub instructions: usize,
+    pub stdout: usize,
+    pub stderr: usize,
+    pub remote_calls: usize,
+    pub incoming_calls: usize,
+    /// Remote calls that got an error back, or no reply at all
+    pub failed_remote_calls: usize,
+}
+
+/// How long CheckInterrupt waits for an incoming call by default before the service continues
+const DEFAULT_INTERRUPT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How spans are named unless the service configures another template
+const DEFAULT_SPAN_NAME: &str = "{service}/{method}";
+
+/// Bucket boundaries in milliseconds. Most instructions finish well below a millisecond, sleeps take longer.
+const INSTRUCTION_DURATION_BOUNDARIES: [f64; 12] = [
+    0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0, 5000.0,
+];
+
+/// Bucket boundaries in milliseconds. A remote call only waits for the coordinator queue.
+const REMOTE_CALL_DURATION_BOUNDARIES: [f64; 10] =
+    [0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 50.0, 100.0, 1000.0];
+
+#[derive(Clone)]
+struct Instruments {
+    remote_invocation_counter: Counter<u64>,
+    local_invocation_counter: Counter<u64>,
+    instruction_duration: Histogram<f64>,
+    remote_call_duration: Histogram<f64>,
+    /// The instruction and remote call duration gauges, if enabled
+    legacy_gauges: Option<(Gauge<u64>, Gauge<u64>)>,
+}
+
+/// Bookkeeping for an active call, used when the function returns
+struct CallFrame {
+    /// The trace context of the caller, restored on return
+    caller_cx: Option<Context>,
+    /// Tells the remote caller that the function completed
+    reply: Option<CallReply>,
+}
 
+/// Runs the code of one instance of a service
 pub struct VM {
-    code: Vec<u8>,
-    stack: Vec<Vec<StackValue>>,
-    vars: HashMap<String, StackValue>,
-    label_jump_map: HashMap<String, usize>,
-    label_index_map: HashMap<usize, String>,
-    ip: usize,
-    print_tx: mpsc::Sender<PrintMessage>,
+    /// The code of the service, with its stack, variables and baggage
+    interpreter: Interpreter,
+    print_sink: Arc<dyn PrintSink>,
     max_execution_counter: Option<usize>,
-    return_addresses: Vec<usize>,
+    call_frames: Vec<CallFrame>,
     remote_call_tx: Option<mpsc::Sender<ServiceMessage>>,
-    remote_call_rx: Option<mpsc::Receiver<String>>,
-    remote_call_counter: usize,
-    remote_call_limit: usize,
+    remote_call_rx: Option<mpsc::Receiver<IncomingCall>>,
+    remote_call_limit: Option<usize>,
+    interrupt_interval: Duration,
+    shutdown: Option<Arc<AtomicBool>>,
+    shutting_down: bool,
+    /// Set while the service is killed
+    suspension: Option<watch::Receiver<bool>>,
+    /// A call taken from the queue while answering health checks at a loop back-edge,
+    /// handled at the next CheckInterrupt
+    stashed_call: Option<IncomingCall>,
+    /// Calls that arrived before the call a replay waits for
+    replayed_calls: VecDeque<IncomingCall>,
+    /// Random numbers and the calls taken at CheckInterrupt, drawn, recorded or replayed
+    decisions: Decisions,
+    stats: VmStats,
+    /// Replies arrive after the VM moved on, so failed calls are counted apart from the other stats
+    failed_remote_calls: Arc<AtomicUsize>,
     service_name: String,
     tracer: Option<SdkTracerProvider>,
     meter_provider: SdkMeterProvider,
+    legacy_duration_gauges: bool,
+    span_events: bool,
+    /// The template for span names, with `{service}` and `{method}` placeholders
+    span_name: String,
     otel_context: Option<opentelemetry::Context>,
+    dry_run: Option<Arc<DryRun>>,
+    loop_iterations: usize,
+    /// Whether the loop still counts towards the dry run
+    loop_running: bool,
+    /// Paces the loop along the load profile of the run, shared with the other VMs
+    load: Option<Arc<LoadShaper>>,
+    /// When the current iteration of the loop started, to pause after it for the traffic pattern
+    loop_iteration_started: Option<Instant>,
+    /// The incidents of the service, with the start of the run they are timed from. Prints to
+    /// stdout go to stderr at their error rate
+    incidents: Option<(Vec<Incident>, Instant)>,
+    /// Makes a share of the sleeps and handled calls extremely slow
+    spike: Option<LatencySpike>,
+    /// The start of the run, that the generators of print templates drift from and the load
+    /// profile is timed from. On the clock of tokio, so a run on a paused runtime is timed in
+    /// virtual time
+    started: Instant,
+    /// The wall-clock time at `started`, that access logs are timestamped from
+    started_at: SystemTime,
+    /// Shared with the other VMs, for the runtime metrics of mustermann
+    instruction_counter: Option<Arc<AtomicU64>>,
+    extensions: ExtensionRegistry,
 }
 
 ///Generate the bytecode for a given set of instructions
-/// Returns the bytecode and a map of label to jump position
-/// This is used to optimize the code by precomputing the jump positions
-fn generate_bytecode(
-    instructions: Vec<Instruction>,
-) -> (Vec<u8>, HashMap<String, usize>, HashMap<usize, String>) {
-    let mut bytes = vec![];
-    let mut label_jump_map = HashMap::new();
-    let mut label_index_map = HashMap::new();
-    for instruction in instructions {
-        let instruction_bytes = instruction.to_bytes();
-        bytes.extend(instruction_bytes);
-
-        if let Instruction::Label(label) = instruction {
-            //Store the position of the label + the length of the instruction + the length of the label
-            label_jump_map.insert(label.clone(), bytes.len());
-            label_index_map.insert(bytes.len(), label);
-        }
-    }
-    (bytes, label_jump_map, label_index_map)
+fn generate_bytecode(instructions: &[Instruction]) -> Vec<u8> {
+    instructions
+        .iter()
+        .flat_map(|instruction| instruction.to_bytes())
+        .collect()
 }
 
 impl VM {
+    /// A VM that sends everything the service prints to `print_sink`, e.g. the sender of a channel
     pub fn new(
         code: Vec<Instruction>,
         service_name: &str,
-        print_tx: mpsc::Sender<PrintMessage>,
+        print_sink: impl PrintSink + 'static,
     ) -> Self {
+        Self::from_bytecode(&generate_bytecode(&code), service_name, print_sink)
+            .expect("Bytecode generated from instructions is always valid")
+    }
+
+    /// Creates a VM from a byte stream. The bytecode is decoded once here, not on every execution.
+    pub fn from_bytecode(
+        code: &[u8],
+        service_name: &str,
+        print_sink: impl PrintSink + 'static,
+    ) -> Result<Self, VMError> {
         let service_name = service_name.to_string();
-        let (code, label_jump_map, label_index_map) = generate_bytecode(code);
-
-        Self {
-            code,
-            label_jump_map,
-            label_index_map,
-            stack: vec![Vec::new()],
-            vars: HashMap::new(),
-            ip: 0,
-            print_tx,
+        let program = decode(code).map_err(VMError::InvalidBytecode)?;
+
+        Ok(Self {
+            interpreter: Interpreter::new(program),
+            print_sink: Arc::new(print_sink),
             max_execution_counter: None,
-            return_addresses: Vec::new(),
+            call_frames: Vec::new(),
             remote_call_tx: None,
             remote_call_rx: None,
-            remote_call_counter: 0,
-            remote_call_limit: 10000,
+            remote_call_limit: None,
+            interrupt_interval: DEFAULT_INTERRUPT_INTERVAL,
+            shutdown: None,
+            shutting_down: false,
+            suspension: None,
+            stashed_call: None,
+            replayed_calls: VecDeque::new(),
+            decisions: Decisions::default(),
+            stats: VmStats::default(),
+            failed_remote_calls: Arc::new(AtomicUsize::new(0)),
             service_name: service_name.to_string(),
             tracer: None,
             otel_context: None,
-            meter_provider: init_meter_provider(None, &service_name).unwrap(),
-        }
+            meter_provider: init_meter_provider(None, &service_name, None, &[]).unwrap(),
+            legacy_duration_gauges: false,
+            span_events: false,
+            span_name: DEFAULT_SPAN_NAME.to_string(),
+            dry_run: None,
+            loop_iterations: 0,
+            loop_running: false,
+            load: None,
+            loop_iteration_started: None,
+            incidents: None,
+            spike: None,
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            instruction_counter: None,
+            extensions: ExtensionRegistry::new(),
+        })
     }
 
     pub fn with_max_execution_counter(mut self, max_execution_counter: usize) -> Self {
@@ -230,21 +552,68 @@ impl VM {
         self
     }
 
+    /// Also counts the executed instructions in `counter`
+    pub fn with_instruction_counter(mut self, counter: Arc<AtomicU64>) -> Self {
+        self.instruction_counter = Some(counter);
+        self
+    }
+
     pub fn with_remote_call_tx(mut self, remote_call_tx: mpsc::Sender<ServiceMessage>) -> Self {
         self.remote_call_tx = Some(remote_call_tx);
         self
     }
 
-    pub fn with_remote_call_rx(mut self, remote_call_rx: mpsc::Receiver<String>) -> Self {
+    pub fn with_remote_call_rx(mut self, remote_call_rx: mpsc::Receiver<IncomingCall>) -> Self {
         self.remote_call_rx = Some(remote_call_rx);
         self
     }
 
+    /// Limits the number of remote calls this VM makes. Once the limit is reached, the VM stops with an error.
     pub fn with_custom_remote_call_limit(mut self, limit: usize) -> Self {
-        self.remote_call_limit = limit;
+        self.remote_call_limit = Some(limit);
+        self
+    }
+
+    /// Sets how long CheckInterrupt waits for an incoming call before the service continues.
+    /// Incoming calls are handled as soon as they arrive; the interval only bounds the idle wait.
+    pub fn with_interrupt_interval(mut self, interrupt_interval: Duration) -> Self {
+        self.interrupt_interval = interrupt_interval;
+        self
+    }
+
+    /// Sets a flag that requests the VM to shut down once it is set.
+    /// The flag is checked at CheckInterrupt and loop back-edges; the service's shutdown block runs before the VM stops.
+    pub fn with_shutdown_flag(mut self, shutdown: Arc<AtomicBool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Pauses the VM at CheckInterrupt while `suspension` is set, as if its process was killed.
+    /// Calls that are queued when the VM pauses fail, calls made to it while it is paused never reach it.
+    pub fn with_suspension(mut self, suspension: watch::Receiver<bool>) -> Self {
+        self.suspension = Some(suspension);
+        self
+    }
+
+    /// Records the decisions the VM leaves to chance, or replays them from a recording
+    pub fn with_decisions(mut self, decisions: Decisions) -> Self {
+        self.decisions = decisions;
         self
     }
 
+    /// Counts the remote calls that failed. It keeps counting after the VM stopped, until the
+    /// replies to all of its calls arrived
+    pub fn failed_remote_calls(&self) -> Arc<AtomicUsize> {
+        self.failed_remote_calls.clone()
+    }
+
+    pub fn stats(&self) -> VmStats {
+        VmStats {
+            failed_remote_calls: self.failed_remote_calls.load(Ordering::SeqCst),
+            ..self.stats.clone()
+        }
+    }
+
     pub fn with_tracer(mut self, tracer: SdkTracerProvider) -> Self {
         self.tracer = Some(tracer);
         self
@@ -255,9 +624,154 @@ impl VM {
         self
     }
 
-    fn build_counters(
-        &self,
-    ) -> Result<(Counter<u64>, Counter<u64>, Gauge<u64>, Gauge<u64>), VMError> {
+    /// Also records durations to the `instruction_duration` and `remote_call_duration` gauges
+    /// for dashboards built before the histograms existed.
+    pub fn with_legacy_duration_gauges(mut self, enabled: bool) -> Self {
+        self.legacy_duration_gauges = enabled;
+        self
+    }
+
+    /// Also adds every print to the current span as an event, with its level as attribute
+    pub fn with_span_events(mut self, enabled: bool) -> Self {
+        self.span_events = enabled;
+        self
+    }
+
+    /// Names spans after `template` instead of `{service}/{method}`
+    pub fn with_span_name(mut self, template: String) -> Self {
+        self.span_name = template;
+        self
+    }
+
+    /// Runs the extension instructions of the code with the handlers of `extensions`
+    pub fn with_extensions(mut self, extensions: ExtensionRegistry) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// The baggage of the loop and of incoming calls, before the caller's baggage is added
+    pub fn with_baggage(mut self, baggage: BTreeMap<String, String>) -> Self {
+        self.interpreter.baggage = baggage;
+        self
+    }
+
+    /// Runs the VM as part of a dry run: sleeps are skipped, and the loop stops after the
+    /// iterations of the dry run. The service keeps answering calls until it is shut down
+    pub fn with_dry_run(mut self, dry_run: Arc<DryRun>) -> Self {
+        if self.interpreter.loop_call().is_some() {
+            dry_run.start();
+            self.loop_running = true;
+        }
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Waits at the start of every iteration of the loop until the load profile hands one out,
+    /// and pauses between iterations for the traffic pattern, both timed from the start of the run
+    pub fn with_load(mut self, load: Arc<LoadShaper>) -> Self {
+        self.load = Some(load);
+        self
+    }
+
+    /// Turns prints to stdout into errors at the error rate of the incidents of the service, timed
+    /// from `started`, the start of the run
+    pub fn with_incidents(mut self, incidents: &[Incident], started: std::time::Instant) -> Self {
+        let incidents: Vec<Incident> = incidents
+            .iter()
+            .filter(|incident| incident.service == self.service_name)
+            .cloned()
+            .collect();
+        self.incidents = (!incidents.is_empty()).then_some((incidents, started.into()));
+        self
+    }
+
+    /// Stretches a share of the sleeps and of the handled calls to the time of the spike
+    pub fn with_spike(mut self, spike: LatencySpike) -> Self {
+        self.spike = Some(spike);
+        self
+    }
+
+    /// Times the drift of the generators in print templates and the load profile from `started`,
+    /// the start of the run
+    pub fn with_started(mut self, started: std::time::Instant) -> Self {
+        self.started = started.into();
+        self
+    }
+
+    /// Timestamps access logs from `started_at` on, the wall-clock time of the start of the run,
+    /// so a run on a paused runtime logs the same times every time
+    pub fn with_started_at(mut self, started_at: SystemTime) -> Self {
+        self.started_at = started_at;
+        self
+    }
+
+    /// How long a sleep or call takes at least if the dice make it an outlier of the spike.
+    /// A dry run does not spike, as it skips sleeps
+    fn spiked(&self) -> Result<Option<Duration>, VMError> {
+        let Some(spike) = self.spike.as_ref().filter(|_| self.dry_run.is_none()) else {
+            return Ok(None);
+        };
+        let roll = self
+            .decisions
+            .random_f64()
+            .map_err(VMError::ReplayDiverged)?;
+        Ok((roll < spike.rate).then_some(spike.to))
+    }
+
+    /// Waits until the load profile hands out another iteration, if the VM is about to start one.
+    /// Returns true if a shutdown was requested meanwhile and execution continues in the shutdown block
+    async fn wait_for_load(&mut self) -> bool {
+        let Some(load) = self.load.clone() else {
+            return false;
+        };
+        if self.interpreter.loop_call() != Some(self.interpreter.ip) {
+            return false;
+        }
+        let now = Instant::now();
+        let pause = self
+            .loop_iteration_started
+            .map(|started| load.pause(now - started, now.saturating_duration_since(self.started)))
+            .unwrap_or_default();
+        let resume_at = now + pause;
+        loop {
+            let now = Instant::now();
+            let wait = match resume_at.checked_duration_since(now) {
+                Some(wait) if !wait.is_zero() => wait,
+                _ => match load.try_acquire(now.saturating_duration_since(self.started)) {
+                    Ok(()) => break,
+                    Err(wait) => wait,
+                },
+            };
+            if self.check_shutdown() {
+                return true;
+            }
+            self.answer_health_checks();
+            tokio::time::sleep(wait.min(self.interrupt_interval)).await;
+        }
+        self.loop_iteration_started = Some(Instant::now());
+        false
+    }
+
+    /// Counts the iterations of the loop in a dry run. Returns true once the loop ran as often as asked
+    fn dry_run_loop_done(&mut self) -> bool {
+        let Some(dry_run) = self.dry_run.clone() else {
+            return false;
+        };
+        if self.interpreter.loop_call() != Some(self.interpreter.ip) {
+            return false;
+        }
+        if self.loop_iterations < dry_run.iterations() {
+            self.loop_iterations += 1;
+            return false;
+        }
+        if self.loop_running {
+            self.loop_running = false;
+            dry_run.finish();
+        }
+        true
+    }
+
+    fn build_counters(&self) -> Result<Instruments, VMError> {
         let remote_invocation_counter = self
             .meter_provider
             .meter("remote_invocation_counter")
@@ -275,41 +789,77 @@ impl VM {
         let instruction_duration = self
             .meter_provider
             .meter("instruction_duration")
-            .u64_gauge("instruction_duration")
+            .f64_histogram("instruction.duration")
             .with_unit("ms")
             .with_description("The duration of executing an instruction in milliseconds")
-            .build()
-            .to_owned();
+            .with_boundaries(INSTRUCTION_DURATION_BOUNDARIES.to_vec())
+            .build();
 
         let remote_call_duration = self
             .meter_provider
             .meter("remote_call_duration")
-            .u64_gauge("remote_call_duration")
+            .f64_histogram("remote_call.duration")
             .with_unit("ms")
             .with_description("The duration of a remote call in milliseconds")
-            .build()
-            .to_owned();
+            .with_boundaries(REMOTE_CALL_DURATION_BOUNDARIES.to_vec())
+            .build();
 
-        Ok((
+        let legacy_gauges = self.legacy_duration_gauges.then(|| {
+            let instruction_duration = self
+                .meter_provider
+                .meter("instruction_duration")
+                .u64_gauge("instruction_duration")
+                .with_unit("ms")
+                .with_description("The duration of executing an instruction in milliseconds")
+                .build();
+            let remote_call_duration = self
+                .meter_provider
+                .meter("remote_call_duration")
+                .u64_gauge("remote_call_duration")
+                .with_unit("ms")
+                .with_description("The duration of a remote call in milliseconds")
+                .build();
+            (instruction_duration, remote_call_duration)
+        });
+
+        Ok(Instruments {
             remote_invocation_counter,
             local_invocation_counter,
             instruction_duration,
             remote_call_duration,
-        ))
+            legacy_gauges,
+        })
     }
 
+    /// Runs the service until it stops, is shut down or fails
     pub async fn run(&mut self) -> Result<(), VMError> {
-        let mut execution_counter = 0;
+        let result = self.execute().await;
+        if let Err(e) = &result {
+            self.record_error(e);
+        }
+        self.reject_incoming_calls();
+        // A loop that stopped with an error must not keep the dry run waiting
+        if let Some(dry_run) = self.dry_run.as_ref().filter(|_| self.loop_running) {
+            dry_run.finish();
+        }
+        result
+    }
+
+    async fn execute(&mut self) -> Result<(), VMError> {
         let counters = self.build_counters()?;
+        // A service that starts late waits before its first instruction. One that never started
+        // only runs its shutdown block
+        self.wait_while_suspended().await;
+        self.check_shutdown();
 
-        while self.ip < self.code.len() {
-            if self.ip >= self.code.len() {
-                return Err(VMError::IPOutOfBounds(self.ip, self.code.len()));
-            }
+        while !self.interpreter.is_done() {
             self.execute_instruction(counters.clone()).await?;
-            execution_counter += 1;
+            self.stats.instructions += 1;
+            if let Some(instruction_counter) = &self.instruction_counter {
+                instruction_counter.fetch_add(1, Ordering::Relaxed);
+            }
             if let Some(max_execution_counter) = self.max_execution_counter {
-                if execution_counter > max_execution_counter {
+                if self.stats.instructions > max_execution_counter {
                     return Err(VMError::MaxExecutionCounterReached);
                 }
             }
@@ -317,235 +867,440 @@ impl VM {
         Ok(())
     }
 
-    async fn handle_remote_call(&mut self) -> Result<(), VMError> {
+    /// Marks the span the VM stopped in as failed, with the error as an exception event
+    fn record_error(&self, e: &VMError) {
+        if self.tracer.is_none() {
+            return;
+        }
+        if let Some(cx) = self.otel_context.as_ref() {
+            let span = cx.span();
+            span.record_error(e);
+            span.set_status(Status::error(e.to_string()));
+        }
+    }
+
+    /// Closes the queue of incoming calls once the VM stopped, so calls fail instead of
+    /// waiting for a VM that no longer takes them
+    fn reject_incoming_calls(&mut self) {
         if let Some(remote_call_rx) = &mut self.remote_call_rx {
-            self.remote_call_counter += 1;
-            if self.remote_call_counter > self.remote_call_limit {
-                if let Ok(msg) = remote_call_rx.try_recv() {
-                    let label_name = format!("start_{}", msg);
-                    self.handle_local_call(label_name).await?;
+            remote_call_rx.close();
+        }
+        self.reject_queued_calls();
+    }
+
+    fn reject_queued_calls(&mut self) {
+        let Some(remote_call_rx) = &mut self.remote_call_rx else {
+            return;
+        };
+        let stashed_calls = self
+            .stashed_call
+            .take()
+            .into_iter()
+            .chain(std::mem::take(&mut self.replayed_calls));
+        let queued_calls = std::iter::from_fn(|| remote_call_rx.try_recv().ok());
+        for msg in stashed_calls.chain(queued_calls) {
+            tracing::debug!(call_id = msg.id, function = %msg.function, "Rejecting incoming call");
+            if let Some(reply) = msg.reply {
+                let _ = reply.send(Err(CallError::ServiceUnavailable(
+                    self.service_name.clone(),
+                )));
+            }
+        }
+    }
+
+    /// Checks whether a shutdown was requested. On the first check after the request, execution continues
+    /// in the shutdown block of the service, if it has one, and the VM stops afterwards.
+    /// Returns true if execution was redirected.
+    fn check_shutdown(&mut self) -> bool {
+        let requested = self
+            .shutdown
+            .as_ref()
+            .is_some_and(|shutdown| shutdown.load(Ordering::Relaxed));
+        if self.shutting_down || !requested {
+            return false;
+        }
+        self.shutting_down = true;
+        let end = self.interpreter.instructions.len();
+        let shutdown_block = self
+            .interpreter
+            .strings
+            .lookup(&format!("start_{}_shutdown", self.service_name))
+            .and_then(|label| Some((label, self.interpreter.jump_target(label).ok()?)));
+        match shutdown_block {
+            Some((label, target)) => {
+                let caller_cx = self.enter_call_span(label, SpanKind::Internal, None);
+                self.call_frames.push(CallFrame {
+                    caller_cx,
+                    reply: None,
+                });
+                self.interpreter.enter(target, end);
+            }
+            None => self.interpreter.ip = end,
+        }
+        true
+    }
+
+    /// Waits while the service is killed, until it is restarted or a shutdown is requested
+    async fn wait_while_suspended(&mut self) {
+        let Some(mut suspension) = self.suspension.clone() else {
+            return;
+        };
+        if !*suspension.borrow_and_update() {
+            return;
+        }
+        tracing::debug!(service = %self.service_name, "Service killed");
+        self.reject_queued_calls();
+        loop {
+            let requested = self
+                .shutdown
+                .as_ref()
+                .is_some_and(|shutdown| shutdown.load(Ordering::Relaxed));
+            if requested {
+                return;
+            }
+            match tokio::time::timeout(self.interrupt_interval, suspension.changed()).await {
+                Ok(Ok(())) if !*suspension.borrow_and_update() => break,
+                // The coordinator stopped, nothing restarts the service anymore
+                Ok(Err(_)) => return,
+                _ => self.reject_queued_calls(),
+            }
+        }
+        tracing::debug!(service = %self.service_name, "Service restarted");
+    }
+
+    /// Answers the health checks at the front of the queue, so services that run a loop and never
+    /// reach CheckInterrupt answer them too. Like the shutdown flag, they are checked on returns and
+    /// loop back-edges. The first other call waits for the next CheckInterrupt.
+    fn answer_health_checks(&mut self) {
+        let Some(remote_call_rx) = &mut self.remote_call_rx else {
+            return;
+        };
+        while self.stashed_call.is_none() {
+            let Ok(msg) = remote_call_rx.try_recv() else {
+                return;
+            };
+            if msg.function != HEALTH_CHECK {
+                self.stashed_call = Some(msg);
+            } else if let Some(reply) = msg.reply {
+                let _ = reply.send(Ok(()));
+            }
+        }
+    }
+
+    async fn handle_remote_call(&mut self) -> Result<(), VMError> {
+        if self.decisions.is_replaying() {
+            return self.handle_replayed_call().await;
+        }
+        let Some(remote_call_rx) = &mut self.remote_call_rx else {
+            self.decisions.record_call(None);
+            tokio::time::sleep(self.interrupt_interval).await;
+            return Ok(());
+        };
+        let msg = match self
+            .stashed_call
+            .take()
+            .map_or_else(|| remote_call_rx.try_recv(), Ok)
+        {
+            Ok(msg) => Some(msg),
+            Err(TryRecvError::Empty) => {
+                tokio::time::timeout(self.interrupt_interval, remote_call_rx.recv())
+                    .await
+                    .ok()
+                    .flatten()
+            }
+            Err(TryRecvError::Disconnected) => {
+                tokio::time::sleep(self.interrupt_interval).await;
+                None
+            }
+        };
+        match msg {
+            Some(msg) if msg.function == HEALTH_CHECK => {
+                // Reaching CheckInterrupt is all a health check asks for
+                self.decisions.record_call(None);
+                if let Some(reply) = msg.reply {
+                    let _ = reply.send(Ok(()));
+                }
+                Ok(())
+            }
+            Some(msg) => {
+                self.decisions.record_call(Some(&msg.function));
+                self.take_call(msg).await
+            }
+            None => {
+                self.decisions.record_call(None);
+                Ok(())
+            }
+        }
+    }
+
+    /// Takes the call the recording took at this CheckInterrupt, and waits for it if it has not
+    /// arrived yet. Calls that arrive before it wait for their turn
+    async fn handle_replayed_call(&mut self) -> Result<(), VMError> {
+        let function = self
+            .decisions
+            .replayed_call()
+            .map_err(VMError::ReplayDiverged)?;
+        let Some(function) = function else {
+            tokio::time::sleep(self.interrupt_interval).await;
+            self.answer_health_checks();
+            return Ok(());
+        };
+        self.replayed_calls.extend(self.stashed_call.take());
+        loop {
+            if let Some(index) = self
+                .replayed_calls
+                .iter()
+                .position(|msg| msg.function == function)
+            {
+                let msg = self.replayed_calls.remove(index).unwrap();
+                return self.take_call(msg).await;
+            }
+            let requested = self
+                .shutdown
+                .as_ref()
+                .is_some_and(|shutdown| shutdown.load(Ordering::Relaxed));
+            let Some(remote_call_rx) = self.remote_call_rx.as_mut().filter(|_| !requested) else {
+                return Ok(());
+            };
+            match tokio::time::timeout(self.interrupt_interval, remote_call_rx.recv()).await {
+                Ok(Some(msg)) if msg.function == HEALTH_CHECK => {
+                    if let Some(reply) = msg.reply {
+                        let _ = reply.send(Ok(()));
+                    }
+                }
+                Ok(Some(msg)) => self.replayed_calls.push_back(msg),
+                Ok(None) => {
+                    return Err(VMError::ReplayDiverged(Diverged(format!(
+                        "{} stopped getting calls before a call to {}",
+                        self.service_name, function
+                    ))))
                 }
-                self.remote_call_counter = 0;
+                Err(_) => {}
             }
         }
-        Ok(())
     }
 
-    async fn handle_local_call(&mut self, label: String) -> Result<(), VMError> {
-        self.return_addresses.push(self.ip);
-        self.stack.push(Vec::new());
-        self.ip = *self
-            .label_jump_map
-            .get(&label)
-            .ok_or(VMError::MissingLabel(label.clone()))?;
+    /// Runs the function an incoming call asks for, in a server span that continues the trace of the caller
+    async fn take_call(&mut self, mut msg: IncomingCall) -> Result<(), VMError> {
+        self.stats.incoming_calls += 1;
+        tracing::debug!(call_id = msg.id, function = %msg.function, "Incoming call");
+        let label_name = format!("start_{}", msg.function);
+        let Some(label) = self.interpreter.strings.lookup(&label_name) else {
+            // A caller that waits for the reply learns of the unknown function, e.g. a request to
+            // the admin API, and the service keeps running
+            if let Some(reply) = msg.reply {
+                let _ = reply.send(Err(CallError::UnknownFunction(msg.function)));
+                return Ok(());
+            }
+            return Err(VMError::MissingLabel(label_name));
+        };
+        // Continue the trace of the caller, so the server span becomes a child of its client span
+        let propagator = TraceContextPropagator::new();
+        let parent_cx = propagator.extract(&metadata_map::MetadataMap(&mut msg.context));
+        let parent_cx = BaggagePropagator::new()
+            .extract_with_context(&parent_cx, &metadata_map::MetadataMap(&mut msg.context));
+        let caller_baggage: Vec<(String, String)> = parent_cx
+            .baggage()
+            .iter()
+            .map(|(key, (value, _))| (key.to_string(), value.to_string()))
+            .collect();
+        self.call(label, SpanKind::Server, Some(parent_cx), msg.reply)?;
+        // The baggage of the caller goes on top of the VM's own until the call returns
+        self.interpreter.baggage.extend(caller_baggage);
+        if self.tracer.is_some() {
+            if let Some(cx) = self.otel_context.as_ref() {
+                cx.span()
+                    .set_attribute(KeyValue::new(SERVICE_INSTANCE_ID, msg.instance));
+            }
+        }
+        // A spiked call is slow before its method runs, inside the server span
+        if let Some(spike) = self.spiked()? {
+            tokio::time::sleep(spike).await;
+        }
         Ok(())
     }
 
-    #[inline]
-    fn extract_length(&self) -> (usize, usize, usize) {
-        let start = self.ip + 1;
-        let end = start + LENGTH_OFFSET;
-        let length_bytes: [u8; LENGTH_OFFSET] = self.code[start..end].try_into().unwrap();
-        let length = usize::from_le_bytes(length_bytes.try_into().unwrap());
-        (start, end, length)
+    fn call(
+        &mut self,
+        label: Symbol,
+        kind: SpanKind,
+        parent_cx: Option<Context>,
+        reply: Option<CallReply>,
+    ) -> Result<(), VMError> {
+        self.interpreter.call(label)?;
+        let caller_cx = self.enter_call_span(label, kind, parent_cx);
+        self.call_frames.push(CallFrame { caller_cx, reply });
+        Ok(())
     }
 
-    fn current_stackframe(&mut self) -> Result<&mut Vec<StackValue>, VMError> {
-        self.stack.last_mut().ok_or(VMError::MissingStackFrame)
+    fn span_name(&self, method: &str) -> String {
+        self.span_name
+            .replace("{service}", &self.service_name)
+            .replace("{method}", method)
     }
 
-    async fn execute_instruction(
+    /// Starts a span for the called function and makes it the current context until the function returns.
+    /// The span is a child of `parent_cx` if given, otherwise of the current context.
+    /// Returns the context of the caller.
+    fn enter_call_span(
         &mut self,
-        counters: (Counter<u64>, Counter<u64>, Gauge<u64>, Gauge<u64>),
-    ) -> Result<(), VMError> {
-        let instruction = self.code[self.ip];
-        let (
+        label: Symbol,
+        kind: SpanKind,
+        parent_cx: Option<Context>,
+    ) -> Option<Context> {
+        let caller_cx = self.otel_context.clone();
+        if let Some(tracer_provider) = self.tracer.as_ref() {
+            let tracer = tracer_provider.tracer(self.service_name.clone());
+            let label_name = self.interpreter.strings.get(label);
+            let function_name = label_name.strip_prefix("start_").unwrap_or(label_name);
+            let parent_cx = parent_cx
+                .or_else(|| caller_cx.clone())
+                .unwrap_or_else(Context::current);
+            let mut attributes = vec![KeyValue::new(SERVICE_NAME, self.service_name.clone())];
+            if kind == SpanKind::Server {
+                attributes.extend(rpc_attributes(&self.service_name, function_name));
+            }
+            let span = tracer
+                .span_builder(self.span_name(function_name))
+                .with_kind(kind)
+                .with_attributes(attributes)
+                .start_with_context(&tracer, &parent_cx);
+            self.otel_context = Some(parent_cx.with_span(span));
+        }
+        caller_cx
+    }
+
+    /// Adds the span the VM is in to a message, to correlate the log with the trace
+    fn print(&self, message: PrintMessage) -> Print {
+        let span_context = self
+            .otel_context
+            .as_ref()
+            .map(|cx| cx.span().span_context().clone())
+            .filter(SpanContext::is_valid);
+        Print {
+            message,
+            span_context,
+        }
+    }
+
+    /// Prints to stdout, or to stderr at the error rate of the incidents of the service
+    async fn print_stdout(&mut self, message: String) -> Result<(), VMError> {
+        if let Some((incidents, started)) = &self.incidents {
+            let error_rate =
+                chaos::incident_error_rate(incidents, &self.service_name, started.elapsed())
+                    .unwrap_or(0.0);
+            let roll = self
+                .decisions
+                .random_f64()
+                .map_err(VMError::ReplayDiverged)?;
+            if roll < error_rate {
+                return self.print_stderr(message).await;
+            }
+        }
+        self.add_span_event(&message, "INFO");
+        self.print_sink
+            .print(self.print(PrintMessage::Stdout(message)))
+            .await
+            .map_err(VMError::PrintError)?;
+        self.stats.stdout += 1;
+        Ok(())
+    }
+
+    async fn print_stderr(&mut self, message: String) -> Result<(), VMError> {
+        self.add_span_event(&message, "ERROR");
+        self.print_sink
+            .print(self.print(PrintMessage::Stderr(message)))
+            .await
+            .map_err(VMError::PrintError)?;
+        self.stats.stderr += 1;
+        Ok(())
+    }
+
+    /// Adds a print to the current span, if prints become span events and a span is active
+    fn add_span_event(&self, message: &str, level: &'static str) {
+        if !self.span_events || self.tracer.is_none() {
+            return;
+        }
+        if let Some(cx) = self.otel_context.as_ref() {
+            cx.span()
+                .add_event(message.to_string(), vec![KeyValue::new("level", level)]);
+        }
+    }
+
+    /// Ends the span of the returning function, restores the context of the caller
+    /// and tells a remote caller that the function completed.
+    fn return_from_call(&mut self) {
+        if self.tracer.is_some() {
+            if let Some(cx) = self.otel_context.as_ref() {
+                cx.span().end();
+            }
+        }
+        let Some(frame) = self.call_frames.pop() else {
+            self.otel_context = None;
+            return;
+        };
+        self.otel_context = frame.caller_cx;
+        if let Some(reply) = frame.reply {
+            // The caller may have stopped waiting for the reply
+            let _ = reply.send(Ok(()));
+        }
+    }
+
+    async fn execute_instruction(&mut self, counters: Instruments) -> Result<(), VMError> {
+        let code = self
+            .interpreter
+            .instructions
+            .get(self.interpreter.ip)
+            .ok_or(VMError::IPOutOfBounds(
+                self.interpreter.ip,
+                self.interpreter.instructions.len(),
+            ))?
+            .code();
+        let Instruments {
             remote_invocation_counter,
             local_invocation_counter,
             instruction_duration,
             remote_call_duration,
-        ) = counters;
+            legacy_gauges,
+        } = counters;
         let start = std::time::Instant::now();
-        match instruction {
-            PUSH_STRING_CODE => {
-                let (_start, end, str_len) = self.extract_length();
-                let str = &self.code[end..end + str_len];
-                let str = String::from_utf8(str.to_vec()).unwrap();
-                self.current_stackframe()?.push(StackValue::String(str));
-                self.ip = end + str_len;
-            }
-            PUSH_INT_CODE => {
-                let (_start, end, int_len) = self.extract_length();
-                let int = &self.code[end..end + int_len];
-                let int = u64::from_le_bytes(int.try_into().unwrap());
-                self.current_stackframe()?.push(StackValue::Int(int));
-                self.ip = end + int_len;
-            }
-            POP_CODE => {
-                self.stack.pop();
-                self.ip += 1;
-            }
-            DEC_CODE => {
-                let top = self
-                    .current_stackframe()?
-                    .pop()
-                    .ok_or(VMError::StackUnderflow)?;
-                match top {
-                    StackValue::Int(n) => self.current_stackframe()?.push(StackValue::Int(n - 1)),
-                    _ => return Err(VMError::InvalidStackValue),
-                }
-                self.ip += 1;
-            }
-            JMP_IF_ZERO_CODE => {
-                let (_start, end, jump_to_label_len) = self.extract_length();
-                let jump_to_label_bytes = &self.code[end..end + jump_to_label_len];
-                let jump_to_label = String::from_utf8(jump_to_label_bytes.to_vec()).unwrap();
-                let top = self
-                    .current_stackframe()?
-                    .pop()
-                    .ok_or(VMError::StackUnderflow)?;
-                match top {
-                    StackValue::Int(n) => {
-                        if n == 0 {
-                            self.ip = self
-                                .label_jump_map
-                                .get(&jump_to_label)
-                                .ok_or(VMError::MissingLabel(jump_to_label.clone()))?
-                                .to_owned();
-                        }
-                    }
-                    _ => return Err(VMError::InvalidStackValue),
-                }
-                self.ip += 1;
-            }
-            LABEL_CODE => {
-                let (_start, end, label_len) = self.extract_length();
-                self.ip = end + label_len;
-            }
-            STDOUT_CODE => {
-                let str = self
-                    .current_stackframe()?
-                    .pop()
-                    .ok_or(VMError::StackUnderflow)?;
-                match str {
-                    StackValue::String(s) => self
-                        .print_tx
-                        .send(PrintMessage::Stdout(s))
-                        .await
-                        .map_err(VMError::PrintError)?,
-                    StackValue::Int(i) => self
-                        .print_tx
-                        .send(PrintMessage::Stdout(i.to_string()))
-                        .await
-                        .map_err(VMError::PrintError)?,
-                }
-                self.ip += 1;
-            }
-            STDERR_CODE => {
-                let top = self
-                    .current_stackframe()?
-                    .pop()
-                    .ok_or(VMError::StackUnderflow)?;
-                match top {
-                    StackValue::String(s) => {
-                        self.print_tx
-                            .send(PrintMessage::Stderr(s))
-                            .await
-                            .map_err(VMError::PrintError)?;
-                    }
-                    _ => return Err(VMError::InvalidStackValue),
-                }
-                self.ip += 1;
-            }
-            SLEEP_CODE => {
-                let (_start, end, sleep_len) = self.extract_length();
-                let sleep_bytes = &self.code[end..end + sleep_len];
-                let sleep_ms = u64::from_le_bytes(sleep_bytes.try_into().unwrap());
-                std::thread::sleep(std::time::Duration::from_millis(sleep_ms));
-                self.ip = end + sleep_len;
-            }
-            STORE_VAR_CODE => {
-                let (_start, end, key_len) = self.extract_length();
-                let key = &self.code[end..end + key_len];
-                let key = String::from_utf8(key.to_vec()).unwrap();
-
-                //We need to substract one here because extract_length adds +1 to compensate for the instruction byte
-                self.ip = end + key_len - 1;
-
-                let (_start, end, value_len) = self.extract_length();
-                let value = &self.code[end..end + value_len];
-                let value = String::from_utf8(value.to_vec()).unwrap();
-
-                self.vars.insert(key, StackValue::String(value));
-                self.ip = end + value_len;
-            }
-            LOAD_VAR_CODE => {
-                let (_start, end, key_len) = self.extract_length();
-                let key = &self.code[end..end + key_len];
-                let key = String::from_utf8(key.to_vec()).unwrap();
-                let value = self
-                    .vars
-                    .get(&key)
-                    .ok_or(VMError::MissingVar(key.clone()))?
-                    .clone();
-                self.current_stackframe()?.push(value);
-                self.ip = end + key_len;
-            }
-            DUP_CODE => {
-                let top = self
-                    .current_stackframe()?
-                    .last()
-                    .ok_or(VMError::StackUnderflow)?
-                    .clone();
-                self.current_stackframe()?.push(top);
-                self.ip += 1;
-            }
-            JUMP_CODE => {
-                let (_start, end, jump_to_label_len) = self.extract_length();
-                let jump_to_label_bytes = &self.code[end..end + jump_to_label_len];
-                let jump_to_label = String::from_utf8(jump_to_label_bytes.to_vec()).unwrap();
-                self.ip = self
-                    .label_jump_map
-                    .get(&jump_to_label)
-                    .ok_or(VMError::MissingLabel(jump_to_label.clone()))?
-                    .to_owned();
-            }
-            PRINTF_CODE => {
-                let var = self
-                    .current_stackframe()?
-                    .pop()
-                    .ok_or(VMError::StackUnderflow)?;
-                let template = self
-                    .current_stackframe()?
-                    .pop()
-                    .ok_or(VMError::StackUnderflow)?;
-                let template = match template {
-                    StackValue::String(s) => s,
-                    _ => return Err(VMError::InvalidStackValue),
-                };
-
-                if template.contains("%s") {
-                    let var = match var {
-                        StackValue::String(s) => s,
-                        _ => return Err(VMError::InvalidStackValue),
-                    };
-                    let formatted = template.replace("%s", &var);
-                    self.current_stackframe()?
-                        .push(StackValue::String(formatted));
-                } else if template.contains("%d") {
-                    let var = match var {
-                        StackValue::Int(i) => i,
-                        _ => return Err(VMError::InvalidStackValue),
-                    };
-                    let formatted = template.replace("%d", &var.to_string());
-                    self.current_stackframe()?
-                        .push(StackValue::String(formatted));
-                } else {
-                    return Err(VMError::InvalidTemplate(template.clone()));
+        let mut host = VmHost {
+            decisions: &self.decisions,
+            started: self.started,
+            service_name: &self.service_name,
+            extensions: &self.extensions,
+        };
+        match self.interpreter.step(&mut host)? {
+            Step::Continue => {}
+            Step::JumpedBack => {
+                self.check_shutdown();
+                self.answer_health_checks();
+            }
+            Step::Print(interpreter::Stream::Stdout, message) => self.print_stdout(message).await?,
+            Step::Print(interpreter::Stream::Stderr, message) => self.print_stderr(message).await?,
+            Step::Sleep(sleep) => {
+                if self.dry_run.is_none() {
+                    let sleep = self.spiked()?.map_or(sleep, |spike| spike.max(sleep));
+                    tokio::time::sleep(sleep).await;
                 }
-                self.ip += 1;
             }
-            REMOTE_CALL_CODE => {
+            Step::AccessLog(format) => {
+                // One draw seeds the line, so a replay repeats it
+                let seed = self
+                    .decisions
+                    .random_range(0..u64::MAX)
+                    .map_err(VMError::ReplayDiverged)?;
+                let time = self
+                    .decisions
+                    .timestamp(self.started_at + self.started.elapsed())
+                    .map_err(VMError::ReplayDiverged)?;
+                let line = access_log::generate(format, time, &mut StdRng::seed_from_u64(seed));
+                self.interpreter.push(Value::String(line.into()))?;
+            }
+            Step::RemoteCall {
+                service: remote_service,
+                method: remote_method,
+            } => {
                 let start = std::time::Instant::now();
                 let remote_call_tx = self
                     .remote_call_tx
@@ -554,82 +1309,161 @@ impl VM {
                         "Remote call tx not set".to_string(),
                     ))?
                     .clone();
+                if let Some(remote_call_limit) = self.remote_call_limit {
+                    if self.stats.remote_calls >= remote_call_limit {
+                        return Err(VMError::RemoteCallLimitReached);
+                    }
+                }
+                self.stats.remote_calls += 1;
 
-                let remote_method = self
-                    .current_stackframe()?
-                    .pop()
-                    .ok_or(VMError::StackUnderflow)?
-                    .clone();
-                let remote_service = self
-                    .current_stackframe()?
-                    .pop()
-                    .ok_or(VMError::StackUnderflow)?
-                    .clone();
                 let local_function_name = self
-                    .find_current_function_name()
+                    .interpreter
+                    .current_function_name()
                     .ok_or(VMError::MissingFunctionName)?;
                 let mut cx = None;
+                let mut metadata = HashMap::new();
 
                 if let Some(tracer_provider) = self.tracer.as_ref() {
                     if let Some(otel_cx) = self.otel_context.as_ref() {
                         let tracer = tracer_provider.tracer(self.service_name.clone());
 
+                        // Named like the span of the calling function
+                        let function_name = local_function_name
+                            .strip_prefix("start_")
+                            .unwrap_or(&local_function_name);
                         let span = tracer
-                            .span_builder(format!("{}/{}", self.service_name, local_function_name))
+                            .span_builder(self.span_name(function_name))
                             .with_kind(SpanKind::Client)
-                            .with_attributes(vec![KeyValue::new(
-                                SERVICE_NAME,
-                                self.service_name.clone(),
-                            )])
-                            .start(&tracer);
-
-                        cx = Some(otel_cx.with_span(span));
-                        let mut metadata = HashMap::new();
+                            .with_attributes(
+                                [
+                                    KeyValue::new(SERVICE_NAME, self.service_name.clone()),
+                                    KeyValue::new(PEER_SERVICE, remote_service.clone()),
+                                ]
+                                .into_iter()
+                                .chain(rpc_attributes(
+                                    &remote_service.clone(),
+                                    &remote_method.clone(),
+                                )),
+                            )
+                            .start_with_context(&tracer, otel_cx);
+
+                        let client_cx = otel_cx.with_span(span);
                         let propagator = TraceContextPropagator::new();
-                        propagator.inject_context(&cx.clone().unwrap(), &mut metadata);
+                        propagator.inject_context(
+                            &client_cx,
+                            &mut metadata_map::MetadataMap(&mut metadata),
+                        );
+                        cx = Some(client_cx);
                     } else {
                         return Err(VMError::MissingContext);
                     }
                 }
+                if !self.interpreter.baggage.is_empty() {
+                    let baggage_cx = Context::new().with_baggage(
+                        self.interpreter
+                            .baggage
+                            .iter()
+                            .map(|(key, value)| KeyValue::new(key.clone(), value.clone())),
+                    );
+                    BaggagePropagator::new()
+                        .inject_context(&baggage_cx, &mut metadata_map::MetadataMap(&mut metadata));
+                }
 
+                let (reply_tx, reply_rx) = oneshot::channel();
                 remote_call_tx
                     .send(ServiceMessage::Call {
-                        to: remote_service.to_string(),
-                        function: remote_method.to_string(),
-                        context: cx.clone().unwrap_or(opentelemetry::Context::current()),
+                        id: vm_coordinator::next_call_id(),
+                        from: self.service_name.clone(),
+                        to: remote_service.clone(),
+                        function: remote_method.clone(),
+                        context: metadata,
+                        reply: Some(reply_tx),
                     })
                     .await
                     .map_err(|e| VMError::RemoteCallError(e.to_string()))?;
+                if let Some(dry_run) = &self.dry_run {
+                    dry_run.start();
+                }
 
                 remote_invocation_counter.add(
                     1,
                     &[
                         KeyValue::new("service", self.service_name.clone()),
-                        KeyValue::new("method", remote_method.to_string().clone()),
+                        KeyValue::new("method", remote_method.clone()),
                     ],
                 );
 
                 let duration = start.elapsed();
-                let duration_ms = duration.as_millis() as u64;
-                remote_call_duration.record(
-                    duration_ms,
-                    &[
-                        KeyValue::new("service", self.service_name.clone()),
-                        KeyValue::new("method", remote_method.to_string().clone()),
-                    ],
-                );
-                if let Some(cx) = cx {
-                    cx.span()
-                        .set_attributes(vec![KeyValue::new("response", "OK")]);
+                let attributes = [
+                    KeyValue::new("service", self.service_name.clone()),
+                    KeyValue::new("target_service", remote_service.clone()),
+                    KeyValue::new("method", remote_method.clone()),
+                ];
+                remote_call_duration.record(duration.as_secs_f64() * 1000.0, &attributes);
+                if let Some((_, legacy_remote_call_duration)) = legacy_gauges.as_ref() {
+                    legacy_remote_call_duration.record(
+                        duration.as_millis() as u64,
+                        &[
+                            KeyValue::new("service", self.service_name.clone()),
+                            KeyValue::new("method", remote_method.clone()),
+                        ],
+                    );
                 }
-                self.ip += 1;
+                // The client span stays open until the callee replies, without blocking the caller.
+                // Failed calls also end up on stderr.
+                let print_sink = self.print_sink.clone();
+                let failed_remote_calls = self.failed_remote_calls.clone();
+                let call_name = format!("{}.{}", remote_service, remote_method);
+                let dry_run = self.dry_run.clone();
+                tokio::spawn(async move {
+                    let result = reply_rx.await.unwrap_or(Err(CallError::Dropped));
+                    if let Err(e) = &result {
+                        failed_remote_calls.fetch_add(1, Ordering::SeqCst);
+                        let _ = print_sink
+                            .print(Print {
+                                message: PrintMessage::Stderr(format!(
+                                    "Call to {} failed: {}",
+                                    call_name, e
+                                )),
+                                span_context: cx
+                                    .as_ref()
+                                    .map(|cx| cx.span().span_context().clone()),
+                            })
+                            .await;
+                    }
+                    if let Some(cx) = cx {
+                        let span = cx.span();
+                        match result {
+                            Ok(()) => span.set_attribute(KeyValue::new("response", "OK")),
+                            Err(e) => {
+                                match e {
+                                    CallError::CircuitOpen(_) => span.set_attribute(KeyValue::new(
+                                        "circuit_breaker.state",
+                                        CircuitState::Open.to_string(),
+                                    )),
+                                    CallError::RateLimited(_) => span.set_attribute(KeyValue::new(
+                                        "http.response.status_code",
+                                        429,
+                                    )),
+                                    _ => {}
+                                }
+                                span.record_error(&e);
+                                span.set_status(Status::error(e.to_string()))
+                            }
+                        }
+                        span.end();
+                    }
+                    if let Some(dry_run) = dry_run {
+                        dry_run.finish();
+                    }
+                });
             }
-            START_CONTEXT_CODE => {
+            Step::StartContext => {
                 if let Some(tracer_provider) = self.tracer.as_ref() {
                     let mut metadata = HashMap::new();
                     let tracer = tracer_provider.tracer(self.service_name.clone());
                     let span = tracer
-                        .span_builder(format!("{}/{}", self.service_name, "start_context"))
+                        .span_builder(self.span_name("start_context"))
                         .with_kind(SpanKind::Server)
                         .start(&tracer);
                     let cx = Context::current_with_span(span);
@@ -638,64 +1472,73 @@ impl VM {
                     });
                     self.otel_context = Some(cx);
                 }
-                self.ip += 1;
             }
-            END_CONTEXT_CODE => {
-                match self.otel_context.as_mut() {
-                    Some(_) => {
-                        self.otel_context = None;
-                    }
-                    None => {
-                        return Err(VMError::MissingSpan);
-                    }
+            Step::EndContext => match self.otel_context.as_mut() {
+                Some(_) => {
+                    self.otel_context = None;
+                }
+                None => {
+                    return Err(VMError::MissingSpan);
+                }
+            },
+            Step::CheckInterrupt => {
+                self.wait_while_suspended().await;
+                if !self.check_shutdown() {
+                    self.handle_remote_call().await?;
                 }
-                self.ip += 1;
-            }
-            CHECK_INTERRUPT_CODE => {
-                self.handle_remote_call().await?;
-            }
-            CALL_CODE => {
-                let (_start, end, label_len) = self.extract_length();
-                let label = &self.code[end..end + label_len];
-                let label = String::from_utf8(label.to_vec()).unwrap();
-                self.handle_local_call(label.clone()).await?;
-                local_invocation_counter
-                    .add(1, &[KeyValue::new("method", label.to_string().clone())]);
             }
-            RET_CODE => {
-                self.ip = self.return_addresses.pop().unwrap();
-                self.stack.pop();
+            Step::Call(label) => {
+                if self.dry_run_loop_done() {
+                    // Like a service without a loop, it only answers calls from now on
+                    self.wait_while_suspended().await;
+                    if !self.check_shutdown() {
+                        self.handle_remote_call().await?;
+                    }
+                } else if !self.wait_for_load().await {
+                    self.call(label, SpanKind::Internal, None, None)?;
+                    local_invocation_counter.add(
+                        1,
+                        &[KeyValue::new(
+                            "method",
+                            self.interpreter.strings.get(label).to_string(),
+                        )],
+                    );
+                }
             }
-            _ => {
-                return Err(VMError::InvalidInstruction(instruction));
+            Step::Returned => {
+                self.return_from_call();
+                // A killed service stops running its loops
+                self.wait_while_suspended().await;
+                self.check_shutdown();
+                self.answer_health_checks();
             }
         }
         let duration = start.elapsed();
-        let duration_ms = duration.as_millis() as u64;
+        let instruction_name = crate::code_gen::instruction::code_to_name(code);
         instruction_duration.record(
-            duration_ms,
-            &[KeyValue::new(
-                "instruction",
-                crate::code_gen::instruction::code_to_name(instruction),
-            )],
+            duration.as_secs_f64() * 1000.0,
+            &[
+                KeyValue::new("service", self.service_name.clone()),
+                KeyValue::new("instruction", instruction_name.clone()),
+            ],
         );
+        if let Some((legacy_instruction_duration, _)) = legacy_gauges.as_ref() {
+            legacy_instruction_duration.record(
+                duration.as_millis() as u64,
+                &[KeyValue::new("instruction", instruction_name)],
+            );
+        }
         Ok(())
     }
-
-    fn find_current_function_name(&self) -> Option<String> {
-        for i in (0..self.ip).rev() {
-            if self.label_index_map.contains_key(&i) {
-                return Some(self.label_index_map.get(&i).unwrap().clone());
-            }
-        }
-
-        None
-    }
-}
+}
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
-    use crate::{code_gen::CodeGenerator, parser};
+    use crate::{
+        code_gen::{instruction::StackValue, CodeGenerator},
+        otel, parser,
+    };
 
     use super::*;
 
@@ -756,21 +1599,6 @@ mod tests {
         .to_string()
     }
 
-    fn service_with_broken_template() -> String {
-        "
-        service frontend {
-            method main_page {
-                print \"Main page\" with [\"12345\", \"67890\"];
-            }
-
-            loop {
-                call main_page;
-            }
-        }
-        "
-        .to_string()
-    }
-
     fn call_other_service() -> String {
         "
         service products {
@@ -803,7 +1631,7 @@ mod tests {
         let mut vm = VM::new(code.clone(), "test", print_tx).with_max_execution_counter(2);
         match vm.run().await {
             Ok(_) => {
-                let print_messages = print_rx.recv().await.unwrap();
+                let print_messages = print_rx.recv().await.unwrap().message;
                 assert_eq!(
                     print_messages,
                     PrintMessage::Stdout("Hello, world!".to_string())
@@ -825,7 +1653,7 @@ mod tests {
         let mut vm = VM::new(code.clone(), "test", print_tx).with_max_execution_counter(2);
         match vm.run().await {
             Ok(_) => {
-                let print_messages = print_rx.recv().await.unwrap();
+                let print_messages = print_rx.recv().await.unwrap().message;
                 assert_eq!(print_messages, PrintMessage::Stdout("12345".to_string()));
             }
             Err(e) => {
@@ -889,7 +1717,7 @@ mod tests {
         let mut vm = VM::new(code.clone(), "test", print_tx).with_max_execution_counter(3);
         match vm.run().await {
             Ok(_) => {
-                let print_messages = print_rx.recv().await.unwrap();
+                let print_messages = print_rx.recv().await.unwrap().message;
                 assert_eq!(print_messages, PrintMessage::Stdout("test".to_string()));
             }
             Err(e) => {
@@ -944,8 +1772,8 @@ mod tests {
     #[tokio::test]
     async fn test_printf() {
         let code = vec![
-            Instruction::Push(StackValue::String("Hello, %s!".to_string())),
             Instruction::Push(StackValue::String("world".to_string())),
+            Instruction::Push(StackValue::String("Hello, %s!".to_string())),
             Instruction::Printf,
             Instruction::Stdout,
         ];
@@ -953,7 +1781,7 @@ mod tests {
         let mut vm = VM::new(code.clone(), "test", print_tx).with_max_execution_counter(4);
         match vm.run().await {
             Ok(_) => {
-                let print_messages = print_rx.recv().await.unwrap();
+                let print_messages = print_rx.recv().await.unwrap().message;
                 assert_eq!(
                     print_messages,
                     PrintMessage::Stdout("Hello, world!".to_string())
@@ -969,8 +1797,8 @@ mod tests {
     #[tokio::test]
     async fn test_printf_with_int() {
         let code = vec![
-            Instruction::Push(StackValue::String("Hello, %d!".to_string())),
             Instruction::Push(StackValue::Int(12345)),
+            Instruction::Push(StackValue::String("Hello, %d!".to_string())),
             Instruction::Printf,
             Instruction::Stdout,
         ];
@@ -978,7 +1806,7 @@ mod tests {
         let mut vm = VM::new(code.clone(), "test", print_tx).with_max_execution_counter(4);
         match vm.run().await {
             Ok(_) => {
-                let print_messages = print_rx.recv().await.unwrap();
+                let print_messages = print_rx.recv().await.unwrap().message;
                 assert_eq!(
                     print_messages,
                     PrintMessage::Stdout("Hello, 12345!".to_string())
@@ -991,11 +1819,66 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_printf_with_several_values() {
+        let code = vec![
+            Instruction::Push(StackValue::Int(200)),
+            Instruction::Push(StackValue::String("/products".to_string())),
+            Instruction::Push(StackValue::String("GET %s %d".to_string())),
+            Instruction::Printf,
+            Instruction::Stdout,
+        ];
+        let (print_tx, mut print_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code.clone(), "test", print_tx).with_max_execution_counter(5);
+        match vm.run().await {
+            Ok(_) => {
+                let print_messages = print_rx.recv().await.unwrap().message;
+                assert_eq!(
+                    print_messages,
+                    PrintMessage::Stdout("GET /products 200".to_string())
+                );
+            }
+            Err(e) => {
+                panic!("VM should have finished execution: {:?}", e);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_printf_with_missing_value() {
+        let code = vec![
+            Instruction::Push(StackValue::String("/products".to_string())),
+            Instruction::Push(StackValue::String("GET %s %d".to_string())),
+            Instruction::Printf,
+        ];
+        let (print_tx, _print_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code.clone(), "test", print_tx).with_max_execution_counter(3);
+        match vm.run().await {
+            Ok(_) => {
+                panic!("VM should have failed on the missing value");
+            }
+            Err(e) => {
+                assert_eq!(e, VMError::PrintfArityMismatch(2, 1));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_weighted_without_weight() {
+        let code = vec![
+            Instruction::PushWeighted(vec![("a".to_string(), 0), ("b".to_string(), 0)]),
+            Instruction::Stdout,
+        ];
+        let (print_tx, _print_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, "test", print_tx).with_max_execution_counter(2);
+        assert_eq!(vm.run().await, Err(VMError::ZeroTotalWeight));
+    }
+
     #[tokio::test]
     async fn test_printf_with_invalid_template() {
         let code = vec![
-            Instruction::Push(StackValue::String("Hello, %!".to_string())),
             Instruction::Push(StackValue::Int(12345)),
+            Instruction::Push(StackValue::String("Hello, %!".to_string())),
             Instruction::Printf,
             Instruction::Stdout,
         ];
@@ -1048,7 +1931,7 @@ mod tests {
                 assert_eq!(e, VMError::MaxExecutionCounterReached);
                 assert_eq!(print_rx.len(), 5);
                 for _ in 0..5 {
-                    let print_messages = print_rx.recv().await.unwrap();
+                    let print_messages = print_rx.recv().await.unwrap().message;
                     assert_eq!(
                         print_messages,
                         PrintMessage::Stdout("Main page".to_string())
@@ -1074,12 +1957,12 @@ mod tests {
             Err(e) => {
                 assert_eq!(e, VMError::MaxExecutionCounterReached);
                 assert_eq!(print_rx.len(), 2);
-                let print_messages = print_rx.recv().await.unwrap();
+                let print_messages = print_rx.recv().await.unwrap().message;
                 assert_eq!(
                     print_messages,
                     PrintMessage::Stdout("Main page 12345".to_string())
                 );
-                let print_messages = print_rx.recv().await.unwrap();
+                let print_messages = print_rx.recv().await.unwrap().message;
                 assert_eq!(
                     print_messages,
                     PrintMessage::Stdout("Main page 67890".to_string())
@@ -1104,12 +1987,12 @@ mod tests {
             Err(e) => {
                 assert_eq!(e, VMError::MaxExecutionCounterReached);
                 assert_eq!(print_rx.len(), 2);
-                let print_messages = print_rx.recv().await.unwrap();
+                let print_messages = print_rx.recv().await.unwrap().message;
                 assert_eq!(
                     print_messages,
                     PrintMessage::Stderr("Main page 12345".to_string())
                 );
-                let print_messages = print_rx.recv().await.unwrap();
+                let print_messages = print_rx.recv().await.unwrap().message;
                 assert_eq!(
                     print_messages,
                     PrintMessage::Stderr("Main page 67890".to_string())
@@ -1120,13 +2003,16 @@ mod tests {
 
     #[tokio::test]
     async fn test_vm_with_broken_template() {
-        let service = service_with_broken_template();
-        let ast = parser::parse(&service).unwrap();
-        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+        // The code generator rejects a template without placeholders for the values
+        let code = vec![
+            Instruction::Push(StackValue::String("12345".to_string())),
+            Instruction::Push(StackValue::String("Main page".to_string())),
+            Instruction::Printf,
+            Instruction::Stdout,
+        ];
 
         let (print_tx, print_rx) = mpsc::channel(10);
-        let mut vm =
-            VM::new(code.clone(), &ast.services[0].name, print_tx).with_max_execution_counter(10);
+        let mut vm = VM::new(code, "frontend", print_tx).with_max_execution_counter(10);
         match vm.run().await {
             Ok(_) => {
                 assert!(false, "VM should have reached max execution counter");
@@ -1138,6 +2024,357 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_vm_creates_span_for_local_call() {
+        let service = service_with_local_call();
+        let ast = parser::parse(&service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+        let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+        let tracer = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+
+        let (print_tx, _print_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_tracer(tracer.clone())
+            .with_max_execution_counter(12);
+        assert_eq!(vm.run().await, Err(VMError::MaxExecutionCounterReached));
+        drop(vm);
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let context_span = spans
+            .iter()
+            .find(|span| span.name == "frontend/start_context")
+            .expect("Expected a span for the loop context");
+        let call_span = spans
+            .iter()
+            .find(|span| span.name == "frontend/main_page")
+            .expect("Expected a span for the local call");
+        assert_eq!(call_span.span_kind, SpanKind::Internal);
+        assert_eq!(
+            call_span.parent_span_id,
+            context_span.span_context.span_id()
+        );
+        assert_eq!(
+            call_span.span_context.trace_id(),
+            context_span.span_context.trace_id()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vm_adds_prints_to_the_span_as_events() {
+        let service = "
+        service frontend {
+            method main_page {
+                print \"Main page\";
+                stderr \"Cart is empty\";
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+        let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+        let tracer = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+
+        let (print_tx, _print_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_tracer(tracer.clone())
+            .with_span_events(true)
+            .with_max_execution_counter(12);
+        assert_eq!(vm.run().await, Err(VMError::MaxExecutionCounterReached));
+        drop(vm);
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let call_span = spans
+            .iter()
+            .find(|span| span.name == "frontend/main_page")
+            .expect("Expected a span for the local call");
+        let events: Vec<(&str, &KeyValue)> = call_span
+            .events
+            .iter()
+            .map(|event| (event.name.as_ref(), &event.attributes[0]))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                ("Main page", &KeyValue::new("level", "INFO")),
+                ("Cart is empty", &KeyValue::new("level", "ERROR")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_metric_views_rename_and_drop_instruments() {
+        use opentelemetry_sdk::metrics::{InMemoryMetricExporter, PeriodicReader};
+
+        let exporter = InMemoryMetricExporter::default();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter.clone()).build())
+            .with_view(metric_view(vec![
+                parser::parse_metric_view("remote_invocation_counter rename rpc.client.calls")
+                    .unwrap(),
+                parser::parse_metric_view("local_invocation_counter drop").unwrap(),
+                parser::parse_metric_view("remote_invocation_counter rename ignored").unwrap(),
+            ]))
+            .build();
+        let meter = meter_provider.meter("test");
+        for name in [
+            "remote_invocation_counter",
+            "local_invocation_counter",
+            "print_dropped_counter",
+        ] {
+            meter
+                .u64_counter(name)
+                .with_unit("{call}")
+                .build()
+                .add(1, &[]);
+        }
+        meter_provider.force_flush().unwrap();
+
+        let metrics = exporter.get_finished_metrics().unwrap();
+        let mut exported: Vec<(String, String)> = metrics
+            .iter()
+            .flat_map(|resource| &resource.scope_metrics)
+            .flat_map(|scope| &scope.metrics)
+            .map(|metric| (metric.name.to_string(), metric.unit.to_string()))
+            .collect();
+        exported.sort();
+        assert_eq!(
+            exported,
+            [
+                ("print_dropped_counter".to_string(), "{call}".to_string()),
+                ("rpc.client.calls".to_string(), "{call}".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics_are_exported_in_the_configured_temporality() {
+        use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+
+        let endpoint = Endpoint::local(Protocol::Grpc);
+        assert_eq!(
+            metric_exporter(&endpoint).unwrap().temporality(),
+            Temporality::Delta
+        );
+        let endpoint = endpoint.with_metrics(otel::MetricsExport {
+            interval: Some(Duration::from_secs(10)),
+            temporality: otel::MetricsTemporality::Cumulative,
+        });
+        assert_eq!(
+            metric_exporter(&endpoint).unwrap().temporality(),
+            Temporality::Cumulative
+        );
+    }
+
+    #[test]
+    fn test_replicas_have_their_own_instance_id() {
+        let resource = service_resource("products", Some(1));
+        assert_eq!(
+            resource.get(&opentelemetry::Key::new(SERVICE_NAME)),
+            Some(opentelemetry::Value::from("products"))
+        );
+        assert_eq!(
+            resource.get(&opentelemetry::Key::new(SERVICE_INSTANCE_ID)),
+            Some(opentelemetry::Value::from("products-1"))
+        );
+        assert_eq!(
+            resource.get(&opentelemetry::Key::new(REPLICA)),
+            Some(opentelemetry::Value::I64(1))
+        );
+        assert_eq!(
+            service_resource("products", None).get(&opentelemetry::Key::new(SERVICE_INSTANCE_ID)),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vm_sends_baggage_with_remote_calls() {
+        let service = "
+        service frontend {
+            method main_page {
+                baggage tenant = \"acme\";
+                call products.get_products;
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, _print_rx) = mpsc::channel(5);
+        let (remote_call_tx, mut remote_call_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_baggage(BTreeMap::from([("region".to_string(), "eu".to_string())]))
+            .with_max_execution_counter(10)
+            .with_remote_call_tx(remote_call_tx);
+        assert_eq!(vm.run().await, Err(VMError::MaxExecutionCounterReached));
+
+        match remote_call_rx.recv().await.unwrap() {
+            ServiceMessage::Call { context, .. } => {
+                let mut entries: Vec<&str> = context["baggage"].split(',').collect();
+                entries.sort();
+                assert_eq!(entries, ["region=eu", "tenant=acme"]);
+            }
+            message => panic!("Expected a call, got {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vm_prints_the_baggage_of_the_caller() {
+        let service = "
+        service products {
+            method get_products {
+                print \"Fetching products for %{tenant}\";
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, mut print_rx) = mpsc::channel(5);
+        let (remote_call_tx, remote_call_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_max_execution_counter(10)
+            .with_remote_call_rx(remote_call_rx);
+        remote_call_tx
+            .send(IncomingCall {
+                id: 1,
+                instance: "products-0".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::from([("baggage".to_string(), "tenant=acme".to_string())]),
+                reply: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(vm.run().await, Err(VMError::MaxExecutionCounterReached));
+
+        assert_eq!(
+            print_rx.recv().await.unwrap().message,
+            PrintMessage::Stdout("Fetching products for acme".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prints_carry_the_span_they_ran_in() {
+        let service = "
+        service frontend {
+            method main_page {
+                print \"Main page\";
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+        let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+        let tracer = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+
+        let (print_tx, mut print_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_tracer(tracer.clone())
+            .with_max_execution_counter(12);
+        assert_eq!(vm.run().await, Err(VMError::MaxExecutionCounterReached));
+        drop(vm);
+
+        let print = print_rx.recv().await.unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        let call_span = spans
+            .iter()
+            .find(|span| span.name == "frontend/main_page")
+            .expect("Expected a span for the local call");
+        assert_eq!(print.span_context, Some(call_span.span_context.clone()));
+    }
+
+    #[tokio::test]
+    async fn test_failures_mark_their_spans_as_errors() {
+        let ast = parser::parse(&call_other_service()).unwrap();
+        let code = CodeGenerator::new(&ast.services[1]).process().unwrap();
+        let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+        let tracer = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+
+        let (print_tx, _print_rx) = mpsc::channel(10);
+        let (remote_call_tx, mut remote_call_rx) = mpsc::channel(10);
+        tokio::spawn(async move {
+            while let Some(ServiceMessage::Call { reply, .. }) = remote_call_rx.recv().await {
+                let _ = reply
+                    .unwrap()
+                    .send(Err(CallError::InjectedFault("frontend->products".into())));
+            }
+        });
+        let mut vm = VM::new(code, &ast.services[1].name, print_tx)
+            .with_tracer(tracer.clone())
+            .with_remote_call_tx(remote_call_tx)
+            .with_custom_remote_call_limit(1)
+            .with_max_execution_counter(100);
+        assert_eq!(vm.run().await, Err(VMError::RemoteCallLimitReached));
+        drop(vm);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let failed: Vec<(SpanKind, Status)> = spans
+            .iter()
+            .filter(|span| matches!(span.status, Status::Error { .. }))
+            .map(|span| {
+                assert_eq!(span.events.events[0].name, "exception");
+                (span.span_kind.clone(), span.status.clone())
+            })
+            .collect();
+        let fault = CallError::InjectedFault("frontend->products".into());
+        assert_eq!(failed.len(), 2);
+        assert!(failed.contains(&(SpanKind::Client, Status::error(fault.to_string()))));
+        assert!(failed.contains(&(
+            SpanKind::Internal,
+            Status::error(VMError::RemoteCallLimitReached.to_string())
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_spans_are_named_after_the_template() {
+        let ast = parser::parse(&call_other_service()).unwrap();
+        let code = CodeGenerator::new(&ast.services[1]).process().unwrap();
+        let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+        let tracer = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+
+        let (print_tx, _print_rx) = mpsc::channel(10);
+        let (remote_call_tx, mut remote_call_rx) = mpsc::channel(10);
+        tokio::spawn(async move {
+            while let Some(ServiceMessage::Call { reply, .. }) = remote_call_rx.recv().await {
+                let _ = reply.unwrap().send(Ok(()));
+            }
+        });
+        let mut vm = VM::new(code, &ast.services[1].name, print_tx)
+            .with_tracer(tracer.clone())
+            .with_remote_call_tx(remote_call_tx)
+            .with_span_name("HTTP GET /{service}/{method}".to_string())
+            .with_max_execution_counter(20);
+        let _ = vm.run().await;
+        drop(vm);
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+        assert!(names.contains(&"HTTP GET /frontend/start_context"));
+        assert!(names.contains(&"HTTP GET /frontend/main_page"));
+    }
+
     #[tokio::test]
     async fn test_vm_with_remote_call_tx() {
         let service = call_other_service();
@@ -1180,14 +2417,11 @@ mod tests {
                 assert_eq!(remote_call_rx.len(), 1);
                 let remote_call_messages = remote_call_rx.recv().await.unwrap();
                 match remote_call_messages {
-                    ServiceMessage::Call {
-                        to,
-                        function,
-                        context: _,
-                    } => {
+                    ServiceMessage::Call { to, function, .. } => {
                         assert_eq!(to, "products".to_string());
                         assert_eq!(function, "get_products".to_string());
                     }
+                    message => panic!("Expected a call, got {:?}", message),
                 }
             }
         }
@@ -1207,7 +2441,13 @@ mod tests {
             .with_remote_call_rx(remote_call_rx);
 
         remote_call_tx
-            .send("get_products".to_string())
+            .send(IncomingCall {
+                id: 1,
+                instance: "products-0".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: None,
+            })
             .await
             .unwrap();
 
@@ -1218,7 +2458,7 @@ mod tests {
             Err(e) => {
                 assert_eq!(e, VMError::MaxExecutionCounterReached);
                 assert_eq!(print_rx.len(), 2);
-                let print_messages = print_rx.recv().await.unwrap();
+                let print_messages = print_rx.recv().await.unwrap().message;
                 assert_eq!(
                     print_messages,
                     PrintMessage::Stdout("Fetching product orders 12345".to_string())
@@ -1227,6 +2467,830 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_vm_keeps_running_after_a_call_to_an_unknown_function() {
+        let service = call_other_service();
+        let ast = parser::parse(&service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, mut print_rx) = mpsc::channel(5);
+        let (remote_call_tx, remote_call_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_max_execution_counter(15)
+            .with_custom_remote_call_limit(1)
+            .with_remote_call_rx(remote_call_rx);
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        for (function, reply) in [("nope", Some(reply_tx)), ("get_products", None)] {
+            remote_call_tx
+                .send(IncomingCall {
+                    id: 1,
+                    instance: "products-0".to_string(),
+                    function: function.to_string(),
+                    context: HashMap::new(),
+                    reply,
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(vm.run().await, Err(VMError::MaxExecutionCounterReached));
+        assert_eq!(
+            reply_rx.await.unwrap(),
+            Err(CallError::UnknownFunction("nope".to_string()))
+        );
+        assert_eq!(
+            print_rx.recv().await.unwrap().message,
+            PrintMessage::Stdout("Fetching product orders 12345".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vm_propagates_trace_context_to_callee() {
+        let service = call_other_service();
+        let ast = parser::parse(&service).unwrap();
+        let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+        let tracer = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+
+        let (print_tx, _print_rx) = mpsc::channel(5);
+        let (remote_call_tx, mut remote_call_rx) = mpsc::channel(10);
+        let code = CodeGenerator::new(&ast.services[1]).process().unwrap();
+        let mut caller = VM::new(code, &ast.services[1].name, print_tx.clone())
+            .with_tracer(tracer.clone())
+            .with_max_execution_counter(7)
+            .with_remote_call_tx(remote_call_tx);
+        caller.run().await.unwrap_err();
+        drop(caller);
+
+        let ServiceMessage::Call {
+            id,
+            function,
+            context,
+            reply,
+            ..
+        } = remote_call_rx.recv().await.unwrap()
+        else {
+            panic!("Expected a call");
+        };
+        assert!(context.contains_key("traceparent"));
+
+        let (incoming_tx, incoming_rx) = mpsc::channel(10);
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+        let mut callee = VM::new(code, &ast.services[0].name, print_tx)
+            .with_tracer(tracer.clone())
+            .with_max_execution_counter(5)
+            .with_remote_call_rx(incoming_rx);
+        incoming_tx
+            .send(IncomingCall {
+                id,
+                instance: "products-1".to_string(),
+                function,
+                context,
+                reply,
+            })
+            .await
+            .unwrap();
+        callee.run().await.unwrap_err();
+        drop(callee);
+
+        // The client span ends in the background once the reply channel closes
+        let mut spans = exporter.get_finished_spans().unwrap();
+        for _ in 0..100 {
+            if spans.iter().any(|span| span.span_kind == SpanKind::Client) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            spans = exporter.get_finished_spans().unwrap();
+        }
+        let client_span = spans
+            .iter()
+            .find(|span| span.span_kind == SpanKind::Client)
+            .expect("Expected a client span for the remote call");
+        let server_span = spans
+            .iter()
+            .find(|span| span.name == "products/get_products")
+            .expect("Expected a server span for the incoming call");
+        assert_eq!(server_span.span_kind, SpanKind::Server);
+        assert!(server_span
+            .attributes
+            .contains(&KeyValue::new(SERVICE_INSTANCE_ID, "products-1")));
+        for span in [client_span, server_span] {
+            assert!(span
+                .attributes
+                .contains(&KeyValue::new(RPC_SYSTEM, "mustermann")));
+            assert!(span
+                .attributes
+                .contains(&KeyValue::new(RPC_SERVICE, "products")));
+            assert!(span
+                .attributes
+                .contains(&KeyValue::new(RPC_METHOD, "get_products")));
+        }
+        assert!(client_span
+            .attributes
+            .contains(&KeyValue::new(PEER_SERVICE, "products")));
+        assert!(!server_span
+            .attributes
+            .iter()
+            .any(|attribute| attribute.key.as_str() == PEER_SERVICE));
+        assert_eq!(
+            server_span.parent_span_id,
+            client_span.span_context.span_id()
+        );
+        assert_eq!(
+            server_span.span_context.trace_id(),
+            client_span.span_context.trace_id()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multi_hop_calls_form_one_trace() {
+        let service = "
+        service inventory {
+            method count {
+                print \"Counting\";
+            }
+        }
+
+        service products {
+            method get_products {
+                call inventory.count;
+            }
+        }
+
+        service frontend {
+            method main_page {
+                call products.get_products;
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+        let tracer = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let (print_tx, _print_rx) = mpsc::channel(5);
+
+        // Relays the first call a VM makes to the next VM, as the coordinator would
+        async fn relay(
+            remote_call_rx: &mut mpsc::Receiver<ServiceMessage>,
+            incoming_tx: &mpsc::Sender<IncomingCall>,
+        ) {
+            let ServiceMessage::Call {
+                id,
+                to,
+                function,
+                context,
+                reply,
+                ..
+            } = remote_call_rx.recv().await.unwrap()
+            else {
+                panic!("Expected a call");
+            };
+            incoming_tx
+                .send(IncomingCall {
+                    id,
+                    instance: format!("{}-0", to),
+                    function,
+                    context,
+                    reply,
+                })
+                .await
+                .unwrap();
+        }
+
+        let (frontend_tx, mut frontend_rx) = mpsc::channel(10);
+        let code = CodeGenerator::new(&ast.services[2]).process().unwrap();
+        let mut frontend = VM::new(code, &ast.services[2].name, print_tx.clone())
+            .with_tracer(tracer.clone())
+            .with_max_execution_counter(7)
+            .with_remote_call_tx(frontend_tx);
+        frontend.run().await.unwrap_err();
+        drop(frontend);
+
+        let (products_incoming_tx, products_incoming_rx) = mpsc::channel(10);
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        relay(&mut frontend_rx, &products_incoming_tx).await;
+        let code = CodeGenerator::new(&ast.services[1]).process().unwrap();
+        let mut products = VM::new(code, &ast.services[1].name, print_tx.clone())
+            .with_tracer(tracer.clone())
+            .with_max_execution_counter(8)
+            .with_remote_call_rx(products_incoming_rx)
+            .with_remote_call_tx(products_tx);
+        products.run().await.unwrap_err();
+        drop(products);
+
+        let (inventory_incoming_tx, inventory_incoming_rx) = mpsc::channel(10);
+        relay(&mut products_rx, &inventory_incoming_tx).await;
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+        let mut inventory = VM::new(code, &ast.services[0].name, print_tx)
+            .with_tracer(tracer.clone())
+            .with_max_execution_counter(8)
+            .with_remote_call_rx(inventory_incoming_rx);
+        inventory.run().await.unwrap_err();
+        drop(inventory);
+
+        // Client spans end in the background once the reply channel closes
+        let mut spans = exporter.get_finished_spans().unwrap();
+        for _ in 0..100 {
+            let client_spans = spans
+                .iter()
+                .filter(|span| span.span_kind == SpanKind::Client)
+                .count();
+            if client_spans == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            spans = exporter.get_finished_spans().unwrap();
+        }
+        let span = |name: &str, kind: SpanKind| {
+            spans
+                .iter()
+                .find(|span| span.name == name && span.span_kind == kind)
+                .unwrap_or_else(|| panic!("Expected a {:?} span {}", kind, name))
+        };
+        let frontend_client = span("frontend/main_page", SpanKind::Client);
+        let products_server = span("products/get_products", SpanKind::Server);
+        let products_client = span("products/get_products", SpanKind::Client);
+        let inventory_server = span("inventory/count", SpanKind::Server);
+
+        let hops = [
+            (frontend_client, products_server),
+            (products_server, products_client),
+            (products_client, inventory_server),
+        ];
+        for (parent, child) in hops {
+            assert_eq!(child.parent_span_id, parent.span_context.span_id());
+            assert_eq!(
+                child.span_context.trace_id(),
+                frontend_client.span_context.trace_id()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vm_prints_failed_call_to_stderr() {
+        let service = call_other_service();
+        let ast = parser::parse(&service).unwrap();
+        let code = CodeGenerator::new(&ast.services[1]).process().unwrap();
+
+        let (print_tx, mut print_rx) = mpsc::channel(5);
+        let (remote_call_tx, mut remote_call_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[1].name, print_tx)
+            .with_max_execution_counter(7)
+            .with_remote_call_tx(remote_call_tx);
+        vm.run().await.unwrap_err();
+
+        let Some(ServiceMessage::Call {
+            reply: Some(reply), ..
+        }) = remote_call_rx.recv().await
+        else {
+            panic!("Expected a call with a reply channel");
+        };
+        reply
+            .send(Err(CallError::InjectedFault(
+                "frontend->products".to_string(),
+            )))
+            .unwrap();
+
+        assert_eq!(
+            print_rx.recv().await.unwrap().message,
+            PrintMessage::Stderr(
+                "Call to products.get_products failed: Injected fault on frontend->products"
+                    .to_string()
+            )
+        );
+        assert_eq!(vm.stats().failed_remote_calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_vm_rejects_queued_calls_once_stopped() {
+        let service = "
+        service products {
+            method get_products {
+                print \"Fetching products\";
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, _print_rx) = mpsc::channel(5);
+        let (incoming_tx, incoming_rx) = mpsc::channel(10);
+        // Stops before it takes the first call
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_max_execution_counter(1)
+            .with_remote_call_rx(incoming_rx);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        incoming_tx
+            .send(IncomingCall {
+                id: 1,
+                instance: "products-0".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        vm.run().await.unwrap_err();
+
+        assert_eq!(
+            reply_rx.await.unwrap(),
+            Err(CallError::ServiceUnavailable("products".to_string()))
+        );
+        assert!(incoming_tx.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_killed_vm_rejects_calls_until_restarted() {
+        let service = "
+        service products {
+            method get_products {
+                print \"Fetching products\";
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, mut print_rx) = mpsc::channel(5);
+        let (incoming_tx, incoming_rx) = mpsc::channel(10);
+        let (suspension_tx, suspension_rx) = watch::channel(true);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_max_execution_counter(1000)
+            .with_interrupt_interval(Duration::from_millis(10))
+            .with_remote_call_rx(incoming_rx)
+            .with_suspension(suspension_rx);
+        let call = |id| {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let call = IncomingCall {
+                id,
+                instance: "products-0".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            };
+            (call, reply_rx)
+        };
+        let (killed_call, killed_reply) = call(1);
+        incoming_tx.send(killed_call).await.unwrap();
+        let vm_handle = tokio::spawn(async move { vm.run().await });
+
+        assert_eq!(
+            killed_reply.await.unwrap(),
+            Err(CallError::ServiceUnavailable("products".to_string()))
+        );
+        suspension_tx.send(false).unwrap();
+        let (restarted_call, restarted_reply) = call(2);
+        incoming_tx.send(restarted_call).await.unwrap();
+        assert_eq!(restarted_reply.await.unwrap(), Ok(()));
+        assert!(matches!(
+            print_rx.recv().await.map(|print| print.message),
+            Some(PrintMessage::Stdout(message)) if message == "Fetching products"
+        ));
+        vm_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_replayed_vm_prints_what_the_recorded_one_did() {
+        let service = "
+        service products {
+            method get_products {
+                print \"Fetching product %s\" with [\"1\", \"2\", \"3\", \"4\", \"5\"];
+                print \"Checked stock\" severity [info: 50, error: 50];
+            }
+
+            loop {
+                call get_products;
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "mustermann-vm-recording-{}.jsonl",
+            std::process::id()
+        ));
+        let run = |decisions: Decisions| {
+            let code = code.clone();
+            async move {
+                let (print_tx, mut print_rx) = mpsc::channel(1000);
+                let mut vm = VM::new(code, "products", print_tx)
+                    .with_max_execution_counter(200)
+                    .with_decisions(decisions);
+                vm.run().await.unwrap_err();
+                drop(vm);
+                let mut prints = Vec::new();
+                while let Some(print) = print_rx.recv().await {
+                    prints.push(print.message);
+                }
+                prints
+            }
+        };
+
+        let recorder = crate::recording::Recorder::create(&path).unwrap();
+        let recorded = run(Decisions::record("products-0", recorder.clone())).await;
+        recorder.flush();
+        let recording = crate::recording::Recording::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let replayed = run(recording.decisions("products-0")).await;
+
+        assert!(recorded.len() > 10);
+        assert_eq!(replayed, recorded);
+    }
+
+    #[tokio::test]
+    async fn test_killed_vm_stops_its_loop_until_restarted() {
+        let service = "
+        service backup {
+            method run {
+                print \"Backing up\";
+                sleep 10ms;
+            }
+
+            loop {
+                call run;
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, mut print_rx) = mpsc::channel(100);
+        let (suspension_tx, suspension_rx) = watch::channel(true);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_interrupt_interval(Duration::from_millis(10))
+            .with_suspension(suspension_rx);
+        let vm_handle = tokio::spawn(async move { vm.run().await });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(print_rx.try_recv().is_err());
+        suspension_tx.send(false).unwrap();
+        assert!(print_rx.recv().await.is_some());
+        suspension_tx.send(true).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        while print_rx.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(print_rx.try_recv().is_err());
+        vm_handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_vm_answers_health_checks_at_check_interrupt() {
+        let service = "
+        service products {
+            method get_products {
+                print \"Fetching products\";
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, _print_rx) = mpsc::channel(5);
+        let (incoming_tx, incoming_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_max_execution_counter(10)
+            .with_remote_call_rx(incoming_rx);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        incoming_tx
+            .send(IncomingCall {
+                id: 7,
+                instance: "products-0".to_string(),
+                function: HEALTH_CHECK.to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        vm.run().await.unwrap_err();
+
+        assert_eq!(reply_rx.await.unwrap(), Ok(()));
+        assert_eq!(vm.stats().incoming_calls, 0);
+    }
+
+    #[tokio::test]
+    async fn test_vm_answers_health_checks_while_looping() {
+        let service = "
+        service frontend {
+            method main_page {
+                print \"Rendering\";
+            }
+            loop {
+                call main_page;
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, mut print_rx) = mpsc::channel(5);
+        tokio::spawn(async move { while print_rx.recv().await.is_some() {} });
+        let (incoming_tx, incoming_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_max_execution_counter(100)
+            .with_remote_call_rx(incoming_rx);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        incoming_tx
+            .send(IncomingCall {
+                id: 7,
+                instance: "frontend-0".to_string(),
+                function: HEALTH_CHECK.to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        vm.run().await.unwrap_err();
+
+        assert_eq!(reply_rx.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_vm_replies_once_incoming_call_returns() {
+        let service = "
+        service products {
+            method get_products {
+                print \"Fetching products\";
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, _print_rx) = mpsc::channel(5);
+        let (incoming_tx, incoming_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_max_execution_counter(10)
+            .with_remote_call_rx(incoming_rx);
+        let (reply_tx, reply_rx) = oneshot::channel();
+        incoming_tx
+            .send(IncomingCall {
+                id: 7,
+                instance: "products-0".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        vm.run().await.unwrap_err();
+
+        assert_eq!(reply_rx.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_vm_with_remote_call_limit() {
+        let service = call_other_service();
+        let ast = parser::parse(&service).unwrap();
+        let code = CodeGenerator::new(&ast.services[1]).process().unwrap();
+
+        let (print_tx, _print_rx) = mpsc::channel(5);
+        let (remote_call_tx, remote_call_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code.clone(), &ast.services[1].name, print_tx)
+            .with_max_execution_counter(100)
+            .with_custom_remote_call_limit(2)
+            .with_remote_call_tx(remote_call_tx);
+
+        match vm.run().await {
+            Ok(_) => panic!("VM should have reached the remote call limit"),
+            Err(e) => {
+                assert_eq!(e, VMError::RemoteCallLimitReached);
+                assert_eq!(remote_call_rx.len(), 2);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vm_handles_incoming_call_without_waiting_for_interval() {
+        let service = call_other_service();
+        let ast = parser::parse(&service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, mut print_rx) = mpsc::channel(5);
+        let (remote_call_tx, remote_call_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code.clone(), &ast.services[0].name, print_tx)
+            .with_max_execution_counter(10)
+            .with_interrupt_interval(Duration::from_secs(10))
+            .with_remote_call_rx(remote_call_rx);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            remote_call_tx
+                .send(IncomingCall {
+                    id: 1,
+                    instance: "products-0".to_string(),
+                    function: "get_products".to_string(),
+                    context: HashMap::new(),
+                    reply: None,
+                })
+                .await
+                .unwrap();
+        });
+
+        let start = std::time::Instant::now();
+        let print_messages = tokio::spawn(async move {
+            vm.run().await.unwrap_err();
+            print_rx.recv().await.unwrap().message
+        });
+        let print_messages = print_messages.await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            print_messages,
+            PrintMessage::Stdout("Fetching product orders 12345".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vm_runs_shutdown_block_when_shutdown_is_requested() {
+        let service = "
+        service frontend {
+            method main_page {
+                print \"Main page\";
+            }
+
+            loop {
+                call main_page;
+            }
+
+            shutdown {
+                print \"Shutting down\";
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (print_tx, mut print_rx) = mpsc::channel(100);
+        let mut vm = VM::new(code.clone(), &ast.services[0].name, print_tx)
+            .with_max_execution_counter(1000)
+            .with_shutdown_flag(shutdown.clone());
+
+        let consumer = tokio::spawn(async move {
+            let mut messages = Vec::new();
+            while let Some(Print { message, .. }) = print_rx.recv().await {
+                if messages.len() == 3 {
+                    shutdown.store(true, Ordering::SeqCst);
+                }
+                messages.push(message);
+            }
+            messages
+        });
+
+        vm.run().await.unwrap();
+        let stats = vm.stats();
+        drop(vm);
+        let messages = consumer.await.unwrap();
+        assert_eq!(
+            messages.last(),
+            Some(&PrintMessage::Stdout("Shutting down".to_string()))
+        );
+        assert_eq!(stats.stdout, messages.len());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_stops_the_loop_after_its_iterations() {
+        let service = "
+        service frontend {
+            method main_page {
+                print \"Main page\";
+                sleep 10s;
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let dry_run = Arc::new(DryRun::new(3));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (print_tx, print_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_interrupt_interval(Duration::from_millis(1))
+            .with_shutdown_flag(shutdown.clone())
+            .with_dry_run(dry_run.clone());
+        let vm_handle = tokio::spawn(async move { vm.run().await });
+
+        dry_run.settled().await;
+        shutdown.store(true, Ordering::SeqCst);
+        vm_handle.await.unwrap().unwrap();
+        assert_eq!(print_rx.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_vm_stops_on_shutdown_without_shutdown_block() {
+        let service = service();
+        let ast = parser::parse(&service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, print_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code.clone(), &ast.services[0].name, print_tx)
+            .with_max_execution_counter(10)
+            .with_shutdown_flag(Arc::new(AtomicBool::new(true)));
+
+        vm.run().await.unwrap();
+        assert!(print_rx.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_vm_waits_for_the_load_profile() {
+        let service = "
+        service frontend {
+            method main_page {
+                print \"Main page\";
+            }
+
+            loop {
+                call main_page;
+            }
+
+            shutdown {
+                print \"Shutting down\";
+            }
+        }
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+        // The load only starts after a minute
+        let profile = parser::parse_load_profile("hold 1m, ramp 10->10 rps over 1m").unwrap();
+        let load = Arc::new(LoadShaper::new().with_profile(profile));
+
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (print_tx, mut print_rx) = mpsc::channel(10);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_max_execution_counter(1000)
+            .with_interrupt_interval(Duration::from_millis(10))
+            .with_shutdown_flag(shutdown.clone())
+            .with_load(load);
+        let stop = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            shutdown.store(true, Ordering::SeqCst);
+        });
+
+        vm.run().await.unwrap();
+        stop.await.unwrap();
+        drop(vm);
+        let print = print_rx.recv().await.unwrap();
+        assert_eq!(
+            print.message,
+            PrintMessage::Stdout("Shutting down".to_string())
+        );
+        assert!(print_rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_vm_prints_errors_during_an_incident() {
+        let service = "
+        service frontend {
+            method main_page {
+                print \"Main page\";
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+        incident at 0s: frontend error_rate 100%->100% over 1m;
+        incident at 0s: products error_rate 0%->0% over 1m;
+        ";
+        let ast = parser::parse(service).unwrap();
+        let code = CodeGenerator::new(&ast.services[0]).process().unwrap();
+
+        let (print_tx, mut print_rx) = mpsc::channel(100);
+        let mut vm = VM::new(code, &ast.services[0].name, print_tx)
+            .with_max_execution_counter(50)
+            .with_incidents(&ast.incidents, std::time::Instant::now());
+
+        assert_eq!(vm.run().await, Err(VMError::MaxExecutionCounterReached));
+        assert!(vm.stats.stderr > 0);
+        assert_eq!(vm.stats.stdout, 0);
+        drop(vm);
+        while let Some(print) = print_rx.recv().await {
+            assert_eq!(print.message, PrintMessage::Stderr("Main page".to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_vm_from_invalid_bytecode() {
+        let (print_tx, _print_rx) = mpsc::channel(1);
+        match VM::from_bytecode(&[0xff], "test", print_tx) {
+            Ok(_) => panic!("VM should have rejected the bytecode"),
+            Err(e) => assert_eq!(
+                e,
+                VMError::InvalidBytecode(DecodeError::InvalidInstruction(0, 0xff))
+            ),
+        }
+    }
+
     #[tokio::test]
     async fn test_vm_creates_new_stackframe_on_call() {
         let code = vec![
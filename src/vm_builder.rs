@@ -0,0 +1,460 @@
+//! Builds a [`VM`] from its code and configuration, and rejects configurations that cannot work,
+//! like a program that makes remote calls without a coordinator to send them to.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tokio::sync::{mpsc, watch};
+
+use crate::code_gen::instruction::Instruction;
+use crate::dry_run::DryRun;
+use crate::extension::ExtensionRegistry;
+use crate::load::LoadShaper;
+use crate::parser::{Incident, LatencySpike};
+use crate::recording::Decisions;
+use crate::vm::{Print, VM};
+use crate::vm_coordinator::{IncomingCall, ServiceMessage};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmConfigError {
+    /// The print queue needs room for at least one message
+    ZeroPrintQueueSize,
+    /// The incoming call queue needs room for at least one call
+    ZeroIncomingCallQueueSize,
+    /// The VM receives calls, but is not connected to a coordinator to make its own
+    IncomingCallsWithoutRemoteCallSender,
+    /// The program makes remote calls, but there is nowhere to send them
+    RemoteCallWithoutSender,
+    /// A tracer is set, but the program never starts a trace context and receives no calls that carry one
+    TracerWithoutContext,
+}
+
+impl std::error::Error for VmConfigError {}
+
+impl std::fmt::Display for VmConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmConfigError::ZeroPrintQueueSize => write!(f, "Print queue size must be at least 1"),
+            VmConfigError::ZeroIncomingCallQueueSize => {
+                write!(f, "Incoming call queue size must be at least 1")
+            }
+            VmConfigError::IncomingCallsWithoutRemoteCallSender => {
+                write!(f, "VM receives calls but has no remote call sender")
+            }
+            VmConfigError::RemoteCallWithoutSender => {
+                write!(
+                    f,
+                    "Program makes remote calls but the VM has no remote call sender"
+                )
+            }
+            VmConfigError::TracerWithoutContext => {
+                write!(
+                    f,
+                    "Tracer is set but the program never starts a trace context"
+                )
+            }
+        }
+    }
+}
+
+/// The channel ends of a built VM that the caller wires up
+pub struct VmChannels {
+    /// Receives everything the VM prints
+    pub print_rx: mpsc::Receiver<Print>,
+    /// Delivers calls to the VM, if it accepts incoming calls
+    pub incoming_call_tx: Option<mpsc::Sender<IncomingCall>>,
+}
+
+/// Collects the configuration of a VM and checks that it fits together before the VM is built
+pub struct VmBuilder {
+    code: Vec<Instruction>,
+    service_name: String,
+    print_queue_size: usize,
+    incoming_call_queue_size: Option<usize>,
+    remote_call_tx: Option<mpsc::Sender<ServiceMessage>>,
+    remote_call_limit: Option<usize>,
+    max_execution_counter: Option<usize>,
+    interrupt_interval: Option<Duration>,
+    shutdown: Option<Arc<AtomicBool>>,
+    suspension: Option<watch::Receiver<bool>>,
+    tracer: Option<SdkTracerProvider>,
+    meter_provider: Option<SdkMeterProvider>,
+    legacy_duration_gauges: bool,
+    span_events: bool,
+    span_name: Option<String>,
+    baggage: BTreeMap<String, String>,
+    instruction_counter: Option<Arc<AtomicU64>>,
+    dry_run: Option<Arc<DryRun>>,
+    load: Option<Arc<LoadShaper>>,
+    incidents: Option<(Vec<Incident>, Instant)>,
+    spike: Option<LatencySpike>,
+    started: Option<Instant>,
+    started_at: Option<SystemTime>,
+    extensions: Option<ExtensionRegistry>,
+    decisions: Decisions,
+}
+
+impl VmBuilder {
+    pub fn new(code: Vec<Instruction>, service_name: &str) -> Self {
+        Self {
+            code,
+            service_name: service_name.to_string(),
+            print_queue_size: 1,
+            incoming_call_queue_size: None,
+            remote_call_tx: None,
+            remote_call_limit: None,
+            max_execution_counter: None,
+            interrupt_interval: None,
+            shutdown: None,
+            suspension: None,
+            tracer: None,
+            meter_provider: None,
+            legacy_duration_gauges: false,
+            span_events: false,
+            span_name: None,
+            baggage: BTreeMap::new(),
+            instruction_counter: None,
+            dry_run: None,
+            load: None,
+            incidents: None,
+            spike: None,
+            started: None,
+            started_at: None,
+            extensions: None,
+            decisions: Decisions::default(),
+        }
+    }
+
+    pub fn with_print_queue_size(mut self, size: usize) -> Self {
+        self.print_queue_size = size;
+        self
+    }
+
+    /// Lets the VM receive calls from other services, buffering up to `queue_size` calls
+    pub fn with_incoming_calls(mut self, queue_size: usize) -> Self {
+        self.incoming_call_queue_size = Some(queue_size);
+        self
+    }
+
+    pub fn with_remote_call_tx(mut self, remote_call_tx: mpsc::Sender<ServiceMessage>) -> Self {
+        self.remote_call_tx = Some(remote_call_tx);
+        self
+    }
+
+    pub fn with_remote_call_limit(mut self, limit: usize) -> Self {
+        self.remote_call_limit = Some(limit);
+        self
+    }
+
+    pub fn with_max_execution_counter(mut self, max_execution_counter: usize) -> Self {
+        self.max_execution_counter = Some(max_execution_counter);
+        self
+    }
+
+    pub fn with_interrupt_interval(mut self, interrupt_interval: Duration) -> Self {
+        self.interrupt_interval = Some(interrupt_interval);
+        self
+    }
+
+    pub fn with_shutdown_flag(mut self, shutdown: Arc<AtomicBool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Records the decisions the VM leaves to chance, or replays them
+    pub fn with_decisions(mut self, decisions: Decisions) -> Self {
+        self.decisions = decisions;
+        self
+    }
+
+    pub fn with_suspension(mut self, suspension: watch::Receiver<bool>) -> Self {
+        self.suspension = Some(suspension);
+        self
+    }
+
+    pub fn with_tracer(mut self, tracer: SdkTracerProvider) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    pub fn with_meter_provider(mut self, meter_provider: SdkMeterProvider) -> Self {
+        self.meter_provider = Some(meter_provider);
+        self
+    }
+
+    pub fn with_legacy_duration_gauges(mut self, enabled: bool) -> Self {
+        self.legacy_duration_gauges = enabled;
+        self
+    }
+
+    pub fn with_span_events(mut self, enabled: bool) -> Self {
+        self.span_events = enabled;
+        self
+    }
+
+    pub fn with_span_name(mut self, template: String) -> Self {
+        self.span_name = Some(template);
+        self
+    }
+
+    pub fn with_baggage(mut self, baggage: BTreeMap<String, String>) -> Self {
+        self.baggage = baggage;
+        self
+    }
+
+    pub fn with_instruction_counter(mut self, counter: Arc<AtomicU64>) -> Self {
+        self.instruction_counter = Some(counter);
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: Arc<DryRun>) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Paces the loop along the load profile of the run
+    pub fn with_load(mut self, load: Arc<LoadShaper>) -> Self {
+        self.load = Some(load);
+        self
+    }
+
+    /// Turns prints to stdout into errors while the incidents of the service last, timed from
+    /// `started`, the start of the run
+    pub fn with_incidents(mut self, incidents: Vec<Incident>, started: Instant) -> Self {
+        self.incidents = Some((incidents, started));
+        self
+    }
+
+    /// Makes a share of the sleeps and handled calls of the service extremely slow
+    pub fn with_spike(mut self, spike: LatencySpike) -> Self {
+        self.spike = Some(spike);
+        self
+    }
+
+    /// Times the drift of the generators in print templates and the load profile from `started`,
+    /// the start of the run
+    pub fn with_started(mut self, started: Instant) -> Self {
+        self.started = Some(started);
+        self
+    }
+
+    /// Timestamps access logs from `started_at` on, the wall-clock time of the start of the run
+    pub fn with_started_at(mut self, started_at: SystemTime) -> Self {
+        self.started_at = Some(started_at);
+        self
+    }
+
+    /// Runs the extension instructions of the code with the handlers of `extensions`
+    pub fn with_extensions(mut self, extensions: ExtensionRegistry) -> Self {
+        self.extensions = Some(extensions);
+        self
+    }
+
+    fn validate(&self) -> Result<(), VmConfigError> {
+        if self.print_queue_size == 0 {
+            return Err(VmConfigError::ZeroPrintQueueSize);
+        }
+        if self.incoming_call_queue_size == Some(0) {
+            return Err(VmConfigError::ZeroIncomingCallQueueSize);
+        }
+        if self.incoming_call_queue_size.is_some() && self.remote_call_tx.is_none() {
+            return Err(VmConfigError::IncomingCallsWithoutRemoteCallSender);
+        }
+        let makes_remote_calls = self
+            .code
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::RemoteCall));
+        if makes_remote_calls && self.remote_call_tx.is_none() {
+            return Err(VmConfigError::RemoteCallWithoutSender);
+        }
+        let starts_context = self
+            .code
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::StartContext));
+        if self.tracer.is_some() && !starts_context && self.incoming_call_queue_size.is_none() {
+            return Err(VmConfigError::TracerWithoutContext);
+        }
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<(VM, VmChannels), VmConfigError> {
+        self.validate()?;
+
+        let (print_tx, print_rx) = mpsc::channel(self.print_queue_size);
+        let mut vm = VM::new(self.code, &self.service_name, print_tx)
+            .with_legacy_duration_gauges(self.legacy_duration_gauges)
+            .with_span_events(self.span_events)
+            .with_baggage(self.baggage)
+            .with_decisions(self.decisions);
+
+        let mut incoming_call_tx = None;
+        if let Some(queue_size) = self.incoming_call_queue_size {
+            let (tx, rx) = mpsc::channel(queue_size);
+            vm = vm.with_remote_call_rx(rx);
+            incoming_call_tx = Some(tx);
+        }
+        if let Some(remote_call_tx) = self.remote_call_tx {
+            vm = vm.with_remote_call_tx(remote_call_tx);
+        }
+        if let Some(counter) = self.instruction_counter {
+            vm = vm.with_instruction_counter(counter);
+        }
+        if let Some(span_name) = self.span_name {
+            vm = vm.with_span_name(span_name);
+        }
+        if let Some(limit) = self.remote_call_limit {
+            vm = vm.with_custom_remote_call_limit(limit);
+        }
+        if let Some(max_execution_counter) = self.max_execution_counter {
+            vm = vm.with_max_execution_counter(max_execution_counter);
+        }
+        if let Some(interrupt_interval) = self.interrupt_interval {
+            vm = vm.with_interrupt_interval(interrupt_interval);
+        }
+        if let Some(shutdown) = self.shutdown {
+            vm = vm.with_shutdown_flag(shutdown);
+        }
+        if let Some(suspension) = self.suspension {
+            vm = vm.with_suspension(suspension);
+        }
+        if let Some(tracer) = self.tracer {
+            vm = vm.with_tracer(tracer);
+        }
+        if let Some(meter_provider) = self.meter_provider {
+            vm = vm.with_meter_provider(meter_provider);
+        }
+        if let Some(dry_run) = self.dry_run {
+            vm = vm.with_dry_run(dry_run);
+        }
+        if let Some(load) = self.load {
+            vm = vm.with_load(load);
+        }
+        if let Some((incidents, started)) = self.incidents {
+            vm = vm.with_incidents(&incidents, started);
+        }
+        if let Some(spike) = self.spike {
+            vm = vm.with_spike(spike);
+        }
+        if let Some(started) = self.started {
+            vm = vm.with_started(started);
+        }
+        if let Some(started_at) = self.started_at {
+            vm = vm.with_started_at(started_at);
+        }
+        if let Some(extensions) = self.extensions {
+            vm = vm.with_extensions(extensions);
+        }
+
+        Ok((
+            vm,
+            VmChannels {
+                print_rx,
+                incoming_call_tx,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{code_gen::CodeGenerator, parser, vm::PrintMessage};
+
+    fn code(service: &str, index: usize) -> Vec<Instruction> {
+        let ast = parser::parse(service).unwrap();
+        CodeGenerator::new(&ast.services[index]).process().unwrap()
+    }
+
+    fn call_other_service() -> &'static str {
+        "
+        service products {
+            method get_products {
+                print \"Fetching products\";
+            }
+        }
+
+        service frontend {
+            method main_page {
+                call products.get_products;
+            }
+
+            loop {
+                call main_page;
+            }
+        }
+        "
+    }
+
+    #[tokio::test]
+    async fn test_build_connected_vm() {
+        let (remote_call_tx, _remote_call_rx) = mpsc::channel(1);
+        let (mut vm, mut channels) = VmBuilder::new(code(call_other_service(), 0), "products")
+            .with_incoming_calls(1)
+            .with_remote_call_tx(remote_call_tx)
+            .with_max_execution_counter(10)
+            .build()
+            .unwrap();
+
+        channels
+            .incoming_call_tx
+            .unwrap()
+            .send(IncomingCall {
+                id: 1,
+                instance: "products-0".to_string(),
+                function: "get_products".to_string(),
+                context: Default::default(),
+                reply: None,
+            })
+            .await
+            .unwrap();
+        vm.run().await.unwrap_err();
+        assert_eq!(
+            channels.print_rx.recv().await.unwrap().message,
+            PrintMessage::Stdout("Fetching products".to_string())
+        );
+    }
+
+    #[test]
+    fn test_zero_queue_sizes() {
+        let result = VmBuilder::new(Vec::new(), "test")
+            .with_print_queue_size(0)
+            .build();
+        assert_eq!(result.err(), Some(VmConfigError::ZeroPrintQueueSize));
+
+        let (remote_call_tx, _remote_call_rx) = mpsc::channel(1);
+        let result = VmBuilder::new(Vec::new(), "test")
+            .with_remote_call_tx(remote_call_tx)
+            .with_incoming_calls(0)
+            .build();
+        assert_eq!(result.err(), Some(VmConfigError::ZeroIncomingCallQueueSize));
+    }
+
+    #[test]
+    fn test_incoming_calls_without_remote_call_sender() {
+        let result = VmBuilder::new(Vec::new(), "test")
+            .with_incoming_calls(1)
+            .build();
+        assert_eq!(
+            result.err(),
+            Some(VmConfigError::IncomingCallsWithoutRemoteCallSender)
+        );
+    }
+
+    #[test]
+    fn test_remote_call_without_sender() {
+        let result = VmBuilder::new(code(call_other_service(), 1), "frontend").build();
+        assert_eq!(result.err(), Some(VmConfigError::RemoteCallWithoutSender));
+    }
+
+    #[test]
+    fn test_tracer_without_context() {
+        let result = VmBuilder::new(code(call_other_service(), 0), "products")
+            .with_tracer(SdkTracerProvider::builder().build())
+            .build();
+        assert_eq!(result.err(), Some(VmConfigError::TracerWithoutContext));
+    }
+}
@@ -1,111 +1,2239 @@
-use std::collections::HashMap;
+//! Relays remote calls between the VMs of a simulation. A [`ServiceCoordinator`] runs as a task
+//! of its own, and VMs register and call through a [`CoordinatorHandle`].
 
-use opentelemetry::trace::{Span, SpanKind, Status, Tracer};
-use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::future::Future;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use opentelemetry::metrics::{Gauge, Histogram, MeterProvider};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{
+    Link, SpanContext, SpanKind, Status, TraceContextExt, Tracer, TracerProvider,
+};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
 use opentelemetry_sdk::trace::SdkTracerProvider;
-use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
-use tokio::sync::mpsc;
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
 
-#[derive(Debug, Clone)]
+use crate::chaos::{self, ChaosEvent, ChaosSchedule, Fault};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::events::RunEvent;
+use crate::health::{HealthReport, HealthStatus, HEALTH_CHECK};
+use crate::journal::{Journal, JournalEntry};
+use crate::metadata_map;
+use crate::parser::{
+    CallPriority, ChaosAction, CircuitBreakerConfig, Incident, Injection, RateLimit, RetryPolicy,
+};
+use crate::rate_limiter::TokenBucket;
+use crate::recording::Decisions;
+use crate::topology::Topology;
+
+/// Correlates a call with its reply
+pub type CallId = u64;
+
+/// Receives the outcome of a call once the callee's function returns
+pub type CallReply = oneshot::Sender<Result<(), CallError>>;
+
+static NEXT_CALL_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How many calls an instance holds back while its queue is full, before the coordinator waits for it
+const HELD_BACK_CALLS: usize = 100;
+
+/// Returns an ID that is unique across all services of this process
+pub fn next_call_id() -> CallId {
+    NEXT_CALL_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallError {
+    /// No service with this name is registered
+    ServiceNotFound(String),
+    /// The service stopped accepting calls
+    ServiceUnavailable(String),
+    /// The service has no method with this name
+    UnknownFunction(String),
+    /// The callee stopped before the function returned
+    Dropped,
+    /// The call failed on purpose, because of a fault injected into its route
+    InjectedFault(String),
+    /// The circuit breaker of the route rejected the call
+    CircuitOpen(String),
+    /// The service rejected the call because it received too many, like an HTTP 429
+    RateLimited(String),
+    /// The coordinator no longer accepts calls, because it is shutting down
+    ShuttingDown,
+}
+
+impl CallError {
+    /// Whether another attempt of the call may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            CallError::ServiceUnavailable(_)
+                | CallError::Dropped
+                | CallError::InjectedFault(_)
+                | CallError::RateLimited(_)
+        )
+    }
+}
+
+impl std::error::Error for CallError {}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallError::ServiceNotFound(service) => write!(f, "Service not found: {}", service),
+            CallError::ServiceUnavailable(service) => {
+                write!(f, "Service unavailable: {}", service)
+            }
+            CallError::UnknownFunction(function) => write!(f, "Unknown function: {}", function),
+            CallError::Dropped => write!(f, "Call dropped before it completed"),
+            CallError::InjectedFault(route) => write!(f, "Injected fault on {}", route),
+            CallError::CircuitOpen(route) => write!(f, "Circuit open on {}", route),
+            CallError::RateLimited(service) => write!(f, "Too many requests to {}", service),
+            CallError::ShuttingDown => write!(f, "Coordinator is shutting down"),
+        }
+    }
+}
+
+/// What VMs and the CLI send to the coordinator
+#[derive(Debug)]
 pub enum ServiceMessage {
     Call {
+        id: CallId,
+        /// The calling service
+        from: String,
         to: String,
         function: String,
-        context: opentelemetry::Context,
+        /// The trace context and baggage of the caller, serialized as W3C headers
+        context: HashMap<String, String>,
+        /// Relays completion or failure back to the caller
+        reply: Option<CallReply>,
+    },
+    /// Adds an instance of a service, or replaces the sender of the instance with the same ID
+    RegisterService {
+        name: String,
+        instance: String,
+        sender: mpsc::Sender<IncomingCall>,
+    },
+    /// Removes one instance of a service, or all of them if no instance is given.
+    /// Calls to a service without instances fail with `CallError::ServiceNotFound`.
+    DeregisterService {
+        name: String,
+        instance: Option<String>,
     },
+    /// Sent by the coordinator to itself to repeat a call that failed
+    Retry {
+        call: PendingCall,
+        /// Counts from 1 for the first attempt
+        attempt: usize,
+        /// The spans of the failed attempts, oldest first, which the span of this attempt links to
+        earlier_attempts: Vec<SpanContext>,
+    },
+    /// Kills or restarts a service, or injects or lifts latency or faults, right away
+    Chaos(ChaosEvent),
+    /// Asks for the counters of the coordinator
+    Stats {
+        reply: oneshot::Sender<CoordinatorStats>,
+    },
+    /// Stops accepting calls. Calls sent after this one fail with `CallError::ShuttingDown`.
+    Shutdown,
+}
+
+/// What the coordinator did so far
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CoordinatorStats {
+    /// Since the coordinator started to run
+    pub uptime_ms: u64,
+    /// Every service that was ever registered, ordered by name
+    pub services: Vec<ServiceStats>,
+    /// How many injections currently apply
+    pub injections: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ServiceStats {
+    pub name: String,
+    pub instances: usize,
+    /// The calls the instances have yet to take
+    pub queued_calls: usize,
+    pub delivered_calls: u64,
+    /// Whether the service is killed or paused
+    pub killed: bool,
+}
+
+/// A call to a service that was never registered, kept with everything needed to track it down
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub call_id: CallId,
+    pub from: String,
+    pub to: String,
+    pub function: String,
+    /// The trace context and baggage of the caller
+    pub context: HashMap<String, String>,
+}
+
+/// A call the coordinator has yet to deliver
+#[derive(Debug)]
+pub struct PendingCall {
+    pub id: CallId,
+    pub from: String,
+    pub to: String,
+    pub function: String,
+    /// The trace context and baggage of the caller
+    pub context: HashMap<String, String>,
+    pub reply: Option<CallReply>,
+}
+
+/// How the coordinator picks the instance of a service that receives a call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LoadBalancing {
+    /// Every instance in turn
+    #[default]
+    RoundRobin,
+    /// A random instance
+    Random,
+    /// The instance with the fewest queued calls
+    LeastLoaded,
 }
 
+/// How the coordinator answers calls to stubbed services, the ones of the file that do not run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StubCalls {
+    /// Fail the calls as if the service was down
+    #[default]
+    Fail,
+    /// Complete the calls right away
+    Ignore,
+}
+
+/// The coordinator stopped and no longer accepts messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoordinatorStopped;
+
+impl std::error::Error for CoordinatorStopped {}
+
+impl std::fmt::Display for CoordinatorStopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Coordinator stopped")
+    }
+}
+
+/// Sends messages to a coordinator, also while it runs.
+/// The coordinator keeps running as long as a handle or a sender from `sender()` exists.
+#[derive(Debug, Clone)]
+pub struct CoordinatorHandle {
+    tx: mpsc::Sender<ServiceMessage>,
+    /// Tell the instances of the services the chaos schedule kills whether they are killed
+    suspensions: HashMap<String, watch::Receiver<bool>>,
+}
+
+impl CoordinatorHandle {
+    /// A handle whose messages go to `tx`, e.g. to relay them to a coordinator in another process
+    pub fn from_sender(tx: mpsc::Sender<ServiceMessage>) -> Self {
+        Self {
+            tx,
+            suspensions: HashMap::new(),
+        }
+    }
+
+    /// Whether the service is killed at the moment, if the chaos schedule kills it at some point
+    pub fn suspension(&self, service: &str) -> Option<watch::Receiver<bool>> {
+        self.suspensions.get(service).cloned()
+    }
+
+    /// A sender for VMs to make remote calls through the coordinator
+    pub fn sender(&self) -> mpsc::Sender<ServiceMessage> {
+        self.tx.clone()
+    }
+
+    pub async fn register_service(
+        &self,
+        name: &str,
+        instance: &str,
+        sender: mpsc::Sender<IncomingCall>,
+    ) -> Result<(), CoordinatorStopped> {
+        self.tx
+            .send(ServiceMessage::RegisterService {
+                name: name.to_string(),
+                instance: instance.to_string(),
+                sender,
+            })
+            .await
+            .map_err(|_| CoordinatorStopped)
+    }
+
+    /// Lets the coordinator finish the calls it accepted and stop, even while senders still exist
+    pub async fn shutdown(&self) -> Result<(), CoordinatorStopped> {
+        self.tx
+            .send(ServiceMessage::Shutdown)
+            .await
+            .map_err(|_| CoordinatorStopped)
+    }
+
+    /// Removes one instance of a service, or the whole service if `instance` is `None`
+    pub async fn deregister_service(
+        &self,
+        name: &str,
+        instance: Option<&str>,
+    ) -> Result<(), CoordinatorStopped> {
+        self.tx
+            .send(ServiceMessage::DeregisterService {
+                name: name.to_string(),
+                instance: instance.map(|instance| instance.to_string()),
+            })
+            .await
+            .map_err(|_| CoordinatorStopped)
+    }
+}
+
+/// A call delivered to the service that implements the function
+#[derive(Debug)]
+pub struct IncomingCall {
+    pub id: CallId,
+    /// The instance the coordinator picked for the call
+    pub instance: String,
+    pub function: String,
+    pub context: HashMap<String, String>,
+    pub reply: Option<CallReply>,
+}
+
+struct Instance {
+    id: String,
+    sender: mpsc::Sender<IncomingCall>,
+    /// Calls on their way to the queue of the instance, with their priority
+    outbox: mpsc::Sender<(usize, IncomingCall)>,
+    /// How many calls are held back until the queue of the instance has room
+    held_back: Arc<AtomicUsize>,
+}
+
+impl Instance {
+    // Starts the task that moves calls from the outbox to the queue of the instance
+    fn start(
+        id: String,
+        service: String,
+        sender: mpsc::Sender<IncomingCall>,
+    ) -> (Self, impl Future<Output = ()>) {
+        let (outbox, outbox_rx) = mpsc::channel(HELD_BACK_CALLS);
+        let held_back = Arc::new(AtomicUsize::new(0));
+        let delivery = deliver_by_priority(sender.clone(), outbox_rx, held_back.clone(), service);
+        let instance = Self {
+            id,
+            sender,
+            outbox,
+            held_back,
+        };
+        (instance, delivery)
+    }
+
+    /// The calls the instance has yet to take
+    fn load(&self) -> usize {
+        let queued = self.sender.max_capacity() - self.sender.capacity();
+        let in_outbox = self.outbox.max_capacity() - self.outbox.capacity();
+        queued + in_outbox + self.held_back.load(Ordering::Relaxed)
+    }
+}
+
+/// A call held back until the queue of its instance has room
+struct HeldBackCall {
+    priority: usize,
+    /// Keeps calls of the same priority in order
+    seq: u64,
+    call: IncomingCall,
+}
+
+impl HeldBackCall {
+    fn key(&self) -> (usize, Reverse<u64>) {
+        (self.priority, Reverse(self.seq))
+    }
+}
+
+impl PartialEq for HeldBackCall {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for HeldBackCall {}
+
+impl PartialOrd for HeldBackCall {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeldBackCall {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+#[derive(Default)]
 struct Service {
-    sender: mpsc::Sender<String>,
-    trace_provider: Option<SdkTracerProvider>,
+    instances: Vec<Instance>,
+    /// Where round robin continues
+    next: usize,
+}
+
+impl Service {
+    fn pick_instance(
+        &mut self,
+        load_balancing: LoadBalancing,
+        decisions: &Decisions,
+    ) -> Option<&Instance> {
+        if self.instances.is_empty() {
+            return None;
+        }
+        let len = self.instances.len();
+        let index = match load_balancing {
+            LoadBalancing::RoundRobin => self.next % len,
+            LoadBalancing::Random => random_range(decisions, 0..len as u64) as usize,
+            // Ties go round robin
+            LoadBalancing::LeastLoaded => (0..len)
+                .map(|offset| (self.next + offset) % len)
+                .min_by_key(|index| self.instances[*index].load())
+                .unwrap_or(0),
+        };
+        self.next = self.next.wrapping_add(1);
+        self.instances.get(index)
+    }
+}
+
+/// A random number in `range`. A replay that diverged goes on by chance
+fn random_range(decisions: &Decisions, range: Range<u64>) -> u64 {
+    decisions.random_range(range.clone()).unwrap_or_else(|e| {
+        tracing::warn!("{}", e);
+        rand::random_range(range)
+    })
+}
+
+/// Pings every instance at an interval, to catch instances that stopped taking calls
+struct HealthChecks {
+    interval: Duration,
+    report: Arc<Mutex<HealthReport>>,
+}
+
+#[derive(Clone)]
+struct HealthInstruments {
+    /// How long instances take to answer health checks
+    duration: Histogram<f64>,
+    /// 1 while an instance is healthy, 0 otherwise
+    health: Gauge<u64>,
 }
 
+/// Delivers the calls between services, with the faults, limits and policies it is configured with
 pub struct ServiceCoordinator {
     services: HashMap<String, Service>,
-    main_tx: mpsc::Sender<ServiceMessage>,
+    /// Every service that was ever registered, to tell unknown services from stopped ones
+    known_services: HashSet<String>,
+    dead_letter_tx: Option<mpsc::Sender<DeadLetter>>,
+    /// Reports delivered calls and dead letters
+    events_tx: Option<mpsc::Sender<RunEvent>>,
+    /// Records every call the services make, retries aside
+    topology: Option<Arc<Mutex<Topology>>>,
+    /// Records every call delivered to an instance
+    journal: Option<Journal>,
+    /// How many calls each service received
+    delivered_calls: HashMap<String, u64>,
+    /// When the coordinator started to run
+    started: Instant,
+    chaos: ChaosSchedule,
+    health_checks: Option<HealthChecks>,
+    /// Records the health checks as metrics
+    meter_provider: Option<SdkMeterProvider>,
+    /// Services the chaos schedule killed and did not restart yet
+    killed_services: HashSet<String>,
+    /// Pause the instances of a service while it is killed
+    suspensions: HashMap<String, watch::Sender<bool>>,
+    /// Services of the file that do not run, answered according to `stub_calls`
+    stubs: HashSet<String>,
+    stub_calls: StubCalls,
+    load_balancing: LoadBalancing,
+    injections: Vec<Injection>,
+    /// Fail a growing share of the calls to their services while they last
+    incidents: Vec<Incident>,
+    circuit_breaker_configs: Vec<CircuitBreakerConfig>,
+    /// One breaker per route, created on the first call
+    circuit_breakers: HashMap<(String, String), Arc<Mutex<CircuitBreaker>>>,
+    retry_policies: Vec<RetryPolicy>,
+    priorities: Vec<CallPriority>,
+    rate_limits: Vec<RateLimit>,
+    /// One bucket per rate limited service, created on the first call
+    token_buckets: HashMap<String, TokenBucket>,
+    /// Set once a shutdown is requested
+    shutting_down: bool,
+    /// Hand the calls over to the instances, one task per instance
+    deliveries: JoinSet<()>,
+    /// Records a span for every attempt on routes with a retry policy
+    tracer: Option<SdkTracerProvider>,
+    /// Lets retries reach the coordinator without keeping it running
+    retry_tx: mpsc::WeakSender<ServiceMessage>,
+    /// Random numbers for load balancing, latency and faults, drawn, recorded or replayed
+    decisions: Decisions,
+    /// Only `None` once the coordinator runs
+    main_tx: Option<mpsc::Sender<ServiceMessage>>,
     main_rx: mpsc::Receiver<ServiceMessage>,
-    remote_call_counter: usize,
+}
+
+impl Default for ServiceCoordinator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ServiceCoordinator {
-    async fn handle_remote_call(&self, msg: ServiceMessage) {
+    async fn handle_message(&mut self, msg: ServiceMessage) {
         match msg {
             ServiceMessage::Call {
+                id,
+                from,
                 to,
                 function,
                 context,
+                reply,
             } => {
-                if let Some(service) = self.services.get(&to) {
-                    let mut span = None;
-                    if let Some(trace_provider) = &service.trace_provider {
-                        let tracer = trace_provider.tracer(to.clone());
-                        span = Some(
-                            tracer
-                                .span_builder(format!("{}/{}", to.clone(), function))
-                                .with_kind(SpanKind::Server)
-                                .with_attributes(vec![KeyValue::new(SERVICE_NAME, to.clone())])
-                                .start_with_context(&tracer, &context),
-                        );
-                    }
+                let call = PendingCall {
+                    id,
+                    from,
+                    to,
+                    function,
+                    context,
+                    reply,
+                };
+                self.dispatch(call, 1, Vec::new()).await;
+            }
+            ServiceMessage::Retry {
+                call,
+                attempt,
+                earlier_attempts,
+            } => {
+                tracing::debug!(
+                    call_id = call.id,
+                    attempt,
+                    "Retrying call from {} to {}",
+                    call.from,
+                    call.to
+                );
+                self.dispatch(call, attempt, earlier_attempts).await;
+            }
+            ServiceMessage::RegisterService {
+                name,
+                instance,
+                sender,
+            } => {
+                tracing::debug!("Registering instance {} of service {}", instance, name);
+                self.known_services.insert(name.clone());
+                let (instance, delivery) = Instance::start(instance, name.clone(), sender);
+                self.deliveries.spawn(delivery);
+                let service = self.services.entry(name).or_default();
+                service
+                    .instances
+                    .retain(|existing| existing.id != instance.id);
+                service.instances.push(instance);
+            }
+            ServiceMessage::Shutdown => {
+                tracing::debug!("Shutting down the coordinator");
+                self.shutting_down = true;
+            }
+            ServiceMessage::Chaos(event) => self.apply_chaos(event),
+            ServiceMessage::Stats { reply } => {
+                // The caller may have stopped waiting for the stats
+                let _ = reply.send(self.stats());
+            }
+            ServiceMessage::DeregisterService {
+                name,
+                instance: None,
+            } => {
+                self.forget_health(&name, None);
+                if self.services.remove(&name).is_some() {
+                    tracing::debug!("Deregistered service {}", name);
+                } else {
+                    tracing::warn!("Cannot deregister unknown service {}", name);
+                }
+            }
+            ServiceMessage::DeregisterService {
+                name,
+                instance: Some(instance),
+            } => {
+                let Some(service) = self.services.get_mut(&name) else {
+                    tracing::warn!("Cannot deregister unknown service {}", name);
+                    return;
+                };
+                service.instances.retain(|existing| existing.id != instance);
+                tracing::debug!("Deregistered instance {} of service {}", instance, name);
+                if service.instances.is_empty() {
+                    self.services.remove(&name);
+                }
+                self.forget_health(&name, Some(&instance));
+            }
+        }
+    }
 
-                    service.sender.send(function).await.unwrap_or_else(|_| {
-                        tracing::error!("Error sending message");
-                        if let Some(span) = &mut span {
-                            span.set_status(Status::error("Error sending message"));
-                        }
-                    });
-                    if let Some(span) = span {
-                        drop(span);
+    // Delivers a call, applying the retry policy, circuit breaker and injections of its route
+    async fn dispatch(
+        &mut self,
+        call: PendingCall,
+        attempt: usize,
+        earlier_attempts: Vec<SpanContext>,
+    ) {
+        let PendingCall {
+            id,
+            from,
+            to,
+            function,
+            mut context,
+            mut reply,
+        } = call;
+        let route = format!("{}->{}", from, to);
+        if let (Some(topology), 1) = (self.topology.as_ref(), attempt) {
+            topology.lock().unwrap().record_call(&from, &to, &function);
+        }
+        if let Some(policy) = self.retry_policy(&from, &to) {
+            let call = PendingCall {
+                id,
+                from: from.clone(),
+                to: to.clone(),
+                function: function.clone(),
+                context: context.clone(),
+                reply,
+            };
+            let (attempt_context, attempt_reply) =
+                self.start_attempt(policy, call, attempt, earlier_attempts);
+            context = attempt_context;
+            reply = Some(attempt_reply);
+        }
+        if let Some(circuit_breaker) = self.circuit_breaker(&from, &to) {
+            let allowed = update_circuit_breaker(&circuit_breaker, &route, |breaker| {
+                breaker.allow(Instant::now().into_std())
+            });
+            if !allowed {
+                tracing::debug!(call_id = id, "Circuit open on {}", route);
+                reply_with_error(reply, CallError::CircuitOpen(route));
+                return;
+            }
+            reply = Some(observe_outcome(circuit_breaker, route.clone(), reply));
+        }
+        match self.injected_fault(&from, &to) {
+            Some(Fault::Drop) => {
+                // Dropping the reply tells the caller the call never completed
+                tracing::debug!(call_id = id, "Dropping call from {} to {}", from, to);
+                return;
+            }
+            Some(Fault::Error) => {
+                tracing::debug!(call_id = id, "Failing call from {} to {}", from, to);
+                reply_with_error(reply, CallError::InjectedFault(route));
+                return;
+            }
+            None => {}
+        }
+        if self.killed_services.contains(&to) {
+            tracing::debug!(call_id = id, "{} is killed", to);
+            reply_with_error(reply, CallError::ServiceUnavailable(to));
+            return;
+        }
+        if self.stubs.contains(&to) {
+            tracing::debug!(call_id = id, "{} is a stub", to);
+            match self.stub_calls {
+                StubCalls::Fail => reply_with_error(reply, CallError::ServiceUnavailable(to)),
+                StubCalls::Ignore => {
+                    if let Some(reply) = reply {
+                        let _ = reply.send(Ok(()));
                     }
-                } else {
-                    tracing::error!("Service not found: {}", to);
                 }
             }
+            return;
+        }
+        if self.services.contains_key(&to) && !self.admit(&to) {
+            tracing::debug!(call_id = id, "Rate limit of {} exceeded", to);
+            reply_with_error(reply, CallError::RateLimited(to));
+            return;
+        }
+        let delay = self.injected_latency(&from, &to);
+        let priority = self.priority(&from, &to);
+        let load_balancing = self.load_balancing;
+        let Some(instance) = self
+            .services
+            .get_mut(&to)
+            .and_then(|service| service.pick_instance(load_balancing, &self.decisions))
+        else {
+            if self.known_services.contains(&to) {
+                tracing::error!(call_id = id, "Service not found: {}", to);
+            } else {
+                self.dead_letter(DeadLetter {
+                    call_id: id,
+                    from,
+                    to: to.clone(),
+                    function,
+                    context,
+                });
+            }
+            reply_with_error(reply, CallError::ServiceNotFound(to));
+            return;
+        };
+        if let Some(journal) = self.journal.as_mut() {
+            journal.record(&JournalEntry::new(
+                id,
+                &from,
+                &to,
+                &function,
+                &instance.id,
+                attempt,
+                &context,
+            ));
+        }
+        send_event(
+            self.events_tx.as_ref(),
+            RunEvent::RemoteCall {
+                from: from.clone(),
+                to: to.clone(),
+                function: function.clone(),
+                instance: instance.id.clone(),
+            },
+        );
+        *self.delivered_calls.entry(to.clone()).or_default() += 1;
+        let call = IncomingCall {
+            id,
+            instance: instance.id.clone(),
+            function,
+            context,
+            reply,
+        };
+        if delay.is_zero() {
+            deliver(instance.outbox.clone(), priority, call, to).await;
+        } else {
+            // Delay in the background, so calls on other routes are not held up
+            tracing::debug!(
+                call_id = id,
+                ?delay,
+                "Delaying call from {} to {}",
+                from,
+                to
+            );
+            let outbox = instance.outbox.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                deliver(outbox, priority, call, to).await;
+            });
+        }
+    }
+
+    // Logs a call to an unknown service and hands it to the dead letter queue, if there is one
+    fn dead_letter(&self, dead_letter: DeadLetter) {
+        tracing::error!(
+            target: "dead_letter",
+            call_id = dead_letter.call_id,
+            from = %dead_letter.from,
+            to = %dead_letter.to,
+            function = %dead_letter.function,
+            traceparent = dead_letter.context.get("traceparent").map(String::as_str),
+            "Dead letter: call to unknown service {}",
+            dead_letter.to
+        );
+        send_event(
+            self.events_tx.as_ref(),
+            RunEvent::Error {
+                service: dead_letter.from.clone(),
+                error: format!(
+                    "{} called {}.{}, but there is no service {}",
+                    dead_letter.from, dead_letter.to, dead_letter.function, dead_letter.to
+                ),
+            },
+        );
+        if let Some(dead_letter_tx) = self.dead_letter_tx.as_ref() {
+            if let Err(mpsc::error::TrySendError::Full(dead_letter)) =
+                dead_letter_tx.try_send(dead_letter)
+            {
+                tracing::warn!(
+                    call_id = dead_letter.call_id,
+                    "Dead letter queue is full, dropping entry"
+                );
+            }
         }
     }
-    pub async fn run(&mut self) {
-        loop {
-            self.remote_call_counter += 1;
-            if self.remote_call_counter > 10000 {
-                match self.main_rx.try_recv() {
-                    Ok(msg) => {
-                        self.handle_remote_call(msg).await;
+
+    fn stats(&self) -> CoordinatorStats {
+        let services = self
+            .known_services
+            .iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|name| {
+                let instances = self
+                    .services
+                    .get(name)
+                    .map_or(&[][..], |service| &service.instances);
+                ServiceStats {
+                    name: name.clone(),
+                    instances: instances.len(),
+                    queued_calls: instances.iter().map(Instance::load).sum(),
+                    delivered_calls: self.delivered_calls.get(name).copied().unwrap_or_default(),
+                    killed: self.killed_services.contains(name),
+                }
+            })
+            .collect();
+        CoordinatorStats {
+            uptime_ms: self.started.elapsed().as_millis() as u64,
+            services,
+            injections: self.injections.len(),
+        }
+    }
+
+    fn apply_chaos(&mut self, event: ChaosEvent) {
+        match event {
+            ChaosEvent::Kill(service) => {
+                tracing::warn!("Chaos: killing {}", service);
+                if let Some(suspension) = self.suspensions.get(&service) {
+                    suspension.send_replace(true);
+                }
+                self.killed_services.insert(service);
+            }
+            ChaosEvent::Restart(service) => {
+                tracing::warn!("Chaos: restarting {}", service);
+                if let Some(suspension) = self.suspensions.get(&service) {
+                    suspension.send_replace(false);
+                }
+                self.killed_services.remove(&service);
+            }
+            ChaosEvent::Inject(injection) => {
+                tracing::warn!("Chaos: injecting {:?}", injection);
+                self.injections.push(injection);
+            }
+            ChaosEvent::Lift(injection) => {
+                tracing::warn!("Chaos: lifting {:?}", injection);
+                if let Some(index) = self.injections.iter().rposition(|i| *i == injection) {
+                    self.injections.remove(index);
+                }
+            }
+        }
+    }
+
+    // Sends a health check to every instance that answered the previous one. The checks skip the
+    // route settings and overtake the calls held back for the instance, so only an instance that
+    // stopped taking calls misses them.
+    fn check_health(&self, instruments: Option<&HealthInstruments>) {
+        let Some(health_checks) = self.health_checks.as_ref() else {
+            return;
+        };
+        // An instance has until the next round to answer
+        let timeout = health_checks.interval;
+        for (service, instances) in &self.services {
+            for instance in &instances.instances {
+                let report = health_checks.report.clone();
+                if !report.lock().unwrap().start_check(service, &instance.id) {
+                    continue;
+                }
+                let (reply_tx, reply_rx) = oneshot::channel();
+                let call = IncomingCall {
+                    id: next_call_id(),
+                    instance: instance.id.clone(),
+                    function: HEALTH_CHECK.to_string(),
+                    context: HashMap::new(),
+                    reply: Some(reply_tx),
+                };
+                let outbox = instance.outbox.clone();
+                let service = service.clone();
+                let instance = instance.id.clone();
+                let instruments = instruments.cloned();
+                tokio::spawn(async move {
+                    let started = Instant::now();
+                    let answered = tokio::time::timeout(timeout, async {
+                        deliver(outbox, usize::MAX, call, service.clone()).await;
+                        reply_rx.await.unwrap_or(Err(CallError::Dropped))
+                    })
+                    .await;
+                    let outcome = match answered {
+                        Ok(Ok(())) => Ok(started.elapsed()),
+                        Ok(Err(e)) => Err(e.to_string()),
+                        Err(_) => Err(format!("No answer within {:?}", timeout)),
+                    };
+                    let attributes = [
+                        KeyValue::new("service", service.clone()),
+                        KeyValue::new("instance", instance.clone()),
+                    ];
+                    if let (Some(instruments), Ok(latency)) = (instruments.as_ref(), &outcome) {
+                        instruments
+                            .duration
+                            .record(latency.as_secs_f64() * 1000.0, &attributes);
+                    }
+                    let error = outcome.as_ref().err().cloned();
+                    let mut report = report.lock().unwrap();
+                    match report.record(&service, &instance, outcome) {
+                        Some(HealthStatus::Unhealthy) => tracing::warn!(
+                            app_name = %service,
+                            instance = %instance,
+                            error = error.as_deref(),
+                            "Instance is unhealthy"
+                        ),
+                        Some(HealthStatus::Healthy) => tracing::info!(
+                            app_name = %service,
+                            instance = %instance,
+                            "Instance is healthy again"
+                        ),
+                        None => {}
                     }
-                    Err(e) => {
-                        tracing::debug!("Error: {}", e);
+                    if let Some(instruments) = instruments.as_ref() {
+                        let healthy =
+                            report.status(&service, &instance) == Some(HealthStatus::Healthy);
+                        instruments.health.record(healthy as u64, &attributes);
                     }
+                });
+            }
+        }
+    }
+
+    fn forget_health(&self, service: &str, instance: Option<&str>) {
+        if let Some(health_checks) = self.health_checks.as_ref() {
+            health_checks
+                .report
+                .lock()
+                .unwrap()
+                .remove(service, instance);
+        }
+    }
+
+    fn health_instruments(&self) -> Option<HealthInstruments> {
+        let meter = self.meter_provider.as_ref()?.meter("coordinator");
+        Some(HealthInstruments {
+            duration: meter
+                .f64_histogram("health_check_duration")
+                .with_description("How long instances take to answer health checks")
+                .with_unit("ms")
+                .build(),
+            health: meter
+                .u64_gauge("instance_health")
+                .with_description("1 while an instance answers its health checks, 0 otherwise")
+                .build(),
+        })
+    }
+
+    fn retry_policy(&self, from: &str, to: &str) -> Option<RetryPolicy> {
+        self.retry_policies
+            .iter()
+            .find(|policy| policy.route.matches(from, to))
+            .cloned()
+    }
+
+    // Starts the span of an attempt and returns the trace context for the callee along with a reply
+    // that retries the call on failure, or passes the outcome on to the caller
+    fn start_attempt(
+        &self,
+        policy: RetryPolicy,
+        mut call: PendingCall,
+        attempt: usize,
+        mut earlier_attempts: Vec<SpanContext>,
+    ) -> (HashMap<String, String>, CallReply) {
+        let mut context = call.context.clone();
+        let attempt_cx = self.tracer.as_ref().map(|tracer_provider| {
+            let tracer = tracer_provider.tracer("coordinator");
+            let propagator = TraceContextPropagator::new();
+            let parent_cx = propagator.extract(&metadata_map::MetadataMap(&mut call.context));
+            // Links every earlier attempt, so the original call can be found from any retry
+            let links = earlier_attempts
+                .iter()
+                .enumerate()
+                .map(|(i, span_context)| {
+                    let attributes = vec![KeyValue::new("retry.attempt", i as i64 + 1)];
+                    Link::new(span_context.clone(), attributes, 0)
+                })
+                .collect();
+            let span = tracer
+                .span_builder(format!("{}/{} attempt", call.to, call.function))
+                .with_kind(SpanKind::Internal)
+                .with_attributes(vec![
+                    KeyValue::new("route", format!("{}->{}", call.from, call.to)),
+                    KeyValue::new("retry.attempt", attempt as i64),
+                ])
+                .with_links(links)
+                .start_with_context(&tracer, &parent_cx);
+            let attempt_cx = parent_cx.with_span(span);
+            // The callee continues the trace under the attempt
+            context.clear();
+            propagator.inject_context(&attempt_cx, &mut metadata_map::MetadataMap(&mut context));
+            attempt_cx
+        });
+
+        let retry_tx = self.retry_tx.clone();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let result = reply_rx.await.unwrap_or(Err(CallError::Dropped));
+            if let Some(cx) = attempt_cx {
+                let span = cx.span();
+                if let Err(e) = &result {
+                    span.record_error(e);
+                    span.set_status(Status::error(e.to_string()));
+                }
+                span.end();
+                earlier_attempts.push(span.span_context().clone());
+            }
+            let retry = match &result {
+                Err(e) => e.is_retryable() && attempt < policy.attempts,
+                Ok(()) => false,
+            };
+            if !retry {
+                if let Some(reply) = call.reply {
+                    let _ = reply.send(result);
+                }
+                return;
+            }
+
+            // Exponential backoff, capped so the multiplier cannot overflow
+            let exponent = (attempt - 1).min(16) as u32;
+            tokio::time::sleep(policy.backoff * 2u32.pow(exponent)).await;
+            let message = ServiceMessage::Retry {
+                call,
+                attempt: attempt + 1,
+                earlier_attempts,
+            };
+            let Some(retry_tx) = retry_tx.upgrade() else {
+                // The coordinator stopped, so the last failure is final
+                if let ServiceMessage::Retry { call, .. } = message {
+                    if let Some(reply) = call.reply {
+                        let _ = reply.send(result);
+                    }
+                }
+                return;
+            };
+            if let Err(mpsc::error::SendError(ServiceMessage::Retry { call, .. })) =
+                retry_tx.send(message).await
+            {
+                if let Some(reply) = call.reply {
+                    let _ = reply.send(result);
                 }
-                self.remote_call_counter = 0;
             }
+        });
+        (context, reply_tx)
+    }
+
+    // Takes a token from the bucket of the service. Services without a rate limit admit every call.
+    fn admit(&mut self, service: &str) -> bool {
+        let now = Instant::now().into_std();
+        if let Some(bucket) = self.token_buckets.get_mut(service) {
+            return bucket.try_acquire(now);
         }
+        let Some(rate_limit) = self
+            .rate_limits
+            .iter()
+            .find(|rate_limit| rate_limit.service == "*" || rate_limit.service == service)
+        else {
+            return true;
+        };
+        let mut bucket = TokenBucket::new(rate_limit.calls, rate_limit.per, rate_limit.burst, now);
+        let admitted = bucket.try_acquire(now);
+        self.token_buckets.insert(service.to_string(), bucket);
+        admitted
+    }
+
+    // The priority of the first matching route, 0 without one
+    fn priority(&self, from: &str, to: &str) -> usize {
+        self.priorities
+            .iter()
+            .find(|priority| priority.route.matches(from, to))
+            .map_or(0, |priority| priority.priority)
+    }
+
+    // The total latency injected into calls from `from` to `to`
+    fn injected_latency(&self, from: &str, to: &str) -> Duration {
+        chaos::injected_latency(&self.injections, (from, to), |range| {
+            random_range(&self.decisions, range)
+        })
+    }
+
+    // The circuit breaker of the route from `from` to `to`, if one is configured
+    fn circuit_breaker(&mut self, from: &str, to: &str) -> Option<Arc<Mutex<CircuitBreaker>>> {
+        let key = (from.to_string(), to.to_string());
+        if let Some(circuit_breaker) = self.circuit_breakers.get(&key) {
+            return Some(circuit_breaker.clone());
+        }
+        let config = self
+            .circuit_breaker_configs
+            .iter()
+            .find(|config| config.route.matches(from, to))?;
+        let circuit_breaker = Arc::new(Mutex::new(CircuitBreaker::new(
+            config.failure_threshold,
+            config.window,
+            config.cooldown,
+        )));
+        self.circuit_breakers.insert(key, circuit_breaker.clone());
+        Some(circuit_breaker)
+    }
+
+    // A random number in `0.0..1.0`, drawn live if the replay diverged
+    fn roll(&self) -> f64 {
+        self.decisions.random_f64().unwrap_or_else(|e| {
+            tracing::warn!("{}", e);
+            rand::random()
+        })
+    }
+
+    // Rolls the dice for every fault injected into the route from `from` to `to`, and for the
+    // incidents of `to`
+    fn injected_fault(&self, from: &str, to: &str) -> Option<Fault> {
+        chaos::injected_fault(
+            &self.injections,
+            &self.incidents,
+            (from, to),
+            self.started.elapsed(),
+            || self.roll(),
+        )
+    }
+
+    /// Relays calls between services until every service has stopped or a shutdown is requested.
+    /// The coordinator drops its own sender here, so the queue closes once the last service drops its sender.
+    /// Before it returns, every call it received is either delivered or failed, and the queues of
+    /// the instances are closed.
+    pub async fn run(mut self) {
+        self.main_tx.take();
+        self.started = Instant::now();
+        let started = self.started;
+        let health_instruments = self.health_instruments();
+        let mut health_interval = self
+            .health_checks
+            .as_ref()
+            .map(|health_checks| tokio::time::interval(health_checks.interval));
+        while !self.shutting_down {
+            let next_chaos = self.chaos.next_at();
+            let chaos_due = async move {
+                match next_chaos {
+                    Some(at) => tokio::time::sleep_until(started + at).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let msg = tokio::select! {
+                // Chaos that is due applies before the calls that arrive meanwhile, so a run with
+                // the same calls and seed fails the same of them
+                biased;
+                _ = chaos_due => {
+                    while let Some(event) = self.chaos.pop_due(started.elapsed()) {
+                        self.apply_chaos(event);
+                    }
+                    continue;
+                }
+                _ = next_tick(health_interval.as_mut()) => {
+                    self.check_health(health_instruments.as_ref());
+                    continue;
+                }
+                msg = self.main_rx.recv() => msg,
+            };
+            let Some(msg) = msg else {
+                break;
+            };
+            self.handle_message(msg).await;
+        }
+        // Senders fail from now on, the calls that are already queued fail with `ShuttingDown`
+        self.main_rx.close();
+        while let Some(msg) = self.main_rx.recv().await {
+            reject_while_shutting_down(msg);
+        }
+        // Closes the outboxes, so the deliveries end once they handed over the held back calls
+        self.services.clear();
+        while self.deliveries.join_next().await.is_some() {}
+        if let Some(journal) = self.journal.as_mut() {
+            journal.flush();
+        }
+        tracing::debug!("Coordinator stopped");
     }
 
     pub fn new() -> Self {
         let (main_tx, main_rx) = mpsc::channel(100);
         Self {
             services: HashMap::new(),
-            main_tx,
+            known_services: HashSet::new(),
+            dead_letter_tx: None,
+            events_tx: None,
+            topology: None,
+            journal: None,
+            delivered_calls: HashMap::new(),
+            started: Instant::now(),
+            chaos: ChaosSchedule::default(),
+            health_checks: None,
+            meter_provider: None,
+            killed_services: HashSet::new(),
+            stubs: HashSet::new(),
+            stub_calls: StubCalls::default(),
+            suspensions: HashMap::new(),
+            load_balancing: LoadBalancing::default(),
+            injections: Vec::new(),
+            incidents: Vec::new(),
+            circuit_breaker_configs: Vec::new(),
+            circuit_breakers: HashMap::new(),
+            retry_policies: Vec::new(),
+            priorities: Vec::new(),
+            rate_limits: Vec::new(),
+            token_buckets: HashMap::new(),
+            shutting_down: false,
+            deliveries: JoinSet::new(),
+            tracer: None,
+            decisions: Decisions::default(),
+            retry_tx: main_tx.downgrade(),
+            main_tx: Some(main_tx),
             main_rx,
-            remote_call_counter: 0,
         }
     }
 
-    pub fn get_main_tx(&self) -> mpsc::Sender<ServiceMessage> {
-        self.main_tx.clone()
+    /// Sets how calls are spread across the instances of a service
+    pub fn with_load_balancing(mut self, load_balancing: LoadBalancing) -> Self {
+        self.load_balancing = load_balancing;
+        self
     }
 
-    pub fn add_service(
-        &mut self,
-        name: String,
-        tx: mpsc::Sender<String>,
-        tracer: Option<SdkTracerProvider>,
-    ) {
-        self.services.insert(
-            name,
-            Service {
-                sender: tx,
-                trace_provider: tracer,
+    /// Records the random numbers of the coordinator, or replays them from a recording
+    pub fn with_decisions(mut self, decisions: Decisions) -> Self {
+        self.decisions = decisions;
+        self
+    }
+
+    /// Sets the latency and faults injected into the calls between services
+    pub fn with_injections(mut self, injections: Vec<Injection>) -> Self {
+        self.injections = injections;
+        self
+    }
+
+    /// Fails the calls to the services of `incidents` at their error rate over time
+    pub fn with_incidents(mut self, incidents: Vec<Incident>) -> Self {
+        self.incidents = incidents;
+        self
+    }
+
+    /// Puts circuit breakers on routes. The first matching config applies to a route.
+    pub fn with_circuit_breakers(mut self, circuit_breakers: Vec<CircuitBreakerConfig>) -> Self {
+        self.circuit_breaker_configs = circuit_breakers;
+        self
+    }
+
+    /// Retries failed calls on routes. The first matching policy applies to a route.
+    pub fn with_retry_policies(mut self, retry_policies: Vec<RetryPolicy>) -> Self {
+        self.retry_policies = retry_policies;
+        self
+    }
+
+    /// Lets calls on some routes overtake others when a service falls behind.
+    /// The first matching priority applies to a route.
+    pub fn with_priorities(mut self, priorities: Vec<CallPriority>) -> Self {
+        self.priorities = priorities;
+        self
+    }
+
+    /// Caps the rate of calls services accept. The first matching limit applies to a service.
+    pub fn with_rate_limits(mut self, rate_limits: Vec<RateLimit>) -> Self {
+        self.rate_limits = rate_limits;
+        self
+    }
+
+    pub fn with_tracer(mut self, tracer: SdkTracerProvider) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Sends calls to services that were never registered to `dead_letter_tx`
+    pub fn with_dead_letters(mut self, dead_letter_tx: mpsc::Sender<DeadLetter>) -> Self {
+        self.dead_letter_tx = Some(dead_letter_tx);
+        self
+    }
+
+    /// Sends a [`RunEvent::RemoteCall`] for every call delivered to an instance and a
+    /// [`RunEvent::Error`] for every dead letter to `events_tx`. Events are dropped while it is full
+    pub fn with_events(mut self, events_tx: mpsc::Sender<RunEvent>) -> Self {
+        self.events_tx = Some(events_tx);
+        self
+    }
+
+    /// Records the calls between services in `topology`
+    pub fn with_topology(mut self, topology: Arc<Mutex<Topology>>) -> Self {
+        self.topology = Some(topology);
+        self
+    }
+
+    /// Appends every call delivered to an instance to `journal`
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    /// Kills and restarts services, and injects latency or faults, at the times of `actions`
+    pub fn with_chaos(mut self, actions: &[ChaosAction]) -> Self {
+        self.chaos = ChaosSchedule::new(actions);
+        let killed_services: Vec<String> =
+            self.chaos.killed_services().map(str::to_string).collect();
+        let mut coordinator = self.with_pausable_services(killed_services);
+        // Services that start late must not run before the first tick of the schedule
+        while let Some(event) = coordinator.chaos.pop_due(Duration::ZERO) {
+            coordinator.apply_chaos(event);
+        }
+        coordinator
+    }
+
+    /// Answers the calls to `services` without running them, instead of treating them as unknown
+    pub fn with_stubs(
+        mut self,
+        services: impl IntoIterator<Item = String>,
+        calls: StubCalls,
+    ) -> Self {
+        self.stubs.extend(services);
+        self.stub_calls = calls;
+        self
+    }
+
+    /// Lets `ServiceMessage::Chaos` pause the instances of `services`, not only cut the calls to them
+    pub fn with_pausable_services(mut self, services: impl IntoIterator<Item = String>) -> Self {
+        for service in services {
+            self.suspensions
+                .entry(service)
+                .or_insert_with(|| watch::Sender::new(false));
+        }
+        self
+    }
+
+    /// Pings every instance at `interval` and keeps the outcome in `report`. An instance that
+    /// misses a few health checks in a row is logged as unhealthy.
+    pub fn with_health_checks(
+        mut self,
+        interval: Duration,
+        report: Arc<Mutex<HealthReport>>,
+    ) -> Self {
+        self.health_checks = Some(HealthChecks { interval, report });
+        self
+    }
+
+    /// Exports the latency and outcome of the health checks as metrics
+    pub fn with_meter_provider(mut self, meter_provider: SdkMeterProvider) -> Self {
+        self.meter_provider = Some(meter_provider);
+        self
+    }
+
+    /// A handle to register services with and send calls through, also once the coordinator runs
+    pub fn handle(&self) -> CoordinatorHandle {
+        CoordinatorHandle {
+            tx: self
+                .main_tx
+                .clone()
+                .expect("The coordinator hands out handles before it runs"),
+            suspensions: self
+                .suspensions
+                .iter()
+                .map(|(service, suspension)| (service.clone(), suspension.subscribe()))
+                .collect(),
+        }
+    }
+}
+
+// Waits for the next tick of `interval`, or forever without one
+async fn next_tick(interval: Option<&mut tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// The coordinator does not wait for observers, so a full queue loses the event
+fn send_event(events_tx: Option<&mpsc::Sender<RunEvent>>, event: RunEvent) {
+    if let Some(events_tx) = events_tx {
+        if let Err(mpsc::error::TrySendError::Full(event)) = events_tx.try_send(event) {
+            tracing::debug!(?event, "Event queue is full, dropping event");
+        }
+    }
+}
+
+async fn deliver(
+    outbox: mpsc::Sender<(usize, IncomingCall)>,
+    priority: usize,
+    call: IncomingCall,
+    to: String,
+) {
+    let id = call.id;
+    if let Err(mpsc::error::SendError((_, call))) = outbox.send((priority, call)).await {
+        tracing::error!(call_id = id, "Error sending message");
+        reply_with_error(call.reply, CallError::ServiceUnavailable(to));
+    }
+}
+
+// Moves calls from the outbox to the queue of an instance as it has room,
+// so while the queue is full, calls with a higher priority overtake the others.
+// Ends once the outbox is closed and every call was handed over.
+async fn deliver_by_priority(
+    sender: mpsc::Sender<IncomingCall>,
+    mut outbox: mpsc::Receiver<(usize, IncomingCall)>,
+    held_back: Arc<AtomicUsize>,
+    to: String,
+) {
+    let mut calls = BinaryHeap::new();
+    let mut next_seq = 0;
+    let mut outbox_open = true;
+    while outbox_open || !calls.is_empty() {
+        tokio::select! {
+            // Takes in new calls first, so they can overtake the ones already held back
+            biased;
+            received = outbox.recv(), if outbox_open && calls.len() < HELD_BACK_CALLS => {
+                match received {
+                    Some((priority, call)) => {
+                        calls.push(HeldBackCall { priority, seq: next_seq, call });
+                        next_seq += 1;
+                        held_back.fetch_add(1, Ordering::Relaxed);
+                    }
+                    None => outbox_open = false,
+                }
+            }
+            permit = sender.reserve(), if !calls.is_empty() => {
+                let Some(HeldBackCall { call, .. }) = calls.pop() else {
+                    continue;
+                };
+                held_back.fetch_sub(1, Ordering::Relaxed);
+                match permit {
+                    Ok(permit) => permit.send(call),
+                    Err(_) => {
+                        tracing::error!(call_id = call.id, "Error sending message");
+                        reply_with_error(call.reply, CallError::ServiceUnavailable(to.clone()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Runs `f` on the breaker and logs when the state of the circuit changes
+fn update_circuit_breaker<T>(
+    circuit_breaker: &Mutex<CircuitBreaker>,
+    route: &str,
+    f: impl FnOnce(&mut CircuitBreaker) -> T,
+) -> T {
+    let mut circuit_breaker = circuit_breaker.lock().unwrap();
+    let before = circuit_breaker.state();
+    let result = f(&mut circuit_breaker);
+    let after = circuit_breaker.state();
+    if before != after {
+        tracing::warn!(route, from = %before, to = %after, "Circuit breaker changed state");
+    }
+    result
+}
+
+// Returns a reply that records the outcome of the call on the breaker before passing it on to the caller
+fn observe_outcome(
+    circuit_breaker: Arc<Mutex<CircuitBreaker>>,
+    route: String,
+    reply: Option<CallReply>,
+) -> CallReply {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let result = reply_rx.await.unwrap_or(Err(CallError::Dropped));
+        update_circuit_breaker(&circuit_breaker, &route, |breaker| {
+            breaker.record(result.is_ok(), Instant::now().into_std())
+        });
+        if let Some(reply) = reply {
+            let _ = reply.send(result);
+        }
+    });
+    reply_tx
+}
+
+// Fails a call that arrives after the coordinator started shutting down
+fn reject_while_shutting_down(msg: ServiceMessage) {
+    match msg {
+        ServiceMessage::Call { id, reply, .. } => {
+            tracing::debug!(call_id = id, "Rejecting call while shutting down");
+            reply_with_error(reply, CallError::ShuttingDown);
+        }
+        ServiceMessage::Retry { call, .. } => {
+            tracing::debug!(call_id = call.id, "Rejecting retry while shutting down");
+            reply_with_error(call.reply, CallError::ShuttingDown);
+        }
+        ServiceMessage::RegisterService { .. }
+        | ServiceMessage::DeregisterService { .. }
+        | ServiceMessage::Chaos(_)
+        | ServiceMessage::Stats { .. }
+        | ServiceMessage::Shutdown => {}
+    }
+}
+
+fn reply_with_error(reply: Option<CallReply>, error: CallError) {
+    if let Some(reply) = reply {
+        // The caller may have stopped waiting for the reply
+        let _ = reply.send(Err(error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::ChaosKind;
+
+    #[tokio::test]
+    async fn test_coordinator_relays_calls_and_stops_with_services() {
+        let coordinator = ServiceCoordinator::new();
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+        let main_tx = coordinator_handle.sender();
+
+        main_tx
+            .send(ServiceMessage::Call {
+                id: 1,
+                from: "frontend".to_string(),
+                to: "products".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: None,
+            })
+            .await
+            .unwrap();
+        let call = products_rx.recv().await.unwrap();
+        assert_eq!(call.id, 1);
+        assert_eq!(call.instance, "products-0");
+        assert_eq!(call.function, "get_products");
+
+        drop(main_tx);
+        drop(coordinator_handle);
+        tokio::time::timeout(std::time::Duration::from_secs(1), handle)
+            .await
+            .expect("Coordinator should stop once all senders are dropped")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_coordinator_replies_when_service_is_missing() {
+        let coordinator = ServiceCoordinator::new();
+        let main_tx = coordinator.handle().sender();
+        let handle = tokio::spawn(coordinator.run());
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        main_tx
+            .send(ServiceMessage::Call {
+                id: 2,
+                from: "frontend".to_string(),
+                to: "inventory".to_string(),
+                function: "count".to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            reply_rx.await.unwrap(),
+            Err(CallError::ServiceNotFound("inventory".to_string()))
+        );
+
+        drop(main_tx);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_coordinator_answers_calls_to_stubs() {
+        for (calls, expected) in [
+            (
+                StubCalls::Fail,
+                Err(CallError::ServiceUnavailable("batch".to_string())),
+            ),
+            (StubCalls::Ignore, Ok(())),
+        ] {
+            let coordinator = ServiceCoordinator::new().with_stubs(["batch".to_string()], calls);
+            let main_tx = coordinator.handle().sender();
+            let handle = tokio::spawn(coordinator.run());
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            main_tx
+                .send(ServiceMessage::Call {
+                    id: 4,
+                    from: "frontend".to_string(),
+                    to: "batch".to_string(),
+                    function: "export".to_string(),
+                    context: HashMap::new(),
+                    reply: Some(reply_tx),
+                })
+                .await
+                .unwrap();
+            assert_eq!(reply_rx.await.unwrap(), expected);
+
+            drop(main_tx);
+            handle.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deregistered_service_no_longer_receives_calls() {
+        let coordinator = ServiceCoordinator::new();
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+        coordinator_handle
+            .deregister_service("products", None)
+            .await
+            .unwrap();
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_handle
+            .sender()
+            .send(ServiceMessage::Call {
+                id: 3,
+                from: "frontend".to_string(),
+                to: "products".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            reply_rx.await.unwrap(),
+            Err(CallError::ServiceNotFound("products".to_string()))
+        );
+        // The coordinator dropped its sender, so the service sees its queue close
+        assert!(products_rx.recv().await.is_none());
+
+        drop(coordinator_handle);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_held_back_calls() {
+        let coordinator = ServiceCoordinator::new();
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        // A full queue, so the calls are held back
+        let (products_tx, mut products_rx) = mpsc::channel(1);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx.clone())
+            .await
+            .unwrap();
+        products_tx
+            .send(IncomingCall {
+                id: 0,
+                instance: "products-0".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: None,
+            })
+            .await
+            .unwrap();
+        drop(products_tx);
+
+        for id in 1..=2 {
+            coordinator_handle.sender().send(call(id)).await.unwrap();
+        }
+        coordinator_handle.shutdown().await.unwrap();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_handle
+            .sender()
+            .send(ServiceMessage::Call {
+                id: 3,
+                from: "frontend".to_string(),
+                to: "products".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+
+        let mut ids = Vec::new();
+        while let Some(call) = products_rx.recv().await {
+            ids.push(call.id);
+        }
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert_eq!(reply_rx.await.unwrap(), Err(CallError::ShuttingDown));
+        // Stops although the handle still exists
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .expect("Coordinator should stop after a shutdown")
+            .unwrap();
+        assert_eq!(coordinator_handle.shutdown().await, Err(CoordinatorStopped));
+    }
+
+    fn call(id: CallId) -> ServiceMessage {
+        ServiceMessage::Call {
+            id,
+            from: "frontend".to_string(),
+            to: "products".to_string(),
+            function: "get_products".to_string(),
+            context: HashMap::new(),
+            reply: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_robin_across_instances() {
+        let coordinator = ServiceCoordinator::new();
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (first_tx, mut first_rx) = mpsc::channel(10);
+        let (second_tx, mut second_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", first_tx)
+            .await
+            .unwrap();
+        coordinator_handle
+            .register_service("products", "products-1", second_tx)
+            .await
+            .unwrap();
+
+        for id in 0..4 {
+            coordinator_handle.sender().send(call(id)).await.unwrap();
+        }
+        drop(coordinator_handle);
+        handle.await.unwrap();
+
+        let mut first_ids = Vec::new();
+        while let Ok(call) = first_rx.try_recv() {
+            assert_eq!(call.instance, "products-0");
+            first_ids.push(call.id);
+        }
+        let mut second_ids = Vec::new();
+        while let Ok(call) = second_rx.try_recv() {
+            assert_eq!(call.instance, "products-1");
+            second_ids.push(call.id);
+        }
+        assert_eq!(first_ids, vec![0, 2]);
+        assert_eq!(second_ids, vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_least_loaded_prefers_empty_queue() {
+        let coordinator = ServiceCoordinator::new().with_load_balancing(LoadBalancing::LeastLoaded);
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (busy_tx, busy_rx) = mpsc::channel(10);
+        let (idle_tx, mut idle_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", busy_tx.clone())
+            .await
+            .unwrap();
+        coordinator_handle
+            .register_service("products", "products-1", idle_tx)
+            .await
+            .unwrap();
+        for id in 0..3 {
+            busy_tx
+                .send(IncomingCall {
+                    id,
+                    instance: "products-0".to_string(),
+                    function: "get_products".to_string(),
+                    context: HashMap::new(),
+                    reply: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        coordinator_handle.sender().send(call(10)).await.unwrap();
+        drop(coordinator_handle);
+        handle.await.unwrap();
+
+        assert_eq!(idle_rx.try_recv().unwrap().id, 10);
+        assert_eq!(busy_rx.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_latency_injection_delays_matching_route() {
+        let injections = vec![Injection::Latency {
+            route: crate::parser::Route {
+                from: "frontend".to_string(),
+                to: "products".to_string(),
+            },
+            delay: Duration::from_millis(50),
+            jitter: Duration::ZERO,
+        }];
+        let coordinator = ServiceCoordinator::new().with_injections(injections);
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+
+        let start = std::time::Instant::now();
+        coordinator_handle.sender().send(call(1)).await.unwrap();
+        drop(coordinator_handle);
+        handle.await.unwrap();
+
+        assert_eq!(products_rx.recv().await.unwrap().id, 1);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    fn faults(drop_rate: f64, error_rate: f64) -> Vec<Injection> {
+        vec![Injection::Faults {
+            route: crate::parser::Route {
+                from: "*".to_string(),
+                to: "products".to_string(),
+            },
+            drop_rate,
+            error_rate,
+        }]
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection() {
+        for (injections, expected) in [
+            (
+                faults(0.0, 1.0),
+                Ok(Err(CallError::InjectedFault(
+                    "frontend->products".to_string(),
+                ))),
+            ),
+            (faults(1.0, 0.0), Err(())),
+        ] {
+            let coordinator = ServiceCoordinator::new().with_injections(injections);
+            let coordinator_handle = coordinator.handle();
+            let handle = tokio::spawn(coordinator.run());
+            let (products_tx, mut products_rx) = mpsc::channel(10);
+            coordinator_handle
+                .register_service("products", "products-0", products_tx)
+                .await
+                .unwrap();
+
+            let (reply_tx, reply_rx) = oneshot::channel();
+            coordinator_handle
+                .sender()
+                .send(ServiceMessage::Call {
+                    id: 1,
+                    from: "frontend".to_string(),
+                    to: "products".to_string(),
+                    function: "get_products".to_string(),
+                    context: HashMap::new(),
+                    reply: Some(reply_tx),
+                })
+                .await
+                .unwrap();
+            drop(coordinator_handle);
+            handle.await.unwrap();
+
+            assert_eq!(reply_rx.await.map_err(|_| ()), expected);
+            assert!(products_rx.try_recv().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_rejects_calls_once_open() {
+        let circuit_breakers = vec![CircuitBreakerConfig {
+            route: crate::parser::Route {
+                from: "frontend".to_string(),
+                to: "products".to_string(),
+            },
+            failure_threshold: 0.5,
+            window: 2,
+            cooldown: Duration::from_secs(60),
+        }];
+        let coordinator = ServiceCoordinator::new()
+            .with_injections(faults(0.0, 1.0))
+            .with_circuit_breakers(circuit_breakers);
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+
+        let mut replies = Vec::new();
+        for id in 0..3 {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            coordinator_handle
+                .sender()
+                .send(ServiceMessage::Call {
+                    id,
+                    from: "frontend".to_string(),
+                    to: "products".to_string(),
+                    function: "get_products".to_string(),
+                    context: HashMap::new(),
+                    reply: Some(reply_tx),
+                })
+                .await
+                .unwrap();
+            // Wait for the outcome, so the breaker has seen it before the next call
+            replies.push(reply_rx.await.unwrap());
+        }
+        drop(coordinator_handle);
+        handle.await.unwrap();
+
+        let injected = Err(CallError::InjectedFault("frontend->products".to_string()));
+        assert_eq!(
+            replies,
+            vec![
+                injected.clone(),
+                injected,
+                Err(CallError::CircuitOpen("frontend->products".to_string()))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_high_priority_calls_overtake_held_back_calls() {
+        let priorities = vec![CallPriority {
+            route: crate::parser::Route {
+                from: "monitor".to_string(),
+                to: "*".to_string(),
+            },
+            priority: 10,
+        }];
+        let coordinator = ServiceCoordinator::new().with_priorities(priorities);
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        // A full queue, so every call is held back
+        let (products_tx, mut products_rx) = mpsc::channel(1);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx.clone())
+            .await
+            .unwrap();
+        products_tx
+            .send(IncomingCall {
+                id: 0,
+                instance: "products-0".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: None,
+            })
+            .await
+            .unwrap();
+        drop(products_tx);
+
+        for id in 1..=3 {
+            coordinator_handle.sender().send(call(id)).await.unwrap();
+        }
+        coordinator_handle
+            .sender()
+            .send(ServiceMessage::Call {
+                id: 4,
+                from: "monitor".to_string(),
+                to: "products".to_string(),
+                function: "health".to_string(),
+                context: HashMap::new(),
+                reply: None,
+            })
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(coordinator_handle);
+
+        let mut ids = Vec::new();
+        while let Some(call) = products_rx.recv().await {
+            ids.push(call.id);
+        }
+        assert_eq!(ids, vec![0, 4, 1, 2, 3]);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_rejects_excess_calls() {
+        let rate_limits = vec![RateLimit {
+            service: "products".to_string(),
+            calls: 1,
+            per: Duration::from_secs(60),
+            burst: 2,
+        }];
+        let coordinator = ServiceCoordinator::new().with_rate_limits(rate_limits);
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+
+        let mut replies = Vec::new();
+        for id in 0..3 {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            coordinator_handle
+                .sender()
+                .send(ServiceMessage::Call {
+                    id,
+                    from: "frontend".to_string(),
+                    to: "products".to_string(),
+                    function: "get_products".to_string(),
+                    context: HashMap::new(),
+                    reply: Some(reply_tx),
+                })
+                .await
+                .unwrap();
+            replies.push(reply_rx);
+        }
+        let rejected = replies.pop().unwrap();
+        assert_eq!(
+            rejected.await.unwrap(),
+            Err(CallError::RateLimited("products".to_string()))
+        );
+        assert_eq!(products_rx.recv().await.unwrap().id, 0);
+        assert_eq!(products_rx.recv().await.unwrap().id, 1);
+
+        drop(coordinator_handle);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retry_links_attempt_spans() {
+        let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+        let tracer = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let retry_policies = vec![RetryPolicy {
+            route: crate::parser::Route {
+                from: "frontend".to_string(),
+                to: "products".to_string(),
             },
+            attempts: 3,
+            backoff: Duration::from_millis(1),
+        }];
+        let coordinator = ServiceCoordinator::new()
+            .with_retry_policies(retry_policies)
+            .with_tracer(tracer.clone());
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_handle
+            .sender()
+            .send(ServiceMessage::Call {
+                id: 1,
+                from: "frontend".to_string(),
+                to: "products".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        // The first two attempts are dropped by the callee, the third one succeeds
+        for _ in 0..2 {
+            drop(products_rx.recv().await.unwrap());
+        }
+        let third = products_rx.recv().await.unwrap();
+        assert_eq!(third.id, 1);
+        assert!(third.context.contains_key("traceparent"));
+        third.reply.unwrap().send(Ok(())).unwrap();
+
+        assert_eq!(reply_rx.await.unwrap(), Ok(()));
+        drop(coordinator_handle);
+        handle.await.unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 3);
+        assert_eq!(
+            spans[0].status,
+            Status::error(CallError::Dropped.to_string())
         );
+        assert_eq!(spans[0].events.events[0].name, "exception");
+        let links = &spans[2].links.links;
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].span_context, spans[0].span_context);
+        assert_eq!(links[0].attributes, [KeyValue::new("retry.attempt", 1)]);
+        assert_eq!(links[1].span_context, spans[1].span_context);
+        assert!(spans[2]
+            .attributes
+            .contains(&KeyValue::new("retry.attempt", 3)));
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters_for_unknown_services_only() {
+        let (dead_letter_tx, mut dead_letter_rx) = mpsc::channel(10);
+        let coordinator = ServiceCoordinator::new().with_dead_letters(dead_letter_tx);
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, _products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+        coordinator_handle
+            .deregister_service("products", None)
+            .await
+            .unwrap();
+
+        let mut context = HashMap::new();
+        context.insert("traceparent".to_string(), "00-abc-def-01".to_string());
+        for (id, to) in [(1, "products"), (2, "inventory")] {
+            coordinator_handle
+                .sender()
+                .send(ServiceMessage::Call {
+                    id,
+                    from: "frontend".to_string(),
+                    to: to.to_string(),
+                    function: "get_products".to_string(),
+                    context: context.clone(),
+                    reply: None,
+                })
+                .await
+                .unwrap();
+        }
+        drop(coordinator_handle);
+        handle.await.unwrap();
+
+        assert_eq!(
+            dead_letter_rx.recv().await,
+            Some(DeadLetter {
+                call_id: 2,
+                from: "frontend".to_string(),
+                to: "inventory".to_string(),
+                function: "get_products".to_string(),
+                context,
+            })
+        );
+        assert_eq!(dead_letter_rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_journal_records_delivered_calls() {
+        let path =
+            std::env::temp_dir().join(format!("mustermann-journal-{}.ndjson", next_call_id()));
+        let coordinator = ServiceCoordinator::new().with_journal(Journal::open(&path).unwrap());
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+
+        let mut context = HashMap::new();
+        context.insert("traceparent".to_string(), "00-abc-def-01".to_string());
+        for (id, to) in [(1, "products"), (2, "inventory")] {
+            coordinator_handle
+                .sender()
+                .send(ServiceMessage::Call {
+                    id,
+                    from: "frontend".to_string(),
+                    to: to.to_string(),
+                    function: "get_products".to_string(),
+                    context: context.clone(),
+                    reply: None,
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(products_rx.recv().await.unwrap().id, 1);
+        drop(coordinator_handle);
+        handle.await.unwrap();
+
+        // Only the call that reached an instance is in the journal
+        let journal = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let entries: Vec<serde_json::Value> = journal
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["call_id"], 1);
+        assert_eq!(entries[0]["from"], "frontend");
+        assert_eq!(entries[0]["to"], "products");
+        assert_eq!(entries[0]["instance"], "products-0");
+        assert_eq!(entries[0]["attempt"], 1);
+        assert_eq!(entries[0]["trace_id"], "abc");
+        assert_eq!(entries[0]["span_id"], "def");
+    }
+
+    #[tokio::test]
+    async fn test_chaos_kills_and_restarts_services() {
+        let actions = [
+            ChaosAction {
+                at: Duration::ZERO,
+                kind: ChaosKind::Kill("products".to_string()),
+            },
+            ChaosAction {
+                at: Duration::from_millis(500),
+                kind: ChaosKind::Restart("products".to_string()),
+            },
+        ];
+        let coordinator = ServiceCoordinator::new().with_chaos(&actions);
+        let coordinator_handle = coordinator.handle();
+        let mut suspension = coordinator_handle.suspension("products").unwrap();
+        assert!(coordinator_handle.suspension("frontend").is_none());
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+
+        suspension.wait_for(|killed| *killed).await.unwrap();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        coordinator_handle
+            .sender()
+            .send(ServiceMessage::Call {
+                id: 1,
+                from: "frontend".to_string(),
+                to: "products".to_string(),
+                function: "get_products".to_string(),
+                context: HashMap::new(),
+                reply: Some(reply_tx),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            reply_rx.await.unwrap(),
+            Err(CallError::ServiceUnavailable("products".to_string()))
+        );
+
+        suspension.wait_for(|killed| !*killed).await.unwrap();
+        coordinator_handle.sender().send(call(2)).await.unwrap();
+        assert_eq!(products_rx.recv().await.unwrap().id, 2);
+        drop(coordinator_handle);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_health_checks_find_wedged_instances() {
+        let report = Arc::new(Mutex::new(HealthReport::default()));
+        let coordinator =
+            ServiceCoordinator::new().with_health_checks(Duration::from_millis(20), report.clone());
+        let coordinator_handle = coordinator.handle();
+        let handle = tokio::spawn(coordinator.run());
+        let (products_tx, mut products_rx) = mpsc::channel(10);
+        coordinator_handle
+            .register_service("products", "products-0", products_tx)
+            .await
+            .unwrap();
+        // Never takes a call, like a VM stuck in a function
+        let (payments_tx, payments_rx) = mpsc::channel(1);
+        coordinator_handle
+            .register_service("payments", "payments-0", payments_tx)
+            .await
+            .unwrap();
+        let products = tokio::spawn(async move {
+            while let Some(call) = products_rx.recv().await {
+                assert_eq!(call.function, HEALTH_CHECK);
+                call.reply.unwrap().send(Ok(())).unwrap();
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        {
+            let report = report.lock().unwrap();
+            assert_eq!(
+                report.status("products", "products-0"),
+                Some(HealthStatus::Healthy)
+            );
+            assert_eq!(
+                report.status("payments", "payments-0"),
+                Some(HealthStatus::Unhealthy)
+            );
+        }
+
+        coordinator_handle
+            .deregister_service("payments", None)
+            .await
+            .unwrap();
+        // Lets the delivery give up on the health checks held back for it
+        drop(payments_rx);
+        coordinator_handle
+            .deregister_service("products", None)
+            .await
+            .unwrap();
+        drop(coordinator_handle);
+        handle.await.unwrap();
+        products.await.unwrap();
+        assert!(report.lock().unwrap().instances().is_empty());
     }
 }
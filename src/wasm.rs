@@ -0,0 +1,78 @@
+//! JavaScript bindings of the parser and the [`crate::simulation`], e.g. for a playground in the
+//! browser:
+//!
+//! ```text
+//! const playground = new Playground(source);
+//! playground.run(1000);
+//! for (const line of playground.takeOutput()) {
+//!   console.log(line.at, line.service, line.stream, line.message);
+//! }
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+use crate::parser;
+use crate::simulation::{Simulation, Stream};
+
+#[wasm_bindgen]
+pub struct Playground {
+    simulation: Simulation,
+}
+
+#[wasm_bindgen]
+impl Playground {
+    /// Parses and compiles the program, or throws the parse or compile error
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str) -> Result<Playground, JsError> {
+        let program = parser::parse(source).map_err(|e| JsError::new(&e.to_string()))?;
+        let simulation =
+            Simulation::from_program(&program).map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(Playground { simulation })
+    }
+
+    /// Runs one instruction. Returns false once all services stopped
+    pub fn step(&mut self) -> Result<bool, JsError> {
+        self.simulation
+            .step()
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Runs up to `max_steps` instructions and returns how many ran
+    pub fn run(&mut self, max_steps: usize) -> Result<usize, JsError> {
+        self.simulation
+            .run(max_steps)
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// The simulated time in milliseconds
+    pub fn clock(&self) -> f64 {
+        self.simulation.clock().as_secs_f64() * 1000.0
+    }
+
+    /// The lines printed since the last call
+    #[wasm_bindgen(js_name = takeOutput)]
+    pub fn take_output(&mut self) -> Vec<OutputLine> {
+        self.simulation
+            .take_output()
+            .into_iter()
+            .map(|output| OutputLine {
+                service: output.service,
+                stream: match output.stream {
+                    Stream::Stdout => "stdout".to_string(),
+                    Stream::Stderr => "stderr".to_string(),
+                },
+                message: output.message,
+                at: output.at.as_secs_f64() * 1000.0,
+            })
+            .collect()
+    }
+}
+
+/// A printed line, with the simulated time in milliseconds
+#[wasm_bindgen(getter_with_clone)]
+pub struct OutputLine {
+    pub service: String,
+    pub stream: String,
+    pub message: String,
+    pub at: f64,
+}
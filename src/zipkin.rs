@@ -0,0 +1,160 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use opentelemetry::trace::{SpanId, SpanKind, Status};
+use opentelemetry::Key;
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+use opentelemetry_sdk::Resource;
+use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
+use serde_json::{json, Map, Value};
+
+/// Where Zipkin takes spans unless told otherwise
+pub const DEFAULT_URL: &str = "http://localhost:9411/api/v2/spans";
+
+/// Sends spans as Zipkin v2 JSON, for tracing backends that do not take OTLP
+#[derive(Debug)]
+pub struct ZipkinExporter {
+    client: reqwest::blocking::Client,
+    url: String,
+    service_name: String,
+}
+
+impl ZipkinExporter {
+    pub fn new(url: &str) -> Self {
+        // The blocking client runs a runtime of its own, which cannot be created inside tokio's
+        let client = std::thread::spawn(|| {
+            reqwest::blocking::Client::builder()
+                .timeout(Duration::from_secs(3))
+                .build()
+                .unwrap_or_else(|_| reqwest::blocking::Client::new())
+        })
+        .join()
+        .expect("creating the HTTP client does not panic");
+        Self {
+            client,
+            url: url.to_string(),
+            service_name: String::new(),
+        }
+    }
+}
+
+impl SpanExporter for ZipkinExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        let spans: Vec<Value> = batch
+            .iter()
+            .map(|span| span_json(span, &self.service_name))
+            .collect();
+        let response = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(Value::Array(spans).to_string())
+            .send()
+            .map_err(|e| OTelSdkError::InternalFailure(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(OTelSdkError::InternalFailure(format!(
+                "Zipkin answered {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        if let Some(service_name) = resource.get(&Key::from_static_str(SERVICE_NAME)) {
+            self.service_name = service_name.to_string();
+        }
+    }
+}
+
+fn micros(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros()
+}
+
+/// A span in the Zipkin v2 format, see https://zipkin.io/zipkin-api/#/default/post_spans
+fn span_json(span: &SpanData, service_name: &str) -> Value {
+    let mut tags: Map<String, Value> = span
+        .attributes
+        .iter()
+        .map(|attribute| {
+            (
+                attribute.key.to_string(),
+                attribute.value.to_string().into(),
+            )
+        })
+        .collect();
+    if let Status::Error { description } = &span.status {
+        tags.insert("otel.status_code".into(), "ERROR".into());
+        tags.insert("error".into(), description.to_string().into());
+    }
+    let start = micros(span.start_time);
+    let mut json = json!({
+        "traceId": span.span_context.trace_id().to_string(),
+        "id": span.span_context.span_id().to_string(),
+        "name": span.name,
+        "timestamp": start,
+        // Zipkin drops spans without a duration
+        "duration": micros(span.end_time).saturating_sub(start).max(1),
+        "localEndpoint": { "serviceName": service_name },
+        "tags": tags,
+        "annotations": span.events.iter().map(|event| json!({
+            "timestamp": micros(event.timestamp),
+            "value": event.name,
+        })).collect::<Vec<_>>(),
+    });
+    if span.parent_span_id != SpanId::INVALID {
+        json["parentId"] = span.parent_span_id.to_string().into();
+    }
+    // Internal spans have no kind in Zipkin
+    let kind = match span.span_kind {
+        SpanKind::Client => Some("CLIENT"),
+        SpanKind::Server => Some("SERVER"),
+        SpanKind::Producer => Some("PRODUCER"),
+        SpanKind::Consumer => Some("CONSUMER"),
+        SpanKind::Internal => None,
+    };
+    if let Some(kind) = kind {
+        json["kind"] = kind.into();
+    }
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{TraceContextExt, Tracer, TracerProvider};
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+    #[test]
+    fn test_spans_in_zipkin_format() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let tracer = provider.tracer("test");
+        tracer.in_span("frontend/main_page", |cx| {
+            let span = tracer
+                .span_builder("frontend/main_page")
+                .with_kind(SpanKind::Client)
+                .with_attributes(vec![KeyValue::new("peer.service", "products")])
+                .start(&tracer);
+            drop(span);
+            cx.span().set_status(Status::error("Service not found"));
+        });
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let client = span_json(&spans[0], "frontend");
+        let parent = span_json(&spans[1], "frontend");
+        assert_eq!(client["kind"], "CLIENT");
+        assert_eq!(client["parentId"], parent["id"]);
+        assert_eq!(client["traceId"], parent["traceId"]);
+        assert_eq!(client["localEndpoint"]["serviceName"], "frontend");
+        assert_eq!(client["tags"]["peer.service"], "products");
+        assert!(parent.get("kind").is_none());
+        assert!(parent.get("parentId").is_none());
+        assert_eq!(parent["tags"]["error"], "Service not found");
+    }
+}
@@ -0,0 +1,7 @@
+#!/bin/sh
+# Starts the collector and Jaeger, then runs the scenario until Ctrl+C
+set -e
+cd "$(dirname "$0")"
+
+docker compose up -d
+mustermann run scenario.muster http://localhost:4317 "$@"